@@ -0,0 +1,16 @@
+extern crate grass;
+extern crate calculator;
+
+use grass::driver;
+use calculator::{PUSH, MUL, SUB};
+
+fn main() {
+    let mut my_driver = driver::Driver::default();
+    let guest = calculator::register(&mut my_driver);
+
+    let program = [PUSH, 6, PUSH, 7, MUL, PUSH, 2, SUB];
+    println!("{:?}", calculator::run(&mut my_driver, guest, &program).expect("guest error"));
+
+    let sum = calculator::sum_to(100);
+    println!("{:?}", calculator::run(&mut my_driver, guest, &sum).expect("guest error"));
+}
@@ -0,0 +1,62 @@
+//! The calculator guest: programs for a stack machine, with tokens for
+//! pushing numbers, arithmetic, shuffling the operand stack and looping.
+//! The interpreter in `this` runs standalone on the driver, see
+//! `Driver::run_program`. It calls a function for the arithmetic and keeps
+//! pairs of operands in tuples, both of which get recorded into traces.
+
+extern crate grass;
+
+use grass::bc::Program;
+use grass::core::objects::{R_BoxedValue, R_Struct};
+use grass::driver::{Driver, InterpResult, ProgramId};
+
+pub mod this;
+
+
+/// `PUSH n` pushes `n`
+pub const PUSH: usize = 0;
+/// the arithmetic replaces the top two values with the result
+pub const ADD: usize = 1;
+pub const SUB: usize = 2;
+pub const MUL: usize = 3;
+/// swap the top two values
+pub const SWAP: usize = 4;
+/// push a copy of the value below the top
+pub const OVER: usize = 5;
+/// remove the top value
+pub const DROP: usize = 6;
+/// `JNZ target` continues at the token `target` unless the top value is 0
+pub const JNZ: usize = 7;
+
+/// values the operand stack can hold
+pub const STACK_SIZE: usize = 16;
+
+/// Make the calculator interpreter known to `driver`.
+pub fn register(driver: &mut Driver) -> ProgramId {
+    driver.register_program(Program::from_static(this::PROGRAM, this::IDX))
+}
+
+/// Run the guest `tokens` and return the value on top of the operand stack.
+pub fn run(driver: &mut Driver, guest: ProgramId, tokens: &[usize]) -> InterpResult<usize> {
+    let mut program = R_Struct::with_size(tokens.len());
+    for (idx, &token) in tokens.iter().enumerate() {
+        program.set(idx, R_BoxedValue::Usize(token));
+    }
+    let args = vec![R_BoxedValue::Struct(R_Struct::with_size(STACK_SIZE)),
+                    R_BoxedValue::Struct(program),
+                    R_BoxedValue::Usize(0),
+                    R_BoxedValue::Usize(0)];
+
+    match try!(driver.run_program(guest, this::IDX.0, args)) {
+        R_BoxedValue::Usize(result) => Ok(result),
+        result => panic!("expected a number, got {:?}", result),
+    }
+}
+
+/// Tokens which add up the numbers from `n` down to 1.
+pub fn sum_to(n: usize) -> Vec<usize> {
+    vec![PUSH, 0, PUSH, n,
+         // loop: acc, n
+         SWAP, OVER, ADD, SWAP, PUSH, 1, SUB, JNZ, 4,
+         DROP]
+}
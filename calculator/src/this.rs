@@ -0,0 +1,94 @@
+//! Bytecode of the calculator interpreter. It is assembled by hand rather
+//! than generated by `grassc`, the comments give the Rust it stands for.
+
+use grass::core::objects::R_BoxedValue::*;
+
+use grass::bc::bytecode::OpCode;
+use grass::bc::bytecode::BinOp::*;
+
+use super::{PUSH, ADD, SUB, JNZ, SWAP, OVER, DROP};
+
+
+pub static PROGRAM: &'static [(usize, usize, &'static [OpCode])] = &[
+    // the merge point
+    (0, 0, &[]),
+    // fn main(stack: [usize; STACK_SIZE], tokens: &[usize], sp: usize, pc: usize) -> usize
+    // with `op` in local 4 and `pair` in local 5
+    (4, 6, &[
+        // loop {
+        //     pc = merge_point(tokens, sp, pc);
+        OpCode::Load(1), OpCode::Load(2), OpCode::Load(3), OpCode::ConstValue(Func(0)),
+        OpCode::Call, OpCode::Store(3),
+        //     if pc >= tokens.len() { break }
+        OpCode::Load(3), OpCode::Load(1), OpCode::Len, OpCode::BinOp(Ge), OpCode::SkipIf(151),
+        //     let op = tokens[pc];
+        OpCode::Load(3), OpCode::Load(1), OpCode::GetIndex, OpCode::Use, OpCode::Store(4),
+        //     match op {
+        OpCode::Load(4), OpCode::ConstValue(Usize(PUSH)), OpCode::BinOp(Eq), OpCode::SkipIf(47),
+        OpCode::Load(4), OpCode::ConstValue(Usize(JNZ)), OpCode::BinOp(Eq), OpCode::SkipIf(60),
+        OpCode::Load(4), OpCode::ConstValue(Usize(SWAP)), OpCode::BinOp(Eq), OpCode::SkipIf(77),
+        OpCode::Load(4), OpCode::ConstValue(Usize(OVER)), OpCode::BinOp(Eq), OpCode::SkipIf(104),
+        OpCode::Load(4), OpCode::ConstValue(Usize(DROP)), OpCode::BinOp(Eq), OpCode::SkipIf(117),
+        //         _ => {
+        //             stack[sp - 2] = apply(op, stack[sp - 2], stack[sp - 1]);
+        OpCode::Load(4), OpCode::Use, OpCode::Load(2), OpCode::ConstValue(Usize(2)),
+        OpCode::BinOp(Sub), OpCode::Load(0), OpCode::GetIndex, OpCode::Use, OpCode::Load(2),
+        OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub), OpCode::Load(0), OpCode::GetIndex,
+        OpCode::Use, OpCode::ConstValue(Func(2)), OpCode::Call, OpCode::Load(2),
+        OpCode::ConstValue(Usize(2)), OpCode::BinOp(Sub), OpCode::Load(0), OpCode::AssignIndex,
+        //             sp -= 1; pc += 1;
+        OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub), OpCode::Store(2),
+        OpCode::Load(3), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(3),
+        OpCode::JumpBack(65),
+        //         }
+        //         PUSH => { stack[sp] = tokens[pc + 1]; sp += 1; pc += 2; }
+        OpCode::Load(3), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Load(1),
+        OpCode::GetIndex, OpCode::Load(2), OpCode::Load(0), OpCode::AssignIndex, OpCode::Load(2),
+        OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(2), OpCode::Load(3),
+        OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add), OpCode::Store(3), OpCode::JumpBack(82),
+        //         JNZ => if stack[sp - 1] != 0 { pc = tokens[pc + 1] } else { pc += 2 },
+        OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub), OpCode::Load(0),
+        OpCode::GetIndex, OpCode::ConstValue(Usize(0)), OpCode::BinOp(Ne), OpCode::SkipIf(6),
+        OpCode::Load(3), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add), OpCode::Store(3),
+        OpCode::JumpBack(95), OpCode::Load(3), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add),
+        OpCode::Load(1), OpCode::GetIndex, OpCode::Use, OpCode::Store(3), OpCode::JumpBack(103),
+        //         SWAP => {
+        //             let pair = (stack[sp - 2], stack[sp - 1]);
+        OpCode::Load(2), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Sub), OpCode::Load(0),
+        OpCode::GetIndex, OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub),
+        OpCode::Load(0), OpCode::GetIndex, OpCode::TupleFromStack(2), OpCode::Store(5),
+        //             stack[sp - 2] = pair.1; stack[sp - 1] = pair.0; pc += 1;
+        OpCode::Load(5), OpCode::TupleGet(1), OpCode::Load(2), OpCode::ConstValue(Usize(2)),
+        OpCode::BinOp(Sub), OpCode::Load(0), OpCode::AssignIndex, OpCode::Load(5),
+        OpCode::TupleGet(0), OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub),
+        OpCode::Load(0), OpCode::AssignIndex, OpCode::Load(3), OpCode::ConstValue(Usize(1)),
+        OpCode::BinOp(Add), OpCode::Store(3), OpCode::JumpBack(134),
+        //         }
+        //         OVER => { stack[sp] = stack[sp - 2]; sp += 1; pc += 1; }
+        OpCode::Load(2), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Sub), OpCode::Load(0),
+        OpCode::GetIndex, OpCode::Load(2), OpCode::Load(0), OpCode::AssignIndex, OpCode::Load(2),
+        OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(2), OpCode::Load(3),
+        OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(3), OpCode::JumpBack(151),
+        //         DROP => { sp -= 1; pc += 1; }
+        OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub), OpCode::Store(2),
+        OpCode::Load(3), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(3),
+        OpCode::JumpBack(160),
+        //     }
+        // }
+        // stack[sp - 1]
+        OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Sub), OpCode::Load(0),
+        OpCode::GetIndex, OpCode::Use, OpCode::Return,
+    ]),
+    // fn apply(op: usize, a: usize, b: usize) -> usize
+    (3, 3, &[
+        OpCode::Load(0), OpCode::ConstValue(Usize(ADD)), OpCode::BinOp(Eq), OpCode::SkipIf(9),
+        OpCode::Load(0), OpCode::ConstValue(Usize(SUB)), OpCode::BinOp(Eq), OpCode::SkipIf(9),
+        // a * b
+        OpCode::Load(1), OpCode::Load(2), OpCode::BinOp(Mul), OpCode::Return,
+        // a + b
+        OpCode::Load(1), OpCode::Load(2), OpCode::BinOp(Add), OpCode::Return,
+        // a - b
+        OpCode::Load(1), OpCode::Load(2), OpCode::BinOp(Sub), OpCode::Return,
+    ]),
+];
+pub const IDX: (usize, usize) = (1, 6);
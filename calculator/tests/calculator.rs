@@ -0,0 +1,88 @@
+extern crate grass;
+extern crate calculator;
+
+use grass::bc::Program;
+use grass::bc::bytecode::OpCode;
+use grass::bc::verify::verify;
+use grass::driver::{Anchoring, Driver};
+use grass::driver::golden::{check_golden, dump_traces};
+use calculator::{PUSH, ADD, SUB, MUL, SWAP, OVER, DROP};
+
+fn run(tokens: &[usize]) -> usize {
+    let mut driver = Driver::default();
+    let guest = calculator::register(&mut driver);
+    calculator::run(&mut driver, guest, tokens).unwrap()
+}
+
+#[test]
+fn computes_arithmetic() {
+    assert_eq!(run(&[PUSH, 6, PUSH, 7, MUL, PUSH, 2, SUB]), 40);
+    assert_eq!(run(&[PUSH, 2, PUSH, 3, ADD]), 5);
+    assert_eq!(run(&[PUSH, 2, PUSH, 9, SWAP, SUB]), 7);
+    assert_eq!(run(&[PUSH, 2, PUSH, 9, OVER, ADD]), 11);
+    assert_eq!(run(&[PUSH, 2, PUSH, 9, DROP]), 2);
+}
+
+#[test]
+fn sums_in_a_loop() {
+    for &n in &[1, 5, 100] {
+        assert_eq!(run(&calculator::sum_to(n)), n * (n + 1) / 2);
+    }
+}
+
+#[test]
+fn traces_the_loop() {
+    let mut driver = Driver::default();
+    let guest = calculator::register(&mut driver);
+    assert_eq!(calculator::run(&mut driver, guest, &calculator::sum_to(100)), Ok(5050));
+    assert!(driver.coverage(guest).traced() > 0);
+}
+
+#[test]
+fn traces_match_the_golden_file() {
+    let mut driver = Driver::default();
+    let guest = calculator::register(&mut driver);
+    assert_eq!(calculator::run(&mut driver, guest, &calculator::sum_to(100)), Ok(5050));
+    check_golden(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/sum_to.txt"),
+                 &dump_traces(&driver, guest));
+}
+
+// Every merge point starts recording, including those of iterations which
+// call `apply`.
+#[test]
+fn traces_calls_anywhere() {
+    let mut driver = Driver::default();
+    driver.set_anchoring(Anchoring::Everywhere);
+    let guest = calculator::register(&mut driver);
+    for &n in &[5, 100] {
+        assert_eq!(calculator::run(&mut driver, guest, &calculator::sum_to(n)),
+                   Ok(n * (n + 1) / 2));
+    }
+}
+
+// The iterations which call `apply` run their traces through its opcodes,
+// in a frame of its own.
+#[test]
+fn traces_inline_calls() {
+    let mut driver = Driver::default();
+    driver.set_anchoring(Anchoring::Everywhere);
+    let guest = calculator::register(&mut driver);
+    assert_eq!(calculator::run(&mut driver, guest, &calculator::sum_to(100)), Ok(5050));
+
+    let traces = driver.traces(guest);
+    let inlined: Vec<_> = traces.iter()
+        .filter(|trace| trace.ops.iter().any(|op| match *op {
+            OpCode::FlatCall(..) => true,
+            _ => false,
+        }))
+        .collect();
+    assert!(!inlined.is_empty());
+    assert!(inlined.iter().all(|trace| trace.ops.contains(&OpCode::Return)));
+    assert!(inlined.iter().any(|trace| trace.stats.entries() > 0));
+}
+
+#[test]
+fn bytecode_passes_the_verifier() {
+    let program = Program::from_static(calculator::this::PROGRAM, calculator::this::IDX);
+    assert_eq!(verify(&program), Ok(()));
+}
@@ -0,0 +1,15 @@
+extern crate grass;
+extern crate meta_bf;
+
+use grass::driver;
+use meta_bf::{DEC, REP};
+
+fn main() {
+    let mut my_driver = driver::Driver::default();
+    let guest = meta_bf::register(&mut my_driver);
+
+    let program = [DEC, REP];
+    let cell = meta_bf::run(&mut my_driver, guest, &program, 10).expect("guest error");
+
+    println!("{:?}", cell);
+}
@@ -0,0 +1,44 @@
+//! The counter guest: a program of `DEC` and `REP` instructions which counts
+//! a cell down, run by the interpreter compiled to bytecode in `this`.
+
+extern crate grass;
+
+use grass::bc::Program;
+use grass::driver::{Driver, InterpResult, ProgramId};
+
+pub mod this;
+
+
+pub const DEC: usize = 0;
+pub const REP: usize = 1;
+
+/// Make the counter interpreter known to `driver`.
+pub fn register(driver: &mut Driver) -> ProgramId {
+    driver.register_program(Program::from_static(this::PROGRAM, this::IDX))
+}
+
+/// Run the guest `program` on `cell` and return the final value of the cell.
+pub fn run(driver: &mut Driver, guest: ProgramId, program: &[usize], mut cell: usize)
+           -> InterpResult<usize> {
+    let mut pc = 0;
+
+    loop {
+        pc = try!(driver.merge_point(guest, program, pc, &mut cell));
+
+        if pc >= program.len() {
+            break;
+        }
+
+        let opcode = program[pc];
+
+        if opcode == DEC {
+            cell -= 1;
+        } else if opcode == REP && cell > 0 {
+            pc -= 1;
+            continue;
+        }
+        pc += 1;
+    }
+
+    Ok(cell)
+}
@@ -0,0 +1,529 @@
+extern crate grass;
+extern crate meta_bf;
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use grass::bc::Program;
+use grass::bc::bytecode::{BinOp, Guard, InternalFunc, OpCode};
+use grass::bc::analysis::{find_loops, suggest_merge_points};
+use grass::bc::verify::verify;
+use grass::core::cell::SharedCell;
+use grass::core::objects::{CallFrame, InstructionPointer, R_BoxedValue, R_Struct};
+use grass::driver::{Driver, Decision, ErrorKind, GuardFailurePolicy, GuardLog, HashValue,
+                    JitEvent, MergeState, StepClock, CELL_LOCAL, PC_LOCAL, USER_PROGRAM_LOCAL};
+use grass::driver::golden::{check_golden, dump_traces};
+use meta_bf::{DEC, REP};
+
+#[test]
+fn counts_down_to_zero() {
+    for &start in &[1, 10, 1000] {
+        let mut driver = Driver::default();
+        let guest = meta_bf::register(&mut driver);
+        assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], start), Ok(0));
+    }
+}
+
+#[test]
+fn traces_the_loop_once() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+
+    let (mut started, mut finished, mut executed) = (0, 0, 0);
+    let mut exits = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        match event {
+            JitEvent::TraceStarted { .. } => started += 1,
+            JitEvent::TraceFinished { .. } => finished += 1,
+            // every execution of a trace ends in a guard failure
+            JitEvent::GuardFailed { trace_pc, .. } => {
+                executed += 1;
+                exits.push(trace_pc);
+            }
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+    assert_eq!((started, finished, executed), (1, 1, 2));
+    // always through the same guard
+    exits.dedup();
+    assert_eq!(exits.len(), 1);
+}
+
+#[test]
+fn guard_failures_are_counted_per_guard() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+
+    let mut exits = Vec::new();
+    while let Ok(event) = events.try_recv() {
+        if let JitEvent::GuardFailed { key, guard_id, .. } = event {
+            exits.push((key, guard_id.expect("exit through a guard")));
+        }
+    }
+    let (key, id) = exits[0];
+    let trace = driver.traces(guest).into_iter().find(|trace| trace.entry_key == key).unwrap();
+    assert_eq!(trace.stats.guard_failures_of(id), exits.len());
+    assert_eq!(trace.stats.hottest_guard(), Some((id, exits.len())));
+
+    let guards = driver.guards(key);
+    assert_eq!(guards.len(), trace.guards.len());
+    let guard = &guards[id];
+    assert_eq!(guard.id, id);
+    assert!(guard.origin.is_some());
+    assert!(guard.name().starts_with(&format!("guard {} (", id)), "{}", guard.name());
+}
+
+#[test]
+fn hot_guards_get_a_bridge() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 1000), Ok(0));
+
+    let (mut bridged, mut failures) = (None, Vec::new());
+    while let Ok(event) = events.try_recv() {
+        match event {
+            JitEvent::BridgeFinished { key, guard_id, .. } => {
+                assert_eq!(bridged, None);
+                bridged = Some((key, guard_id));
+            }
+            JitEvent::GuardFailed { guard_id, bridge, .. } => failures.push((guard_id, bridge)),
+            _ => {}
+        }
+    }
+    // the guard on `REP` failed until it got its bridge, after that the
+    // loop stays in the trace until the cell is empty
+    let (key, id) = bridged.expect("a bridge");
+    assert!(failures.len() < 10, "{} failures", failures.len());
+    assert!(failures[..failures.len() - 1].iter().all(|&failure| failure == (Some(id), None)));
+    assert_eq!(failures.last().unwrap().1, Some(id));
+
+    let trace = driver.trace(key).unwrap();
+    let bridge = trace.bridge(id).unwrap();
+    assert_eq!(bridge.entry_key, key);
+    assert_eq!(bridge.stats.guard_failures(), 1);
+    assert!(trace.stats.iterations() > 900);
+}
+
+#[test]
+fn traces_which_keep_failing_are_recorded_again() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    // without a bridge the trace is left in every iteration
+    driver.set_max_bridges(0);
+    driver.set_max_guard_failures(2);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 100), Ok(0));
+
+    let (mut finished, mut evicted) = (Vec::new(), Vec::new());
+    while let Ok(event) = events.try_recv() {
+        match event {
+            JitEvent::TraceFinished { key, id, .. } => finished.push((key, id)),
+            JitEvent::TraceEvicted { key, id } => evicted.push((key, id)),
+            _ => {}
+        }
+    }
+    let key = finished[0].0;
+    assert!(evicted.len() > 1);
+    assert_eq!(driver.invalidations(key), evicted.len());
+    // every trace but the last got discarded, and each got recorded anew
+    assert_eq!(&evicted[..], &finished[..evicted.len()]);
+    assert!(finished.len() - evicted.len() <= 1);
+}
+
+#[test]
+fn long_traces_run_in_pieces() {
+    let mut driver = Driver::default();
+    let guest = meta_bf::register(&mut driver);
+    driver.set_max_trace_len(50);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 1000), Ok(0));
+
+    let traces = driver.traces(guest);
+    let trace = &traces[0];
+    assert!(!trace.links.is_empty());
+    assert!(trace.ops.len() <= 50);
+    assert!(trace.links.iter().all(|link| link.ops.len() <= 50));
+    assert!(trace.stats.iterations() > 900);
+}
+
+#[test]
+fn programs_do_not_share_traces() {
+    let mut driver = Driver::default();
+    let first = meta_bf::register(&mut driver);
+    let second = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, first, &[DEC, REP], 10), Ok(0));
+    assert!(!driver.traces(first).is_empty());
+    assert!(driver.traces(second).is_empty());
+
+    // the same merge point in the same guest state, but another program
+    let key = driver.trace_key(second, &[DEC, REP], 0);
+    assert!(driver.traces(first).iter().any(|trace| {
+        let other = trace.entry_key;
+        (other.ip, other.pc, other.specialization) == (key.ip, key.pc, key.specialization)
+    }));
+    assert!(driver.traces(first).iter().all(|trace| trace.entry_key != key));
+    match driver.enter(key) {
+        Decision::Execute(..) => panic!("ran a trace of another program"),
+        _ => {}
+    }
+
+    assert_eq!(meta_bf::run(&mut driver, second, &[DEC, REP], 10), Ok(0));
+    let keys = |program| {
+        driver.traces(program).iter().map(|trace| trace.entry_key.program).collect::<Vec<_>>()
+    };
+    assert!(keys(first).iter().all(|&program| program == first));
+    assert!(keys(second).iter().all(|&program| program == second));
+}
+
+// only checked in debug builds
+#[cfg(debug_assertions)]
+#[test]
+#[should_panic]
+fn traces_do_not_run_for_another_program() {
+    let mut driver = Driver::default();
+    let first = meta_bf::register(&mut driver);
+    let second = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, first, &[DEC, REP], 10), Ok(0));
+
+    let trace = driver.traces(first).pop().unwrap();
+    let key = driver.trace_key(second, &[DEC, REP], trace.entry_key.pc);
+    let mut frame = driver.entry_frame(second, &[DEC, REP], trace.entry_key.pc, 10);
+    let _ = driver.execute_trace(key, &trace, &mut frame);
+}
+
+#[test]
+fn merge_points_become_steady() {
+    let mut driver = Driver::default();
+    driver.set_clock(Box::new(StepClock::default()));
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 100), Ok(0));
+
+    let key = driver.traces(guest)[0].entry_key;
+    let warmup = driver.stats().warmups[&key];
+    assert!(warmup.is_steady());
+    // interpreted until the loop got hot, then one iteration to record it
+    assert!(warmup.iterations > 5 && warmup.iterations < 10, "{:?}", warmup);
+    // the clock was read at the first visits of the merge points
+    assert!(warmup.nanos.unwrap() <= 2, "{:?}", warmup);
+
+    // steady merge points stop counting
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 100), Ok(0));
+    assert_eq!(driver.stats().warmups[&key], warmup);
+}
+
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn guard_log_samples_every_nth_failure() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    let out = Rc::new(RefCell::new(Vec::new()));
+    driver.set_guard_log(Some(GuardLog::new(Box::new(SharedOutput(out.clone())), 3, 2000)));
+    for _ in 0..20 {
+        assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+    }
+
+    let mut failures = 0;
+    while let Ok(event) = events.try_recv() {
+        if let JitEvent::GuardFailed { .. } = event {
+            failures += 1;
+        }
+    }
+    let log = driver.guard_log().unwrap();
+    assert_eq!(log.failures(), failures);
+    assert_eq!(log.sampled() + log.dropped(), failures / 3);
+    // the log is full before the runs are over
+    assert!(log.dropped() > 0);
+
+    let out = String::from_utf8(out.borrow().clone()).unwrap();
+    assert!(out.len() <= 2000);
+    let samples: Vec<_> = out.lines().filter(|line| line.starts_with("guard failure")).collect();
+    assert_eq!(samples.len(), log.sampled());
+    assert!(samples[0].starts_with("guard failure 3 "), "{}", samples[0]);
+    assert!(out.contains("  stack ") && out.contains("  frame 0"), "{}", out);
+    // the state of the host is named
+    assert!(out.contains(", cell=") && out.contains(", pc="), "{}", out);
+}
+
+#[test]
+fn returning_to_the_host_only_undoes_the_locals() {
+    let mut driver = Driver::default();
+    let out = Rc::new(RefCell::new(Vec::new()));
+    driver.set_output(SharedOutput(out.clone()));
+    driver.set_guard_failure_policy(GuardFailurePolicy::ReturnToHost);
+    let guest = meta_bf::register(&mut driver);
+    let program = [DEC, REP];
+    let key = driver.trace_key(guest, &program, 0);
+    // cell -= 1; print(cell); guard cell == 0
+    let guard = Guard {
+        expected: true,
+        recovery: InstructionPointer { func: key.ip.func, pc: key.ip.pc },
+    };
+    driver.seed_trace(key, vec![
+        OpCode::Load(CELL_LOCAL),
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::BinOp(BinOp::Sub),
+        OpCode::Store(CELL_LOCAL),
+        OpCode::Load(CELL_LOCAL),
+        OpCode::InternalFunc(InternalFunc::Print),
+        OpCode::Load(CELL_LOCAL),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::BinOp(BinOp::Eq),
+        OpCode::Guard(guard),
+        OpCode::Pop,
+    ]).unwrap();
+
+    let mut cell = 10;
+    let err = driver.merge_point(guest, &program, 0, &mut cell).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::GuardFailed { resume: 0 });
+    assert_eq!(cell, 10);
+    // the output of the undone iteration stays
+    assert_eq!(&out.borrow()[..], b"9\n");
+}
+
+#[test]
+fn traces_match_the_golden_file() {
+    let mut driver = Driver::default();
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+    check_golden(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/count_down.txt"),
+                 &dump_traces(&driver, guest));
+}
+
+fn usize_local(frame: &CallFrame, idx: usize) -> usize {
+    match frame.locals[idx].get() {
+        R_BoxedValue::Usize(n) => n,
+        val => panic!("expected usize, got {:?}", val),
+    }
+}
+
+#[test]
+fn counts_down_in_a_frame_of_the_host() {
+    let mut driver = Driver::default();
+    let guest = meta_bf::register(&mut driver);
+    let program = [DEC, REP];
+    let (mut pc, mut cell) = (0, 1000);
+    // built once, only its locals change
+    let mut frame = driver.entry_frame(guest, &program, pc, cell);
+
+    while pc < program.len() {
+        let key = driver.trace_key(guest, &program, pc);
+        let ran = match driver.enter(key) {
+            Decision::Interpret => false,
+            Decision::Record(key) => {
+                frame.locals[CELL_LOCAL].set(R_BoxedValue::Usize(cell));
+                frame.locals[PC_LOCAL].set(R_BoxedValue::Usize(pc));
+                driver.record(key, &mut frame).unwrap()
+            }
+            Decision::Execute(key, trace) => {
+                frame.locals[CELL_LOCAL].set(R_BoxedValue::Usize(cell));
+                frame.locals[PC_LOCAL].set(R_BoxedValue::Usize(pc));
+                driver.execute_trace(key, &trace, &mut frame).unwrap();
+                true
+            }
+        };
+        if ran {
+            cell = usize_local(&frame, CELL_LOCAL);
+            pc = usize_local(&frame, PC_LOCAL);
+            continue;
+        }
+
+        if program[pc] == DEC {
+            cell -= 1;
+        } else if program[pc] == REP && cell > 0 {
+            pc -= 1;
+            continue;
+        }
+        pc += 1;
+    }
+
+    assert_eq!(cell, 0);
+}
+
+// a local of the counter interpreter which its bytecode never uses
+const STEPS_LOCAL: usize = 34;
+
+// a host which keeps its guest in one struct and hands it to the driver
+// as a whole, including how many guest instructions it executed itself
+struct Machine {
+    program: Vec<usize>,
+    pc: usize,
+    cell: usize,
+    steps: usize,
+}
+
+impl MergeState for Machine {
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn specialization(&self) -> HashValue {
+        self.program.get(self.pc).map_or(0, |&op| op as HashValue)
+    }
+
+    fn red_vars(&self) -> Vec<(usize, R_BoxedValue)> {
+        let mut program = R_Struct::with_size(self.program.len());
+        for (idx, &op) in self.program.iter().enumerate() {
+            program.set(idx, R_BoxedValue::Usize(op));
+        }
+        vec![(USER_PROGRAM_LOCAL, R_BoxedValue::Struct(program)),
+             (CELL_LOCAL, R_BoxedValue::Usize(self.cell)),
+             (PC_LOCAL, R_BoxedValue::Usize(self.pc)),
+             (STEPS_LOCAL, R_BoxedValue::Usize(self.steps))]
+    }
+
+    fn restore(&mut self, frame: &CallFrame) {
+        self.cell = usize_local(frame, CELL_LOCAL);
+        self.pc = usize_local(frame, PC_LOCAL);
+        self.steps = usize_local(frame, STEPS_LOCAL);
+    }
+}
+
+#[test]
+fn counts_down_with_the_state_of_the_host() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    let mut machine = Machine {
+        program: vec![DEC, REP],
+        pc: 0,
+        cell: 1000,
+        steps: 0,
+    };
+
+    while machine.pc < machine.program.len() {
+        // the guest leaves the steps of the host alone
+        let steps = machine.steps;
+        driver.merge_point_with(guest, &mut machine).unwrap();
+        assert_eq!(machine.steps, steps);
+        if machine.pc >= machine.program.len() {
+            break;
+        }
+        machine.steps += 1;
+        if machine.program[machine.pc] == DEC {
+            machine.cell -= 1;
+        } else if machine.program[machine.pc] == REP && machine.cell > 0 {
+            machine.pc -= 1;
+            continue;
+        }
+        machine.pc += 1;
+    }
+
+    assert_eq!(machine.cell, 0);
+    // the loop ran traced
+    assert!(machine.steps < 2000);
+    let mut executed = 0;
+    while let Ok(event) = events.try_recv() {
+        if let JitEvent::GuardFailed { .. } = event {
+            executed += 1;
+        }
+    }
+    assert!(executed > 0);
+    let key = driver.state_key(guest, &machine);
+    assert_eq!(key, driver.trace_key(guest, &machine.program, machine.pc));
+    assert!(!driver.traces(guest).is_empty());
+}
+
+#[test]
+fn specializes_the_arithmetic_of_the_loop() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+
+    let mut finished = None;
+    while let Ok(event) = events.try_recv() {
+        if let JitEvent::TraceFinished { key, .. } = event {
+            finished = Some(key);
+        }
+    }
+    let trace = driver.trace(finished.unwrap()).unwrap();
+    // the usize comparisons, the rest compares bools
+    let typed = trace.ops.iter().filter(|op| op.name() == "TypedBinOp").count();
+    let guards = trace.ops.iter().filter(|op| op.name() == "GuardOperands").count();
+    assert!(typed > 0);
+    assert!(guards <= typed);
+}
+
+#[test]
+fn counts_the_iterations_of_the_trace() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+
+    let mut finished = None;
+    while let Ok(event) = events.try_recv() {
+        if let JitEvent::TraceFinished { key, .. } = event {
+            finished = Some(key);
+        }
+    }
+    let trace = driver.trace(finished.unwrap()).unwrap();
+    assert_eq!(trace.ops.last().map(|op| op.name()), Some("LoopBack"));
+    assert_eq!((trace.stats.entries(), trace.stats.iterations()), (2, 2));
+}
+
+#[test]
+fn yields_to_the_host_after_the_iteration_limit() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    driver.set_max_iterations(Some(1));
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+
+    let mut finished = None;
+    while let Ok(event) = events.try_recv() {
+        match event {
+            JitEvent::GuardFailed { .. } => panic!("left through a guard"),
+            JitEvent::TraceFinished { key, .. } => finished = Some(key),
+            _ => (),
+        }
+    }
+    let trace = driver.trace(finished.unwrap()).unwrap();
+    assert!(trace.stats.entries() > 0);
+    assert_eq!(trace.stats.iterations(), trace.stats.entries());
+    assert_eq!(trace.stats.guard_failures(), 0);
+}
+
+#[test]
+fn asks_the_yield_hook_after_every_iteration() {
+    let mut driver = Driver::default();
+    let asked = Rc::new(Cell::new(0));
+    let counter = asked.clone();
+    driver.set_yield_hook(Some(Box::new(move || {
+        counter.set(counter.get() + 1);
+        false
+    })));
+    let guest = meta_bf::register(&mut driver);
+    assert_eq!(meta_bf::run(&mut driver, guest, &[DEC, REP], 10), Ok(0));
+    assert!(asked.get() > 0);
+}
+
+#[test]
+fn bytecode_passes_the_verifier() {
+    let program = Program::from_static(meta_bf::this::PROGRAM, meta_bf::this::IDX);
+    assert_eq!(verify(&program), Ok(()));
+}
+
+#[test]
+fn merge_point_is_in_the_dispatch_loop() {
+    let program = Program::from_static(meta_bf::this::PROGRAM, meta_bf::this::IDX);
+    assert!(find_loops(&program).iter().any(|lp| lp.depth == 0 && lp.calls_merge_point));
+    assert_eq!(suggest_merge_points(&program), vec![]);
+}
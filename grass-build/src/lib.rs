@@ -0,0 +1,59 @@
+//! Generate the `this` module of a host from its build script.
+//!
+//! ```ignore
+//! // build.rs
+//! extern crate grass_build;
+//!
+//! fn main() {
+//!     grass_build::compile("src/main.rs", "src/this.rs").unwrap();
+//! }
+//! ```
+//!
+//! The bytecode is produced by `grassc`, which is looked up in `PATH` unless
+//! the `GRASSC` environment variable points to it.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Translate the guest interpreter defined in `source` and write its module
+/// to `out`.
+///
+/// Cargo is told to only run the build script again once `source` changed,
+/// and `out` is only written to if the module differs from what is there
+/// already, so that it doesn't trigger rebuilds of its own.
+pub fn compile<P: AsRef<Path>, Q: AsRef<Path>>(source: P, out: Q) -> io::Result<()> {
+    let source = source.as_ref();
+    let out = out.as_ref();
+
+    println!("cargo:rerun-if-changed={}", source.display());
+
+    let tmp = out.with_extension("rs.tmp");
+    let status = try!(Command::new(grassc())
+        .arg(source)
+        .env("GRASS_OUT", &tmp)
+        .status());
+    if !status.success() {
+        return Err(io::Error::new(io::ErrorKind::Other,
+                                  format!("grassc failed on {}: {}", source.display(), status)));
+    }
+
+    let module = try!(read(&tmp));
+    if read(out).ok().as_ref() == Some(&module) {
+        fs::remove_file(&tmp)
+    } else {
+        fs::rename(&tmp, out)
+    }
+}
+
+fn grassc() -> PathBuf {
+    env::var_os("GRASSC").map_or_else(|| PathBuf::from("grassc"), PathBuf::from)
+}
+
+fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut content));
+    Ok(content)
+}
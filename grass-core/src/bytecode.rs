@@ -0,0 +1,447 @@
+
+use std::fmt;
+use std::string::String;
+
+// use rustc::hir::def_id::DefId;
+
+use objects::{R_BoxedValue, InstructionPointer, FunctionRef};
+
+use std::marker::Sync;
+
+/// Function index reserved for `Driver::merge_point`. The front-end maps every
+/// call to the merge point to this index, it has no body.
+pub const MERGE_POINT_FUNC: usize = 0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpCode{
+    Noop,
+    Panic,
+    Pop,
+
+    Load(usize),
+    Store(usize),
+
+    Use,
+
+    StoreStatic(usize),
+
+    // Const(Constant<'tcx>),
+    Static(usize),
+    // Functions should be loaded normally
+    // let x = main;
+    // x()
+
+    RunTrace(usize),
+
+    Call,
+    // call of the method with the index in the vtable on top of the stack,
+    // the arguments are below it
+    CallVirtual(usize),
+
+    // a call in traced execution
+    // save the return address
+    FlatCall(usize, InstructionPointer, FunctionRef),
+    Return,
+
+    Resume, //resume stack unwinding
+
+
+    ConstValue(R_BoxedValue),
+    // the value in the read-only data of the program with the index
+    ConstRef(usize),
+    // UnsignedInteger(u64),
+    // Usize(usize),
+    // SignedInteger(i64),
+    // Float(f64),
+    // Bool(bool),
+
+
+    // Ref(BorrowKind),
+    Ref,
+
+    Deref,
+    DerefStore,
+
+    Unsize,
+
+    CheckedBinOp(BinOp),
+    BinOp(BinOp),
+    // BinOp which wraps around or saturates at the bounds of the type on
+    // overflow, for `Add`, `Sub`, `Mul` and, wrapping, the shifts
+    WrappingBinOp(BinOp),
+    SaturatingBinOp(BinOp),
+    // BinOp on operands known to be of the type, see GuardOperands
+    TypedBinOp(BinOp, NumType),
+
+    Not,
+    Neg,
+    // the absolute value of a number, without a branch
+    Abs,
+    // bit counts of an integer, as an integer of the same type
+    CountOnes,
+    LeadingZeros,
+    TrailingZeros,
+    // rotate the bits of an integer by the number on top of it
+    RotateLeft,
+    RotateRight,
+    // converts the integer on top of the stack to the type, failing if it
+    // doesn't fit, see `NumType::cast`
+    CheckedCast(NumType),
+    // CheckedCast of an integer the trace guarded to fit, see GuardCast
+    Cast(NumType),
+
+    Array(usize), // let x = [1, 2, 3, 4];
+    Repeat(usize), // let x = [0u32; 10];
+    Len,
+    AssignIndex,
+    GetIndex,
+    // dst[dst_start..][..len] = src[src_start..][..len], with
+    // len, src_start, src, dst_start, dst pushed in this order
+    CopyRange,
+    // dst[start..][..len] = val, with val, len, start, dst pushed
+    Fill,
+    // &dst[index], with index and dst pushed in this order, a pointer which
+    // MovePtr moves along dst
+    ElemPtr,
+    // moves the pointer into an array on top of the stack by the number of
+    // elements
+    MovePtr(isize),
+
+
+    // a tuple of the size with all fields unset
+    Tuple(usize),
+    // let x = (a, b);
+    // let x = Foo{a: a, b: b};
+    // with the fields pushed first to last
+    TupleFromStack(usize),
+
+    // x.?
+    TupleGet(usize),
+
+    // x.? = 42
+    TupleSet(usize),
+
+    Skip(usize),
+    JumpBack(usize),
+
+    SkipIf(usize),
+    JumpBackIf(usize),
+
+    InternalFunc(InternalFunc),
+
+    // a front-end puts these around the code of the guest function with the
+    // id, for the guest profile of the driver
+    ProfileEnter(usize),
+    ProfileExit(usize),
+
+    // stops at the guest breakpoint with the id for the debugger hook of the
+    // driver, traces are left before it
+    Breakpoint(usize),
+
+    // leaves the trace unless the condition on top of the stack is the
+    // expected one, which stays there for the jump the trace continues at
+    Guard(Guard),
+    // leaves the trace unless both values on top of the stack are of the type
+    GuardOperands(NumType, Guard),
+    // leaves the trace unless the value on top of the stack is the function,
+    // which stays there for the call
+    GuardFunc(usize, Guard),
+    // leaves the trace unless the value on top of the stack is the vtable,
+    // which stays there for the call
+    GuardVTable(usize, Guard),
+    // leaves the trace unless the integer on top of the stack fits into the
+    // type, which stays there for the cast
+    GuardCast(NumType, Guard),
+    // ends the iteration of a loop trace, which starts over
+    LoopBack(LoopInfo),
+
+    Todo(String),
+}
+
+unsafe impl Sync for OpCode {
+
+}
+
+impl OpCode {
+    pub fn todo_s(s: &str) -> Self {
+        OpCode::Todo(String::from(s))
+    }
+
+    pub fn to_rs(&self) -> String {
+        format!("OpCode::{:?}", self)
+    }
+
+    /// Name of the variant, without operands.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            OpCode::Noop => "Noop",
+            OpCode::Panic => "Panic",
+            OpCode::Pop => "Pop",
+            OpCode::Load(_) => "Load",
+            OpCode::Store(_) => "Store",
+            OpCode::Use => "Use",
+            OpCode::StoreStatic(_) => "StoreStatic",
+            OpCode::Static(_) => "Static",
+            OpCode::RunTrace(_) => "RunTrace",
+            OpCode::Call => "Call",
+            OpCode::CallVirtual(_) => "CallVirtual",
+            OpCode::FlatCall(..) => "FlatCall",
+            OpCode::Return => "Return",
+            OpCode::Resume => "Resume",
+            OpCode::ConstValue(_) => "ConstValue",
+            OpCode::ConstRef(_) => "ConstRef",
+            OpCode::Ref => "Ref",
+            OpCode::Deref => "Deref",
+            OpCode::DerefStore => "DerefStore",
+            OpCode::Unsize => "Unsize",
+            OpCode::CheckedBinOp(_) => "CheckedBinOp",
+            OpCode::BinOp(_) => "BinOp",
+            OpCode::TypedBinOp(..) => "TypedBinOp",
+            OpCode::WrappingBinOp(_) => "WrappingBinOp",
+            OpCode::SaturatingBinOp(_) => "SaturatingBinOp",
+            OpCode::Not => "Not",
+            OpCode::Neg => "Neg",
+            OpCode::Abs => "Abs",
+            OpCode::CountOnes => "CountOnes",
+            OpCode::LeadingZeros => "LeadingZeros",
+            OpCode::TrailingZeros => "TrailingZeros",
+            OpCode::RotateLeft => "RotateLeft",
+            OpCode::RotateRight => "RotateRight",
+            OpCode::CheckedCast(_) => "CheckedCast",
+            OpCode::Cast(_) => "Cast",
+            OpCode::Array(_) => "Array",
+            OpCode::Repeat(_) => "Repeat",
+            OpCode::Len => "Len",
+            OpCode::AssignIndex => "AssignIndex",
+            OpCode::GetIndex => "GetIndex",
+            OpCode::CopyRange => "CopyRange",
+            OpCode::Fill => "Fill",
+            OpCode::ElemPtr => "ElemPtr",
+            OpCode::MovePtr(_) => "MovePtr",
+            OpCode::Tuple(_) => "Tuple",
+            OpCode::TupleGet(_) => "TupleGet",
+            OpCode::TupleSet(_) => "TupleSet",
+            OpCode::TupleFromStack(_) => "TupleFromStack",
+            OpCode::Skip(_) => "Skip",
+            OpCode::JumpBack(_) => "JumpBack",
+            OpCode::SkipIf(_) => "SkipIf",
+            OpCode::JumpBackIf(_) => "JumpBackIf",
+            OpCode::InternalFunc(_) => "InternalFunc",
+            OpCode::ProfileEnter(_) => "ProfileEnter",
+            OpCode::ProfileExit(_) => "ProfileExit",
+            OpCode::Breakpoint(_) => "Breakpoint",
+            OpCode::Guard(_) => "Guard",
+            OpCode::GuardOperands(..) => "GuardOperands",
+            OpCode::GuardFunc(..) => "GuardFunc",
+            OpCode::GuardVTable(..) => "GuardVTable",
+            OpCode::GuardCast(..) => "GuardCast",
+            OpCode::LoopBack(_) => "LoopBack",
+            OpCode::Todo(_) => "Todo",
+        }
+    }
+
+    /// Number of values the opcode pops from and pushes onto the operand
+    /// stack, as `(pops, pushes)`.
+    ///
+    /// Returns `None` if the effect isn't known statically, e.g. because it
+    /// depends on the called function. Calls pop the function and its
+    /// arguments and always push one result, `Unit` for functions without a
+    /// return value.
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        let effect = match *self {
+            OpCode::Noop | OpCode::Panic | OpCode::Resume => (0, 0),
+            OpCode::Skip(_) | OpCode::JumpBack(_) => (0, 0),
+            OpCode::ProfileEnter(_) | OpCode::ProfileExit(_) => (0, 0),
+            OpCode::Breakpoint(_) => (0, 0),
+
+            OpCode::Load(_) | OpCode::ConstValue(_) | OpCode::Tuple(_) => (0, 1),
+            OpCode::ConstRef(_) | OpCode::Static(_) => (0, 1),
+
+            OpCode::Pop | OpCode::Store(_) | OpCode::StoreStatic(_) => (1, 0),
+            OpCode::SkipIf(_) | OpCode::JumpBackIf(_) => (1, 0),
+            // only looks at the condition, traces pop it after the guard
+            OpCode::Guard(_) => (0, 0),
+            // only looks at the operands of the following opcode
+            OpCode::GuardOperands(..) => (0, 0),
+            // only looks at the callee of the following `Call`
+            OpCode::GuardFunc(..) => (0, 0),
+            // only looks at the vtable of the following `CallVirtual`
+            OpCode::GuardVTable(..) => (0, 0),
+            OpCode::GuardCast(..) => (0, 0),
+            OpCode::LoopBack(_) => (0, 0),
+
+            OpCode::Use | OpCode::Unsize | OpCode::Ref | OpCode::Deref => (1, 1),
+            OpCode::Not | OpCode::Neg | OpCode::Abs | OpCode::Len => (1, 1),
+            OpCode::Repeat(_) | OpCode::TupleGet(_) | OpCode::MovePtr(_) => (1, 1),
+
+            OpCode::BinOp(_) | OpCode::CheckedBinOp(_) | OpCode::GetIndex => (2, 1),
+            OpCode::ElemPtr => (2, 1),
+            OpCode::TypedBinOp(..) => (2, 1),
+            OpCode::WrappingBinOp(_) | OpCode::SaturatingBinOp(_) => (2, 1),
+            OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros => (1, 1),
+            OpCode::RotateLeft | OpCode::RotateRight => (2, 1),
+            OpCode::CheckedCast(_) | OpCode::Cast(_) => (1, 1),
+            OpCode::DerefStore | OpCode::TupleSet(_) => (2, 0),
+            OpCode::AssignIndex => (3, 0),
+            OpCode::Fill => (4, 0),
+            OpCode::CopyRange => (5, 0),
+
+            OpCode::Array(size) | OpCode::TupleFromStack(size) => (size, 1),
+
+            OpCode::InternalFunc(ref func) => return func.stack_effect(),
+
+            // the callee is part of the opcode, only its arguments are popped
+            OpCode::FlatCall(_, _, ref func) => (func.args_cnt, 1),
+
+            OpCode::Call | OpCode::CallVirtual(_) |
+            OpCode::Return | OpCode::RunTrace(_) | OpCode::Todo(_) => return None,
+        };
+        Some(effect)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InternalFunc {
+    MergePoint,
+    /// read a byte of guest input, `Null` at the end of the input
+    In,
+    /// write the value on top of the stack as a byte
+    Out,
+    /// write the value on top of the stack followed by a newline
+    Print,
+    /// Monotonic time in nanoseconds as `U64`. The result differs on every
+    /// call, traces keep the call instead of the value seen while recording.
+    Clock,
+    /// Pseudo random `U64` from the seedable generator of the interpreter.
+    /// Like `Clock` it is kept as a call in traces.
+    Rand,
+    Assert,
+}
+
+impl InternalFunc {
+    pub fn stack_effect(&self) -> Option<(usize, usize)> {
+        match *self {
+            InternalFunc::In | InternalFunc::Clock | InternalFunc::Rand => Some((0, 1)),
+            InternalFunc::Out | InternalFunc::Print => Some((1, 0)),
+            InternalFunc::MergePoint | InternalFunc::Assert => None,
+        }
+    }
+}
+
+/// What the driver knows about the loop a `LoopBack` closes. How often it
+/// went around is counted with the stats of the trace, which are shared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoopInfo {
+    /// Id of the trace the loop continues in, none for the start of its own
+    /// trace. Only the pieces of a split trace link to another one.
+    pub link: Option<usize>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Guard {
+    pub expected: bool,
+    // pub recovery: Rc<Function>,
+    // pub pc: usize,
+    pub recovery: InstructionPointer,
+}
+
+impl fmt::Debug for Guard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Guard({})", self.expected)
+    }
+}
+
+/// The numbers `BinOp` works on. Both operands always have the same type.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum NumType {
+    Usize,
+    U64,
+    I64,
+}
+
+impl NumType {
+    pub fn of(val: &R_BoxedValue) -> Option<NumType> {
+        match *val {
+            R_BoxedValue::Usize(_) => Some(NumType::Usize),
+            R_BoxedValue::U64(_) => Some(NumType::U64),
+            R_BoxedValue::I64(_) => Some(NumType::I64),
+            _ => None,
+        }
+    }
+
+    /// The integer `val` as one of this type, `None` if it doesn't fit or
+    /// isn't an integer.
+    pub fn cast(self, val: &R_BoxedValue) -> Option<R_BoxedValue> {
+        // every integer fits into an i64 or a u64
+        let (negative, magnitude) = match *val {
+            R_BoxedValue::Usize(v) => (false, v as u64),
+            R_BoxedValue::U64(v) => (false, v),
+            R_BoxedValue::I64(v) if v < 0 => (true, v.wrapping_neg() as u64),
+            R_BoxedValue::I64(v) => (false, v as u64),
+            _ => return None,
+        };
+        match self {
+            NumType::Usize if !negative && magnitude <= usize::max_value() as u64 => {
+                Some(R_BoxedValue::Usize(magnitude as usize))
+            }
+            NumType::U64 if !negative => Some(R_BoxedValue::U64(magnitude)),
+            NumType::I64 if negative && magnitude <= i64::min_value() as u64 => {
+                Some(R_BoxedValue::I64((magnitude as i64).wrapping_neg()))
+            }
+            NumType::I64 if !negative && magnitude <= i64::max_value() as u64 => {
+                Some(R_BoxedValue::I64(magnitude as i64))
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BinOp {
+    /// The `+` operator (addition)
+    Add,
+    /// The `-` operator (subtraction)
+    Sub,
+    /// The `*` operator (multiplication)
+    Mul,
+    /// The `/` operator (division)
+    Div,
+    /// The `%` operator (modulus)
+    Rem,
+    /// The `^` operator (bitwise xor)
+    BitXor,
+    /// The `&` operator (bitwise and)
+    BitAnd,
+    /// The `|` operator (bitwise or)
+    BitOr,
+    /// The `<<` operator (shift left)
+    Shl,
+    /// The `>>` operator (shift right)
+    Shr,
+    /// The `==` operator (equality)
+    Eq,
+    /// The `<` operator (less than)
+    Lt,
+    /// The `<=` operator (less than or equal to)
+    Le,
+    /// The `!=` operator (not equal to)
+    Ne,
+    /// The `>=` operator (greater than or equal to)
+    Ge,
+    /// The `>` operator (greater than)
+    Gt,
+    /// The smaller operand, without a branch
+    Min,
+    /// The larger operand, without a branch
+    Max,
+}
+
+impl BinOp {
+    /// Whether the result is a `Bool` rather than a number.
+    pub fn is_comparison(&self) -> bool {
+        match *self {
+            BinOp::Eq | BinOp::Lt | BinOp::Le | BinOp::Ne | BinOp::Ge | BinOp::Gt => true,
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! The shared cells guest values live in: locals, fields of structs and the
+//! targets of pointers.
+//!
+//! They are `Rc<RefCell<_>>` by default. With the `sync-cells` feature they
+//! become `Arc<RwLock<_>>`, so that objects can be shared between threads.
+//! Code outside this module only uses `Shared`, `shared` and `SharedCell`.
+
+#[cfg(not(feature = "sync-cells"))]
+use std::cell::RefCell;
+#[cfg(not(feature = "sync-cells"))]
+use std::rc::Rc;
+#[cfg(feature = "sync-cells")]
+use std::fmt;
+#[cfg(feature = "sync-cells")]
+use std::sync::{Arc, RwLock};
+
+/// A cell which can be referenced from several places.
+#[cfg(not(feature = "sync-cells"))]
+pub type Shared<T> = Rc<Lock<T>>;
+#[cfg(feature = "sync-cells")]
+pub type Shared<T> = Arc<Lock<T>>;
+
+/// What a `Shared` points to, e.g. to tell its size.
+#[cfg(not(feature = "sync-cells"))]
+pub type Lock<T> = RefCell<T>;
+#[cfg(feature = "sync-cells")]
+pub type Lock<T> = SyncLock<T>;
+
+pub fn shared<T>(value: T) -> Shared<T> {
+    Shared::new(Lock::new(value))
+}
+
+/// Access to the value in a `Shared`. Borrows only last for the call, so
+/// that both kinds of cells work the same.
+pub trait SharedCell<T> {
+    fn get(&self) -> T where T: Clone;
+
+    fn set(&self, value: T);
+
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R;
+
+    fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R;
+
+    /// Whether both are the same cell, rather than equal values.
+    fn same_cell(&self, other: &Self) -> bool;
+}
+
+#[cfg(not(feature = "sync-cells"))]
+impl<T> SharedCell<T> for Shared<T> {
+    fn get(&self) -> T where T: Clone {
+        self.borrow().clone()
+    }
+
+    fn set(&self, value: T) {
+        *self.borrow_mut() = value;
+    }
+
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&*self.borrow())
+    }
+
+    fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut *self.borrow_mut())
+    }
+
+    fn same_cell(&self, other: &Self) -> bool {
+        let this: *const Lock<T> = &**self;
+        let other: *const Lock<T> = &**other;
+        this == other
+    }
+}
+
+/// A `RwLock` which compares by its value, like a `RefCell`.
+#[cfg(feature = "sync-cells")]
+pub struct SyncLock<T> {
+    lock: RwLock<T>,
+}
+
+#[cfg(feature = "sync-cells")]
+impl<T> SyncLock<T> {
+    pub fn new(value: T) -> Self {
+        SyncLock { lock: RwLock::new(value) }
+    }
+}
+
+#[cfg(feature = "sync-cells")]
+impl<T: PartialEq> PartialEq for SyncLock<T> {
+    fn eq(&self, other: &Self) -> bool {
+        *self.lock.read().unwrap() == *other.lock.read().unwrap()
+    }
+}
+
+#[cfg(feature = "sync-cells")]
+impl<T: fmt::Debug> fmt::Debug for SyncLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.lock.fmt(f)
+    }
+}
+
+#[cfg(feature = "sync-cells")]
+impl<T> SharedCell<T> for Shared<T> {
+    fn get(&self) -> T where T: Clone {
+        self.lock.read().unwrap().clone()
+    }
+
+    fn set(&self, value: T) {
+        *self.lock.write().unwrap() = value;
+    }
+
+    fn with<R, F: FnOnce(&T) -> R>(&self, f: F) -> R {
+        f(&*self.lock.read().unwrap())
+    }
+
+    fn with_mut<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        f(&mut *self.lock.write().unwrap())
+    }
+
+    fn same_cell(&self, other: &Self) -> bool {
+        let this: *const Lock<T> = &**self;
+        let other: *const Lock<T> = &**other;
+        this == other
+    }
+}
@@ -0,0 +1,71 @@
+//! Functions decoded once when a program is loaded, so that executing the
+//! common opcodes neither clones them nor computes jump targets.
+//!
+//! Instruction `i` of a decoded function stands for opcode `i` of the
+//! original, positions stay the same. Everything else is left to the
+//! original opcode.
+
+use std::vec::Vec;
+
+use bytecode::OpCode;
+use objects::R_BoxedValue;
+use program::Program;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Insn {
+    Load(usize),
+    Store(usize),
+    /// index into the constants of the function
+    Const(usize),
+    /// absolute position in the function
+    Jump(usize),
+    JumpIf(usize),
+    /// execute the original opcode
+    Slow,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodedFunction {
+    pub insns: Vec<Insn>,
+    pub consts: Vec<R_BoxedValue>,
+}
+
+/// The decoded functions of a `Program`, by the same index.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Code {
+    pub functions: Vec<DecodedFunction>,
+}
+
+impl Code {
+    pub fn decode(program: &Program) -> Self {
+        Code { functions: program.functions.iter().map(|func| decode(&func.opcodes)).collect() }
+    }
+}
+
+fn decode(opcodes: &[OpCode]) -> DecodedFunction {
+    let mut consts = Vec::new();
+    let insns = opcodes.iter()
+        .enumerate()
+        .map(|(pc, opcode)| {
+            match *opcode {
+                OpCode::Load(idx) => Insn::Load(idx),
+                OpCode::Store(idx) => Insn::Store(idx),
+                OpCode::ConstValue(ref val) => {
+                    consts.push(val.clone());
+                    Insn::Const(consts.len() - 1)
+                }
+                OpCode::Skip(n) => Insn::Jump(pc + n),
+                OpCode::SkipIf(n) => Insn::JumpIf(pc + n),
+                // jumps before the start fail as before
+                OpCode::JumpBack(n) if n <= pc => Insn::Jump(pc - n),
+                OpCode::JumpBackIf(n) if n <= pc => Insn::JumpIf(pc - n),
+                _ => Insn::Slow,
+            }
+        })
+        .collect();
+
+    DecodedFunction {
+        insns: insns,
+        consts: consts,
+    }
+}
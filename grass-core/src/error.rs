@@ -0,0 +1,183 @@
+#[cfg(feature = "std")]
+use std::error::Error;
+use std::fmt;
+use std::vec::Vec;
+
+use bytecode::{InternalFunc, NumType, OpCode};
+use objects::R_BoxedValue;
+
+/// Error of the guest program, reported to the host instead of aborting it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InterpError {
+    pub kind: ErrorKind,
+    /// guest frames at the time of the error, innermost first
+    pub backtrace: Vec<BacktraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// allocating `requested` bytes would exceed the heap limit
+    OutOfMemory { requested: usize, limit: usize },
+    /// the guest attempted an effect which isn't allowed in sandbox mode
+    SandboxViolation(Effect),
+    /// a pointer to a local variable was used after its frame returned
+    DanglingPointer { frame: usize },
+    /// the interpreter can't execute the opcode
+    UnsupportedOpcode(OpCode),
+    /// call of a function which is implemented by the host
+    NoBytecode(usize),
+    /// `InternalFunc::Out` of a value which isn't an integer
+    InvalidOutput(R_BoxedValue),
+    /// the input or output of the host failed the internal function
+    IoFailed(InternalFunc),
+    /// call of a function with fewer values on the stack than it takes, or
+    /// a run of it with another number of arguments than it takes
+    ArityMismatch { func: usize, expected: usize, found: usize },
+    /// call of a method which the value on top of the stack doesn't have,
+    /// `vtable` is `None` if that isn't a vtable
+    UnknownMethod { vtable: Option<usize>, method: usize },
+    /// a static whose value the program doesn't have, e.g. one of the host
+    UnknownStatic(usize),
+    /// a function the program doesn't have, e.g. the entry of a run
+    UnknownFunction(usize),
+    /// the merge point was reached in function `func` without a guest pc,
+    /// a `Usize` in the local `PC_LOCAL`
+    MissingGuestPc { func: usize },
+    /// call of the merge point from the guest bytecode at `func` and `pc`,
+    /// only the host may call it
+    MergePointReentered { func: usize, pc: usize },
+    /// `CheckedCast` of a value which doesn't fit into the type `to`
+    InvalidCast { value: R_BoxedValue, to: NumType },
+    /// the result of the opcode doesn't fit into the type of its operand,
+    /// e.g. `Abs` of the smallest `i64`
+    Overflow(OpCode),
+    /// `Div` or `Rem` by zero of the opcode
+    DivisionByZero(OpCode),
+    /// Not a failure of the guest: a trace was left while the driver uses
+    /// `GuardFailurePolicy::ReturnToHost`. The host has to execute the guest
+    /// instruction at `resume` itself before it calls `merge_point` again.
+    GuardFailed { resume: usize },
+    /// Not a failure of the guest either: the host asked to stop it, see
+    /// `InterruptHandle` of the driver.
+    Interrupted,
+    /// the run took longer than the timeout of the driver
+    Timeout,
+    /// the run executed more opcodes than the fuel of the driver allows
+    OutOfFuel,
+    /// the driver is strict and the verifier rejected the program at `func`
+    /// and `pc`
+    Unverified { func: usize, pc: usize },
+}
+
+/// Something a guest program does which is visible outside of it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    Io(InternalFunc),
+    /// call of a function without bytecode, i.e. implemented by the host
+    ExternalCall(usize),
+}
+
+/// Position within a guest function on the call stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BacktraceFrame {
+    pub func: usize,
+    pub pc: usize,
+}
+
+pub type InterpResult<T> = Result<T, InterpError>;
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ErrorKind::OutOfMemory { requested, limit } => {
+                write!(f, "out of memory: allocating {} bytes exceeds the limit of {} bytes",
+                       requested, limit)
+            }
+            ErrorKind::SandboxViolation(ref effect) => {
+                write!(f, "{:?} denied in sandbox mode", effect)
+            }
+            ErrorKind::DanglingPointer { frame } => {
+                write!(f, "pointer into frame {} used after the frame returned", frame)
+            }
+            ErrorKind::UnsupportedOpcode(ref opcode) => {
+                write!(f, "unsupported opcode {:?}", opcode)
+            }
+            ErrorKind::NoBytecode(func) => {
+                write!(f, "function {} has no bytecode, it can't be called by the guest", func)
+            }
+            ErrorKind::InvalidOutput(ref value) => write!(f, "can't output {} as a byte", value),
+            ErrorKind::IoFailed(ref func) => write!(f, "guest I/O of {:?} failed", func),
+            ErrorKind::ArityMismatch { func, expected, found } => {
+                write!(f, "function {} takes {} arguments, called with {}", func, expected, found)
+            }
+            ErrorKind::UnknownMethod { vtable: Some(vtable), method } => {
+                write!(f, "vtable {} has no method {}", vtable, method)
+            }
+            ErrorKind::UnknownMethod { vtable: None, method } => {
+                write!(f, "call of method {} without a vtable", method)
+            }
+            ErrorKind::UnknownStatic(idx) => write!(f, "static {} has no value in the program", idx),
+            ErrorKind::UnknownFunction(func) => write!(f, "the program has no function {}", func),
+            ErrorKind::MissingGuestPc { func } => {
+                write!(f, "merge point reached in function {} without a guest pc", func)
+            }
+            ErrorKind::MergePointReentered { func, pc } => {
+                write!(f, "merge point re-entered from interpreted code at function {}, pc {}",
+                       func, pc)
+            }
+            ErrorKind::InvalidCast { ref value, to } => {
+                write!(f, "{} doesn't fit into {:?}", value, to)
+            }
+            ErrorKind::Overflow(ref opcode) => write!(f, "{:?} overflowed", opcode),
+            ErrorKind::DivisionByZero(ref opcode) => write!(f, "{:?} divided by zero", opcode),
+            ErrorKind::GuardFailed { resume } => {
+                write!(f, "trace left, resume at guest pc {}", resume)
+            }
+            ErrorKind::Interrupted => write!(f, "interrupted by the host"),
+            ErrorKind::Timeout => write!(f, "timed out"),
+            ErrorKind::OutOfFuel => write!(f, "out of fuel"),
+            ErrorKind::Unverified { func, pc } => {
+                write!(f, "program rejected by the verifier at function {}, pc {}", func, pc)
+            }
+        }
+    }
+}
+
+impl fmt::Display for InterpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}", self.kind));
+        for frame in &self.backtrace {
+            try!(write!(f, "\n    at function {}, pc {}", frame.func, frame.pc));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for InterpError {
+    fn description(&self) -> &str {
+        match self.kind {
+            ErrorKind::OutOfMemory { .. } => "guest heap limit exceeded",
+            ErrorKind::SandboxViolation(..) => "effect denied in sandbox mode",
+            ErrorKind::DanglingPointer { .. } => "dangling pointer",
+            ErrorKind::UnsupportedOpcode(..) => "unsupported opcode",
+            ErrorKind::NoBytecode(..) => "call of a function without bytecode",
+            ErrorKind::InvalidOutput(..) => "output of a value which isn't a byte",
+            ErrorKind::IoFailed(..) => "guest I/O failed",
+            ErrorKind::ArityMismatch { .. } => "call with the wrong number of arguments",
+            ErrorKind::UnknownMethod { .. } => "call of an unknown method",
+            ErrorKind::UnknownStatic(..) => "static without a value",
+            ErrorKind::UnknownFunction(..) => "unknown function",
+            ErrorKind::MissingGuestPc { .. } => "merge point without a guest pc",
+            ErrorKind::MergePointReentered { .. } => "merge point re-entered by the guest",
+            ErrorKind::InvalidCast { .. } => "integer out of range of the cast",
+            ErrorKind::Overflow(..) => "arithmetic overflow",
+            ErrorKind::DivisionByZero(..) => "division by zero",
+            ErrorKind::GuardFailed { .. } => "trace left through a guard",
+            ErrorKind::Interrupted => "interrupted by the host",
+            ErrorKind::Timeout => "timed out",
+            ErrorKind::OutOfFuel => "out of fuel",
+            ErrorKind::Unverified { .. } => "program rejected by the verifier",
+        }
+    }
+}
@@ -0,0 +1,1142 @@
+use std::cmp;
+use std::mem;
+use std::vec::Vec;
+
+use cell::{shared, Shared, SharedCell};
+use error::{InterpError, InterpResult, ErrorKind, Effect, BacktraceFrame};
+use program::Program;
+use decode::{Code, Insn};
+use bytecode::{OpCode, BinOp, NumType, MERGE_POINT_FUNC};
+use objects::{R_BoxedValue, CallFrame, R_Pointer, R_Struct, InstructionPointer, Heap,
+              AllocationRef};
+#[cfg(feature = "time-travel")]
+use travel::{Step, TimeTravel};
+
+/// Stands in for the history of the executed opcodes, which is only kept
+/// with the `time-travel` feature.
+#[cfg(not(feature = "time-travel"))]
+#[derive(Debug, Clone, Default)]
+pub struct TimeTravel;
+
+
+// copied from miri
+macro_rules! int_binops {
+    ($kind:expr, $v:ident, $l:ident, $r:ident) => ({
+        use bytecode::BinOp::*;
+        match $kind {
+            Add    => $v($l + $r),
+            Sub    => $v($l - $r),
+            Mul    => $v($l * $r),
+            Div    => $v($l / $r),
+            Rem    => $v($l % $r),
+            BitXor => $v($l ^ $r),
+            BitAnd => $v($l & $r),
+            BitOr  => $v($l | $r),
+
+            // TODO(solson): Can have differently-typed RHS.
+            Shl => $v($l << $r),
+            Shr => $v($l >> $r),
+
+            Eq => Bool($l == $r),
+            Ne => Bool($l != $r),
+            Lt => Bool($l < $r),
+            Le => Bool($l <= $r),
+            Gt => Bool($l > $r),
+            Ge => Bool($l >= $r),
+
+            Min => $v(::std::cmp::min($l, $r)),
+            Max => $v(::std::cmp::max($l, $r)),
+        }
+    })
+}
+
+macro_rules! wrapping_binops {
+    ($kind:expr, $v:ident, $l:ident, $r:ident) => ({
+        use bytecode::BinOp::*;
+        match $kind {
+            Add => $v($l.wrapping_add($r)),
+            Sub => $v($l.wrapping_sub($r)),
+            Mul => $v($l.wrapping_mul($r)),
+            Div => $v($l.wrapping_div($r)),
+            Rem => $v($l.wrapping_rem($r)),
+            Shl => $v($l.wrapping_shl($r as u32)),
+            Shr => $v($l.wrapping_shr($r as u32)),
+            _ => int_binops!($kind, $v, $l, $r),
+        }
+    })
+}
+
+macro_rules! saturating_binops {
+    ($kind:expr, $v:ident, $l:ident, $r:ident) => ({
+        use bytecode::BinOp::*;
+        match $kind {
+            Add => $v($l.saturating_add($r)),
+            Sub => $v($l.saturating_sub($r)),
+            Mul => $v($l.saturating_mul($r)),
+            // only `MIN / -1` overflows, into `MAX + 1`, and `!MIN` is `MAX`
+            Div => $v($l.checked_div($r).unwrap_or(!$l)),
+            Rem => $v($l.checked_rem($r).unwrap_or(0)),
+            // a shift by `r` doubles `r` times, once every bit is shifted out
+            // only the sign is left
+            Shl => {
+                let bits = 8 * mem::size_of_val(&$l) as u64;
+                let mut val = $l;
+                for _ in 0..cmp::min($r as u64, bits) {
+                    val = val.saturating_mul(2);
+                }
+                $v(val)
+            }
+            Shr => {
+                let bits = 8 * mem::size_of_val(&$l) as u64;
+                if ($r as u64) < bits { $v($l >> $r) } else { $v($l >> (bits - 1) >> 1) }
+            }
+            _ => int_binops!($kind, $v, $l, $r),
+        }
+    })
+}
+
+// Locals of the function containing the merge point which hold the state of
+// the host. XXX: they depend on how the host is written.
+pub const USER_PROGRAM_LOCAL: usize = 1;
+pub const CELL_LOCAL: usize = 2;
+pub const PC_LOCAL: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackVal {
+    Owned(R_BoxedValue),
+    Ref(Shared<R_BoxedValue>),
+}
+
+impl StackVal {
+    // to_value should be clone
+    pub fn into_owned(self) -> Self {
+        match self {
+            StackVal::Owned(..) => self,
+            StackVal::Ref(cell) => StackVal::Owned(cell.get()),
+        }
+    }
+
+    pub fn into_cell(self) -> Self {
+        match self {
+            StackVal::Owned(boxed) => StackVal::Ref(shared(boxed)),
+            StackVal::Ref(..) => self,
+        }
+    }
+
+    // self has to be owned
+    pub fn unwrap_value(self) -> R_BoxedValue {
+        if let StackVal::Owned(val) = self {
+            val
+        } else {
+            panic!("expected owned val");
+        }
+    }
+
+    pub fn unwrap_cell(self) -> Shared<R_BoxedValue> {
+        if let StackVal::Ref(boxed) = self {
+            boxed
+        } else {
+            panic!("expected ref val");
+        }
+    }
+
+    /// Address as Value
+    pub fn into_pointer(self) -> Self {
+        let cell = self.unwrap_cell();
+        StackVal::Owned(R_BoxedValue::Ptr(R_Pointer::new(cell)))
+    }
+
+    /// Deref pointer
+    pub fn deref(self) -> Self {
+        // self contains an owned R_Pointer
+        let val = self.into_owned().unwrap_value();
+        if let R_BoxedValue::Ptr(ptr) = val {
+            StackVal::Ref(ptr.cell)
+        } else {
+            panic!("expected val to be pointer, got {}", val);
+        }
+    }
+}
+
+
+/// How the interpreter continues after an opcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DispatchResult {
+    /// with the following opcode
+    Next,
+    Jump(InstructionPointer),
+    /// the outermost frame returned
+    Stop,
+}
+
+/// Sizes the buffers of an `Interpreter` start with, so that they don't grow
+/// while the first opcodes run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InterpreterConfig {
+    /// values on the operand stack
+    pub stack_capacity: usize,
+    /// nested calls
+    pub frames_capacity: usize,
+}
+
+impl Default for InterpreterConfig {
+    fn default() -> Self {
+        InterpreterConfig {
+            stack_capacity: 32,
+            frames_capacity: 8,
+        }
+    }
+}
+
+/// Interpreter of the bytecode, without tracing.
+///
+/// It executes every opcode except `InternalFunc`, whose I/O, clock and
+/// random numbers are provided by the interpreter of the `grass` driver.
+pub struct Interpreter<'a> {
+    pub program: &'a Program,
+    /// `program` decoded, see `Code::decode`
+    pub code: &'a Code,
+    /// memory of guest allocated structs, a clone shares the used memory
+    pub heap: Heap,
+    /// calls of host functions fail with `SandboxViolation`
+    pub sandbox: bool,
+
+    // working stack of the interpreter
+    pub stack: Vec<StackVal>,
+
+    // the stack of the interpreted program, consisting of frames
+    pub stack_frames: Vec<CallFrame>,
+
+    // id of the last pushed frame
+    last_frame_id: usize,
+
+    /// position of the interpreted opcode
+    pub ip: InstructionPointer,
+
+    /// the last executed opcodes, see `step_back`
+    pub travel: TimeTravel,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program, code: &'a Code, heap: Heap) -> Self {
+        Interpreter {
+            program: program,
+            code: code,
+            heap: heap,
+            sandbox: false,
+            stack: Vec::new(),
+            stack_frames: Vec::new(),
+            last_frame_id: 0,
+            ip: InstructionPointer { func: 0, pc: 0 },
+            travel: TimeTravel::default(),
+        }
+    }
+
+    pub fn with_config(program: &'a Program, code: &'a Code, heap: Heap,
+                       config: &InterpreterConfig)
+                       -> Self {
+        let mut interp = Interpreter::new(program, code, heap);
+        interp.stack.reserve(config.stack_capacity);
+        interp.stack_frames.reserve(config.frames_capacity);
+        interp
+    }
+
+    /// Continue with the buffers `take_buffers` returned, for another
+    /// interpreter, which keep their capacity.
+    pub fn reuse_buffers(&mut self, buffers: (Vec<StackVal>, Vec<CallFrame>)) {
+        let (mut stack, mut frames) = buffers;
+        stack.clear();
+        frames.clear();
+        self.stack = stack;
+        self.stack_frames = frames;
+    }
+
+    /// Empty the operand stack and the frames and take their buffers.
+    pub fn take_buffers(&mut self) -> (Vec<StackVal>, Vec<CallFrame>) {
+        self.stack.clear();
+        self.stack_frames.clear();
+        let stack = mem::replace(&mut self.stack, Vec::new());
+        let frames = mem::replace(&mut self.stack_frames, Vec::new());
+        (stack, frames)
+    }
+
+    pub fn push_frame(&mut self, mut frame: CallFrame) {
+        self.last_frame_id += 1;
+        frame.id = self.last_frame_id;
+        self.stack_frames.push(frame);
+    }
+
+    /// Execute function `func_idx` of the program until it returns, and
+    /// return its result. The call of the merge point does nothing.
+    pub fn run(&mut self, func_idx: usize) -> InterpResult<R_BoxedValue> {
+        self.ip = InstructionPointer { func: func_idx, pc: 0 };
+
+        loop {
+            let program = self.program;
+            let opcode = &program.functions[self.ip.func].opcodes[self.ip.pc];
+
+            if *opcode == OpCode::Call && self.calls_merge_point() {
+                self.skip_merge_point();
+                self.ip.pc += 1;
+                continue;
+            }
+
+            match try!(self.execute()) {
+                DispatchResult::Next => self.ip.pc += 1,
+                DispatchResult::Jump(target) => self.ip = target,
+                DispatchResult::Stop => break,
+            }
+        }
+
+        Ok(self.result())
+    }
+
+    /// The value left on the stack by the outermost frame, `Null` if it
+    /// didn't return.
+    pub fn result(&mut self) -> R_BoxedValue {
+        if self.stack.is_empty() {
+            R_BoxedValue::Null
+        } else {
+            self.pop_value()
+        }
+    }
+
+    /// Whether the opcode about to be executed is a call of the merge point.
+    pub fn calls_merge_point(&self) -> bool {
+        match self.stack.last() {
+            Some(&StackVal::Owned(R_BoxedValue::Func(idx))) => idx == MERGE_POINT_FUNC,
+            _ => false,
+        }
+    }
+
+    /// Execute a call of the merge point which does nothing.
+    pub fn skip_merge_point(&mut self) {
+        // The arguments are the only values on the stack, the result is the
+        // unchanged guest pc.
+        self.stack.clear();
+        let pc = self.active_frame().locals[PC_LOCAL].get();
+        self.stack.push(StackVal::Owned(pc));
+    }
+
+    /// Execute the instruction at `self.ip`, in its decoded form if it has
+    /// one.
+    #[cfg(not(feature = "time-travel"))]
+    #[inline(always)]
+    pub fn execute(&mut self) -> InterpResult<DispatchResult> {
+        self.execute_opcode()
+    }
+
+    /// Execute the instruction at `self.ip`, in its decoded form if it has
+    /// one, and record what it changed.
+    #[cfg(feature = "time-travel")]
+    pub fn execute(&mut self) -> InterpResult<DispatchResult> {
+        let ip = self.ip;
+        let program = self.program;
+        let opcode = &program.functions[ip.func].opcodes[ip.pc];
+        let effect = opcode.stack_effect();
+        let depth = self.stack_frames.len();
+
+        // calls and returns don't say how much they pop, but stay within the
+        // operands of the active frame
+        let stack_base = match effect {
+            Some((pops, _)) => self.stack.len().saturating_sub(pops),
+            None => {
+                let base = self.stack_frames.last().map_or(0, |frame| frame.stack_base);
+                if base < self.stack.len() { base } else { self.stack.len() }
+            }
+        };
+        let popped = self.stack[stack_base..].to_vec();
+        let overwritten = self.overwritten_cell(opcode).map(|cell| {
+            let old = cell.get();
+            (cell, old)
+        });
+        let top = match effect {
+            Some(..) => None,
+            None => self.stack_frames.last().cloned(),
+        };
+
+        let res = try!(self.execute_opcode());
+
+        let returned = if self.stack_frames.len() < depth { top } else { None };
+        self.travel.record(Step {
+            ip: ip,
+            stack_base: stack_base,
+            popped: popped,
+            depth: depth,
+            overwritten: overwritten,
+            returned: returned,
+        });
+        Ok(res)
+    }
+
+    /// The cell `opcode` is about to store into, if it stores into one.
+    #[cfg(feature = "time-travel")]
+    fn overwritten_cell(&self, opcode: &OpCode) -> Option<Shared<R_BoxedValue>> {
+        match *opcode {
+            OpCode::Store(idx) => self.stack_frames.last().map(|frame| frame.locals[idx].clone()),
+            OpCode::DerefStore => {
+                match self.stack.last().cloned().map(StackVal::into_owned) {
+                    Some(StackVal::Owned(R_BoxedValue::Ptr(ptr))) => Some(ptr.cell),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Undo the last `n` executed opcodes, as far as they were recorded.
+    /// Returns the number of undone opcodes, `ip` is the one to execute next.
+    #[cfg(feature = "time-travel")]
+    pub fn step_back(&mut self, n: usize) -> usize {
+        for undone in 0..n {
+            let step = match self.travel.pop() {
+                Some(step) => step,
+                None => return undone,
+            };
+            if let Some(frame) = step.returned {
+                self.stack_frames.push(frame);
+            }
+            self.stack_frames.truncate(step.depth);
+            if let Some((cell, old)) = step.overwritten {
+                cell.set(old);
+            }
+            self.stack.truncate(step.stack_base);
+            self.stack.extend(step.popped);
+            self.ip = step.ip;
+        }
+        n
+    }
+
+    fn execute_opcode(&mut self) -> InterpResult<DispatchResult> {
+        let InstructionPointer { func, pc } = self.ip;
+        let code = self.code;
+        let decoded = &code.functions[func];
+
+        match decoded.insns[pc] {
+            Insn::Load(idx) => self.o_load(idx),
+            Insn::Store(idx) => self.o_store(idx),
+            Insn::Const(idx) => self.stack.push(StackVal::Owned(decoded.consts[idx].clone())),
+            Insn::Jump(target) => {
+                return Ok(DispatchResult::Jump(InstructionPointer { func: func, pc: target }));
+            }
+            Insn::JumpIf(target) => {
+                match self.pop_value() {
+                    R_BoxedValue::Bool(true) => {
+                        return Ok(DispatchResult::Jump(InstructionPointer { func: func, pc: target }));
+                    }
+                    R_BoxedValue::Bool(false) => (),
+                    val => panic!("expected bool, got {}", val),
+                }
+            }
+            Insn::Slow => {
+                let program = self.program;
+                return self.dispatch(&program.functions[func].opcodes[pc]);
+            }
+        }
+        Ok(DispatchResult::Next)
+    }
+
+    /// Execute a single opcode at `self.ip`.
+    pub fn dispatch(&mut self, opcode: &OpCode) -> InterpResult<DispatchResult> {
+        let InstructionPointer { func: func_pointer, pc } = self.ip;
+
+        match *opcode {
+            OpCode::Panic => panic!("assertion failed"),
+
+            OpCode::ConstValue(ref val) => {
+                self.stack.push(StackVal::Owned(val.clone()));
+            }
+
+            OpCode::Tuple(size) => try!(self.o_tuple(size)),
+            OpCode::TupleFromStack(size) => try!(self.o_tuple_from_stack(size)),
+            OpCode::TupleGet(idx) => self.o_tuple_get(idx),
+            OpCode::TupleSet(idx) => self.o_tuple_set(idx),
+
+            // XXX: proper implementation of unsize
+            OpCode::Unsize | OpCode::Use => {
+                let val = self.stack.pop().unwrap().into_owned();
+                self.stack.push(val);
+            }
+
+            OpCode::Ref => self.o_ref(),
+
+            OpCode::Deref => try!(self.o_deref()),
+
+            OpCode::Load(local_index) => self.o_load(local_index),
+
+            OpCode::Store(local_index) => self.o_store(local_index),
+
+            OpCode::Call => {
+                // load and activate func, and jump to its first instruction
+                let func = try!(self.o_call(func_pointer, pc));
+                return Ok(DispatchResult::Jump(InstructionPointer { func: func, pc: 0 }));
+            }
+
+            OpCode::CallVirtual(method) => {
+                let func = try!(self.o_call_virtual(method, func_pointer, pc));
+                return Ok(DispatchResult::Jump(InstructionPointer { func: func, pc: 0 }));
+            }
+
+            OpCode::Static(static_idx) => try!(self.o_load_static(static_idx)),
+
+            OpCode::Return => {
+                return Ok(match self.o_return() {
+                    // continue after the call
+                    Some(ret) => DispatchResult::Jump(InstructionPointer { func: ret.func, pc: ret.pc + 1 }),
+                    None => DispatchResult::Stop,
+                });
+            }
+
+            OpCode::Skip(n) => {
+                return Ok(DispatchResult::Jump(InstructionPointer { func: func_pointer, pc: pc + n }));
+            }
+            OpCode::JumpBack(n) => {
+                return Ok(DispatchResult::Jump(InstructionPointer { func: func_pointer, pc: pc - n }));
+            }
+
+            OpCode::SkipIf(n) => {
+                let val = self.pop_value();
+                if let R_BoxedValue::Bool(b) = val {
+                    if b {
+                        return Ok(DispatchResult::Jump(InstructionPointer { func: func_pointer, pc: pc + n }));
+                    }
+                } else {
+                    panic!("expected bool, got {}", val);
+                }
+            }
+            OpCode::JumpBackIf(n) => {
+                let val = self.pop_value();
+                if let R_BoxedValue::Bool(b) = val {
+                    if b {
+                        return Ok(DispatchResult::Jump(InstructionPointer { func: func_pointer, pc: pc - n }));
+                    }
+                } else {
+                    panic!("expected bool, got {}", val);
+                }
+            }
+
+            OpCode::ConstRef(idx) => self.o_const_ref(idx),
+            OpCode::GetIndex => self.o_get_index(),
+            OpCode::AssignIndex => self.o_assign_index(),
+            OpCode::ElemPtr => self.o_elem_ptr(),
+            OpCode::MovePtr(offset) => self.o_move_ptr(offset),
+            OpCode::DerefStore => try!(self.o_deref_store()),
+            OpCode::CopyRange => self.o_copy_range(),
+            OpCode::Fill => self.o_fill(),
+
+            OpCode::Array(size) => try!(self.o_array(size)),
+
+            OpCode::Repeat(size) => try!(self.o_repeat(size)),
+
+            OpCode::Len => self.o_len(),
+
+            OpCode::BinOp(kind) => self.o_binop(kind),
+            OpCode::TypedBinOp(kind, ty) => self.o_typed_binop(kind, ty),
+            OpCode::CheckedBinOp(kind) => try!(self.o_checked_binop(kind)),
+            OpCode::WrappingBinOp(kind) => try!(self.o_wrapping_binop(kind)),
+            OpCode::SaturatingBinOp(kind) => try!(self.o_saturating_binop(kind)),
+
+            OpCode::Not => self.o_not(),
+            OpCode::Abs => try!(self.o_abs()),
+            OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros => {
+                try!(self.o_count_bits(opcode))
+            }
+            OpCode::RotateLeft => try!(self.o_rotate(true)),
+            OpCode::RotateRight => try!(self.o_rotate(false)),
+            OpCode::CheckedCast(ty) => try!(self.o_checked_cast(ty)),
+            OpCode::Cast(ty) => try!(self.o_cast(ty)),
+            OpCode::Noop => (),
+            // without a clock there is nothing to profile, the driver does
+            OpCode::ProfileEnter(_) | OpCode::ProfileExit(_) => (),
+            // there is no debugger to stop for
+            OpCode::Breakpoint(_) => (),
+            OpCode::Pop => {
+                self.stack.pop();
+            }
+
+            _ => return Err(self.error(ErrorKind::UnsupportedOpcode(opcode.clone()))),
+        }
+
+        Ok(DispatchResult::Next)
+    }
+
+    /// The guest call stack, innermost frame first.
+    pub fn backtrace(&self) -> Vec<BacktraceFrame> {
+        let mut frames = vec![BacktraceFrame { func: self.ip.func, pc: self.ip.pc }];
+        for frame in self.stack_frames.iter().rev() {
+            if let Some(ref ret) = frame.return_addr {
+                frames.push(BacktraceFrame { func: ret.func, pc: ret.pc });
+            }
+        }
+        frames
+    }
+
+    pub fn error(&self, kind: ErrorKind) -> InterpError {
+        InterpError { kind: kind, backtrace: self.backtrace() }
+    }
+
+    pub fn stack_ptr(&self) -> usize {
+        self.stack_frames.len() - 1
+    }
+
+    pub fn active_frame(&self) -> &CallFrame {
+        self.stack_frames.last().unwrap()
+    }
+
+    pub fn o_load(&mut self, local_idx: usize) {
+        let cell_ptr = self.active_frame().locals[local_idx].clone();
+        self.stack.push(StackVal::Ref(cell_ptr))
+    }
+
+    pub fn o_store(&mut self, local_idx: usize) {
+        let val = self.stack.pop().unwrap();
+        self.active_frame().locals[local_idx].set(val.unwrap_value());
+    }
+
+    // Fail if the pointer on top of the stack outlived its frame.
+    fn check_dangling(&self) -> InterpResult<()> {
+        if cfg!(debug_assertions) {
+            if let Some(&StackVal::Owned(R_BoxedValue::Ptr(R_Pointer { frame: Some(id), .. }))) =
+                self.stack.last() {
+                if !self.stack_frames.iter().any(|frame| frame.id == id) {
+                    return Err(self.error(ErrorKind::DanglingPointer { frame: id }));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn o_ref(&mut self) {
+        let mut addr = self.stack.pop().unwrap().into_pointer();
+        if cfg!(debug_assertions) {
+            if let StackVal::Owned(R_BoxedValue::Ptr(ref mut ptr)) = addr {
+                ptr.frame = self.stack_frames
+                    .iter()
+                    .rev()
+                    .find(|frame| frame.owns(&ptr.cell))
+                    .map(|frame| frame.id);
+            }
+        }
+        self.stack.push(addr);
+    }
+
+    pub fn o_deref(&mut self) -> InterpResult<()> {
+        try!(self.check_dangling());
+        let address = self.stack.pop().unwrap().deref();
+        self.stack.push(address);
+        Ok(())
+    }
+
+    /// `*ptr = val`, with val and ptr pushed in this order.
+    pub fn o_deref_store(&mut self) -> InterpResult<()> {
+        try!(self.check_dangling());
+        let ptr = self.pop_value();
+        let val = self.pop_value();
+        match ptr {
+            R_BoxedValue::Ptr(ptr) => ptr.cell.set(val),
+            ptr => panic!("expected pointer, got {}", ptr),
+        }
+        Ok(())
+    }
+
+    pub fn o_call(&mut self, cur_func: usize, cur_pc: usize) -> InterpResult<usize> {
+        if let R_BoxedValue::Func(idx) = self.stack.pop().unwrap().into_owned().unwrap_value() {
+            if idx == MERGE_POINT_FUNC {
+                // The merge point is handled by the `Driver` which runs this
+                // interpreter. Reaching it again from guest code means a
+                // nested interpreter loop, which would corrupt the state of
+                // the `Tracer` driving the outer one.
+                let kind = ErrorKind::MergePointReentered { func: cur_func, pc: cur_pc };
+                return Err(self.error(kind));
+            }
+            let program = self.program;
+            let func = &program.functions[idx];
+            if func.opcodes.is_empty() {
+                let kind = if self.sandbox {
+                    ErrorKind::SandboxViolation(Effect::ExternalCall(idx))
+                } else {
+                    ErrorKind::NoBytecode(idx)
+                };
+                return Err(self.error(kind));
+            }
+            // the values the caller pushed, besides the callee
+            let base = self.stack_frames.last().map_or(0, |frame| frame.stack_base);
+            let found = self.stack.len() - base;
+            if found < func.args_cnt {
+                return Err(self.error(ErrorKind::ArityMismatch {
+                    func: idx,
+                    expected: func.args_cnt,
+                    found: found,
+                }));
+            }
+            let return_addr = InstructionPointer {
+                func: cur_func,
+                pc: cur_pc,
+            };
+            let mut frame = CallFrame::new(Some(return_addr), func.locals_cnt);
+            frame.local_names = self.program.local_names(idx).cloned();
+            for idx in (0..func.args_cnt).rev() {
+                frame.locals[idx] = self.stack.pop().unwrap().into_cell().unwrap_cell();
+            }
+            frame.stack_base = self.stack.len();
+            self.push_frame(frame);
+            Ok(idx)
+        } else {
+            panic!("expected func");
+        }
+    }
+
+    /// Call `method` of the vtable on top of the stack like `o_call`.
+    pub fn o_call_virtual(&mut self, method: usize, cur_func: usize, cur_pc: usize)
+                          -> InterpResult<usize> {
+        let vtable = match self.pop_value() {
+            R_BoxedValue::VTable(idx) => Some(idx),
+            _ => None,
+        };
+        let func = match vtable.and_then(|idx| self.program.method(idx, method)) {
+            Some(func) => func,
+            None => {
+                return Err(self.error(ErrorKind::UnknownMethod { vtable: vtable, method: method }));
+            }
+        };
+        self.stack.push(StackVal::Owned(R_BoxedValue::Func(func)));
+        self.o_call(cur_func, cur_pc)
+    }
+
+    pub fn o_load_static(&mut self, static_idx: usize) -> InterpResult<()> {
+        let val = match self.program.static_value(static_idx) {
+            Some(val) => val.clone(),
+            // statics of the host
+            None => return Err(self.error(ErrorKind::UnknownStatic(static_idx))),
+        };
+        self.stack.push(StackVal::Owned(val));
+        Ok(())
+    }
+
+    /// Every call leaves exactly one value on the stack of the caller: the
+    /// top of the stack of the callee, or `Unit` if it left none. Values the
+    /// callee left below its result are dropped.
+    pub fn o_return(&mut self) -> Option<InstructionPointer> {
+        match self.stack_frames.pop() {
+            Some(frame) => {
+                let result = if self.stack.len() > frame.stack_base {
+                    self.stack.pop().unwrap()
+                } else {
+                    StackVal::Owned(R_BoxedValue::Unit)
+                };
+                self.stack.truncate(frame.stack_base);
+                self.stack.push(result);
+                frame.return_addr
+            }
+            None => None,
+        }
+    }
+
+    /// Account for the memory of a struct created by the guest.
+    fn alloc_struct(&mut self, mut obj: R_Struct) -> InterpResult<R_Struct> {
+        let size = R_Struct::heap_size(obj.data.len());
+        match self.heap.alloc(size) {
+            Some(allocation) => {
+                obj.allocation = Some(AllocationRef::new(allocation));
+                Ok(obj)
+            }
+            None => {
+                let limit = self.heap.limit.unwrap_or(0);
+                Err(self.error(ErrorKind::OutOfMemory { requested: size, limit: limit }))
+            }
+        }
+    }
+
+    pub fn o_tuple(&mut self, size: usize) -> InterpResult<()> {
+        let tuple = try!(self.alloc_struct(R_Struct::tuple(size)));
+        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(tuple)));
+        Ok(())
+    }
+
+    /// The fields are set before the tuple is on the stack, so no copy of it
+    /// can see them change.
+    pub fn o_tuple_from_stack(&mut self, size: usize) -> InterpResult<()> {
+        let mut tuple = R_Struct::tuple(size);
+        for idx in (0..size).rev() {
+            let val = self.pop_value();
+            tuple.set(idx, val);
+        }
+        let tuple = try!(self.alloc_struct(tuple));
+        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(tuple)));
+        Ok(())
+    }
+
+    pub fn o_tuple_set(&mut self, idx: usize) {
+        let boxed_tuple = self.pop_value();
+        let val = self.pop_value();
+
+        if let R_BoxedValue::Struct(mut tuple) = boxed_tuple {
+            tuple.set(idx, val);
+        } else {
+            panic!("expected struct, got {}", boxed_tuple);
+        }
+    }
+
+    pub fn o_tuple_get(&mut self, idx: usize) {
+        let val = self.pop_value();
+        if let R_BoxedValue::Struct(r_struct) = val {
+            let ptr = r_struct.data[idx].clone();
+            self.stack.push(StackVal::Ref(ptr));
+        } else {
+            panic!("expected struct, got {}", val);
+        }
+    }
+
+    pub fn pop_value(&mut self) -> R_BoxedValue {
+        let val = self.stack.pop().unwrap().into_owned().unwrap_value();
+        if let R_BoxedValue::Static(def_id) = val {
+            match self.program.static_value(def_id) {
+                Some(val) => val.clone(),
+                None => panic!("static {} has no value", def_id),
+            }
+        } else {
+            val
+        }
+    }
+
+    pub fn o_binop(&mut self, kind: BinOp) {
+        let val = self._do_binop(kind);
+        self.stack.push(StackVal::Owned(val));
+    }
+
+    pub fn o_checked_binop(&mut self, kind: BinOp) -> InterpResult<()> {
+        // TODO: actually check binops
+        let tuple = try!(self.alloc_struct(R_Struct::tuple(2)));
+        tuple.data[0].set(self._do_binop(kind));
+        // false == no error
+        tuple.data[1].set(R_BoxedValue::Bool(false));
+        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(tuple)));
+        Ok(())
+    }
+
+    /// `BinOp` without looking at the types of the operands, other than to
+    /// unwrap them. The trace guarded them already.
+    pub fn o_typed_binop(&mut self, kind: BinOp, ty: NumType) {
+        use objects::R_BoxedValue::*;
+
+        let right = self.pop_value();
+        let left = self.pop_value();
+        let val = match (ty, left, right) {
+            (NumType::Usize, Usize(l), Usize(r)) => int_binops!(kind, Usize, l, r),
+            (NumType::U64, U64(l), U64(r)) => int_binops!(kind, U64, l, r),
+            (NumType::I64, I64(l), I64(r)) => int_binops!(kind, I64, l, r),
+            (ty, l, r) => panic!("operands {} and {} aren't {:?}", l, r, ty),
+        };
+        self.stack.push(StackVal::Owned(val));
+    }
+
+    /// `BinOp` on integers which wraps around on overflow, whatever the
+    /// host was built with. Division by zero is an error, and so are
+    /// operands which aren't integers of the same type.
+    pub fn o_wrapping_binop(&mut self, kind: BinOp) -> InterpResult<()> {
+        use objects::R_BoxedValue::*;
+
+        let right = self.pop_value();
+        let left = self.pop_value();
+        try!(self.check_divisor(OpCode::WrappingBinOp(kind), &right));
+        let val = match (left, right) {
+            (I64(l), I64(r)) => wrapping_binops!(kind, I64, l, r),
+            (U64(l), U64(r)) => wrapping_binops!(kind, U64, l, r),
+            (Usize(l), Usize(r)) => wrapping_binops!(kind, Usize, l, r),
+            _ => {
+                let opcode = OpCode::WrappingBinOp(kind);
+                return Err(self.error(ErrorKind::UnsupportedOpcode(opcode)));
+            }
+        };
+        self.stack.push(StackVal::Owned(val));
+        Ok(())
+    }
+
+    /// `BinOp` on integers which stops at the bounds of their type on
+    /// overflow. Division by zero is an error, and so are operands which
+    /// aren't integers of the same type.
+    pub fn o_saturating_binop(&mut self, kind: BinOp) -> InterpResult<()> {
+        use objects::R_BoxedValue::*;
+
+        let right = self.pop_value();
+        let left = self.pop_value();
+        try!(self.check_divisor(OpCode::SaturatingBinOp(kind), &right));
+        let val = match (left, right) {
+            (I64(l), I64(r)) => saturating_binops!(kind, I64, l, r),
+            (U64(l), U64(r)) => saturating_binops!(kind, U64, l, r),
+            (Usize(l), Usize(r)) => saturating_binops!(kind, Usize, l, r),
+            _ => {
+                let opcode = OpCode::SaturatingBinOp(kind);
+                return Err(self.error(ErrorKind::UnsupportedOpcode(opcode)));
+            }
+        };
+        self.stack.push(StackVal::Owned(val));
+        Ok(())
+    }
+
+    // Wrapping and saturating `Div` and `Rem` have no result to give for a
+    // zero divisor.
+    fn check_divisor(&self, opcode: OpCode, divisor: &R_BoxedValue) -> InterpResult<()> {
+        use objects::R_BoxedValue::*;
+
+        let divides = match opcode {
+            OpCode::WrappingBinOp(kind) | OpCode::SaturatingBinOp(kind) => {
+                kind == BinOp::Div || kind == BinOp::Rem
+            }
+            _ => false,
+        };
+        let zero = match *divisor {
+            I64(0) | U64(0) | Usize(0) => true,
+            _ => false,
+        };
+        if divides && zero {
+            Err(self.error(ErrorKind::DivisionByZero(opcode)))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn _do_binop(&mut self, kind: BinOp) -> R_BoxedValue {
+
+        use objects::R_BoxedValue::*;
+        use bytecode::BinOp::*;
+
+        let right = self.pop_value();
+        let left = self.pop_value();
+
+        match (left, right) {
+            (I64(l), I64(r)) => int_binops!(kind, I64, l, r),
+            (U64(l), U64(r)) => int_binops!(kind, U64, l, r),
+            (Usize(l), Usize(r)) => int_binops!(kind, Usize, l, r),
+
+            // copied from miri
+            (Bool(l), Bool(r)) => {
+                Bool(match kind {
+                    Eq => l == r,
+                    Ne => l != r,
+                    Lt => l < r,
+                    Le => l <= r,
+                    Gt => l > r,
+                    Ge => l >= r,
+                    BitOr | Max => l | r,
+                    BitXor => l ^ r,
+                    BitAnd | Min => l & r,
+                    Add | Sub | Mul | Div | Rem | Shl | Shr => {
+                        panic!("invalid binary operation on booleans: {:?}", kind)
+                    }
+                })
+            }
+
+            (l, r) => panic!("unimplemented binary operation {:?} on {} and {}", kind, l, r),
+        }
+    }
+
+    pub fn o_not(&mut self) {
+        if let R_BoxedValue::Bool(boolean) = self.pop_value() {
+            self.stack.push(StackVal::Owned(R_BoxedValue::Bool(!boolean)));
+        } else {
+            panic!("expected bool");
+        }
+    }
+
+    /// Absolute value of the number on top of the stack. The one of the
+    /// smallest `i64` doesn't fit into it and is an error, other values are
+    /// unsupported.
+    pub fn o_abs(&mut self) -> InterpResult<()> {
+        let val = match self.pop_value() {
+            R_BoxedValue::I64(val) => match val.checked_abs() {
+                Some(abs) => R_BoxedValue::I64(abs),
+                None => return Err(self.error(ErrorKind::Overflow(OpCode::Abs))),
+            },
+            val @ R_BoxedValue::Usize(_) | val @ R_BoxedValue::U64(_) => val,
+            _ => return Err(self.error(ErrorKind::UnsupportedOpcode(OpCode::Abs))),
+        };
+        self.stack.push(StackVal::Owned(val));
+        Ok(())
+    }
+
+    /// `CountOnes`, `LeadingZeros` or `TrailingZeros` of the integer on top
+    /// of the stack, other values are unsupported.
+    pub fn o_count_bits(&mut self, opcode: &OpCode) -> InterpResult<()> {
+        use objects::R_BoxedValue::*;
+
+        macro_rules! count {
+            ($v:ident, $val:expr) => ({
+                let bits = match *opcode {
+                    OpCode::CountOnes => $val.count_ones(),
+                    OpCode::LeadingZeros => $val.leading_zeros(),
+                    _ => $val.trailing_zeros(),
+                };
+                $v(bits as _)
+            })
+        }
+        let val = match self.pop_value() {
+            I64(val) => count!(I64, val),
+            U64(val) => count!(U64, val),
+            Usize(val) => count!(Usize, val),
+            _ => return Err(self.error(ErrorKind::UnsupportedOpcode(opcode.clone()))),
+        };
+        self.stack.push(StackVal::Owned(val));
+        Ok(())
+    }
+
+    /// Rotate the bits of an integer by the integer on top of it, to the
+    /// left or to the right. Rotating by the width of the type or more
+    /// wraps around. Other values than integers are unsupported.
+    pub fn o_rotate(&mut self, left: bool) -> InterpResult<()> {
+        use objects::R_BoxedValue::*;
+
+        let opcode = if left { OpCode::RotateLeft } else { OpCode::RotateRight };
+        let by = match self.pop_value() {
+            I64(by) => by as u32,
+            U64(by) => by as u32,
+            Usize(by) => by as u32,
+            _ => return Err(self.error(ErrorKind::UnsupportedOpcode(opcode))),
+        };
+        let val = match self.pop_value() {
+            I64(val) => I64(if left { val.rotate_left(by) } else { val.rotate_right(by) }),
+            U64(val) => U64(if left { val.rotate_left(by) } else { val.rotate_right(by) }),
+            Usize(val) => Usize(if left { val.rotate_left(by) } else { val.rotate_right(by) }),
+            _ => return Err(self.error(ErrorKind::UnsupportedOpcode(opcode))),
+        };
+        self.stack.push(StackVal::Owned(val));
+        Ok(())
+    }
+
+    pub fn o_checked_cast(&mut self, ty: NumType) -> InterpResult<()> {
+        let val = self.pop_value();
+        match ty.cast(&val) {
+            Some(cast) => {
+                self.stack.push(StackVal::Owned(cast));
+                Ok(())
+            }
+            None => Err(self.error(ErrorKind::InvalidCast { value: val, to: ty })),
+        }
+    }
+
+    /// `CheckedCast` of a value the trace guarded to fit already. Outside of
+    /// a trace, e.g. in an unverified program, a value which doesn't fit is
+    /// the same error.
+    pub fn o_cast(&mut self, ty: NumType) -> InterpResult<()> {
+        self.o_checked_cast(ty)
+    }
+
+    pub fn o_get_index(&mut self) {
+        let target = self.pop_value();
+        let index = self.pop_value();
+        if let (R_BoxedValue::Struct(mut r_struct), R_BoxedValue::Usize(idx)) = (target, index) {
+            let val = r_struct.get(idx);
+            self.stack.push(StackVal::Ref(val));
+        } else {
+            panic!("error");
+        }
+    }
+
+    pub fn o_const_ref(&mut self, idx: usize) {
+        let val = self.program.data[idx].clone();
+        self.stack.push(StackVal::Owned(val));
+    }
+
+    pub fn o_elem_ptr(&mut self) {
+        let target = self.pop_value();
+        let index = self.pop_value();
+        if let (R_BoxedValue::Struct(r_struct), R_BoxedValue::Usize(idx)) = (target, index) {
+            let ptr = R_Pointer::elem(r_struct, idx);
+            self.stack.push(StackVal::Owned(R_BoxedValue::Ptr(ptr)));
+        } else {
+            panic!("error");
+        }
+    }
+
+    pub fn o_move_ptr(&mut self, offset: isize) {
+        match self.pop_value() {
+            R_BoxedValue::Ptr(ptr) => {
+                self.stack.push(StackVal::Owned(R_BoxedValue::Ptr(ptr.moved(offset))));
+            }
+            val => panic!("expected pointer, got {}", val),
+        }
+    }
+
+    pub fn o_assign_index(&mut self) {
+        let target = self.pop_value();
+        let index = self.pop_value();
+        let val = self.pop_value();
+        if let (R_BoxedValue::Struct(mut r_struct), R_BoxedValue::Usize(idx)) = (target, index) {
+            r_struct.set(idx, val);
+        } else {
+            panic!("error");
+        }
+    }
+
+    /// Copy the fields one after the other, like the loop it replaces.
+    pub fn o_copy_range(&mut self) {
+        let dst = self.pop_value();
+        let dst_start = self.pop_value();
+        let src = self.pop_value();
+        let src_start = self.pop_value();
+        let len = self.pop_value();
+        match (dst, dst_start, src, src_start, len) {
+            (R_BoxedValue::Struct(mut dst),
+             R_BoxedValue::Usize(dst_start),
+             R_BoxedValue::Struct(src),
+             R_BoxedValue::Usize(src_start),
+             R_BoxedValue::Usize(len)) => {
+                for idx in 0..len {
+                    let val = src.data[src_start + idx].get();
+                    dst.set(dst_start + idx, val);
+                }
+            }
+            (dst, _, src, _, _) => panic!("can't copy from {} into {}", src, dst),
+        }
+    }
+
+    pub fn o_fill(&mut self) {
+        let dst = self.pop_value();
+        let start = self.pop_value();
+        let len = self.pop_value();
+        let val = self.pop_value();
+        match (dst, start, len) {
+            (R_BoxedValue::Struct(mut dst), R_BoxedValue::Usize(start), R_BoxedValue::Usize(len)) => {
+                for idx in start..start + len {
+                    dst.set(idx, val.clone());
+                }
+            }
+            (dst, _, _) => panic!("can't fill {}", dst),
+        }
+    }
+
+    pub fn o_array(&mut self, size: usize) -> InterpResult<()> {
+        let mut obj = try!(self.alloc_struct(R_Struct::with_size(size)));
+        for idx in (0..size).rev() {
+            let val = self.pop_value();
+            obj.set(idx, val.clone());
+        }
+        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(obj)));
+        Ok(())
+    }
+
+    pub fn o_repeat(&mut self, size: usize) -> InterpResult<()> {
+        let val = self.pop_value();
+
+        let mut obj = try!(self.alloc_struct(R_Struct::with_size(size)));
+        for idx in 0..size {
+            obj.set(idx, val.clone());
+        }
+
+        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(obj)));
+        Ok(())
+    }
+
+    pub fn o_len(&mut self) {
+        let x = self.pop_value();
+        match x {
+            R_BoxedValue::Struct(s) => {
+                self.stack.push(StackVal::Owned(R_BoxedValue::Usize(s.data.len())));
+            }
+            R_BoxedValue::Array(inner_vec) => {
+                self.stack.push(StackVal::Owned(R_BoxedValue::Usize(inner_vec.len())));
+            }
+            _ => panic!("can't get len of {}", x),
+        }
+    }
+}
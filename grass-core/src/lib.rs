@@ -0,0 +1,36 @@
+//! Bytecode, objects and the basic interpreter of grass, without the tracer.
+//!
+//! Built without the default `std` feature the crate only depends on `core`
+//! and an allocator, so that small embedded hosts can run translated guests.
+//! The `Driver` and everything JIT related stay in the `grass` crate, which
+//! re-exports these modules.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(feature = "std"), feature(alloc, collections))]
+
+#[cfg(not(feature = "std"))]
+extern crate alloc;
+#[cfg(not(feature = "std"))]
+#[macro_use]
+extern crate collections;
+
+// Modules import from `std` either way, without the `std` feature this stands
+// in for it.
+#[cfg(not(feature = "std"))]
+mod std {
+    pub use core::{cell, cmp, fmt, hash, marker, mem};
+    pub use alloc::rc;
+    pub use collections::{string, vec};
+}
+
+pub mod cell;
+pub mod objects;
+pub mod bytecode;
+pub mod program;
+pub mod decode;
+pub mod error;
+pub mod interp;
+#[cfg(feature = "time-travel")]
+pub mod travel;
+#[cfg(feature = "word-values")]
+pub mod word;
@@ -0,0 +1,675 @@
+#![allow(non_camel_case_types)]
+
+use std::fmt;
+use std::mem;
+#[cfg(not(feature = "sync-cells"))]
+use std::rc::Rc;
+#[cfg(not(feature = "sync-cells"))]
+use std::cell::Cell;
+use std::hash::{Hash, Hasher};
+use std::string::String;
+#[cfg(feature = "sync-cells")]
+use std::sync::Arc;
+#[cfg(feature = "sync-cells")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::vec::Vec;
+
+// use rustc::hir::def_id::DefId;
+
+use bytecode::OpCode;
+use cell::{shared, Lock, Shared, SharedCell};
+
+
+
+#[derive(Clone, PartialEq, Default, Debug)]
+pub struct R_Function {
+    pub args_cnt: usize,
+    pub locals_cnt: usize,
+    pub opcodes: Vec<OpCode>,
+}
+
+/// A function shared by the opcodes which run it inlined, see
+/// `OpCode::FlatCall`.
+#[cfg(not(feature = "sync-cells"))]
+pub type FunctionRef = Rc<R_Function>;
+#[cfg(feature = "sync-cells")]
+pub type FunctionRef = Arc<R_Function>;
+
+// impl fmt::Debug for R_Function {
+//     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+//         write!(f, "Function({})", self.args_cnt)
+//     }
+// }
+
+
+
+// Since we don't have a flat memory model, pointers can point to different
+// locations. E.g. a pointer to the stack is different from one to the heap.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
+pub struct R_Pointer {
+    pub cell: Shared<R_BoxedValue>,
+    /// Id of the frame owning the cell if it is a local variable. Only set
+    /// in debug builds, where it is used to detect dangling pointers.
+    pub frame: Option<usize>,
+    /// The array and the index of the element `cell` is, for pointers which
+    /// `OpCode::MovePtr` can move along the array.
+    pub elem: Option<(R_Struct, usize)>,
+}
+
+impl R_Pointer {
+    pub fn new(cell: Shared<R_BoxedValue>) -> Self {
+        R_Pointer { cell: cell, frame: None, elem: None }
+    }
+
+    /// Pointer to element `index` of `array`.
+    pub fn elem(mut array: R_Struct, index: usize) -> Self {
+        R_Pointer { cell: array.get(index), frame: None, elem: Some((array, index)) }
+    }
+
+    /// The pointer `offset` elements further along its array. Panics if it
+    /// doesn't point into an array, or the element is out of its bounds.
+    pub fn moved(&self, offset: isize) -> Self {
+        match self.elem {
+            Some((ref array, index)) => {
+                let moved = index as isize + offset;
+                if moved < 0 || moved as usize >= array.data.len() {
+                    panic!("pointer moved to element {} of an array of {}", moved, array.data.len());
+                }
+                R_Pointer::elem(array.clone(), moved as usize)
+            }
+            None => panic!("can't move a pointer which doesn't point into an array"),
+        }
+    }
+}
+
+// pub enum R_Pointer {
+
+//     // XXX: Can we used that? Or do we have to use Stack?
+//     // Whenever we leave the current stack (call another function)
+//     // we need a reference to the stack.
+//     // Data in the same frame
+//     // Local(usize),
+
+//     /// Simple values on stack
+//     Stack(R_StackPointer),
+
+//     /// A pointer to a field within a struct on the stack.
+//     // let x = &foo.bar;
+//     // XXX: This can be recursive, what happens with &.a.b.c ?
+//     Nested(R_NestedPointer),
+
+//     // XXX: do we just have const pointers or can we have special const-func pointers?
+//     ConstFunc(Rc<R_Function>),
+// }
+
+// impl R_Pointer {
+//     pub fn deref<'a>(&self, env: &'a Interpreter) -> &'a R_BoxedValue {
+//         match *self {
+//             R_Pointer::Stack(ptr) => {
+//                 &env.stack_frames[ptr.frame].locals[ptr.idx]
+//             },
+
+//             _ => unimplemented!(),
+//         }
+//     }
+// }
+
+
+// Boxed rust values.
+// Only these values can life on the stack. XXX: is this true?
+
+/// Values compare and hash structurally: pointers and fields by the values
+/// in their cells, see `PartialEq` and `Hash` below.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
+pub enum R_BoxedValue {
+    Null,
+    /// result of functions without a return value
+    Unit,
+    Ptr(R_Pointer),
+    I64(i64),
+    U64(u64),
+    F64(f64),
+    Usize(usize),
+    Bool(bool),
+    Struct(R_Struct),
+    Func(usize),
+    Static(usize),
+    Array(Vec<R_BoxedValue>),
+    /// methods of a type, by the index into `Program::vtables`
+    VTable(usize),
+}
+
+fn null_values(n: usize) -> Vec<Shared<R_BoxedValue>> {
+    (0..n).map(|_|
+        shared(R_BoxedValue::Null)
+    ).collect()
+}
+
+// For not we allocate the meta information on the stack with a pointer to the
+// host-level heap.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone)]
+pub struct R_Struct {
+    pub alive: bool,
+    pub behaviour: MoveSemantics,
+    pub data: Vec<Shared<R_BoxedValue>>,
+    /// accounting of the memory of guest allocated structs, `None` for
+    /// structs created by the host
+    pub allocation: Option<AllocationRef>,
+}
+
+impl R_Struct {
+    pub fn tuple(size: usize) -> Self {
+        R_Struct { alive: true, behaviour: MoveSemantics::Move,
+                   data: null_values(size), allocation: None }
+    }
+
+    pub fn with_size(size: usize) -> Self {
+        R_Struct { alive: true, behaviour: MoveSemantics::Copy,
+                   data: null_values(size), allocation: None }
+    }
+
+    /// Bytes of guest memory the fields of a struct with `size` fields take.
+    pub fn heap_size(size: usize) -> usize {
+        size * mem::size_of::<Lock<R_BoxedValue>>()
+    }
+
+    pub fn set(&mut self, index: usize, value: R_BoxedValue) {
+        self.data[index].set(value);
+    }
+    pub fn get(&mut self, index: usize) -> Shared<R_BoxedValue> {
+        self.data[index].clone()
+    }
+}
+
+// Structural equality. Cells count as equal if they are the same cell or hold
+// equal values. Pairs of cells whose comparison is already under way count as
+// equal too, which ends the comparison of cyclic values.
+type Comparing = Vec<(*const Lock<R_BoxedValue>, *const Lock<R_BoxedValue>)>;
+
+fn values_eq(left: &R_BoxedValue, right: &R_BoxedValue, comparing: &mut Comparing) -> bool {
+    use self::R_BoxedValue::*;
+
+    match (left, right) {
+        (&Null, &Null) | (&Unit, &Unit) => true,
+        (&Ptr(ref l), &Ptr(ref r)) => cells_eq(&l.cell, &r.cell, comparing),
+        (&I64(l), &I64(r)) => l == r,
+        (&U64(l), &U64(r)) => l == r,
+        // NaNs are equal, so that every value is equal to itself
+        (&F64(l), &F64(r)) => l == r || (l.is_nan() && r.is_nan()),
+        (&Usize(l), &Usize(r)) => l == r,
+        (&Bool(l), &Bool(r)) => l == r,
+        (&Struct(ref l), &Struct(ref r)) => structs_eq(l, r, comparing),
+        (&Func(l), &Func(r)) => l == r,
+        (&Static(l), &Static(r)) => l == r,
+        (&VTable(l), &VTable(r)) => l == r,
+        (&Array(ref l), &Array(ref r)) => {
+            l.len() == r.len() && l.iter().zip(r).all(|(l, r)| values_eq(l, r, comparing))
+        }
+        _ => false,
+    }
+}
+
+fn structs_eq(left: &R_Struct, right: &R_Struct, comparing: &mut Comparing) -> bool {
+    left.alive == right.alive && left.behaviour == right.behaviour &&
+    left.data.len() == right.data.len() &&
+    left.data.iter().zip(&right.data).all(|(l, r)| cells_eq(l, r, comparing))
+}
+
+fn cells_eq(left: &Shared<R_BoxedValue>, right: &Shared<R_BoxedValue>, comparing: &mut Comparing)
+            -> bool {
+    if left.same_cell(right) {
+        return true;
+    }
+    let pair: (*const Lock<R_BoxedValue>, *const Lock<R_BoxedValue>) = (&**left, &**right);
+    if comparing.contains(&pair) {
+        return true;
+    }
+    comparing.push(pair);
+    left.with(|l| right.with(|r| values_eq(l, r, comparing)))
+}
+
+impl PartialEq for R_BoxedValue {
+    fn eq(&self, other: &Self) -> bool {
+        values_eq(self, other, &mut Vec::new())
+    }
+}
+
+impl Eq for R_BoxedValue {}
+
+impl PartialEq for R_Struct {
+    fn eq(&self, other: &Self) -> bool {
+        structs_eq(self, other, &mut Vec::new())
+    }
+}
+
+impl Eq for R_Struct {}
+
+// `frame` is only a debugging aid
+impl PartialEq for R_Pointer {
+    fn eq(&self, other: &Self) -> bool {
+        cells_eq(&self.cell, &other.cell, &mut Vec::new())
+    }
+}
+
+impl Eq for R_Pointer {}
+
+// Hashing follows cells only this deep, cyclic values have to end somewhere.
+// Equal values agree up to any depth, so they still hash the same.
+const HASH_DEPTH: usize = 4;
+
+fn hash_value<H: Hasher>(val: &R_BoxedValue, depth: usize, state: &mut H) {
+    use self::R_BoxedValue::*;
+
+    match *val {
+        Null => 0u8.hash(state),
+        Unit => 1u8.hash(state),
+        Ptr(ref ptr) => {
+            2u8.hash(state);
+            hash_cell(&ptr.cell, depth, state);
+        }
+        I64(n) => {
+            3u8.hash(state);
+            n.hash(state);
+        }
+        U64(n) => {
+            4u8.hash(state);
+            n.hash(state);
+        }
+        F64(n) => {
+            5u8.hash(state);
+            // the same bits for the values `values_eq` considers equal
+            let bits: u64 = if n.is_nan() {
+                0x7ff8_0000_0000_0000
+            } else if n == 0.0 {
+                0
+            } else {
+                unsafe { mem::transmute(n) }
+            };
+            bits.hash(state);
+        }
+        Usize(n) => {
+            6u8.hash(state);
+            n.hash(state);
+        }
+        Bool(b) => {
+            7u8.hash(state);
+            b.hash(state);
+        }
+        Struct(ref s) => {
+            8u8.hash(state);
+            hash_struct(s, depth, state);
+        }
+        Func(idx) => {
+            9u8.hash(state);
+            idx.hash(state);
+        }
+        Static(idx) => {
+            10u8.hash(state);
+            idx.hash(state);
+        }
+        Array(ref values) => {
+            11u8.hash(state);
+            values.len().hash(state);
+            for val in values {
+                hash_value(val, depth, state);
+            }
+        }
+        VTable(idx) => {
+            12u8.hash(state);
+            idx.hash(state);
+        }
+    }
+}
+
+fn hash_struct<H: Hasher>(s: &R_Struct, depth: usize, state: &mut H) {
+    s.alive.hash(state);
+    s.behaviour.hash(state);
+    s.data.len().hash(state);
+    for cell in &s.data {
+        hash_cell(cell, depth, state);
+    }
+}
+
+fn hash_cell<H: Hasher>(cell: &Shared<R_BoxedValue>, depth: usize, state: &mut H) {
+    if depth > 0 {
+        cell.with(|val| hash_value(val, depth - 1, state));
+    }
+}
+
+impl Hash for R_BoxedValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(self, HASH_DEPTH, state);
+    }
+}
+
+impl Hash for R_Struct {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_struct(self, HASH_DEPTH, state);
+    }
+}
+
+impl Hash for R_Pointer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_cell(&self.cell, HASH_DEPTH, state);
+    }
+}
+
+// Values are shown only this deep, and only this many elements of arrays
+// and fields of structs, for the rest there is `..`.
+const DISPLAY_DEPTH: usize = 3;
+const DISPLAY_ELEMS: usize = 16;
+
+/// A value shown up to `depth` levels of structs, arrays and pointers, see
+/// `R_BoxedValue::pretty`.
+pub struct Pretty<'a> {
+    value: &'a R_BoxedValue,
+    depth: usize,
+}
+
+impl R_BoxedValue {
+    /// Show the value with `depth` levels of nesting, the `Display` of
+    /// values shows a few.
+    pub fn pretty<'a>(&'a self, depth: usize) -> Pretty<'a> {
+        Pretty { value: self, depth: depth }
+    }
+}
+
+// cells on the way from the shown value to the current one, a cell which
+// is met again is a cycle
+type Showing = Vec<*const Lock<R_BoxedValue>>;
+
+fn fmt_value(f: &mut fmt::Formatter, val: &R_BoxedValue, depth: usize, showing: &mut Showing)
+             -> fmt::Result {
+    use self::R_BoxedValue::*;
+
+    match *val {
+        Null => write!(f, "null"),
+        Unit => write!(f, "()"),
+        Ptr(_) if depth == 0 => write!(f, "&.."),
+        Ptr(ref ptr) => {
+            try!(write!(f, "&"));
+            try!(fmt_cell(f, &ptr.cell, depth - 1, showing));
+            match ptr.elem {
+                Some((_, index)) => write!(f, " @{}", index),
+                None => Ok(()),
+            }
+        }
+        I64(n) => write!(f, "{}i64", n),
+        U64(n) => write!(f, "{}u64", n),
+        F64(n) => write!(f, "{:?}", n),
+        Usize(n) => write!(f, "{}", n),
+        Bool(b) => write!(f, "{}", b),
+        Struct(ref s) => {
+            if !s.alive {
+                try!(write!(f, "moved "));
+            }
+            try!(write!(f, "{{"));
+            if depth == 0 && !s.data.is_empty() {
+                try!(write!(f, ".."));
+            } else {
+                for (i, cell) in s.data.iter().take(DISPLAY_ELEMS).enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(fmt_cell(f, cell, depth - 1, showing));
+                }
+                try!(fmt_rest(f, s.data.len()));
+            }
+            write!(f, "}}")
+        }
+        Func(idx) => write!(f, "fn {}", idx),
+        Static(idx) => write!(f, "static {}", idx),
+        Array(ref values) => {
+            try!(write!(f, "["));
+            if depth == 0 && !values.is_empty() {
+                try!(write!(f, ".."));
+            } else {
+                for (i, val) in values.iter().take(DISPLAY_ELEMS).enumerate() {
+                    if i > 0 {
+                        try!(write!(f, ", "));
+                    }
+                    try!(fmt_value(f, val, depth - 1, showing));
+                }
+                try!(fmt_rest(f, values.len()));
+            }
+            write!(f, "]")
+        }
+        VTable(idx) => write!(f, "vtable {}", idx),
+    }
+}
+
+// what `fmt_value` left out of `len` elements
+fn fmt_rest(f: &mut fmt::Formatter, len: usize) -> fmt::Result {
+    if len > DISPLAY_ELEMS {
+        write!(f, ", .. {} more", len - DISPLAY_ELEMS)
+    } else {
+        Ok(())
+    }
+}
+
+fn fmt_cell(f: &mut fmt::Formatter, cell: &Shared<R_BoxedValue>, depth: usize,
+            showing: &mut Showing)
+            -> fmt::Result {
+    let this: *const Lock<R_BoxedValue> = &**cell;
+    if showing.contains(&this) {
+        return write!(f, "<cycle>");
+    }
+    showing.push(this);
+    let res = cell.with(|val| fmt_value(f, val, depth, showing));
+    showing.pop();
+    res
+}
+
+impl<'a> fmt::Display for Pretty<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt_value(f, self.value, self.depth, &mut Vec::new())
+    }
+}
+
+/// Readable for diagnostics, unlike `Debug`: cycles are cut short, and
+/// nesting beyond a few levels shows as `..`.
+impl fmt::Display for R_BoxedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.pretty(DISPLAY_DEPTH).fmt(f)
+    }
+}
+
+/// Memory used by guest objects, with an optional upper limit.
+///
+/// Every guest allocated struct holds an `Allocation`. Once the last copy of
+/// the struct is gone its memory is no longer counted as used. Fields which
+/// are still referenced by pointers after that aren't accounted for.
+#[derive(Debug, Clone, Default)]
+pub struct Heap {
+    used: Usage,
+    pub limit: Option<usize>,
+}
+
+// The bytes in use, shared by a heap and its allocations. Atomic with
+// `sync-cells`, where structs can be dropped on any thread.
+#[cfg(not(feature = "sync-cells"))]
+type Usage = Rc<Cell<usize>>;
+#[cfg(feature = "sync-cells")]
+type Usage = Arc<AtomicUsize>;
+
+#[cfg(not(feature = "sync-cells"))]
+fn usage(used: &Usage) -> usize {
+    used.get()
+}
+#[cfg(feature = "sync-cells")]
+fn usage(used: &Usage) -> usize {
+    used.load(Ordering::SeqCst)
+}
+
+#[cfg(not(feature = "sync-cells"))]
+fn grow_usage(used: &Usage, bytes: usize) {
+    used.set(used.get() + bytes)
+}
+#[cfg(feature = "sync-cells")]
+fn grow_usage(used: &Usage, bytes: usize) {
+    used.fetch_add(bytes, Ordering::SeqCst);
+}
+
+#[cfg(not(feature = "sync-cells"))]
+fn shrink_usage(used: &Usage, bytes: usize) {
+    used.set(used.get() - bytes)
+}
+#[cfg(feature = "sync-cells")]
+fn shrink_usage(used: &Usage, bytes: usize) {
+    used.fetch_sub(bytes, Ordering::SeqCst);
+}
+
+impl Heap {
+    pub fn used(&self) -> usize {
+        usage(&self.used)
+    }
+
+    /// Account for `size` more bytes. Returns `None` if that would exceed
+    /// the limit.
+    pub fn alloc(&self, size: usize) -> Option<Allocation> {
+        let used = usage(&self.used) + size;
+        if self.limit.map_or(false, |limit| used > limit) {
+            return None;
+        }
+        grow_usage(&self.used, size);
+        Some(Allocation { size: size, used: self.used.clone() })
+    }
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    size: usize,
+    used: Usage,
+}
+
+/// The accounting a guest allocated struct shares with its copies.
+#[cfg(not(feature = "sync-cells"))]
+pub type AllocationRef = Rc<Allocation>;
+#[cfg(feature = "sync-cells")]
+pub type AllocationRef = Arc<Allocation>;
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        shrink_usage(&self.used, self.size);
+    }
+}
+
+// memory accounting doesn't make structs different
+impl PartialEq for Allocation {
+    fn eq(&self, _other: &Allocation) -> bool {
+        true
+    }
+}
+
+/// MoveSemantics
+///
+/// **FIXME:** Do we have to consider the difference between Move and Drop here?
+// Have a look at type_needs_drop_given_env
+
+/// ## Example
+/// In the following the variants are exlained using this code example:
+///
+///     let a = X(1);
+///     let b = a
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MoveSemantics {
+    /// **Copy semantics**
+    ///
+    /// `b` is a copy of `a`, with `a.alive == true` and `b.alive == true`
+    ///
+    ///     impl copy -> !impl drop
+    ///
+    /// Both a and b have to be considered when going out of scope
+    /// However a is bitwise identical to b. Thus this limits which types can
+    /// actually implement Copy. For example it is not possible to have an Rc
+    /// field, since the copy does not increase the ref counter. Also &mut T
+    /// is not allowed, as this would result in two mutable references to the
+    /// same resource.
+    /// No destruction needed.
+    Copy,
+
+    /// **Default.**
+    ///
+    /// `b` is still a bitwise copy of `a`. However `a.alive == false`.
+    ///
+    /// In other words, *ownership has been transferred*. The stack slot of a is
+    /// still available but is set to unitialised. Thus this is possible:
+    ///
+    ///     let mut a = X(1);
+    ///     let b = a;
+    ///     a = X(2);
+    ///
+    /// Transfer of ownership does not mean that b takes control of a's memory space.
+    /// Instead it takes control of a's data (for example it inherits mutable
+    /// references or Rc's).
+    ///
+    /// Consequently if possible `a` type should implement copy if it can.
+    /// If a goes out of scope it can be ignored since nothing needs to be dropped.
+    /// Destruction may be needed (?).
+    Move,
+
+    /// Same as Move, but it has a Drop handler attached. This normally means
+    /// that the type manages other special resources. rustc inserts explicit drops
+    /// whenever a type is moved.
+    Drop,
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InstructionPointer {
+    pub func: usize,
+    pub pc: usize,
+}
+
+/// Names of the locals of a function by their index, as far as the
+/// front-end knows them. Shared by all frames of the function.
+#[cfg(not(feature = "sync-cells"))]
+pub type LocalNames = Rc<Vec<Option<String>>>;
+#[cfg(feature = "sync-cells")]
+pub type LocalNames = Arc<Vec<Option<String>>>;
+
+// A stack frame.
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub return_addr: Option<InstructionPointer>,
+    pub locals: Vec<Shared<R_BoxedValue>>,
+    /// unique within an interpreter, assigned when the frame is pushed
+    pub id: usize,
+    /// height of the operand stack when the function was called, without
+    /// its arguments
+    pub stack_base: usize,
+    /// see `Program::local_names`
+    pub local_names: Option<LocalNames>,
+}
+
+impl CallFrame {
+    pub fn new(return_addr: Option<InstructionPointer>, locals_len: usize) -> Self {
+        CallFrame {
+            return_addr: return_addr,
+            locals: null_values(locals_len),
+            id: 0,
+            stack_base: 0,
+            local_names: None,
+        }
+    }
+
+    /// The name of local `idx` in the source of the guest, for debugging.
+    pub fn local_name(&self, idx: usize) -> Option<&str> {
+        self.local_names
+            .as_ref()
+            .and_then(|names| names.get(idx))
+            .and_then(|name| name.as_ref())
+            .map(|name| &name[..])
+    }
+
+    /// Whether `cell` is one of the local variables of this frame.
+    pub fn owns(&self, cell: &Shared<R_BoxedValue>) -> bool {
+        self.locals.iter().any(|local| local.same_cell(cell))
+    }
+}
+
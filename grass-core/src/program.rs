@@ -0,0 +1,174 @@
+use std::string::ToString;
+use std::vec::Vec;
+
+use bytecode::OpCode;
+use interp::{USER_PROGRAM_LOCAL, CELL_LOCAL, PC_LOCAL};
+use objects::{LocalNames, R_BoxedValue, R_Function};
+
+/// A translated guest program.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Program {
+    /// Functions by their index, as referenced by `R_BoxedValue::Func`.
+    /// Index `MERGE_POINT_FUNC` is taken by the merge point and stays empty.
+    pub functions: Vec<R_Function>,
+    /// `(function, opcode)` where the interpreter continues after the call
+    /// of the merge point, passed to `Driver::merge_point`.
+    pub entry: (usize, usize),
+    /// what the front-end knows about which locals alias
+    pub aliases: AliasClasses,
+    /// Read-only data, e.g. static arrays and strings, by the index
+    /// `OpCode::ConstRef` pushes it with. Pushing a struct shares its fields
+    /// instead of building it, so the guest must not write into them.
+    pub data: Vec<R_BoxedValue>,
+    /// Values of the statics by the index of `R_BoxedValue::Static` and
+    /// `OpCode::Static`, `None` for indices which aren't statics or whose
+    /// value the host provides.
+    pub statics: Vec<Option<R_BoxedValue>>,
+    /// Method tables by the index of `R_BoxedValue::VTable`: the function of
+    /// every method, by the index `OpCode::CallVirtual` calls it with.
+    pub vtables: Vec<Vec<usize>>,
+    /// Names of the locals by function, for debugging. Frames of the
+    /// function carry them, see `CallFrame::local_name`.
+    pub local_names: Vec<Option<LocalNames>>,
+}
+
+impl Program {
+    /// Program from the `PROGRAM` and `IDX` constants of a generated module.
+    pub fn from_static(functions: &[(usize, usize, &[OpCode])], entry: (usize, usize)) -> Self {
+        let functions = functions.iter()
+            .map(|&(args_cnt, locals_cnt, opcodes)| {
+                R_Function {
+                    args_cnt: args_cnt,
+                    locals_cnt: locals_cnt,
+                    opcodes: opcodes.to_vec(),
+                }
+            })
+            .collect();
+
+        let mut program = Program {
+            functions: functions,
+            entry: entry,
+            aliases: AliasClasses::default(),
+            data: Vec::new(),
+            statics: Vec::new(),
+            vtables: Vec::new(),
+            local_names: Vec::new(),
+        };
+        program.resolve_statics();
+        program.name_host_locals();
+        program
+    }
+
+    /// Name `local` of function `func`.
+    pub fn set_local_name(&mut self, func: usize, local: usize, name: &str) {
+        if self.local_names.len() <= func {
+            self.local_names.resize(func + 1, None);
+        }
+        if self.local_names[func].is_none() {
+            self.local_names[func] = Some(LocalNames::default());
+        }
+        let names = LocalNames::make_mut(self.local_names[func].as_mut().unwrap());
+        if names.len() <= local {
+            names.resize(local + 1, None);
+        }
+        names[local] = Some(name.to_string());
+    }
+
+    /// The names of the locals of `func`, if the front-end named any.
+    pub fn local_names(&self, func: usize) -> Option<&LocalNames> {
+        self.local_names.get(func).and_then(|names| names.as_ref())
+    }
+
+    /// Name the locals of the function with the merge point which hold the
+    /// state of the host, unless the front-end named them already.
+    pub fn name_host_locals(&mut self) {
+        let func = self.entry.0;
+        if func >= self.functions.len() {
+            return;
+        }
+        let locals_cnt = self.functions[func].locals_cnt;
+        let host = [(USER_PROGRAM_LOCAL, "user_program"), (CELL_LOCAL, "cell"), (PC_LOCAL, "pc")];
+        for &(local, name) in &host {
+            let named = self.local_names(func)
+                .and_then(|names| names.get(local))
+                .map_or(false, |name| name.is_some());
+            if local < locals_cnt && !named {
+                self.set_local_name(func, local, name);
+            }
+        }
+    }
+
+    /// The value of the static `idx`.
+    pub fn static_value(&self, idx: usize) -> Option<&R_BoxedValue> {
+        self.statics.get(idx).and_then(|val| val.as_ref())
+    }
+
+    /// The function of `method` in `vtable`.
+    pub fn method(&self, vtable: usize, method: usize) -> Option<usize> {
+        self.vtables.get(vtable).and_then(|methods| methods.get(method)).cloned()
+    }
+
+    /// Fill `statics` from the functions the front-end emits for the statics
+    /// the program refers to: their initializer starts with the constant
+    /// value.
+    pub fn resolve_statics(&mut self) {
+        let mut statics = Vec::new();
+        for func in &self.functions {
+            for opcode in &func.opcodes {
+                match *opcode {
+                    OpCode::Static(idx) |
+                    OpCode::ConstValue(R_BoxedValue::Static(idx)) => statics.push(idx),
+                    _ => (),
+                }
+            }
+        }
+
+        for idx in statics {
+            let val = match self.functions.get(idx).and_then(|func| func.opcodes.first()) {
+                Some(&OpCode::ConstValue(ref val)) => val.clone(),
+                _ => continue,
+            };
+            if self.statics.len() <= idx {
+                self.statics.resize(idx + 1, None);
+            }
+            self.statics[idx] = Some(val);
+        }
+    }
+}
+
+/// Which locals may hold the same struct, or pointers into it. The
+/// interpreter can't tell from the values, the front-end knows e.g. that the
+/// tape of a guest never is its program. Locals of different classes never
+/// alias, a local without a class may alias any other.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AliasClasses {
+    /// class of every local, by function
+    classes: Vec<Vec<Option<usize>>>,
+}
+
+impl AliasClasses {
+    /// Put `local` of function `func` into `class`.
+    pub fn set(&mut self, func: usize, local: usize, class: usize) {
+        if self.classes.len() <= func {
+            self.classes.resize(func + 1, Vec::new());
+        }
+        let locals = &mut self.classes[func];
+        if locals.len() <= local {
+            locals.resize(local + 1, None);
+        }
+        locals[local] = Some(class);
+    }
+
+    pub fn class(&self, func: usize, local: usize) -> Option<usize> {
+        self.classes.get(func).and_then(|locals| locals.get(local)).and_then(|&class| class)
+    }
+
+    /// Whether locals `a` and `b` of function `func` may hold the same struct.
+    pub fn may_alias(&self, func: usize, a: usize, b: usize) -> bool {
+        match (self.class(func, a), self.class(func, b)) {
+            _ if a == b => true,
+            (Some(a), Some(b)) => a == b,
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! History of the opcodes an `Interpreter` executed, with what each changed,
+//! so that debuggers can step the guest backwards, see
+//! `Interpreter::step_back`.
+//!
+//! A step keeps only what its opcode took away: the operands it popped and
+//! the old value of the cell it stored into. Changes within structs and
+//! arrays are not undone, their cells are shared with the recorded values.
+
+use std::vec::Vec;
+
+use cell::Shared;
+use interp::StackVal;
+use objects::{CallFrame, InstructionPointer, R_BoxedValue};
+
+/// steps kept unless `TimeTravel::set_len` says otherwise
+pub const DEFAULT_TIME_TRAVEL_LEN: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub ip: InstructionPointer,
+    /// height of the operand stack below the popped operands
+    pub stack_base: usize,
+    /// the operands the opcode popped, all of those of the frame for calls
+    /// and returns
+    pub popped: Vec<StackVal>,
+    /// number of frames before the opcode
+    pub depth: usize,
+    /// the cell the opcode stored into, with its old value
+    pub overwritten: Option<(Shared<R_BoxedValue>, R_BoxedValue)>,
+    /// the frame the opcode returned from
+    pub returned: Option<CallFrame>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TimeTravel {
+    steps: Vec<Step>,
+    len: usize,
+}
+
+impl Default for TimeTravel {
+    fn default() -> Self {
+        TimeTravel {
+            steps: Vec::new(),
+            len: DEFAULT_TIME_TRAVEL_LEN,
+        }
+    }
+}
+
+impl TimeTravel {
+    /// Keep the last `len` steps.
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len;
+        self.trim(len);
+    }
+
+    pub fn record(&mut self, step: Step) {
+        self.steps.push(step);
+        // trim in batches, so that recording stays cheap
+        if self.steps.len() > 2 * self.len {
+            let len = self.len;
+            self.trim(len);
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Step> {
+        if self.steps.len() > self.len {
+            let len = self.len;
+            self.trim(len);
+        }
+        self.steps.pop()
+    }
+
+    /// Number of steps which can be undone.
+    pub fn len(&self) -> usize {
+        if self.steps.len() < self.len { self.steps.len() } else { self.len }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.steps.clear();
+    }
+
+    fn trim(&mut self, len: usize) {
+        if self.steps.len() > len {
+            let excess = self.steps.len() - len;
+            self.steps.drain(..excess);
+        }
+    }
+}
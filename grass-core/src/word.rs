@@ -0,0 +1,98 @@
+//! Guest values packed into a single 64 bit word, an experiment to measure
+//! against the `R_BoxedValue` enum. Only built with the `word-values`
+//! feature.
+//!
+//! Floats are stored as they are, NaNs as one canonical NaN. All other
+//! values live in the payload of negative quiet NaNs, which no float uses
+//! then: the top 13 bits are set, the next 3 bits are the tag and the low 48
+//! bits the payload. Values which don't fit, structs, pointers, arrays and
+//! numbers beyond 48 bits, stay boxed.
+
+use std::mem;
+
+use objects::R_BoxedValue;
+
+const CANONICAL_NAN: u64 = 0x7ff8_0000_0000_0000;
+// negative quiet NaN, tag 0 is left to floats
+const BOXED: u64 = 0xfff8_0000_0000_0000;
+const TAG_SHIFT: u64 = 48;
+const PAYLOAD: u64 = (1 << TAG_SHIFT) - 1;
+
+const TAG_USIZE: u64 = 1;
+const TAG_I64: u64 = 2;
+const TAG_U64: u64 = 3;
+const TAG_BOOL: u64 = 4;
+const TAG_FUNC: u64 = 5;
+const TAG_STATIC: u64 = 6;
+// payload 0 is `Null`, 1 is `Unit`
+const TAG_EMPTY: u64 = 7;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct R_Value(u64);
+
+impl R_Value {
+    /// `None` if `val` has to stay boxed.
+    pub fn pack(val: &R_BoxedValue) -> Option<R_Value> {
+        let (tag, payload) = match *val {
+            R_BoxedValue::F64(n) => {
+                let bits = if n.is_nan() { CANONICAL_NAN } else { unsafe { mem::transmute(n) } };
+                return Some(R_Value(bits));
+            }
+            R_BoxedValue::Null => (TAG_EMPTY, 0),
+            R_BoxedValue::Unit => (TAG_EMPTY, 1),
+            R_BoxedValue::Bool(b) => (TAG_BOOL, b as u64),
+            R_BoxedValue::Usize(n) => (TAG_USIZE, n as u64),
+            R_BoxedValue::U64(n) => (TAG_U64, n),
+            R_BoxedValue::Func(idx) => (TAG_FUNC, idx as u64),
+            R_BoxedValue::Static(idx) => (TAG_STATIC, idx as u64),
+            R_BoxedValue::I64(n) => {
+                // sign extending the low 48 bits has to give `n` back
+                if (n << 16) >> 16 != n {
+                    return None;
+                }
+                (TAG_I64, n as u64 & PAYLOAD)
+            }
+            _ => return None,
+        };
+        if payload > PAYLOAD {
+            return None;
+        }
+        Some(R_Value(BOXED | tag << TAG_SHIFT | payload))
+    }
+
+    pub fn unpack(self) -> R_BoxedValue {
+        if self.0 & BOXED != BOXED || self.tag() == 0 {
+            return R_BoxedValue::F64(unsafe { mem::transmute(self.0) });
+        }
+        let payload = self.0 & PAYLOAD;
+        match self.tag() {
+            TAG_USIZE => R_BoxedValue::Usize(payload as usize),
+            TAG_I64 => R_BoxedValue::I64(((payload << 16) as i64) >> 16),
+            TAG_U64 => R_BoxedValue::U64(payload),
+            TAG_BOOL => R_BoxedValue::Bool(payload != 0),
+            TAG_FUNC => R_BoxedValue::Func(payload as usize),
+            TAG_STATIC => R_BoxedValue::Static(payload as usize),
+            TAG_EMPTY if payload == 0 => R_BoxedValue::Null,
+            _ => R_BoxedValue::Unit,
+        }
+    }
+
+    /// The `usize` without unpacking, `None` for other values.
+    #[inline]
+    pub fn as_usize(self) -> Option<usize> {
+        if self.0 >> TAG_SHIFT == BOXED >> TAG_SHIFT | TAG_USIZE {
+            Some((self.0 & PAYLOAD) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    fn tag(self) -> u64 {
+        (self.0 >> TAG_SHIFT) & 0x7
+    }
+}
@@ -0,0 +1,29 @@
+//! Helpers shared by the tests to build guest programs.
+
+use grass_core::program::{AliasClasses, Program};
+use grass_core::bytecode::OpCode;
+use grass_core::objects::R_Function;
+
+pub fn function(args_cnt: usize, locals_cnt: usize, opcodes: Vec<OpCode>) -> R_Function {
+    R_Function {
+        args_cnt: args_cnt,
+        locals_cnt: locals_cnt,
+        opcodes: opcodes,
+    }
+}
+
+/// A program of `functions`, starting at function 1. Function 0 is the
+/// merge point, which these programs don't have.
+pub fn program(functions: Vec<R_Function>) -> Program {
+    let mut all = vec![R_Function::default()];
+    all.extend(functions);
+    Program {
+        functions: all,
+        entry: (0, 0),
+        aliases: AliasClasses::default(),
+        data: Vec::new(),
+        statics: Vec::new(),
+        vtables: Vec::new(),
+        local_names: Vec::new(),
+    }
+}
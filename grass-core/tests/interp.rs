@@ -0,0 +1,510 @@
+extern crate grass_core;
+
+mod common;
+
+use grass_core::bytecode::{OpCode, BinOp, InternalFunc, NumType, MERGE_POINT_FUNC};
+use grass_core::decode::{Code, Insn};
+use grass_core::error::ErrorKind;
+use grass_core::interp::{Interpreter, InterpreterConfig, StackVal};
+use grass_core::objects::{CallFrame, Heap, R_BoxedValue, R_Struct};
+use grass_core::program::Program;
+
+use common::{function, program};
+
+fn run(program: &Program, heap: Heap) -> Result<R_BoxedValue, ErrorKind> {
+    let code = Code::decode(program);
+    let mut interp = Interpreter::new(program, &code, heap);
+    interp.push_frame(CallFrame::new(None, program.functions[1].locals_cnt));
+    interp.run(1).map_err(|err| err.kind)
+}
+
+// 1: n = 5; sum = 0; while n != 0 { sum += n; n -= 1 }; sum
+fn summing_loop() -> Program {
+    program(vec![
+        function(0, 2, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(5)),
+            OpCode::Store(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::Store(1),
+            // 4: loop header
+            OpCode::Load(1),
+            OpCode::Load(0),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Store(0),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::BinOp(BinOp::Ne),
+            OpCode::JumpBackIf(11),
+            OpCode::Load(1),
+            OpCode::Return,
+        ]),
+    ])
+}
+
+#[test]
+fn sums_in_a_loop() {
+    assert_eq!(run(&summing_loop(), Heap::default()), Ok(R_BoxedValue::Usize(15)));
+}
+
+#[test]
+fn clones_of_the_heap_count_the_same_memory() {
+    let program = program(vec![
+        function(0, 0, vec![OpCode::Tuple(4), OpCode::Return]),
+    ]);
+    let heap = Heap::default();
+
+    let tuple = run(&program, heap.clone());
+    assert!(tuple.is_ok());
+    assert!(heap.used() > 0);
+    drop(tuple);
+    assert_eq!(heap.used(), 0);
+}
+
+#[test]
+fn internal_funcs_are_left_to_the_driver() {
+    let program = program(vec![
+        function(0, 0, vec![OpCode::InternalFunc(InternalFunc::Rand), OpCode::Return]),
+    ]);
+
+    assert_eq!(run(&program, Heap::default()),
+               Err(ErrorKind::UnsupportedOpcode(OpCode::InternalFunc(InternalFunc::Rand))));
+}
+
+#[test]
+fn builds_tuples_from_the_fields_on_the_stack() {
+    // 1: pair = (10, 3); pair.0 - pair.1
+    let program = program(vec![
+        function(0, 1, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(10)),
+            OpCode::ConstValue(R_BoxedValue::Usize(3)),
+            OpCode::TupleFromStack(2),
+            OpCode::Store(0),
+            OpCode::Load(0),
+            OpCode::TupleGet(0),
+            OpCode::Load(0),
+            OpCode::TupleGet(1),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Return,
+        ]),
+    ]);
+
+    assert_eq!(run(&program, Heap::default()), Ok(R_BoxedValue::Usize(7)));
+}
+
+#[test]
+fn abs_of_the_smallest_i64_overflows() {
+    let abs = |val| {
+        let program = program(vec![
+            function(0, 0, vec![OpCode::ConstValue(val), OpCode::Abs, OpCode::Return]),
+        ]);
+        run(&program, Heap::default())
+    };
+
+    assert_eq!(abs(R_BoxedValue::I64(-7)), Ok(R_BoxedValue::I64(7)));
+    assert_eq!(abs(R_BoxedValue::I64(i64::min_value() + 1)),
+               Ok(R_BoxedValue::I64(i64::max_value())));
+    assert_eq!(abs(R_BoxedValue::I64(i64::min_value())), Err(ErrorKind::Overflow(OpCode::Abs)));
+    assert_eq!(abs(R_BoxedValue::Bool(true)), Err(ErrorKind::UnsupportedOpcode(OpCode::Abs)));
+}
+
+#[test]
+fn overflows_wrap_or_saturate_as_asked() {
+    let compute = |opcode, l, r| {
+        let program = program(vec![
+            function(0, 0, vec![
+                OpCode::ConstValue(l),
+                OpCode::ConstValue(r),
+                opcode,
+                OpCode::Return,
+            ]),
+        ]);
+        run(&program, Heap::default())
+    };
+    let max = R_BoxedValue::U64(u64::max_value());
+    let one = R_BoxedValue::U64(1);
+
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Add), max.clone(), one.clone()),
+               Ok(R_BoxedValue::U64(0)));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Add), max.clone(), one.clone()),
+               Ok(max.clone()));
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Sub), R_BoxedValue::U64(0), one.clone()),
+               Ok(max.clone()));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Sub), R_BoxedValue::U64(0), one.clone()),
+               Ok(R_BoxedValue::U64(0)));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Mul),
+                       R_BoxedValue::I64(i64::min_value()),
+                       R_BoxedValue::I64(2)),
+               Ok(R_BoxedValue::I64(i64::min_value())));
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Shl), one.clone(), R_BoxedValue::U64(65)),
+               Ok(R_BoxedValue::U64(2)));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Shl), one.clone(), R_BoxedValue::U64(65)),
+               Ok(max.clone()));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Shl), one.clone(), R_BoxedValue::U64(3)),
+               Ok(R_BoxedValue::U64(8)));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Shl),
+                       R_BoxedValue::I64(-3),
+                       R_BoxedValue::I64(62)),
+               Ok(R_BoxedValue::I64(i64::min_value())));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Shr), max.clone(), R_BoxedValue::U64(65)),
+               Ok(R_BoxedValue::U64(0)));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Shr),
+                       R_BoxedValue::I64(-8),
+                       R_BoxedValue::I64(64)),
+               Ok(R_BoxedValue::I64(-1)));
+    // without an overflow they are the plain operation
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Lt), one.clone(), max.clone()),
+               Ok(R_BoxedValue::Bool(true)));
+    // only integers of the same type
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Add), one.clone(), R_BoxedValue::I64(1)),
+               Err(ErrorKind::UnsupportedOpcode(OpCode::WrappingBinOp(BinOp::Add))));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Add), R_BoxedValue::Bool(true),
+                       R_BoxedValue::Bool(false)),
+               Err(ErrorKind::UnsupportedOpcode(OpCode::SaturatingBinOp(BinOp::Add))));
+
+    let min = R_BoxedValue::I64(i64::min_value());
+    let minus_one = R_BoxedValue::I64(-1);
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Div), min.clone(), minus_one.clone()),
+               Ok(min.clone()));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Div), min.clone(), minus_one.clone()),
+               Ok(R_BoxedValue::I64(i64::max_value())));
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Rem), min.clone(), minus_one.clone()),
+               Ok(R_BoxedValue::I64(0)));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Rem), min.clone(), minus_one.clone()),
+               Ok(R_BoxedValue::I64(0)));
+    // a zero divisor has no result to wrap or saturate to
+    assert_eq!(compute(OpCode::WrappingBinOp(BinOp::Div), one.clone(), R_BoxedValue::U64(0)),
+               Err(ErrorKind::DivisionByZero(OpCode::WrappingBinOp(BinOp::Div))));
+    assert_eq!(compute(OpCode::SaturatingBinOp(BinOp::Rem), min.clone(), R_BoxedValue::I64(0)),
+               Err(ErrorKind::DivisionByZero(OpCode::SaturatingBinOp(BinOp::Rem))));
+}
+
+#[test]
+fn counts_and_rotates_bits() {
+    let compute = |mut ops: Vec<OpCode>| {
+        ops.push(OpCode::Return);
+        let program = program(vec![function(0, 0, ops)]);
+        run(&program, Heap::default())
+    };
+    let val = R_BoxedValue::U64(0b1011 << 4);
+
+    assert_eq!(compute(vec![OpCode::ConstValue(val.clone()), OpCode::CountOnes]),
+               Ok(R_BoxedValue::U64(3)));
+    assert_eq!(compute(vec![OpCode::ConstValue(val.clone()), OpCode::LeadingZeros]),
+               Ok(R_BoxedValue::U64(56)));
+    assert_eq!(compute(vec![OpCode::ConstValue(val.clone()), OpCode::TrailingZeros]),
+               Ok(R_BoxedValue::U64(4)));
+    assert_eq!(compute(vec![OpCode::ConstValue(R_BoxedValue::I64(-1)), OpCode::CountOnes]),
+               Ok(R_BoxedValue::I64(64)));
+
+    assert_eq!(compute(vec![OpCode::ConstValue(val.clone()),
+                            OpCode::ConstValue(R_BoxedValue::U64(62)),
+                            OpCode::RotateLeft]),
+               Ok(R_BoxedValue::U64(0b1011 << 2)));
+    assert_eq!(compute(vec![OpCode::ConstValue(val.clone()),
+                            OpCode::ConstValue(R_BoxedValue::U64(6)),
+                            OpCode::RotateRight]),
+               Ok(R_BoxedValue::U64(0b11 << 62 | 0b10)));
+    // by the width of the type it is the same value
+    assert_eq!(compute(vec![OpCode::ConstValue(val.clone()),
+                            OpCode::ConstValue(R_BoxedValue::U64(64)),
+                            OpCode::RotateLeft]),
+               Ok(val.clone()));
+
+    // only integers
+    assert_eq!(compute(vec![OpCode::ConstValue(R_BoxedValue::Bool(true)), OpCode::CountOnes]),
+               Err(ErrorKind::UnsupportedOpcode(OpCode::CountOnes)));
+    assert_eq!(compute(vec![OpCode::ConstValue(val),
+                            OpCode::ConstValue(R_BoxedValue::Null),
+                            OpCode::RotateRight]),
+               Err(ErrorKind::UnsupportedOpcode(OpCode::RotateRight)));
+}
+
+#[test]
+fn casts_fail_for_values_out_of_range() {
+    let cast = |val, ty| {
+        let program = program(vec![
+            function(0, 0, vec![OpCode::ConstValue(val), OpCode::CheckedCast(ty), OpCode::Return]),
+        ]);
+        run(&program, Heap::default())
+    };
+
+    assert_eq!(cast(R_BoxedValue::Usize(7), NumType::I64), Ok(R_BoxedValue::I64(7)));
+    assert_eq!(cast(R_BoxedValue::I64(7), NumType::Usize), Ok(R_BoxedValue::Usize(7)));
+    assert_eq!(cast(R_BoxedValue::I64(i64::min_value()), NumType::I64),
+               Ok(R_BoxedValue::I64(i64::min_value())));
+    assert_eq!(cast(R_BoxedValue::I64(-1), NumType::Usize),
+               Err(ErrorKind::InvalidCast { value: R_BoxedValue::I64(-1), to: NumType::Usize }));
+    assert_eq!(cast(R_BoxedValue::U64(u64::max_value()), NumType::I64),
+               Err(ErrorKind::InvalidCast {
+                   value: R_BoxedValue::U64(u64::max_value()),
+                   to: NumType::I64,
+               }));
+
+    // a `Cast` without the guard of a trace fails the same
+    let unguarded = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::I64(-1)),
+            OpCode::Cast(NumType::Usize),
+            OpCode::Return,
+        ]),
+    ]);
+    assert_eq!(run(&unguarded, Heap::default()),
+               Err(ErrorKind::InvalidCast { value: R_BoxedValue::I64(-1), to: NumType::Usize }));
+}
+
+#[test]
+fn walks_an_array_with_a_pointer() {
+    // 1: tape = [0; 4]; p = &tape[0]; *p = 5; p += 2; *p = 7; tape[2] + *(p - 2)
+    let program = program(vec![
+        function(0, 2, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::Repeat(4),
+            OpCode::Store(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::Load(0),
+            OpCode::ElemPtr,
+            OpCode::Store(1),
+            OpCode::ConstValue(R_BoxedValue::Usize(5)),
+            OpCode::Load(1),
+            OpCode::DerefStore,
+            OpCode::Load(1),
+            OpCode::MovePtr(2),
+            OpCode::Store(1),
+            OpCode::ConstValue(R_BoxedValue::Usize(7)),
+            OpCode::Load(1),
+            OpCode::DerefStore,
+            OpCode::ConstValue(R_BoxedValue::Usize(2)),
+            OpCode::Load(0),
+            OpCode::GetIndex,
+            OpCode::Load(1),
+            OpCode::MovePtr(-2),
+            OpCode::Deref,
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+    ]);
+
+    assert_eq!(run(&program, Heap::default()), Ok(R_BoxedValue::Usize(12)));
+}
+
+#[test]
+fn reads_the_data_of_the_program() {
+    // 1: DATA[1] + len(DATA), with DATA = [10, 20, 30]
+    let mut program = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::ConstRef(0),
+            OpCode::GetIndex,
+            OpCode::ConstRef(0),
+            OpCode::Len,
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+    ]);
+    let mut array = R_Struct::with_size(3);
+    for idx in 0..3 {
+        array.set(idx, R_BoxedValue::Usize(10 * (idx + 1)));
+    }
+    program.data.push(R_BoxedValue::Struct(array));
+
+    assert_eq!(run(&program, Heap::default()), Ok(R_BoxedValue::Usize(23)));
+}
+
+#[test]
+fn reads_statics_from_the_program() {
+    // 1: STATIC + STATIC, with STATIC = 20
+    let mut program = program(vec![
+        function(0, 0, vec![
+            OpCode::Static(2),
+            OpCode::ConstValue(R_BoxedValue::Static(2)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(20)),
+            OpCode::Use,
+            OpCode::Skip(1),
+            OpCode::Return,
+        ]),
+    ]);
+    program.resolve_statics();
+
+    assert_eq!(program.static_value(2), Some(&R_BoxedValue::Usize(20)));
+    assert_eq!(run(&program, Heap::default()), Ok(R_BoxedValue::Usize(40)));
+}
+
+#[test]
+fn statics_of_the_host_have_no_value() {
+    let program = program(vec![
+        function(0, 0, vec![OpCode::Static(2), OpCode::Return]),
+        function(0, 0, vec![]),
+    ]);
+
+    assert_eq!(run(&program, Heap::default()), Err(ErrorKind::UnknownStatic(2)));
+}
+
+#[test]
+fn calls_with_too_few_arguments_fail() {
+    let program = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        function(2, 2, vec![OpCode::Load(0), OpCode::Load(1), OpCode::BinOp(BinOp::Add), OpCode::Return]),
+    ]);
+
+    assert_eq!(run(&program, Heap::default()),
+               Err(ErrorKind::ArityMismatch { func: 2, expected: 2, found: 1 }));
+}
+
+#[test]
+fn calls_of_the_merge_point_fail() {
+    let program = program(vec![function(0, 0, vec![])]);
+    let code = Code::decode(&program);
+    let mut interp = Interpreter::new(&program, &code, Heap::default());
+    interp.push_frame(CallFrame::new(None, 0));
+    interp.stack.push(StackVal::Owned(R_BoxedValue::Func(MERGE_POINT_FUNC)));
+
+    let err = interp.o_call(1, 4).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::MergePointReentered { func: 1, pc: 4 });
+}
+
+#[test]
+fn frames_carry_the_names_of_their_locals() {
+    let mut program = program(vec![
+        function(0, 0, vec![]),
+        function(1, 2, vec![OpCode::Load(0), OpCode::Return]),
+    ]);
+    program.set_local_name(2, 0, "n");
+    let code = Code::decode(&program);
+    let mut interp = Interpreter::new(&program, &code, Heap::default());
+    interp.push_frame(CallFrame::new(None, 0));
+    interp.stack.push(StackVal::Owned(R_BoxedValue::Usize(3)));
+    interp.stack.push(StackVal::Owned(R_BoxedValue::Func(2)));
+    assert_eq!(interp.o_call(1, 0), Ok(2));
+
+    let frame = &interp.stack_frames[1];
+    assert_eq!(frame.local_name(0), Some("n"));
+    // temporaries have no name
+    assert_eq!(frame.local_name(1), None);
+    assert_eq!(interp.stack_frames[0].local_name(0), None);
+}
+
+// two types whose method 0 is `x * 10` and `x + 1`
+fn dispatching(vtable: usize) -> Program {
+    let mut program = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(2)),
+            OpCode::ConstValue(R_BoxedValue::VTable(vtable)),
+            OpCode::CallVirtual(0),
+            OpCode::Return,
+        ]),
+        function(1, 1, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(10)),
+            OpCode::BinOp(BinOp::Mul),
+            OpCode::Return,
+        ]),
+        function(1, 1, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+    ]);
+    program.vtables = vec![vec![2], vec![3]];
+    program
+}
+
+#[test]
+fn calls_the_method_of_the_vtable() {
+    assert_eq!(run(&dispatching(0), Heap::default()), Ok(R_BoxedValue::Usize(20)));
+    assert_eq!(run(&dispatching(1), Heap::default()), Ok(R_BoxedValue::Usize(3)));
+    assert_eq!(run(&dispatching(2), Heap::default()),
+               Err(ErrorKind::UnknownMethod { vtable: Some(2), method: 0 }));
+}
+
+#[test]
+fn decodes_jumps_to_absolute_positions() {
+    let program = program(vec![
+        function(0, 1, vec![
+            OpCode::ConstValue(R_BoxedValue::Bool(true)),
+            OpCode::SkipIf(2),
+            OpCode::JumpBack(2),
+            OpCode::Load(0),
+            OpCode::JumpBackIf(3),
+            OpCode::Return,
+        ]),
+    ]);
+
+    let code = Code::decode(&program);
+    assert_eq!(code.functions[1].insns, vec![
+        Insn::Const(0),
+        Insn::JumpIf(3),
+        Insn::Jump(0),
+        Insn::Load(0),
+        Insn::JumpIf(1),
+        Insn::Slow,
+    ]);
+    assert_eq!(code.functions[1].consts, vec![R_BoxedValue::Bool(true)]);
+}
+
+#[test]
+fn buffers_keep_their_capacity_for_the_next_interpreter() {
+    let program = program(vec![
+        function(0, 0, vec![OpCode::ConstValue(R_BoxedValue::Usize(42)), OpCode::Return]),
+    ]);
+    let code = Code::decode(&program);
+    let config = InterpreterConfig {
+        stack_capacity: 100,
+        frames_capacity: 10,
+    };
+
+    let mut interp = Interpreter::with_config(&program, &code, Heap::default(), &config);
+    interp.push_frame(CallFrame::new(None, 0));
+    assert_eq!(interp.run(1), Ok(R_BoxedValue::Usize(42)));
+    let buffers = interp.take_buffers();
+
+    let mut interp = Interpreter::new(&program, &code, Heap::default());
+    interp.reuse_buffers(buffers);
+    assert!(interp.stack.is_empty() && interp.stack.capacity() >= 100);
+    assert!(interp.stack_frames.is_empty() && interp.stack_frames.capacity() >= 10);
+    interp.push_frame(CallFrame::new(None, 0));
+    assert_eq!(interp.run(1), Ok(R_BoxedValue::Usize(42)));
+}
+
+#[cfg(feature = "time-travel")]
+#[test]
+fn steps_back_through_the_history() {
+    use grass_core::cell::SharedCell;
+    use grass_core::objects::InstructionPointer;
+
+    let program = summing_loop();
+    let code = Code::decode(&program);
+    let mut interp = Interpreter::new(&program, &code, Heap::default());
+    interp.push_frame(CallFrame::new(None, 2));
+    assert_eq!(interp.run(1), Ok(R_BoxedValue::Usize(15)));
+
+    // back in front of the final `Load(1)`
+    assert_eq!(interp.step_back(2), 2);
+    assert_eq!(interp.ip, InstructionPointer { func: 1, pc: 16 });
+    assert!(interp.stack.is_empty());
+
+    // and in front of the last `Store(0)`, which set n to 0
+    assert_eq!(interp.step_back(5), 5);
+    assert_eq!(interp.ip, InstructionPointer { func: 1, pc: 11 });
+    assert_eq!(interp.stack.len(), 1);
+    assert_eq!(interp.stack_frames[0].locals[0].get(), R_BoxedValue::Usize(1));
+    assert_eq!(interp.stack_frames[0].locals[1].get(), R_BoxedValue::Usize(15));
+
+    interp.travel.set_len(3);
+    assert_eq!(interp.step_back(10), 3);
+    assert_eq!(interp.ip, InstructionPointer { func: 1, pc: 8 });
+    assert!(interp.travel.is_empty());
+}
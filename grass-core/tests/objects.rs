@@ -0,0 +1,101 @@
+extern crate grass_core;
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+#[cfg(feature = "sync-cells")]
+use grass_core::bytecode::OpCode;
+use grass_core::cell::{shared, SharedCell};
+use grass_core::objects::{R_BoxedValue, R_Pointer, R_Struct};
+
+fn hash(val: &R_BoxedValue) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    val.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn pair(first: R_BoxedValue, second: R_BoxedValue) -> R_BoxedValue {
+    let mut tuple = R_Struct::tuple(2);
+    tuple.set(0, first);
+    tuple.set(1, second);
+    R_BoxedValue::Struct(tuple)
+}
+
+// a struct whose only field points to itself
+fn cycle() -> R_BoxedValue {
+    let mut node = R_Struct::tuple(1);
+    let field = node.get(0);
+    field.set(R_BoxedValue::Ptr(R_Pointer::new(field.clone())));
+    R_BoxedValue::Struct(node)
+}
+
+#[test]
+fn structs_compare_by_their_fields() {
+    let config = pair(R_BoxedValue::Usize(3), R_BoxedValue::Bool(true));
+    let same = pair(R_BoxedValue::Usize(3), R_BoxedValue::Bool(true));
+    let other = pair(R_BoxedValue::Usize(4), R_BoxedValue::Bool(true));
+
+    assert_eq!(config, same);
+    assert_eq!(hash(&config), hash(&same));
+    assert!(config != other);
+}
+
+#[test]
+fn nested_structs_compare_deeply() {
+    let inner = || pair(R_BoxedValue::I64(-1), R_BoxedValue::F64(0.0));
+    let outer = pair(inner(), R_BoxedValue::Null);
+    let same = pair(pair(R_BoxedValue::I64(-1), R_BoxedValue::F64(-0.0)), R_BoxedValue::Null);
+
+    assert_eq!(outer, same);
+    assert_eq!(hash(&outer), hash(&same));
+}
+
+#[test]
+fn nan_is_equal_to_itself() {
+    let nan = R_BoxedValue::F64(0.0 / 0.0);
+    assert_eq!(nan, nan.clone());
+    assert_eq!(hash(&nan), hash(&nan.clone()));
+}
+
+#[test]
+fn cyclic_values_compare_and_hash() {
+    let (left, right) = (cycle(), cycle());
+    assert_eq!(left, right);
+    assert_eq!(hash(&left), hash(&right));
+    assert!(left != pair(R_BoxedValue::Null, R_BoxedValue::Null));
+}
+
+#[test]
+fn values_display_readably() {
+    let config = pair(R_BoxedValue::Usize(3), R_BoxedValue::I64(-1));
+    assert_eq!(config.to_string(), "{3, -1i64}");
+    let ptr = R_BoxedValue::Ptr(R_Pointer::new(shared(R_BoxedValue::Bool(true))));
+    assert_eq!(pair(ptr, R_BoxedValue::Null).to_string(), "{&true, null}");
+    assert_eq!(R_BoxedValue::Array(vec![R_BoxedValue::F64(0.5), R_BoxedValue::Unit]).to_string(),
+               "[0.5, ()]");
+}
+
+#[test]
+fn display_is_depth_limited() {
+    let deep = pair(pair(pair(pair(R_BoxedValue::Null, R_BoxedValue::Null), R_BoxedValue::Null),
+                         R_BoxedValue::Null),
+                    R_BoxedValue::Null);
+    assert_eq!(deep.to_string(), "{{{{..}, null}, null}, null}");
+    assert_eq!(deep.pretty(1).to_string(), "{{..}, null}");
+
+    let long = R_BoxedValue::Array((0..20).map(R_BoxedValue::Usize).collect());
+    assert!(long.to_string().ends_with("15, .. 4 more]"), "{}", long);
+}
+
+#[test]
+fn display_cuts_cycles_short() {
+    assert_eq!(cycle().pretty(10).to_string(), "{&<cycle>}");
+}
+
+#[cfg(feature = "sync-cells")]
+#[test]
+fn values_and_opcodes_can_be_shared_between_threads() {
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<R_BoxedValue>();
+    send_sync::<OpCode>();
+}
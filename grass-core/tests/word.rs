@@ -0,0 +1,52 @@
+#![cfg(feature = "word-values")]
+
+extern crate grass_core;
+
+use std::f64;
+
+use grass_core::objects::{R_BoxedValue, R_Struct};
+use grass_core::word::R_Value;
+
+fn round_trip(val: R_BoxedValue) -> Option<R_BoxedValue> {
+    R_Value::pack(&val).map(R_Value::unpack)
+}
+
+#[test]
+fn small_values_survive_packing() {
+    for val in vec![
+        R_BoxedValue::Null,
+        R_BoxedValue::Unit,
+        R_BoxedValue::Bool(true),
+        R_BoxedValue::Bool(false),
+        R_BoxedValue::Usize(0),
+        R_BoxedValue::Usize((1 << 48) - 1),
+        R_BoxedValue::U64(42),
+        R_BoxedValue::I64(-1),
+        R_BoxedValue::I64(-(1 << 47)),
+        R_BoxedValue::Func(3),
+        R_BoxedValue::Static(7),
+        R_BoxedValue::F64(1.5),
+        R_BoxedValue::F64(f64::NEG_INFINITY),
+    ] {
+        assert_eq!(round_trip(val.clone()), Some(val));
+    }
+
+    match round_trip(R_BoxedValue::F64(f64::NAN)) {
+        Some(R_BoxedValue::F64(n)) => assert!(n.is_nan()),
+        other => panic!("expected NaN, got {:?}", other),
+    }
+}
+
+#[test]
+fn large_values_stay_boxed() {
+    assert_eq!(R_Value::pack(&R_BoxedValue::Usize(1 << 48)), None);
+    assert_eq!(R_Value::pack(&R_BoxedValue::I64(1 << 47)), None);
+    assert_eq!(R_Value::pack(&R_BoxedValue::Struct(R_Struct::tuple(1))), None);
+}
+
+#[test]
+fn usizes_are_read_without_unpacking() {
+    assert_eq!(R_Value::pack(&R_BoxedValue::Usize(9)).unwrap().as_usize(), Some(9));
+    assert_eq!(R_Value::pack(&R_BoxedValue::U64(9)).unwrap().as_usize(), None);
+    assert_eq!(R_Value::pack(&R_BoxedValue::F64(9.0)).unwrap().as_usize(), None);
+}
@@ -0,0 +1,47 @@
+//! Overhead of `Driver::merge_point` when the JIT does nothing, i.e. the cost
+//! every host pays on every iteration of its dispatch loop.
+
+#![feature(test)]
+
+extern crate grass;
+extern crate test;
+
+use grass::bc::Program;
+use grass::driver::{Anchoring, Driver};
+use test::Bencher;
+
+#[bench]
+fn merge_point_straight_line(b: &mut Bencher) {
+    let mut driver = Driver::default();
+    // never run, no merge point gets hot
+    let program = driver.register_program(Program::default());
+    let user_program = [0; 64];
+    let mut cell = 0;
+
+    b.iter(|| {
+        // going from the last position back to 0 would be a back-edge
+        driver.reset_counters(program);
+        for pc in 0..user_program.len() {
+            test::black_box(driver.merge_point(program, &user_program, pc, &mut cell));
+        }
+    });
+}
+
+#[bench]
+fn merge_point_counting(b: &mut Bencher) {
+    let mut driver = Driver::default();
+    driver.set_anchoring(Anchoring::Everywhere);
+    // never run, no merge point gets hot
+    let program = driver.register_program(Program::default());
+    let user_program = [0; 64];
+    let mut cell = 0;
+
+    // every position is counted, but none of them is hit often enough to
+    // start recording
+    b.iter(|| {
+        driver.reset_counters(program);
+        for pc in 0..user_program.len() {
+            test::black_box(driver.merge_point(program, &user_program, pc, &mut cell));
+        }
+    });
+}
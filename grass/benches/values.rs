@@ -0,0 +1,53 @@
+//! The boxed value enum against values packed into a word, see
+//! `grass_core::word`. Both sum numbers on an operand stack the way the
+//! interpreter does for `Load, Load, BinOp(Add), Store`.
+//!
+//! ```text
+//! cargo bench --features word-values --bench values
+//! ```
+
+#![feature(test)]
+#![cfg(feature = "word-values")]
+
+extern crate grass;
+extern crate grass_core;
+extern crate test;
+
+use grass::core::objects::R_BoxedValue;
+use grass_core::word::R_Value;
+use test::Bencher;
+
+const N: usize = 1000;
+
+#[bench]
+fn sum_boxed(b: &mut Bencher) {
+    b.iter(|| {
+        let mut stack = Vec::with_capacity(2);
+        let mut sum = R_BoxedValue::Usize(0);
+        for n in 0..N {
+            stack.push(sum);
+            stack.push(R_BoxedValue::Usize(n));
+            sum = match (stack.pop().unwrap(), stack.pop().unwrap()) {
+                (R_BoxedValue::Usize(a), R_BoxedValue::Usize(b)) => R_BoxedValue::Usize(a + b),
+                _ => panic!("expected usizes"),
+            };
+        }
+        test::black_box(sum)
+    });
+}
+
+#[bench]
+fn sum_packed(b: &mut Bencher) {
+    b.iter(|| {
+        let mut stack = Vec::with_capacity(2);
+        let mut sum = R_Value::pack(&R_BoxedValue::Usize(0)).unwrap();
+        for n in 0..N {
+            stack.push(sum);
+            stack.push(R_Value::pack(&R_BoxedValue::Usize(n)).unwrap());
+            let b = stack.pop().unwrap().as_usize().expect("expected usizes");
+            let a = stack.pop().unwrap().as_usize().expect("expected usizes");
+            sum = R_Value::pack(&R_BoxedValue::Usize(a + b)).unwrap();
+        }
+        test::black_box(sum)
+    });
+}
@@ -0,0 +1,124 @@
+//! Offline analysis for front-end authors: where the loops of a translated
+//! interpreter are, and where its merge point belongs.
+//!
+//! Loops are found through their back-edges, jumps to an opcode which
+//! dominates the jump, i.e. which every path from the start of the function
+//! to the jump passes. The body of such a natural loop is its header and every
+//! opcode from which a back-edge can be reached without passing the header.
+
+use std::collections::BTreeSet;
+
+use bc::Program;
+use bc::cfg::Cfg;
+use bc::bytecode::{OpCode, InternalFunc, MERGE_POINT_FUNC};
+use core::objects::{InstructionPointer, R_BoxedValue};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Loop {
+    pub func: usize,
+    /// first opcode of every iteration
+    pub header: usize,
+    /// positions of the jumps back to the header
+    pub back_edges: Vec<usize>,
+    /// positions of the opcodes of the loop, sorted
+    pub body: Vec<usize>,
+    /// number of loops of the function this one is nested in
+    pub depth: usize,
+    /// whether the body calls the merge point
+    pub calls_merge_point: bool,
+}
+
+/// The natural loops of every function of `program`.
+pub fn find_loops(program: &Program) -> Vec<Loop> {
+    (0..program.functions.len()).flat_map(|func| find_loops_in(program, func)).collect()
+}
+
+/// The natural loops of function `func`, outer loops first.
+pub fn find_loops_in(program: &Program, func: usize) -> Vec<Loop> {
+    let opcodes = &program.functions[func].opcodes;
+    if opcodes.is_empty() {
+        return Vec::new();
+    }
+    let cfg = Cfg::new(opcodes);
+    let dom = cfg.dominators();
+
+    // back-edges by their header block, several jumps can close the same loop
+    let mut headers: Vec<(usize, Vec<usize>)> = Vec::new();
+    for (idx, block) in cfg.blocks.iter().enumerate() {
+        if !dom.is_reachable(idx) {
+            continue;
+        }
+        for &succ in &block.successors {
+            if dom.dominates(succ, idx) {
+                match headers.iter().position(|&(header, _)| header == succ) {
+                    Some(pos) => headers[pos].1.push(idx),
+                    None => headers.push((succ, vec![idx])),
+                }
+            }
+        }
+    }
+
+    let mut loops: Vec<Loop> = headers.into_iter()
+        .map(|(header, back_edges)| {
+            let body = loop_body(&cfg, header, &back_edges);
+            let calls_merge_point = body.iter().any(|&pc| calls_merge_point(&opcodes[pc]));
+            Loop {
+                func: func,
+                header: cfg.blocks[header].start,
+                back_edges: back_edges.iter().map(|&block| cfg.blocks[block].last()).collect(),
+                body: body,
+                depth: 0,
+                calls_merge_point: calls_merge_point,
+            }
+        })
+        .collect();
+
+    let depths: Vec<usize> = loops.iter()
+        .map(|inner| {
+            loops.iter()
+                .filter(|outer| outer.header != inner.header && outer.body.contains(&inner.header))
+                .count()
+        })
+        .collect();
+    for (lp, depth) in loops.iter_mut().zip(depths) {
+        lp.depth = depth;
+    }
+    loops.sort_by(|a, b| (a.depth, a.header).cmp(&(b.depth, b.header)));
+    loops
+}
+
+/// Where to call the merge point: at the header of every outermost loop which
+/// doesn't call it yet. In an interpreter that is its dispatch loop. Nothing
+/// is suggested for a program which calls the merge point in its loops
+/// already.
+pub fn suggest_merge_points(program: &Program) -> Vec<InstructionPointer> {
+    find_loops(program)
+        .into_iter()
+        .filter(|lp| lp.depth == 0 && !lp.calls_merge_point)
+        .map(|lp| InstructionPointer { func: lp.func, pc: lp.header })
+        .collect()
+}
+
+fn calls_merge_point(opcode: &OpCode) -> bool {
+    match *opcode {
+        OpCode::ConstValue(R_BoxedValue::Func(idx)) => idx == MERGE_POINT_FUNC,
+        OpCode::InternalFunc(InternalFunc::MergePoint) => true,
+        _ => false,
+    }
+}
+
+// The opcodes of the header and of every block reaching a back-edge without
+// passing it.
+fn loop_body(cfg: &Cfg, header: usize, back_edges: &[usize]) -> Vec<usize> {
+    let mut blocks = BTreeSet::new();
+    blocks.insert(header);
+    let mut todo = back_edges.to_vec();
+    while let Some(block) = todo.pop() {
+        if blocks.insert(block) {
+            todo.extend(cfg.blocks[block].predecessors.iter().cloned());
+        }
+    }
+    blocks.into_iter()
+        .flat_map(|block| cfg.blocks[block].start..cfg.blocks[block].end)
+        .collect()
+}
@@ -0,0 +1,235 @@
+//! Basic blocks, the control flow graph and the dominator tree of a function,
+//! for passes which need more than the jumps of single opcodes.
+//!
+//! Blocks are numbered in the order of their first opcode, block 0 starts
+//! the function. Jumps out of the function are no edges.
+
+use bc::bytecode::OpCode;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    /// position of the first opcode
+    pub start: usize,
+    /// position after the last opcode
+    pub end: usize,
+    pub successors: Vec<usize>,
+    pub predecessors: Vec<usize>,
+}
+
+impl Block {
+    /// position of the last opcode, the one which leaves the block
+    pub fn last(&self) -> usize {
+        self.end - 1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cfg {
+    pub blocks: Vec<Block>,
+    // block of every opcode
+    block_of: Vec<usize>,
+}
+
+/// The positions execution can continue at after `opcodes[pc]`.
+pub fn successors(opcodes: &[OpCode], pc: usize) -> Vec<usize> {
+    let back = |n: usize| pc.checked_sub(n);
+    let targets = match opcodes[pc] {
+        OpCode::Skip(n) => vec![Some(pc + n)],
+        OpCode::JumpBack(n) => vec![back(n)],
+        OpCode::SkipIf(n) => vec![Some(pc + 1), Some(pc + n)],
+        OpCode::JumpBackIf(n) => vec![Some(pc + 1), back(n)],
+        OpCode::Return | OpCode::Panic => vec![],
+        _ => vec![Some(pc + 1)],
+    };
+    targets.into_iter()
+        .filter_map(|target| target)
+        .filter(|&target| target < opcodes.len())
+        .collect()
+}
+
+// Whether execution can only continue right after `opcodes[pc]`.
+fn falls_through(opcode: &OpCode) -> bool {
+    match *opcode {
+        OpCode::Skip(_) | OpCode::JumpBack(_) | OpCode::SkipIf(_) | OpCode::JumpBackIf(_) |
+        OpCode::Return | OpCode::Panic => false,
+        _ => true,
+    }
+}
+
+impl Cfg {
+    pub fn new(opcodes: &[OpCode]) -> Self {
+        // opcodes which start a block: jump targets and what follows a jump
+        let mut leader = vec![false; opcodes.len()];
+        for pc in 0..opcodes.len() {
+            if pc == 0 || !falls_through(&opcodes[pc - 1]) {
+                leader[pc] = true;
+            }
+            if !falls_through(&opcodes[pc]) {
+                for target in successors(opcodes, pc) {
+                    leader[target] = true;
+                }
+            }
+        }
+
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut block_of = Vec::with_capacity(opcodes.len());
+        for pc in 0..opcodes.len() {
+            if leader[pc] {
+                blocks.push(Block {
+                    start: pc,
+                    end: pc,
+                    successors: Vec::new(),
+                    predecessors: Vec::new(),
+                });
+            }
+            blocks.last_mut().unwrap().end = pc + 1;
+            block_of.push(blocks.len() - 1);
+        }
+
+        for idx in 0..blocks.len() {
+            let last = blocks[idx].last();
+            for target in successors(opcodes, last) {
+                let succ = block_of[target];
+                if !blocks[idx].successors.contains(&succ) {
+                    blocks[idx].successors.push(succ);
+                    blocks[succ].predecessors.push(idx);
+                }
+            }
+        }
+
+        Cfg {
+            blocks: blocks,
+            block_of: block_of,
+        }
+    }
+
+    /// The block containing the opcode at `pc`.
+    pub fn block_of(&self, pc: usize) -> usize {
+        self.block_of[pc]
+    }
+
+    /// The blocks reachable from the start, each before its successors
+    /// unless the edge closes a loop.
+    pub fn reverse_postorder(&self) -> Vec<usize> {
+        let mut order = Vec::new();
+        if self.blocks.is_empty() {
+            return order;
+        }
+        let mut visited = vec![false; self.blocks.len()];
+        // blocks on the path with the index of their next successor
+        let mut stack = vec![(0, 0)];
+        visited[0] = true;
+        while !stack.is_empty() {
+            let (block, next) = *stack.last().unwrap();
+            match self.blocks[block].successors.get(next) {
+                Some(&succ) => {
+                    stack.last_mut().unwrap().1 += 1;
+                    if !visited[succ] {
+                        visited[succ] = true;
+                        stack.push((succ, 0));
+                    }
+                }
+                None => {
+                    order.push(block);
+                    stack.pop();
+                }
+            }
+        }
+        order.reverse();
+        order
+    }
+
+    /// See Cooper, Harvey and Kennedy, "A Simple, Fast Dominance Algorithm".
+    pub fn dominators(&self) -> DominatorTree {
+        let order = self.reverse_postorder();
+        let mut rank = vec![0; self.blocks.len()];
+        for (idx, &block) in order.iter().enumerate() {
+            rank[block] = idx;
+        }
+
+        let mut idom: Vec<Option<usize>> = vec![None; self.blocks.len()];
+        if let Some(&start) = order.first() {
+            idom[start] = Some(start);
+        }
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &self.blocks[block].predecessors {
+                    if idom[pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, &rank, pred, other),
+                    });
+                }
+                if new_idom.is_some() && idom[block] != new_idom {
+                    idom[block] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        DominatorTree { idom: idom }
+    }
+}
+
+fn intersect(idom: &[Option<usize>], rank: &[usize], mut a: usize, mut b: usize) -> usize {
+    while a != b {
+        while rank[a] > rank[b] {
+            a = idom[a].unwrap();
+        }
+        while rank[b] > rank[a] {
+            b = idom[b].unwrap();
+        }
+    }
+    a
+}
+
+/// Which blocks every path from the start to a block passes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DominatorTree {
+    // the start is its own immediate dominator, unreachable blocks have none
+    idom: Vec<Option<usize>>,
+}
+
+impl DominatorTree {
+    /// The parent of `block` in the tree, `None` for the start and for
+    /// blocks which can't be reached.
+    pub fn immediate_dominator(&self, block: usize) -> Option<usize> {
+        match self.idom[block] {
+            Some(parent) if parent != block => Some(parent),
+            _ => None,
+        }
+    }
+
+    pub fn is_reachable(&self, block: usize) -> bool {
+        self.idom[block].is_some()
+    }
+
+    /// Whether every path from the start to `block` passes `dominator`.
+    /// Every reachable block dominates itself.
+    pub fn dominates(&self, dominator: usize, mut block: usize) -> bool {
+        if !self.is_reachable(block) {
+            return false;
+        }
+        loop {
+            if block == dominator {
+                return true;
+            }
+            match self.immediate_dominator(block) {
+                Some(parent) => block = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// The blocks `block` is the immediate dominator of.
+    pub fn children(&self, block: usize) -> Vec<usize> {
+        (0..self.idom.len())
+            .filter(|&child| self.immediate_dominator(child) == Some(block))
+            .collect()
+    }
+}
@@ -1,9 +1,17 @@
 
 
+#[cfg(not(target_arch = "wasm32"))]
 pub mod translate;
 
-pub mod bytecode;
-mod opt;
+pub mod analysis;
+pub mod cfg;
+pub mod opt;
+pub mod symbolic;
+pub mod verify;
 
+pub use grass_core::{bytecode, decode, program};
 
-pub use self::translate::Context;
\ No newline at end of file
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::translate::Context;
+pub use self::program::Program;
\ No newline at end of file
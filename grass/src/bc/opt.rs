@@ -1,7 +1,13 @@
 
+use std::cmp;
+use std::mem;
 use std::collections::{HashMap, HashSet};
 
-use super::bytecode::OpCode;
+use super::Program;
+use super::program::AliasClasses;
+use super::bytecode::{OpCode, BinOp, NumType};
+use core::cell::SharedCell;
+use core::objects::{R_BoxedValue, R_Function};
 
 
 pub fn eliminate_unused_vars(stream: &Vec<OpCode>) -> Vec<OpCode> {
@@ -57,4 +63,1443 @@ pub fn eliminate_unused_vars(stream: &Vec<OpCode>) -> Vec<OpCode> {
     }
 
     new
-}
\ No newline at end of file
+}
+
+/// Replace calls of small functions by their body.
+///
+/// Functions with at most `max_size` opcodes which don't call other
+/// functions themselves get inlined wherever they are called directly, i.e.
+/// through `ConstValue(Func(_)), Call`. Each inlined call gets its own set of
+/// locals in the caller.
+pub fn inline_calls(program: &mut Program, max_size: usize) {
+    let callees: Vec<Option<R_Function>> = program.functions
+        .iter()
+        .map(|func| if is_inlinable(func, max_size) { Some(func.clone()) } else { None })
+        .collect();
+
+    for idx in 0..program.functions.len() {
+        let positions = inline_into(&mut program.functions[idx], &callees);
+        if idx == program.entry.0 {
+            program.entry.1 = positions[program.entry.1];
+        }
+    }
+}
+
+fn is_inlinable(func: &R_Function, max_size: usize) -> bool {
+    !func.opcodes.is_empty() && func.opcodes.len() <= max_size &&
+    func.opcodes.iter().all(|oc| {
+        match *oc {
+            OpCode::Call | OpCode::CallVirtual(_) | OpCode::FlatCall(..) |
+            OpCode::RunTrace(_) => false,
+            _ => true,
+        }
+    }) &&
+    // a call drops what the callee leaves below its result, inlined code
+    // can't
+    return_depth(func).map_or(false, |depth| depth <= 1)
+}
+
+// Number of values on the stack at the first `Return` of `func`, following
+// the opcodes on the straight line from its start.
+fn return_depth(func: &R_Function) -> Option<usize> {
+    let mut depth = 0;
+    for oc in &func.opcodes {
+        if *oc == OpCode::Return {
+            return Some(depth);
+        }
+        let (pops, pushes) = match oc.stack_effect() {
+            Some(effect) => effect,
+            None => return None,
+        };
+        if pops > depth {
+            return None;
+        }
+        depth = depth - pops + pushes;
+    }
+    None
+}
+
+// Inline the calls of `callees` in `func`. Returns the new position of every
+// opcode, and of the end of the function.
+fn inline_into(func: &mut R_Function, callees: &[Option<R_Function>]) -> Vec<usize> {
+    let old = func.opcodes.clone();
+    let mut new = Vec::with_capacity(old.len());
+    let mut positions = Vec::with_capacity(old.len() + 1);
+    // jumps of the caller, as (new position, old target)
+    let mut jumps = Vec::new();
+
+    let mut pc = 0;
+    while pc < old.len() {
+        positions.push(new.len());
+
+        let callee = match (&old[pc], old.get(pc + 1)) {
+            (&OpCode::ConstValue(R_BoxedValue::Func(idx)), Some(&OpCode::Call)) => {
+                callees.get(idx).and_then(|callee| callee.as_ref())
+            }
+            _ => None,
+        };
+
+        if let Some(callee) = callee {
+            positions.push(new.len());
+            let base = func.locals_cnt;
+            func.locals_cnt += callee.locals_cnt;
+
+            // the arguments are on the stack, the last one on top
+            for arg in (0..callee.args_cnt).rev() {
+                new.push(OpCode::Use);
+                new.push(OpCode::Store(base + arg));
+            }
+
+            let len = callee.opcodes.len();
+            for (pos, oc) in callee.opcodes.iter().enumerate() {
+                new.push(match *oc {
+                    OpCode::Load(n) => OpCode::Load(base + n),
+                    OpCode::Store(n) => OpCode::Store(base + n),
+                    // the result stays on the stack
+                    OpCode::Return => OpCode::Skip(len - pos),
+                    // jumps within the callee keep their distance
+                    _ => oc.clone(),
+                });
+            }
+            // what a call of a function without a value returns
+            if return_depth(callee) == Some(0) {
+                new.push(OpCode::ConstValue(R_BoxedValue::Unit));
+            }
+
+            pc += 2;
+            continue;
+        }
+
+        match old[pc] {
+            OpCode::Skip(n) | OpCode::SkipIf(n) => jumps.push((new.len(), pc + n)),
+            OpCode::JumpBack(n) | OpCode::JumpBackIf(n) => jumps.push((new.len(), pc - n)),
+            _ => (),
+        }
+        new.push(old[pc].clone());
+        pc += 1;
+    }
+    positions.push(new.len());
+
+    for (pos, target) in jumps {
+        let target = positions[target];
+        let jump = match new[pos] {
+            OpCode::Skip(_) => OpCode::Skip(target - pos),
+            OpCode::SkipIf(_) => OpCode::SkipIf(target - pos),
+            OpCode::JumpBack(_) => OpCode::JumpBack(pos - target),
+            OpCode::JumpBackIf(_) => OpCode::JumpBackIf(pos - target),
+            _ => unreachable!(),
+        };
+        new[pos] = jump;
+    }
+
+    func.opcodes = new;
+    positions
+}
+
+/// Shorten the control flow of every function: jumps to unconditional
+/// jumps go to the final target directly, conditional jumps over constant
+/// booleans become unconditional, and code which can't be reached or does
+/// nothing is removed.
+pub fn thread_jumps(program: &mut Program) {
+    for idx in 0..program.functions.len() {
+        let positions = thread_jumps_in(&mut program.functions[idx].opcodes);
+        if idx == program.entry.0 {
+            program.entry.1 = positions[program.entry.1];
+        }
+    }
+}
+
+fn jump_target(opcodes: &[OpCode], pc: usize) -> Option<usize> {
+    match opcodes[pc] {
+        OpCode::Skip(n) | OpCode::SkipIf(n) => Some(pc + n),
+        OpCode::JumpBack(n) | OpCode::JumpBackIf(n) => Some(pc - n),
+        _ => None,
+    }
+}
+
+fn successors(opcodes: &[OpCode], pc: usize) -> Vec<usize> {
+    match opcodes[pc] {
+        OpCode::Skip(_) | OpCode::JumpBack(_) => vec![jump_target(opcodes, pc).unwrap()],
+        OpCode::SkipIf(_) | OpCode::JumpBackIf(_) => {
+            vec![jump_target(opcodes, pc).unwrap(), pc + 1]
+        }
+        OpCode::Return | OpCode::Panic => vec![],
+        _ => vec![pc + 1],
+    }
+}
+
+// Follow unconditional jumps from `pc`.
+fn final_target(opcodes: &[OpCode], mut pc: usize) -> usize {
+    let mut seen = HashSet::new();
+    while pc < opcodes.len() && seen.insert(pc) {
+        match opcodes[pc] {
+            OpCode::Skip(n) => pc += n,
+            OpCode::JumpBack(n) => pc -= n,
+            _ => break,
+        }
+    }
+    pc
+}
+
+fn thread_jumps_in(opcodes: &mut Vec<OpCode>) -> Vec<usize> {
+    let targets: HashSet<usize> = (0..opcodes.len())
+        .filter_map(|pc| jump_target(opcodes, pc))
+        .collect();
+
+    // constant conditions, unless the condition is reached by a jump too
+    for pc in 1..opcodes.len() {
+        if targets.contains(&pc) {
+            continue;
+        }
+        let value = match opcodes[pc - 1] {
+            OpCode::ConstValue(R_BoxedValue::Bool(value)) => value,
+            _ => continue,
+        };
+        let jump = match (opcodes[pc].clone(), value) {
+            (OpCode::SkipIf(n), true) => OpCode::Skip(n),
+            (OpCode::JumpBackIf(n), true) => OpCode::JumpBack(n),
+            (OpCode::SkipIf(_), false) | (OpCode::JumpBackIf(_), false) => OpCode::Noop,
+            _ => continue,
+        };
+        opcodes[pc - 1] = OpCode::Noop;
+        opcodes[pc] = jump;
+    }
+
+    // jump chains
+    for pc in 0..opcodes.len() {
+        let target = match jump_target(opcodes, pc) {
+            Some(target) => target,
+            None => continue,
+        };
+        let last = final_target(opcodes, target);
+        if last == target || last == pc {
+            continue;
+        }
+        let jump = match (opcodes[pc].clone(), last > pc) {
+            (OpCode::Skip(_), true) | (OpCode::JumpBack(_), true) => OpCode::Skip(last - pc),
+            (OpCode::Skip(_), false) | (OpCode::JumpBack(_), false) => OpCode::JumpBack(pc - last),
+            (OpCode::SkipIf(_), true) | (OpCode::JumpBackIf(_), true) => OpCode::SkipIf(last - pc),
+            (_, false) => OpCode::JumpBackIf(pc - last),
+            _ => unreachable!(),
+        };
+        opcodes[pc] = jump;
+    }
+
+    // drop unreachable opcodes, and those without effect
+    let mut reachable = vec![false; opcodes.len()];
+    let mut todo = if opcodes.is_empty() { vec![] } else { vec![0] };
+    while let Some(pc) = todo.pop() {
+        if pc >= opcodes.len() || reachable[pc] {
+            continue;
+        }
+        reachable[pc] = true;
+        todo.extend(successors(opcodes, pc));
+    }
+    let keep: Vec<bool> = (0..opcodes.len())
+        .map(|pc| {
+            reachable[pc] &&
+            match opcodes[pc] {
+                OpCode::Noop | OpCode::Skip(1) => false,
+                _ => true,
+            }
+        })
+        .collect();
+
+    remove_opcodes(opcodes, &keep)
+}
+
+/// Replace loops which copy a struct into another, or fill one with a value,
+/// field by field by a single `CopyRange` or `Fill`. Recognized are loops of
+/// the shape `while i < n { dst[i] = src[i]; i += 1 }` and
+/// `while i < n { dst[i] = val; i += 1 }`, where `val` is a constant or a
+/// local other than `i`, which are only entered from the top.
+pub fn fuse_copy_loops(program: &mut Program) {
+    for idx in 0..program.functions.len() {
+        let positions = fuse_copy_loops_in(&mut program.functions[idx].opcodes);
+        if idx == program.entry.0 {
+            program.entry.1 = positions[program.entry.1];
+        }
+    }
+}
+
+fn fuse_copy_loops_in(opcodes: &mut Vec<OpCode>) -> Vec<usize> {
+    let targets: Vec<usize> = (0..opcodes.len())
+        .filter_map(|pc| jump_target(opcodes, pc))
+        .collect();
+
+    // the fused loops by their first opcode, with their last
+    let mut loops = HashMap::new();
+    for end in 0..opcodes.len() {
+        let start = match opcodes[end] {
+            OpCode::JumpBack(n) if n <= end => end - n,
+            _ => continue,
+        };
+        if targets.iter().any(|&target| target > start && target <= end) {
+            continue;
+        }
+        if let Some(fused) = fused_loop(&opcodes[start..end + 1]) {
+            loops.insert(start, (end, fused));
+        }
+    }
+
+    // nothing jumps into a fused loop, its opcodes all move to its start
+    let mut positions = Vec::with_capacity(opcodes.len() + 1);
+    let mut pc = 0;
+    while pc < opcodes.len() {
+        let len = positions.last().map_or(0, |&pos| pos + 1);
+        match loops.get(&pc) {
+            Some(&(end, ref fused)) => {
+                for _ in pc..end + 1 {
+                    positions.push(len);
+                }
+                // the next opcode comes after all of the fused loop
+                *positions.last_mut().unwrap() = len + fused.len() - 1;
+                pc = end + 1;
+            }
+            None => {
+                positions.push(len);
+                pc += 1;
+            }
+        }
+    }
+    let end = positions.last().map_or(0, |&pos| pos + 1);
+    positions.push(end);
+
+    let old = mem::replace(opcodes, Vec::new());
+    let mut pc = 0;
+    while pc < old.len() {
+        if let Some((end, fused)) = loops.remove(&pc) {
+            opcodes.extend(fused);
+            pc = end + 1;
+            continue;
+        }
+        let pos = positions[pc];
+        opcodes.push(match (&old[pc], jump_target(&old, pc).map(|target| positions[target])) {
+            (&OpCode::Skip(_), Some(target)) => OpCode::Skip(target - pos),
+            (&OpCode::SkipIf(_), Some(target)) => OpCode::SkipIf(target - pos),
+            (&OpCode::JumpBack(_), Some(target)) => OpCode::JumpBack(pos - target),
+            (&OpCode::JumpBackIf(_), Some(target)) => OpCode::JumpBackIf(pos - target),
+            (oc, _) => oc.clone(),
+        });
+        pc += 1;
+    }
+
+    positions
+}
+
+// The single opcode version of `lp`, a loop which ends in the jump back to
+// its first opcode.
+fn fused_loop(lp: &[OpCode]) -> Option<Vec<OpCode>> {
+    let len = lp.len();
+    if len < 13 {
+        return None;
+    }
+
+    // if i >= n, leave the loop
+    let (i, n) = match (&lp[0], &lp[1], &lp[2], &lp[3]) {
+        (&OpCode::Load(i), &OpCode::Load(n), &OpCode::BinOp(BinOp::Ge), &OpCode::SkipIf(skip))
+            if skip == len - 3 && i != n => (i, n),
+        _ => return None,
+    };
+    // i += 1
+    match (&lp[len - 5], &lp[len - 4], &lp[len - 3], &lp[len - 2]) {
+        (&OpCode::Load(load),
+         &OpCode::ConstValue(R_BoxedValue::Usize(1)),
+         &OpCode::BinOp(BinOp::Add),
+         &OpCode::Store(store)) if load == i && store == i => (),
+        _ => return None,
+    }
+
+    let body = &lp[4..len - 5];
+    // n - i
+    let mut fused = vec![OpCode::Load(n), OpCode::Load(i), OpCode::BinOp(BinOp::Sub)];
+    if body.len() == 6 {
+        // dst[i] = src[i]
+        let (src, dst) = match (&body[0], &body[1], &body[2], &body[3], &body[4], &body[5]) {
+            (&OpCode::Load(a),
+             &OpCode::Load(src),
+             &OpCode::GetIndex,
+             &OpCode::Load(b),
+             &OpCode::Load(dst),
+             &OpCode::AssignIndex) if a == i && b == i && src != i && dst != i => (src, dst),
+            _ => return None,
+        };
+        fused.extend(vec![OpCode::Load(i), OpCode::Load(src), OpCode::Load(i), OpCode::Load(dst),
+                          OpCode::CopyRange]);
+    } else if body.len() == 4 {
+        // dst[i] = val
+        let constant = match body[0] {
+            OpCode::Load(val) => val != i,
+            OpCode::ConstValue(_) => true,
+            _ => false,
+        };
+        let dst = match (&body[1], &body[2], &body[3]) {
+            (&OpCode::Load(a), &OpCode::Load(dst), &OpCode::AssignIndex)
+                if constant && a == i && dst != i => dst,
+            _ => return None,
+        };
+        fused.insert(0, body[0].clone());
+        fused.extend(vec![OpCode::Load(i), OpCode::Load(dst), OpCode::Fill]);
+    } else {
+        return None;
+    }
+    // the loop leaves i at n
+    fused.extend(vec![OpCode::Load(n), OpCode::Use, OpCode::Store(i)]);
+
+    // the check stays, n - i must not overflow
+    let mut check = lp[..3].to_vec();
+    check.push(OpCode::SkipIf(fused.len() + 1));
+    check.extend(fused);
+    Some(check)
+}
+
+/// Replace branches which pick the smaller or larger of two locals by a
+/// `BinOp` of `Min` or `Max`, so that traces of them need no guard.
+/// Recognized is the shape `d = if a < b { a } else { b }`, with any of
+/// `<`, `<=`, `>` and `>=` and either local in either branch:
+///
+/// ```text
+/// Load(a) Load(b) BinOp(Lt) SkipIf(5)
+/// Load(b) Use Store(d) Skip(4)
+/// Load(a) Use Store(d)
+/// ```
+pub fn select_min_max(program: &mut Program) {
+    for idx in 0..program.functions.len() {
+        let positions = select_min_max_in(&mut program.functions[idx].opcodes);
+        if idx == program.entry.0 {
+            program.entry.1 = positions[program.entry.1];
+        }
+    }
+}
+
+fn select_min_max_in(opcodes: &mut Vec<OpCode>) -> Vec<usize> {
+    let jumps: Vec<(usize, usize)> = (0..opcodes.len())
+        .filter_map(|pc| jump_target(opcodes, pc).map(|target| (pc, target)))
+        .collect();
+
+    let mut keep = vec![true; opcodes.len()];
+    let mut pc = 0;
+    while pc + 11 <= opcodes.len() {
+        let end = pc + 11;
+        // the branches are only entered from the comparison
+        let entered = jumps.iter()
+            .any(|&(from, to)| (from < pc || from >= end) && to > pc && to < end);
+        let select = if entered { None } else { selected(&opcodes[pc..end]) };
+        match select {
+            Some((kind, dst)) => {
+                opcodes[pc + 2] = OpCode::BinOp(kind);
+                opcodes[pc + 3] = OpCode::Store(dst);
+                for kept in &mut keep[pc + 4..end] {
+                    *kept = false;
+                }
+                pc = end;
+            }
+            None => pc += 1,
+        }
+    }
+
+    remove_opcodes(opcodes, &keep)
+}
+
+// The `BinOp` and the local the compare-select `ops` assign, see
+// `select_min_max`.
+fn selected(ops: &[OpCode]) -> Option<(BinOp, usize)> {
+    let (a, b, cmp) = match (&ops[0], &ops[1], &ops[2], &ops[3]) {
+        (&OpCode::Load(a), &OpCode::Load(b), &OpCode::BinOp(cmp), &OpCode::SkipIf(5)) => {
+            (a, b, cmp)
+        }
+        _ => return None,
+    };
+    let (other, dst) = match (&ops[4], &ops[5], &ops[6], &ops[7]) {
+        (&OpCode::Load(other), &OpCode::Use, &OpCode::Store(dst), &OpCode::Skip(4)) => {
+            (other, dst)
+        }
+        _ => return None,
+    };
+    let taken = match (&ops[8], &ops[9], &ops[10]) {
+        (&OpCode::Load(taken), &OpCode::Use, &OpCode::Store(store)) if store == dst => taken,
+        _ => return None,
+    };
+
+    // the branch taken if `a` compares as `cmp` to `b`
+    let takes_a = match (taken, other) {
+        (taken, other) if taken == a && other == b => true,
+        (taken, other) if taken == b && other == a => false,
+        _ => return None,
+    };
+    // ties don't matter, both are the same then
+    let smaller = match cmp {
+        BinOp::Lt | BinOp::Le => takes_a,
+        BinOp::Gt | BinOp::Ge => !takes_a,
+        _ => return None,
+    };
+    Some((if smaller { BinOp::Min } else { BinOp::Max }, dst))
+}
+
+// Remove the opcodes which aren't kept, adjusting the jumps of the others.
+// Returns the new position of every opcode, and of the end of the function.
+fn remove_opcodes(opcodes: &mut Vec<OpCode>, keep: &[bool]) -> Vec<usize> {
+    let mut positions = Vec::with_capacity(opcodes.len() + 1);
+    let mut len = 0;
+    for &kept in keep {
+        positions.push(len);
+        if kept {
+            len += 1;
+        }
+    }
+    positions.push(len);
+
+    let old = opcodes.clone();
+    opcodes.clear();
+    for (pc, oc) in old.iter().enumerate() {
+        if !keep[pc] {
+            continue;
+        }
+        let pos = positions[pc];
+        opcodes.push(match (oc, jump_target(&old, pc).map(|target| positions[target])) {
+            (&OpCode::Skip(_), Some(target)) => OpCode::Skip(target - pos),
+            (&OpCode::SkipIf(_), Some(target)) => OpCode::SkipIf(target - pos),
+            (&OpCode::JumpBack(_), Some(target)) => OpCode::JumpBack(pos - target),
+            (&OpCode::JumpBackIf(_), Some(target)) => OpCode::JumpBackIf(pos - target),
+            _ => oc.clone(),
+        });
+    }
+
+    positions
+}
+
+/// Let locals which are never live at the same time share a slot, and drop
+/// those which aren't used at all. Returns for every function the new slot of
+/// each of its old locals, `None` for dropped ones, to map them back when
+/// debugging.
+///
+/// Locals whose cell may outlive the value read from it, e.g. because a
+/// reference to it is taken, keep a slot of their own, and so do arguments,
+/// whose cells belong to the caller. The entry function is left alone, the
+/// driver reads its locals at the merge point.
+pub fn compact_locals(program: &mut Program) -> Vec<Vec<Option<usize>>> {
+    let entry = program.entry.0;
+    program.functions
+        .iter_mut()
+        .enumerate()
+        .map(|(idx, func)| {
+            if idx == entry || func.opcodes.is_empty() {
+                (0..func.locals_cnt).map(Some).collect()
+            } else {
+                compact_locals_in(func)
+            }
+        })
+        .collect()
+}
+
+// The opcode which consumes the cell pushed by the `Load` at `pc`, if it only
+// reads or writes its value there.
+fn load_consumer(opcodes: &[OpCode], pc: usize) -> Option<usize> {
+    let mut depth = 1;
+    for (consumer, oc) in opcodes.iter().enumerate().skip(pc + 1) {
+        let (pops, pushes) = match oc.stack_effect() {
+            Some(effect) => effect,
+            None => return None,
+        };
+        if pops >= depth {
+            return match *oc {
+                OpCode::Use | OpCode::BinOp(_) | OpCode::TypedBinOp(..) | OpCode::CheckedBinOp(_) |
+                OpCode::WrappingBinOp(_) | OpCode::SaturatingBinOp(_) |
+                OpCode::Not | OpCode::Neg | OpCode::Abs | OpCode::Len | OpCode::Store(_) |
+                OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros |
+                OpCode::RotateLeft | OpCode::RotateRight |
+                OpCode::CheckedCast(_) | OpCode::Cast(_) |
+                OpCode::Pop |
+                OpCode::SkipIf(_) | OpCode::JumpBackIf(_) |
+                OpCode::TupleSet(_) | OpCode::AssignIndex | OpCode::DerefStore => Some(consumer),
+                _ => None,
+            };
+        }
+        // the value has to be consumed on the straight line
+        if jump_target(opcodes, consumer).is_some() {
+            return None;
+        }
+        depth = depth - pops + pushes;
+    }
+    None
+}
+
+fn compact_locals_in(func: &mut R_Function) -> Vec<Option<usize>> {
+    let cnt = func.locals_cnt;
+    let len = func.opcodes.len();
+
+    // locals read and written by every opcode; a loaded local is read up to
+    // where its cell is consumed
+    let mut uses = vec![Vec::new(); len];
+    let mut defs = vec![None; len];
+    let mut used = vec![false; cnt];
+    let mut pinned = vec![false; cnt];
+    for (pc, oc) in func.opcodes.iter().enumerate() {
+        match *oc {
+            OpCode::Load(n) => {
+                used[n] = true;
+                match load_consumer(&func.opcodes, pc) {
+                    Some(consumer) => {
+                        for read in pc..consumer + 1 {
+                            uses[read].push(n);
+                        }
+                    }
+                    None => pinned[n] = true,
+                }
+            }
+            OpCode::Store(n) => {
+                used[n] = true;
+                defs[pc] = Some(n);
+            }
+            _ => (),
+        }
+    }
+
+    // backwards liveness until nothing changes
+    let mut live_in: Vec<HashSet<usize>> = vec![HashSet::new(); len + 1];
+    let mut live_out: Vec<HashSet<usize>> = vec![HashSet::new(); len];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for pc in (0..len).rev() {
+            let mut out = HashSet::new();
+            for succ in successors(&func.opcodes, pc) {
+                out.extend(live_in[cmp::min(succ, len)].iter().cloned());
+            }
+            let mut live = out.clone();
+            if let Some(def) = defs[pc] {
+                live.remove(&def);
+            }
+            live.extend(uses[pc].iter().cloned());
+            if live != live_in[pc] {
+                live_in[pc] = live;
+                changed = true;
+            }
+            live_out[pc] = out;
+        }
+    }
+
+    // a write conflicts with every other local which is still needed, and
+    // the arguments and locals read before being written are all set when
+    // the function is entered
+    let mut conflicts = vec![HashSet::new(); cnt];
+    for pc in 0..len {
+        if let Some(def) = defs[pc] {
+            for &other in live_out[pc].iter().filter(|&&other| other != def) {
+                conflicts[def].insert(other);
+                conflicts[other].insert(def);
+            }
+        }
+    }
+    let entering: Vec<usize> = (0..func.args_cnt).chain(live_in[0].iter().cloned()).collect();
+    for &a in &entering {
+        for &b in entering.iter().filter(|&&b| b != a) {
+            conflicts[a].insert(b);
+        }
+    }
+
+    // the arguments stay where the caller puts them, the other locals take
+    // the first slot none of their conflicts has. Slots of arguments are
+    // never shared, calls pass the cells of the caller, so a write to a dead
+    // argument would change the variable of the caller.
+    let mut slots: Vec<Option<usize>> = vec![None; cnt];
+    for arg in 0..func.args_cnt {
+        slots[arg] = Some(arg);
+    }
+    let mut slots_cnt = func.args_cnt;
+    for local in func.args_cnt..cnt {
+        if !used[local] {
+            continue;
+        }
+        let slot = (func.args_cnt..slots_cnt)
+            .find(|&slot| {
+                !pinned[local] &&
+                (0..cnt).all(|other| {
+                    slots[other] != Some(slot) ||
+                    !pinned[other] && !conflicts[local].contains(&other)
+                })
+            })
+            .unwrap_or(slots_cnt);
+        if slot == slots_cnt {
+            slots_cnt += 1;
+        }
+        slots[local] = Some(slot);
+    }
+
+    for oc in func.opcodes.iter_mut() {
+        *oc = match *oc {
+            OpCode::Load(n) => OpCode::Load(slots[n].unwrap()),
+            OpCode::Store(n) => OpCode::Store(slots[n].unwrap()),
+            _ => continue,
+        };
+    }
+    func.locals_cnt = slots_cnt;
+    slots
+}
+
+/// How many rewrites the passes over one trace may still apply. Running out
+/// stops optimizing the trace midway, which narrows a miscompile down to the
+/// rewrite introducing it, see `bisect_fuel`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Fuel {
+    /// `None` for no limit
+    left: Option<usize>,
+    used: usize,
+}
+
+impl Fuel {
+    pub fn new(limit: Option<usize>) -> Self {
+        Fuel {
+            left: limit,
+            used: 0,
+        }
+    }
+
+    /// Whether one more rewrite may be applied, which is then counted.
+    pub fn take(&mut self) -> bool {
+        match self.left {
+            Some(0) => return false,
+            Some(ref mut left) => *left -= 1,
+            None => (),
+        }
+        self.used += 1;
+        true
+    }
+
+    /// rewrites applied so far
+    pub fn used(&self) -> usize {
+        self.used
+    }
+}
+
+/// The smallest fuel up to `max` with which `passes` fails, `None` if it
+/// passes with `max`. `passes` runs a correctness test with the optimizer
+/// limited to the fuel given; the rewrite breaking it is the last one the
+/// returned fuel allows. Once the test failed with some fuel it has to fail
+/// with more as well.
+pub fn bisect_fuel<F: FnMut(usize) -> bool>(max: usize, mut passes: F) -> Option<usize> {
+    if passes(max) {
+        return None;
+    }
+    if !passes(0) {
+        return Some(0);
+    }
+    // the test passes with `good` and fails with `bad`
+    let (mut good, mut bad) = (0, max);
+    while bad - good > 1 {
+        let mid = good + (bad - good) / 2;
+        if passes(mid) {
+            good = mid;
+        } else {
+            bad = mid;
+        }
+    }
+    Some(bad)
+}
+
+/// Cut `trace` after the first opcode it never continues from: a `Panic`, a
+/// `Breakpoint`, a `LoopBack`, or a guard which fails in every run because
+/// it checks a constant. Only done if no jump leads past that opcode.
+/// Returns the number of removed opcodes.
+pub fn prune_trace(trace: &mut Vec<OpCode>, fuel: &mut Fuel) -> usize {
+    let jumps: Vec<(usize, usize)> = (0..trace.len())
+        .filter_map(|pc| jump_target(trace, pc).map(|target| (pc, target)))
+        .collect();
+    // a constant is only known to be checked if the guard isn't jumped to
+    let entered = |pc: usize| jumps.iter().any(|&(_, target)| target == pc);
+
+    let exit = (0..trace.len()).find(|&pc| {
+        let exits = match trace[pc] {
+            OpCode::Panic | OpCode::Breakpoint(_) | OpCode::LoopBack(_) => true,
+            OpCode::Guard(ref guard) if !entered(pc) => {
+                pushed_constant(trace, pc) == Some(&R_BoxedValue::Bool(!guard.expected))
+            }
+            OpCode::GuardOperands(ty, _) if !entered(pc) => {
+                let mismatch = |pc: usize| match pushed_constant(trace, pc) {
+                    Some(val) => NumType::of(val) != Some(ty),
+                    None => false,
+                };
+                // the operand below was pushed right before the top one
+                let pushes = pc > 0 && !entered(pc - 1) &&
+                             match trace[pc - 1] {
+                    OpCode::ConstValue(_) | OpCode::Load(_) => true,
+                    _ => false,
+                };
+                mismatch(pc) || pushes && mismatch(pc - 1)
+            }
+            OpCode::GuardFunc(func, _) if !entered(pc) => {
+                pushed_constant(trace, pc).map_or(false, |val| *val != R_BoxedValue::Func(func))
+            }
+            OpCode::GuardVTable(idx, _) if !entered(pc) => {
+                pushed_constant(trace, pc).map_or(false, |val| *val != R_BoxedValue::VTable(idx))
+            }
+            OpCode::GuardCast(ty, _) if !entered(pc) => {
+                pushed_constant(trace, pc).map_or(false, |val| ty.cast(val).is_none())
+            }
+            _ => false,
+        };
+        exits && jumps.iter().all(|&(from, target)| from > pc || target <= pc)
+    });
+
+    match exit {
+        Some(pc) if pc + 1 < trace.len() && fuel.take() => {
+            let removed = trace.len() - pc - 1;
+            trace.truncate(pc + 1);
+            removed
+        }
+        _ => 0,
+    }
+}
+
+/// Fold the `MovePtr` which follow each other in `trace` into one, also
+/// across storing the pointer into a local and loading it again right away.
+/// Moves which cancel out are dropped. Returns the number of removed
+/// opcodes.
+pub fn fold_pointer_moves(trace: &mut Vec<OpCode>, fuel: &mut Fuel) -> usize {
+    let targets: HashSet<usize> = (0..trace.len())
+        .filter_map(|pc| jump_target(trace, pc))
+        .collect();
+    let entered = |from: usize, to: usize| (from..to).any(|pc| targets.contains(&pc));
+    let mut keep = vec![true; trace.len()];
+
+    for pc in 0..trace.len() {
+        let offset = match trace[pc] {
+            OpCode::MovePtr(offset) => offset,
+            _ => continue,
+        };
+        if offset == 0 {
+            keep[pc] = !fuel.take();
+            continue;
+        }
+        // the move further on which takes over this one
+        let into = {
+            let next = |n: usize| trace.get(pc + n);
+            match (next(1), next(2), next(3), next(4)) {
+                (Some(&OpCode::MovePtr(_)), _, _, _) if !entered(pc + 1, pc + 2) => pc + 1,
+                (Some(&OpCode::Store(a)),
+                 Some(&OpCode::Load(b)),
+                 Some(&OpCode::MovePtr(_)),
+                 Some(&OpCode::Store(c))) if a == b && b == c && !entered(pc + 1, pc + 5) => pc + 3,
+                _ => continue,
+            }
+        };
+        if !fuel.take() {
+            continue;
+        }
+        for removed in pc..into {
+            keep[removed] = false;
+        }
+        if let OpCode::MovePtr(ref mut next) = trace[into] {
+            *next += offset;
+        }
+    }
+
+    let removed = keep.iter().filter(|&&kept| !kept).count();
+    remove_opcodes(trace, &keep);
+    removed
+}
+
+/// Replace reads of struct fields in `trace` by the value written into the
+/// field before, if the field can't have changed in between. Only fields
+/// indexed by a constant or a local, holding a constant or the value of a
+/// local, are followed. A write into another struct is only known to leave
+/// the field alone if the locals holding the structs don't alias in
+/// function `func`, see `AliasClasses`. Returns the number of removed
+/// opcodes.
+pub fn forward_loads(trace: &mut Vec<OpCode>, aliases: &AliasClasses, func: usize,
+                     fuel: &mut Fuel)
+                     -> usize {
+    // a value may reach a read in several ways
+    if (0..trace.len()).any(|pc| jump_target(trace, pc).is_some()) {
+        return 0;
+    }
+    let may_alias = |a: usize, b: usize| aliases.may_alias(func, a, b);
+    let loads_alias = |op: &OpCode, p: usize| match *op {
+        OpCode::Load(local) => may_alias(local, p),
+        _ => false,
+    };
+    // the struct local, index and value of the fields written
+    let mut known: Vec<(usize, OpCode, OpCode)> = Vec::new();
+    let mut keep = vec![true; trace.len()];
+    // inlined calls have locals of their own, they are skipped
+    let mut calls = 0;
+
+    for pc in 0..trace.len() {
+        let opcode = trace[pc].clone();
+        match opcode {
+            OpCode::FlatCall(..) => calls += 1,
+            OpCode::Return => calls -= 1,
+            _ => (),
+        }
+        if calls > 0 {
+            known.clear();
+            continue;
+        }
+        // the local holding the struct or pointer the opcode works on
+        let target = match pc.checked_sub(1).map(|prev| &trace[prev]) {
+            Some(&OpCode::Load(local)) => Some(local),
+            _ => None,
+        };
+        match opcode {
+            // unless the field is borrowed, its cell counts
+            OpCode::GetIndex if pc >= 2 && trace.get(pc + 1) != Some(&OpCode::Ref) => {
+                let value = target.and_then(|s| {
+                    known.iter()
+                        .find(|&&(local, ref index, _)| local == s && *index == trace[pc - 2])
+                        .map(|&(_, _, ref value)| value.clone())
+                });
+                match value {
+                    Some(value) if fuel.take() => {
+                        trace[pc - 2] = value;
+                        keep[pc - 1] = false;
+                        keep[pc] = false;
+                    }
+                    _ => (),
+                }
+            }
+            OpCode::AssignIndex => {
+                match target {
+                    Some(s) => known.retain(|&(local, _, _)| !may_alias(local, s)),
+                    None => known.clear(),
+                }
+                if let (Some(s), true) = (target, pc >= 3) {
+                    let (index, value) = (&trace[pc - 2], &trace[pc - 3]);
+                    if is_operand(index) && is_operand(value) {
+                        known.push((s, index.clone(), value.clone()));
+                    }
+                }
+            }
+            OpCode::TupleSet(_) | OpCode::CopyRange | OpCode::Fill => {
+                match target {
+                    Some(s) => known.retain(|&(local, _, _)| !may_alias(local, s)),
+                    None => known.clear(),
+                }
+            }
+            // the pointer may point at a local as well
+            OpCode::DerefStore => {
+                match target {
+                    Some(p) => {
+                        known.retain(|&(local, ref index, ref value)| {
+                            !may_alias(local, p) && !loads_alias(index, p) &&
+                            !loads_alias(value, p)
+                        })
+                    }
+                    None => known.clear(),
+                }
+            }
+            OpCode::Store(x) => {
+                let load = OpCode::Load(x);
+                known.retain(|&(local, ref index, ref value)| {
+                    local != x && *index != load && *value != load
+                });
+            }
+            OpCode::Call | OpCode::CallVirtual(_) | OpCode::InternalFunc(_) |
+            OpCode::LoopBack(_) => known.clear(),
+            _ => (),
+        }
+    }
+
+    let removed = keep.iter().filter(|&&kept| !kept).count();
+    remove_opcodes(trace, &keep);
+    removed
+}
+
+/// Replace reads from the read-only `data` of the program in `trace` by the
+/// value read: fields at a constant index, and the length. Returns the
+/// number of removed opcodes.
+pub fn fold_constant_loads(trace: &mut Vec<OpCode>, data: &[R_BoxedValue], fuel: &mut Fuel)
+                           -> usize {
+    let targets: HashSet<usize> = (0..trace.len())
+        .filter_map(|pc| jump_target(trace, pc))
+        .collect();
+    let mut keep = vec![true; trace.len()];
+
+    for pc in 1..trace.len() {
+        let constant = match trace[pc - 1] {
+            OpCode::ConstRef(idx) if !targets.contains(&pc) => data.get(idx),
+            _ => continue,
+        };
+        let (from, val) = match (&trace[pc], constant) {
+            (&OpCode::Len, Some(&R_BoxedValue::Struct(ref s))) => {
+                (pc - 1, R_BoxedValue::Usize(s.data.len()))
+            }
+            // unless the field is borrowed
+            (&OpCode::GetIndex, Some(&R_BoxedValue::Struct(ref s)))
+                if pc >= 2 && !targets.contains(&(pc - 1)) &&
+                   trace.get(pc + 1) != Some(&OpCode::Ref) => {
+                match trace[pc - 2] {
+                    OpCode::ConstValue(R_BoxedValue::Usize(idx)) if idx < s.data.len() => {
+                        (pc - 2, s.data[idx].get())
+                    }
+                    _ => continue,
+                }
+            }
+            _ => continue,
+        };
+        if !fuel.take() {
+            continue;
+        }
+        trace[from] = OpCode::ConstValue(val);
+        for removed in from + 1..pc + 1 {
+            keep[removed] = false;
+        }
+    }
+
+    let removed = keep.iter().filter(|&&kept| !kept).count();
+    remove_opcodes(trace, &keep);
+    removed
+}
+
+/// Which passes `optimize_trace_with` runs over a trace, all by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TracePasses {
+    /// fold operations on constants, see `propagate_constants`
+    pub constants: bool,
+    /// read copied locals from their source, see `forward_copies`
+    pub forwarding: bool,
+    /// drop guards checked before already, see `eliminate_redundant_guards`
+    pub guards: bool,
+    /// drop values and stores nothing sees, see `eliminate_dead_code`
+    pub dead_code: bool,
+}
+
+impl Default for TracePasses {
+    fn default() -> Self {
+        TracePasses {
+            constants: true,
+            forwarding: true,
+            guards: true,
+            dead_code: true,
+        }
+    }
+}
+
+/// Run all passes of the trace pipeline over `trace`.
+pub fn optimize_trace(trace: Vec<OpCode>) -> Vec<OpCode> {
+    optimize_trace_with(trace, TracePasses::default(), &mut Fuel::new(None))
+}
+
+/// Run the trace pipeline with the `passes` asked for, as long as `fuel`
+/// lasts. Copies are forwarded first, so that constants reach the reads of
+/// the copies, guards are compared on what is left of their conditions, and
+/// whatever the others leave unused is removed last.
+pub fn optimize_trace_with(mut trace: Vec<OpCode>, passes: TracePasses, fuel: &mut Fuel)
+                           -> Vec<OpCode> {
+    if passes.forwarding {
+        forward_copies(&mut trace, fuel);
+    }
+    if passes.constants {
+        propagate_constants(&mut trace, fuel);
+    }
+    if passes.guards {
+        eliminate_redundant_guards(&mut trace, fuel);
+    }
+    if passes.dead_code {
+        eliminate_dead_code(&mut trace, fuel);
+    }
+    trace
+}
+
+/// Replace reads in `trace` of locals which hold a number or a boolean
+/// constant by the constant, and fold operations on constants into their
+/// result. Operations which would overflow or divide by zero are left to
+/// fail at runtime. Returns the number of rewritten opcodes.
+pub fn propagate_constants(trace: &mut Vec<OpCode>, fuel: &mut Fuel) -> usize {
+    propagate_values(trace, fuel, true)
+}
+
+/// Replace reads in `trace` of a local which got a copy of another by reads
+/// of the other, as long as neither changed, so that the copy may become
+/// dead. Returns the number of rewritten opcodes.
+pub fn forward_copies(trace: &mut Vec<OpCode>, fuel: &mut Fuel) -> usize {
+    propagate_values(trace, fuel, false)
+}
+
+// What `propagate_constants` or, without `constants`, `forward_copies` do.
+fn propagate_values(trace: &mut Vec<OpCode>, fuel: &mut Fuel, constants: bool) -> usize {
+    if (0..trace.len()).any(|pc| jump_target(trace, pc).is_some()) {
+        return 0;
+    }
+    // the locals with the opcode pushing their value
+    let mut known: Vec<(usize, OpCode)> = Vec::new();
+    let mut out: Vec<OpCode> = Vec::with_capacity(trace.len());
+    let mut rewritten = 0;
+    // inlined calls have locals of their own, they are skipped
+    let mut calls = 0;
+
+    for pc in 0..trace.len() {
+        let opcode = trace[pc].clone();
+        match opcode {
+            OpCode::FlatCall(..) => calls += 1,
+            OpCode::Return => calls -= 1,
+            _ => (),
+        }
+        if calls > 0 || opcode == OpCode::Return {
+            known.clear();
+            out.push(opcode);
+            continue;
+        }
+
+        match opcode {
+            OpCode::Load(local) => {
+                let value = known.iter().find(|&&(known, _)| known == local).map(|k| k.1.clone());
+                let read = match value {
+                    Some(OpCode::Load(source)) => read_right_away(trace, pc, &[local, source]),
+                    Some(_) => read_right_away(trace, pc, &[local]),
+                    None => false,
+                };
+                if read && fuel.take() {
+                    out.push(value.unwrap());
+                    rewritten += 1;
+                    continue;
+                }
+            }
+            OpCode::Store(local) => {
+                let value = {
+                    let stored = match out.last() {
+                        Some(&OpCode::Use) => out.len().checked_sub(2).map(|prev| &out[prev]),
+                        last => last,
+                    };
+                    match stored {
+                        Some(&OpCode::ConstValue(ref val)) if constants && is_scalar(val) => {
+                            Some(OpCode::ConstValue(val.clone()))
+                        }
+                        Some(&OpCode::Load(source)) if !constants && source != local => {
+                            Some(OpCode::Load(source))
+                        }
+                        _ => None,
+                    }
+                };
+                let load = OpCode::Load(local);
+                known.retain(|&(known, ref value)| known != local && *value != load);
+                if let Some(value) = value {
+                    known.push((local, value));
+                }
+            }
+            OpCode::BinOp(kind) | OpCode::TypedBinOp(kind, _) if constants => {
+                let len = out.len();
+                let folded = if len < 2 {
+                    None
+                } else {
+                    match (&out[len - 2], &out[len - 1]) {
+                        (&OpCode::ConstValue(ref l), &OpCode::ConstValue(ref r)) => {
+                            folded_binop(kind, l, r)
+                        }
+                        _ => None,
+                    }
+                };
+                if let (Some(val), true) = (folded, fuel.take()) {
+                    out.truncate(len - 2);
+                    out.push(OpCode::ConstValue(val));
+                    rewritten += 1;
+                    continue;
+                }
+            }
+            OpCode::Not if constants => {
+                let negated = match out.last() {
+                    Some(&OpCode::ConstValue(R_BoxedValue::Bool(b))) => Some(!b),
+                    _ => None,
+                };
+                if let (Some(b), true) = (negated, fuel.take()) {
+                    out.pop();
+                    out.push(OpCode::ConstValue(R_BoxedValue::Bool(b)));
+                    rewritten += 1;
+                    continue;
+                }
+            }
+            // values of structs and behind pointers change here
+            OpCode::AssignIndex | OpCode::TupleSet(_) | OpCode::CopyRange | OpCode::Fill
+                if !constants => known.clear(),
+            OpCode::DerefStore | OpCode::Call | OpCode::CallVirtual(_) | OpCode::InternalFunc(_) |
+            OpCode::LoopBack(_) => known.clear(),
+            _ => (),
+        }
+        out.push(opcode);
+    }
+
+    *trace = out;
+    rewritten
+}
+
+// Whether the cell the `Load` at `pc` pushes is only read for its value,
+// before any of `locals` or a value behind a pointer changes.
+fn read_right_away(trace: &[OpCode], pc: usize, locals: &[usize]) -> bool {
+    let consumer = match load_consumer(trace, pc) {
+        Some(consumer) => consumer,
+        None => return false,
+    };
+    let reads = match trace[consumer] {
+        OpCode::Use | OpCode::BinOp(_) | OpCode::TypedBinOp(..) | OpCode::CheckedBinOp(_) |
+        OpCode::WrappingBinOp(_) | OpCode::SaturatingBinOp(_) |
+        OpCode::Not | OpCode::Neg | OpCode::Abs | OpCode::Pop |
+        OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros |
+        OpCode::RotateLeft | OpCode::RotateRight |
+        OpCode::CheckedCast(_) | OpCode::Cast(_) => true,
+        _ => false,
+    };
+    reads &&
+    trace[pc + 1..consumer].iter().all(|op| match *op {
+        OpCode::Store(local) => !locals.contains(&local),
+        OpCode::DerefStore | OpCode::InternalFunc(_) => false,
+        _ => true,
+    })
+}
+
+fn is_scalar(val: &R_BoxedValue) -> bool {
+    match *val {
+        R_BoxedValue::Bool(_) => true,
+        _ => NumType::of(val).is_some(),
+    }
+}
+
+macro_rules! fold_ints {
+    ($kind:expr, $v:ident, $l:expr, $r:expr) => ({
+        let (l, r) = ($l, $r);
+        match $kind {
+            BinOp::Add => l.checked_add(r).map(R_BoxedValue::$v),
+            BinOp::Sub => l.checked_sub(r).map(R_BoxedValue::$v),
+            BinOp::Mul => l.checked_mul(r).map(R_BoxedValue::$v),
+            BinOp::Div => l.checked_div(r).map(R_BoxedValue::$v),
+            BinOp::Rem => l.checked_rem(r).map(R_BoxedValue::$v),
+            BinOp::BitXor => Some(R_BoxedValue::$v(l ^ r)),
+            BinOp::BitAnd => Some(R_BoxedValue::$v(l & r)),
+            BinOp::BitOr => Some(R_BoxedValue::$v(l | r)),
+            BinOp::Min => Some(R_BoxedValue::$v(cmp::min(l, r))),
+            BinOp::Max => Some(R_BoxedValue::$v(cmp::max(l, r))),
+            BinOp::Eq => Some(R_BoxedValue::Bool(l == r)),
+            BinOp::Ne => Some(R_BoxedValue::Bool(l != r)),
+            BinOp::Lt => Some(R_BoxedValue::Bool(l < r)),
+            BinOp::Le => Some(R_BoxedValue::Bool(l <= r)),
+            BinOp::Gt => Some(R_BoxedValue::Bool(l > r)),
+            BinOp::Ge => Some(R_BoxedValue::Bool(l >= r)),
+            // the interpreter fails on shifts by too much
+            BinOp::Shl | BinOp::Shr => None,
+        }
+    })
+}
+
+// The result of `kind` on the constants `l` and `r`, unless it fails.
+fn folded_binop(kind: BinOp, l: &R_BoxedValue, r: &R_BoxedValue) -> Option<R_BoxedValue> {
+    match (l, r) {
+        (&R_BoxedValue::Usize(l), &R_BoxedValue::Usize(r)) => fold_ints!(kind, Usize, l, r),
+        (&R_BoxedValue::U64(l), &R_BoxedValue::U64(r)) => fold_ints!(kind, U64, l, r),
+        (&R_BoxedValue::I64(l), &R_BoxedValue::I64(r)) => fold_ints!(kind, I64, l, r),
+        (&R_BoxedValue::Bool(l), &R_BoxedValue::Bool(r)) => {
+            let val = match kind {
+                BinOp::Eq => l == r,
+                BinOp::Ne => l != r,
+                BinOp::Lt => l < r,
+                BinOp::Le => l <= r,
+                BinOp::Gt => l > r,
+                BinOp::Ge => l >= r,
+                BinOp::BitOr | BinOp::Max => l | r,
+                BinOp::BitXor => l ^ r,
+                BinOp::BitAnd | BinOp::Min => l & r,
+                _ => return None,
+            };
+            Some(R_BoxedValue::Bool(val))
+        }
+        _ => None,
+    }
+}
+
+/// Remove the guards of `trace` which check a condition an earlier guard
+/// checked already with the same outcome, with none of the locals it reads
+/// stored to in between: if the guard failed, the earlier one would have.
+/// The condition goes along if nothing else uses it. The guards left keep
+/// their recovery, their ids are given when the trace is installed.
+/// Returns the number of removed guards.
+pub fn eliminate_redundant_guards(trace: &mut Vec<OpCode>, fuel: &mut Fuel) -> usize {
+    if (0..trace.len()).any(|pc| jump_target(trace, pc).is_some()) {
+        return 0;
+    }
+    let mut keep = vec![true; trace.len()];
+    // what the guards passed so far checked, with the opcodes computing
+    // the values they check
+    let mut checked: Vec<(Vec<OpCode>, Option<NumType>, bool)> = Vec::new();
+    let mut removed = 0;
+
+    for pc in 0..trace.len() {
+        let (values, check) = match trace[pc] {
+            OpCode::Guard(ref guard) => (1, (None, guard.expected)),
+            // hold whatever the guard expects
+            OpCode::GuardOperands(ty, _) => (2, (Some(ty), true)),
+            OpCode::GuardCast(ty, _) => (1, (Some(ty), true)),
+            OpCode::Store(local) => {
+                let load = OpCode::Load(local);
+                checked.retain(|&(ref condition, _, _)| !condition.contains(&load));
+                continue;
+            }
+            // values of locals may change here, and they are the locals
+            // of another frame
+            OpCode::DerefStore | OpCode::AssignIndex | OpCode::TupleSet(_) |
+            OpCode::CopyRange | OpCode::Fill | OpCode::Call | OpCode::CallVirtual(_) |
+            OpCode::FlatCall(..) | OpCode::Return | OpCode::InternalFunc(_) |
+            OpCode::LoopBack(_) => {
+                checked.clear();
+                continue;
+            }
+            _ => continue,
+        };
+        let start = match condition_start(trace, pc, values) {
+            Some(start) => start,
+            None => continue,
+        };
+        let condition: Vec<OpCode> = trace[start..pc]
+            .iter()
+            .filter(|op| !is_guard(op))
+            .cloned()
+            .collect();
+        let (ty, expected) = check;
+        let redundant = checked.iter().any(|&(ref known, known_ty, known_expected)| {
+            *known == condition && known_ty == ty && known_expected == expected
+        });
+        if !redundant {
+            checked.push((condition, ty, expected));
+            continue;
+        }
+        if !fuel.take() {
+            continue;
+        }
+        keep[pc] = false;
+        removed += 1;
+        // a condition only the guard used is popped right after it
+        let unused = trace.get(pc + 1) == Some(&OpCode::Pop) &&
+                     !trace[start..pc].iter().any(is_guard);
+        if unused {
+            for pos in start..pc + 2 {
+                keep[pos] = false;
+            }
+        }
+    }
+
+    remove_opcodes(trace, &keep);
+    removed
+}
+
+// Where the opcodes start which push the top `values` values the opcode at
+// `pc` sees, if they compute them from locals and constants only.
+fn condition_start(trace: &[OpCode], pc: usize, values: usize) -> Option<usize> {
+    let mut needed = values;
+    let mut start = pc;
+    while needed > 0 {
+        start = match start.checked_sub(1) {
+            Some(start) => start,
+            None => return None,
+        };
+        let pure = match trace[start] {
+            OpCode::Load(_) | OpCode::ConstValue(_) | OpCode::Use |
+            OpCode::BinOp(_) | OpCode::TypedBinOp(..) | OpCode::WrappingBinOp(_) |
+            OpCode::SaturatingBinOp(_) | OpCode::Not | OpCode::Neg | OpCode::Abs |
+            OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros |
+            OpCode::RotateLeft | OpCode::RotateRight | OpCode::Cast(_) => true,
+            ref op => is_guard(op),
+        };
+        let (pops, pushes) = match (pure, trace[start].stack_effect()) {
+            (true, Some(effect)) => effect,
+            _ => return None,
+        };
+        // a value below the condition is used up
+        if pushes > needed {
+            return None;
+        }
+        needed = needed - pushes + pops;
+    }
+    Some(start)
+}
+
+fn is_guard(opcode: &OpCode) -> bool {
+    match *opcode {
+        OpCode::Guard(_) | OpCode::GuardOperands(..) | OpCode::GuardFunc(..) |
+        OpCode::GuardVTable(..) | OpCode::GuardCast(..) => true,
+        _ => false,
+    }
+}
+
+/// Remove from `trace` what has no effect: `Noop`s, values pushed only to
+/// be popped, locals stored into themselves, and stores which are
+/// overwritten before anything could see the local. Guards, calls and the
+/// end of the iteration see all locals. Returns the number of removed
+/// opcodes.
+pub fn eliminate_dead_code(trace: &mut Vec<OpCode>, fuel: &mut Fuel) -> usize {
+    if (0..trace.len()).any(|pc| jump_target(trace, pc).is_some()) {
+        return 0;
+    }
+    let len = trace.len();
+
+    // a dead store only pops its value
+    for pc in 0..trace.len() {
+        if let OpCode::Store(local) = trace[pc] {
+            if overwritten(trace, pc, local) && fuel.take() {
+                trace[pc] = OpCode::Pop;
+            }
+        }
+    }
+
+    let mut out: Vec<OpCode> = Vec::with_capacity(trace.len());
+    for opcode in trace.drain(..) {
+        let dead = match (out.last(), &opcode) {
+            (_, &OpCode::Noop) => 0,
+            (Some(&OpCode::ConstValue(_)), &OpCode::Pop) |
+            (Some(&OpCode::ConstRef(_)), &OpCode::Pop) |
+            (Some(&OpCode::Load(_)), &OpCode::Pop) => 1,
+            (Some(&OpCode::Use), &OpCode::Store(local))
+                if out.len() >= 2 && out[out.len() - 2] == OpCode::Load(local) => 2,
+            _ => {
+                out.push(opcode);
+                continue;
+            }
+        };
+        if !fuel.take() {
+            out.push(opcode);
+            continue;
+        }
+        let len = out.len();
+        out.truncate(len - dead);
+    }
+
+    *trace = out;
+    len - trace.len()
+}
+// Whether the `Store` at `pc` into `local` is stored over again before
+// anything could see the local.
+fn overwritten(trace: &[OpCode], pc: usize, local: usize) -> bool {
+    // a cell of the local still on the stack sees the store
+    let pending = (0..pc).any(|load| {
+        trace[load] == OpCode::Load(local) &&
+        load_consumer(trace, load).map_or(true, |consumer| consumer > pc)
+    });
+    if pending {
+        return false;
+    }
+    for oc in &trace[pc + 1..] {
+        match *oc {
+            OpCode::Store(n) if n == local => return true,
+            OpCode::Load(n) if n == local => return false,
+            OpCode::Guard(_) | OpCode::GuardOperands(..) | OpCode::GuardFunc(..) |
+            OpCode::GuardVTable(..) | OpCode::GuardCast(..) |
+            OpCode::Ref | OpCode::Deref | OpCode::DerefStore |
+            OpCode::Call | OpCode::CallVirtual(_) | OpCode::FlatCall(..) | OpCode::Return |
+            OpCode::InternalFunc(_) | OpCode::Breakpoint(_) | OpCode::LoopBack(_) => return false,
+            _ if oc.stack_effect().is_none() => return false,
+            _ => (),
+        }
+    }
+    false
+}
+
+// Pushes a value which doesn't change unless its local is stored to.
+fn is_operand(opcode: &OpCode) -> bool {
+    match *opcode {
+        OpCode::ConstValue(_) | OpCode::Load(_) => true,
+        _ => false,
+    }
+}
+
+// The constant the opcode in front of `pc` pushes.
+fn pushed_constant(trace: &[OpCode], pc: usize) -> Option<&R_BoxedValue> {
+    match pc.checked_sub(1).map(|prev| &trace[prev]) {
+        Some(&OpCode::ConstValue(ref val)) => Some(val),
+        _ => None,
+    }
+}
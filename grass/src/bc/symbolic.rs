@@ -0,0 +1,221 @@
+//! Symbolic execution of a trace next to the bytecode it was recorded from,
+//! to check that the tracer kept the meaning of the code.
+//!
+//! Both sides start from the same unknown locals and an empty operand stack.
+//! The bytecode follows the path the recording took, given as the outcome of
+//! every conditional jump on it, and the trace runs once through with all its
+//! guards passing. They are equivalent if they leave the same values on the
+//! stack and in the locals, have the same side effects in the same order,
+//! and the guards expect the conditions the jumps branched on.
+
+use std::collections::BTreeMap;
+
+use bc::Program;
+use bc::bytecode::{OpCode, BinOp};
+use core::objects::{InstructionPointer, R_BoxedValue};
+
+/// A value in terms of the state the trace is entered with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sym {
+    Const(R_BoxedValue),
+    /// what the local held when the trace was entered
+    Local(usize),
+    BinOp(BinOp, Box<Sym>, Box<Sym>),
+    /// value `idx` pushed by the `seq`th opaque opcode, see
+    /// `SymState::effects`
+    Output(usize, usize),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SymState {
+    pub stack: Vec<Sym>,
+    /// the locals which were written, with their new values
+    pub locals: BTreeMap<usize, Sym>,
+    /// Opcodes without a symbolic meaning, with their operands, in the order
+    /// they ran. They may have side effects.
+    pub effects: Vec<(OpCode, Vec<Sym>)>,
+    /// what the conditional jumps or guards checked, and the outcome they
+    /// took or expect, in order
+    pub conditions: Vec<(Sym, bool)>,
+}
+
+/// Why a trace and its path aren't equivalent, or can't be compared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Divergence {
+    /// the opcode has no static stack effect
+    UnknownStackEffect(OpCode),
+    /// the opcode pops more values than are on the stack
+    StackUnderflow(OpCode),
+    /// the path has more conditional jumps than outcomes were given
+    MissingOutcome,
+    /// the path runs past the end of its function
+    OutOfBounds(InstructionPointer),
+    /// the path jumps around without reaching a conditional jump or its end
+    Endless,
+    /// the stacks of the trace and of the path differ
+    Stack(Vec<Sym>, Vec<Sym>),
+    /// the values of the local in the trace and in the path differ
+    Local(usize, Sym, Sym),
+    /// the opaque opcodes of the trace and of the path differ
+    Effects,
+    /// the guards of the trace and the jumps of the path check different
+    /// conditions
+    Conditions(Vec<(Sym, bool)>, Vec<(Sym, bool)>),
+}
+
+impl SymState {
+    /// The value of `local` now.
+    pub fn local(&self, local: usize) -> Sym {
+        self.locals.get(&local).cloned().unwrap_or(Sym::Local(local))
+    }
+
+    fn pop(&mut self, opcode: &OpCode) -> Result<Sym, Divergence> {
+        self.stack.pop().ok_or_else(|| Divergence::StackUnderflow(opcode.clone()))
+    }
+
+    // Follow an opcode which doesn't jump.
+    fn step(&mut self, opcode: &OpCode) -> Result<(), Divergence> {
+        match *opcode {
+            OpCode::ConstValue(ref val) => self.stack.push(Sym::Const(val.clone())),
+            OpCode::Load(local) => {
+                let val = self.local(local);
+                self.stack.push(val);
+            }
+            OpCode::Store(local) => {
+                let val = try!(self.pop(opcode));
+                self.locals.insert(local, val);
+            }
+            OpCode::BinOp(kind) | OpCode::TypedBinOp(kind, _) => {
+                let right = try!(self.pop(opcode));
+                let left = try!(self.pop(opcode));
+                self.stack.push(Sym::BinOp(kind, Box::new(left), Box::new(right)));
+            }
+            OpCode::Pop => {
+                try!(self.pop(opcode));
+            }
+            // only checks, or changes how the value is held
+            OpCode::GuardOperands(..) | OpCode::GuardFunc(..) | OpCode::GuardVTable(..) |
+            OpCode::GuardCast(..) | OpCode::LoopBack(_) | OpCode::Noop => (),
+            OpCode::Use | OpCode::Unsize => {
+                let val = try!(self.pop(opcode));
+                self.stack.push(val);
+            }
+            _ => {
+                let (pops, pushes) = match opcode.stack_effect() {
+                    Some(effect) => effect,
+                    None => return Err(Divergence::UnknownStackEffect(opcode.clone())),
+                };
+                if pops > self.stack.len() {
+                    return Err(Divergence::StackUnderflow(opcode.clone()));
+                }
+                let from = self.stack.len() - pops;
+                let operands = self.stack.split_off(from);
+                let seq = self.effects.len();
+                self.effects.push((opcode.clone(), operands));
+                for idx in 0..pushes {
+                    self.stack.push(Sym::Output(seq, idx));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run `trace` once from its start to its end, with every guard passing.
+pub fn run_trace(trace: &[OpCode]) -> Result<SymState, Divergence> {
+    let mut state = SymState::default();
+    for opcode in trace {
+        match *opcode {
+            // the condition stays on the stack
+            OpCode::Guard(ref guard) => {
+                let condition = try!(state.pop(opcode));
+                state.stack.push(condition.clone());
+                state.conditions.push((condition, guard.expected));
+            }
+            _ => try!(state.step(opcode)),
+        }
+    }
+    Ok(state)
+}
+
+/// Run the bytecode of `program` from `start` the way a trace recorded
+/// there went: the `n`th conditional jump is taken if `outcomes[n]` is true.
+/// The path ends at a `Return` or at a jump back before `start`, where
+/// recording a loop ends as well.
+pub fn run_path(program: &Program, start: InstructionPointer, outcomes: &[bool])
+                -> Result<SymState, Divergence> {
+    let opcodes = &program.functions[start.func].opcodes;
+    let mut state = SymState::default();
+    let mut pc = start.pc;
+    let mut outcomes = outcomes.iter();
+    // without conditional jumps every opcode is reached once at most
+    let mut steps = 0;
+
+    loop {
+        let opcode = match opcodes.get(pc) {
+            Some(opcode) => opcode,
+            None => {
+                return Err(Divergence::OutOfBounds(InstructionPointer { func: start.func, pc: pc }));
+            }
+        };
+        steps += 1;
+        if steps > opcodes.len() {
+            return Err(Divergence::Endless);
+        }
+
+        let target = match *opcode {
+            OpCode::Return => return Ok(state),
+            OpCode::Skip(n) => pc + n,
+            OpCode::JumpBack(n) => pc.wrapping_sub(n),
+            OpCode::SkipIf(n) | OpCode::JumpBackIf(n) => {
+                let condition = try!(state.pop(opcode));
+                steps = 0;
+                let taken = match outcomes.next() {
+                    Some(&taken) => taken,
+                    None => return Err(Divergence::MissingOutcome),
+                };
+                state.conditions.push((condition, taken));
+                match (taken, opcode) {
+                    (false, _) => pc + 1,
+                    (true, &OpCode::SkipIf(_)) => pc + n,
+                    (true, _) => pc.wrapping_sub(n),
+                }
+            }
+            _ => {
+                try!(state.step(opcode));
+                pc + 1
+            }
+        };
+
+        if target < start.pc {
+            return Ok(state);
+        }
+        pc = target;
+    }
+}
+
+/// Check that `trace`, recorded from `start` in `program` along the path
+/// which `outcomes` describes, does what that path of the bytecode does.
+pub fn check_trace(program: &Program, start: InstructionPointer, trace: &[OpCode],
+                   outcomes: &[bool])
+                   -> Result<(), Divergence> {
+    let traced = try!(run_trace(trace));
+    let path = try!(run_path(program, start, outcomes));
+
+    if traced.conditions != path.conditions {
+        return Err(Divergence::Conditions(traced.conditions, path.conditions));
+    }
+    if traced.effects != path.effects {
+        return Err(Divergence::Effects);
+    }
+    for &local in traced.locals.keys().chain(path.locals.keys()) {
+        let (in_trace, in_path) = (traced.local(local), path.local(local));
+        if in_trace != in_path {
+            return Err(Divergence::Local(local, in_trace, in_path));
+        }
+    }
+    if traced.stack != path.stack {
+        return Err(Divergence::Stack(traced.stack, path.stack));
+    }
+    Ok(())
+}
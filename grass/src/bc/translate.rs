@@ -12,10 +12,12 @@ use rustc_serialize::{json, hex};
 use std::collections::{BTreeMap, HashSet};
 use std::rc::Rc;
 
-use bc::bytecode::{self, OpCode, InternalFunc};
+use bc::bytecode::{self, OpCode, InternalFunc, MERGE_POINT_FUNC};
 use core::objects::{R_BoxedValue, R_Function};
 
 use bc::opt;
+use bc;
+use bc::program::AliasClasses;
 
 // XXX
 pub type Function = Vec<OpCode>;
@@ -52,7 +54,7 @@ impl IdMap {
     fn new(cstore: Rc<for<'a> CrateStore<'a>>) -> Self {
         let mut map = BTreeMap::new();
         // 0 is reserved for merge_point
-        map.insert(DefId::local(DefIndex::new(0)), 0);
+        map.insert(DefId::local(DefIndex::new(0)), MERGE_POINT_FUNC);
         IdMap { map: map, cstore: cstore }
     }
 
@@ -70,8 +72,8 @@ impl IdMap {
     fn get_index(&mut self, key: &DefId) -> usize {
         // 0 is reserved for merge_point
         if self.is_merge_point(key) {
-            self.map.insert(*key, 0);
-            return 0;
+            self.map.insert(*key, MERGE_POINT_FUNC);
+            return MERGE_POINT_FUNC;
         }
 
         let length = self.map.len();
@@ -83,6 +85,8 @@ pub struct Program<'a, 'tcx: 'a> {
     context: &'a Context<'a, 'tcx>,
     // cache: BTreeMap<DefId, Vec<OpCode>>,
     cache: BTreeMap<usize, R_Function>,
+    /// names of the locals of the functions in `cache`
+    local_names: BTreeMap<usize, Vec<Option<String>>>,
 
     defid_map: IdMap,
 
@@ -91,12 +95,51 @@ pub struct Program<'a, 'tcx: 'a> {
 impl<'a, 'tcx> Program<'a, 'tcx> {
     fn new(context: &'a Context<'a, 'tcx>) -> Program<'a, 'tcx> {
         let mut cache = BTreeMap::new();
-        cache.insert(0, R_Function::default());
-        Program {context: context, cache: cache,
+        cache.insert(MERGE_POINT_FUNC, R_Function::default());
+        Program {context: context, cache: cache, local_names: BTreeMap::new(),
             defid_map: IdMap::new(context.tcx.sess.cstore.clone()) }
     }
 
 
+    /// The loaded functions, in the form the driver executes them.
+    pub fn to_program(&self) -> bc::Program {
+        let mut functions = Vec::new();
+        let mut m_idx = 0;
+
+        for idx in 0..self.cache.len() {
+            if let Some(func) = self.cache.get(&idx) {
+                func.opcodes.iter().enumerate().find(|&(idx, opcode)|
+                    match *opcode {
+                        OpCode::ConstValue(R_BoxedValue::Func(MERGE_POINT_FUNC)) => true,
+                        _ => false
+                    }).map(|(i, _)| m_idx = i);
+                functions.push(func.clone());
+            } else {
+                functions.push(R_Function::default());
+            }
+        }
+
+        let mut program = bc::Program {
+            functions: functions,
+            entry: (1, m_idx + 7),
+            aliases: AliasClasses::default(),
+            data: Vec::new(),
+            statics: Vec::new(),
+            vtables: Vec::new(),
+            local_names: Vec::new(),
+        };
+        for (&func, names) in &self.local_names {
+            for (local, name) in names.iter().enumerate() {
+                if let Some(ref name) = *name {
+                    program.set_local_name(func, local, name);
+                }
+            }
+        }
+        program.resolve_statics();
+        program.name_host_locals();
+        program
+    }
+
     pub fn load_fn_from_def_id(&mut self, def_id: DefId) {
         let local_id = self.defid_map.get_index(&def_id);
 
@@ -136,6 +179,7 @@ impl<'a, 'tcx> Program<'a, 'tcx> {
             func.args_cnt = mir.arg_decls.len();
 
             self.cache.insert(local_id, func);
+            self.local_names.insert(local_id, mir.local_names());
             for func in &mir_analyser.seen_fns {
                 if !self.defid_map.is_merge_point(&def_id) {
                     self.load_fn_from_def_id(*func);
@@ -289,6 +333,8 @@ trait LocalIndex {
     fn var_to_local(&self, n: usize) -> usize;
     fn tmp_to_local(&self, n: usize) -> usize;
     fn locals_len(&self) -> usize;
+    /// names of the arguments and variables, temporaries have none
+    fn local_names(&self) -> Vec<Option<String>>;
 }
 
 impl<'a> LocalIndex for Mir<'a> {
@@ -308,6 +354,13 @@ impl<'a> LocalIndex for Mir<'a> {
         self.arg_decls.len() + self.var_decls.len() + self.temp_decls.len()
     }
 
+    fn local_names(&self) -> Vec<Option<String>> {
+        let args = self.arg_decls.iter().map(|arg| Some(arg.debug_name.to_string()));
+        let vars = self.var_decls.iter().map(|var| Some(var.name.to_string()));
+        let temps = self.temp_decls.iter().map(|_| None);
+        args.chain(vars).chain(temps).collect()
+    }
+
 }
 
 pub struct BlockAnalyser<'b, 'a:'b, 'tcx: 'a> {
@@ -563,21 +616,25 @@ impl<'a> ByteCode for Rvalue<'a> {
             Rvalue::CheckedBinaryOp(binop, ref left, ref right) => {
                 left.as_rvalue(env);
                 right.as_rvalue(env);
-                env.add(OpCode::CheckedBinOp(bytecode::BinOp::new(binop)));
+                env.add(OpCode::CheckedBinOp(binop_of(binop)));
             },
 
             Rvalue::BinaryOp(binop, ref left, ref right) => {
                 left.as_rvalue(env);
                 right.as_rvalue(env);
-                env.add(OpCode::BinOp(bytecode::BinOp::new(binop)));
+                env.add(OpCode::BinOp(binop_of(binop)));
+            },
+
+            // `()`, which is also the result of functions without a value
+            Rvalue::Aggregate(AggregateKind::Tuple, ref vec) if vec.is_empty() => {
+                env.add(OpCode::ConstValue(R_BoxedValue::Unit));
             },
 
             Rvalue::Aggregate(AggregateKind::Tuple, ref vec) => {
-                env.add(OpCode::Tuple(vec.len()));
-                for (i, value) in vec.iter().enumerate() {
+                for value in vec {
                     value.as_rvalue(env);
-                    env.add(OpCode::TupleInit(i));
                 }
+                env.add(OpCode::TupleFromStack(vec.len()));
             },
 
             Rvalue::Aggregate(AggregateKind::Vec, ref vec) => {
@@ -597,13 +654,10 @@ impl<'a> ByteCode for Rvalue<'a> {
                 */
 
                 if adt_def.adt_kind() == AdtKind::Struct {
-                    // the struct definition is the first variant
-                    let struct_def = &adt_def.variants[0];
-                    env.add(OpCode::Tuple(struct_def.fields.len()));
-                    for (i, operand) in operands.iter().enumerate() {
+                    for operand in operands {
                         operand.as_rvalue(env);
-                        env.add(OpCode::TupleInit(i));
                     }
+                    env.add(OpCode::TupleFromStack(operands.len()));
                 }
             },
 
@@ -663,6 +717,29 @@ impl<'a> ByteCode for Rvalue<'a> {
     }
 }
 
+// The bytecode has its own copy of MIR's binary operators, so that it doesn't
+// depend on rustc.
+fn binop_of(kind: BinOp) -> bytecode::BinOp {
+    match kind {
+        BinOp::Add => bytecode::BinOp::Add,
+        BinOp::Sub => bytecode::BinOp::Sub,
+        BinOp::Mul => bytecode::BinOp::Mul,
+        BinOp::Div => bytecode::BinOp::Div,
+        BinOp::Rem => bytecode::BinOp::Rem,
+        BinOp::BitXor => bytecode::BinOp::BitXor,
+        BinOp::BitAnd => bytecode::BinOp::BitAnd,
+        BinOp::BitOr => bytecode::BinOp::BitOr,
+        BinOp::Shl => bytecode::BinOp::Shl,
+        BinOp::Shr => bytecode::BinOp::Shr,
+        BinOp::Eq => bytecode::BinOp::Eq,
+        BinOp::Lt => bytecode::BinOp::Lt,
+        BinOp::Le => bytecode::BinOp::Le,
+        BinOp::Ne => bytecode::BinOp::Ne,
+        BinOp::Ge => bytecode::BinOp::Ge,
+        BinOp::Gt => bytecode::BinOp::Gt,
+    }
+}
+
 fn unpack_const(literal: &Literal, ty: &TyS, env: &mut BlockAnalyser) -> OpCode {
     OpCode::ConstValue(match *literal {
         Literal::Value{ ref value } => {
@@ -769,29 +846,6 @@ pub fn generate_bytecode<'a, 'tcx>(context: &'a Context<'a, 'tcx>, main: DefId)
     program.load_fn_from_def_id(main);
     // println!("];");
 
-    let mut m_idx = 0;
-
-    println!("pub static PROGRAM:&'static [(usize, usize, &'static [OpCode])] = &[ (0, 0, &[]),");
-    for idx in 1..program.cache.len() {
-        if let Some(func) = program.cache.get(&idx) {
-            let output: Vec<String> = func.opcodes.iter().map(|oc|oc.to_rs()).collect();
-            print!("    ({}, {}, ", func.args_cnt, func.locals_cnt);
-            println!("&[{}]),", output.join(", "));
-
-            func.opcodes.iter().enumerate().find(|&(idx, opcode)|
-                match *opcode {
-                    OpCode::ConstValue(R_BoxedValue::Func(0)) => true,
-                    _ => false
-                }).map(|(i, _)| m_idx = i);
-
-            // println!("{:?}", func);
-        } else {
-            println!("    (0, 0, &[]),", );
-        }
-    }
-    println!("];");
-
-    println!("pub const IDX: (usize, usize) = (1, {});", m_idx + 7);
             // let output: Vec<String> = mir_analyser.opcodes.iter().map(|oc|oc.to_rs()).collect();
             // println!("  &[{}]", output.join(", "));
 
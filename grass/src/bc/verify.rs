@@ -0,0 +1,220 @@
+//! Static checks that a program keeps the operand stack intact.
+//!
+//! The calling convention: a function starts with an empty operand stack,
+//! its arguments are moved into its first locals by the `Call`. It returns
+//! with at most its result on the stack, `Return` with an empty stack
+//! returns `Unit`. Callees are only known when the `Call` directly follows
+//! a `ConstValue(Func(_))`. A `Static` runs a function without arguments.
+//!
+//! Calls of the merge point are handled by the driver and leave only the
+//! guest pc on the stack. Functions without bytecode belong to the host,
+//! calling them fails at run time, so the path ends there.
+
+use bc::Program;
+use bc::bytecode::{OpCode, MERGE_POINT_FUNC};
+use core::objects::R_BoxedValue;
+
+/// Where and why `verify` rejected a program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    pub func: usize,
+    pub pc: usize,
+    pub kind: Violation,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    /// the opcode has no static stack effect
+    UnknownStackEffect,
+    /// the opcode pops more values than are on the stack
+    StackUnderflow,
+    /// paths reaching the opcode disagree on the height of the stack
+    InconsistentDepth(usize, usize),
+    /// a `Call` whose callee isn't a function constant
+    UnknownCallee,
+    /// a function index beyond the program
+    UnknownFunction(usize),
+    /// a `Call` with fewer values on the stack than the callee takes
+    MissingArguments { callee: usize, expected: usize, found: usize },
+    /// a `CallVirtual` of a method which no vtable has, or whose functions
+    /// take different numbers of arguments
+    UnknownMethod(usize),
+    /// a `Static` without a value in the statics of the program
+    UnknownStatic(usize),
+    /// number of values on the stack at a `Return`, besides the result
+    LeftoverValues(usize),
+    /// execution continues past the end of the function
+    OutOfBounds,
+    /// a `ConstRef` beyond the data of the program
+    UnknownData(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct State {
+    depth: usize,
+    // function on top of the stack, if it is known
+    callee: Option<usize>,
+}
+
+/// Check every function of `program` against the calling convention.
+pub fn verify(program: &Program) -> Result<(), VerifyError> {
+    for func in 0..program.functions.len() {
+        try!(verify_function(program, func));
+    }
+    Ok(())
+}
+
+/// Follow all paths through function `func` and track the height of the
+/// operand stack at each opcode.
+pub fn verify_function(program: &Program, func: usize) -> Result<(), VerifyError> {
+    let opcodes = &program.functions[func].opcodes;
+    if opcodes.is_empty() {
+        return Ok(());
+    }
+
+    let mut states: Vec<Option<State>> = vec![None; opcodes.len()];
+    let mut pending = vec![0];
+    states[0] = Some(State { depth: 0, callee: None });
+
+    while let Some(pc) = pending.pop() {
+        let state = states[pc].unwrap();
+        let error = |kind: Violation| VerifyError { func: func, pc: pc, kind: kind };
+
+        let next = match try!(step(program, &opcodes[pc], state).map_err(&error)) {
+            Some(next) => next,
+            None => continue,
+        };
+
+        // jumps before the start end up out of bounds as well
+        let back = |n: usize| pc.checked_sub(n).unwrap_or(opcodes.len());
+        let targets = match opcodes[pc] {
+            OpCode::Skip(n) => vec![pc + n],
+            OpCode::JumpBack(n) => vec![back(n)],
+            OpCode::SkipIf(n) => vec![pc + 1, pc + n],
+            OpCode::JumpBackIf(n) => vec![pc + 1, back(n)],
+            _ => vec![pc + 1],
+        };
+
+        for target in targets {
+            if target >= opcodes.len() {
+                return Err(error(Violation::OutOfBounds));
+            }
+            match states[target] {
+                None => {
+                    states[target] = Some(next);
+                    pending.push(target);
+                }
+                Some(old) if old.depth != next.depth => {
+                    return Err(VerifyError {
+                        func: func,
+                        pc: target,
+                        kind: Violation::InconsistentDepth(old.depth, next.depth),
+                    });
+                }
+                // the callee is only known if all paths agree on it
+                Some(old) if old.callee.is_some() && old.callee != next.callee => {
+                    states[target] = Some(State { depth: old.depth, callee: None });
+                    pending.push(target);
+                }
+                Some(_) => (),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// State after `opcode`, `None` if execution doesn't continue in the function.
+fn step(program: &Program, opcode: &OpCode, state: State) -> Result<Option<State>, Violation> {
+    let depth = match *opcode {
+        OpCode::Return => {
+            if state.depth > 1 {
+                return Err(Violation::LeftoverValues(state.depth - 1));
+            }
+            return Ok(None);
+        }
+        // both fail at run time
+        OpCode::Panic | OpCode::Todo(_) => return Ok(None),
+
+        OpCode::Call => {
+            let callee = match state.callee {
+                Some(callee) => callee,
+                None => return Err(Violation::UnknownCallee),
+            };
+            if callee == MERGE_POINT_FUNC {
+                1
+            } else {
+                let func = match program.functions.get(callee) {
+                    Some(func) => func,
+                    None => return Err(Violation::UnknownFunction(callee)),
+                };
+                if func.opcodes.is_empty() {
+                    return Ok(None);
+                }
+                let found = state.depth - 1;
+                if found < func.args_cnt {
+                    return Err(Violation::MissingArguments {
+                        callee: callee,
+                        expected: func.args_cnt,
+                        found: found,
+                    });
+                }
+                found - func.args_cnt + 1
+            }
+        }
+
+        OpCode::CallVirtual(method) => {
+            let mut callees = program.vtables.iter().filter_map(|methods| methods.get(method));
+            let callee = match callees.next() {
+                Some(&callee) => callee,
+                None => return Err(Violation::UnknownMethod(method)),
+            };
+            let args_cnt = |func: usize| program.functions.get(func).map(|func| func.args_cnt);
+            let expected = match args_cnt(callee) {
+                Some(args_cnt) => args_cnt,
+                None => return Err(Violation::UnknownFunction(callee)),
+            };
+            if callees.any(|&func| args_cnt(func) != Some(expected)) {
+                return Err(Violation::UnknownMethod(method));
+            }
+            // the vtable is on top of the arguments
+            if state.depth == 0 {
+                return Err(Violation::StackUnderflow);
+            }
+            let found = state.depth - 1;
+            if found < expected {
+                return Err(Violation::MissingArguments {
+                    callee: callee,
+                    expected: expected,
+                    found: found,
+                });
+            }
+            found - expected + 1
+        }
+
+        OpCode::Static(idx) if program.static_value(idx).is_none() => {
+            return Err(Violation::UnknownStatic(idx));
+        }
+
+        OpCode::ConstRef(idx) if idx >= program.data.len() => {
+            return Err(Violation::UnknownData(idx));
+        }
+
+        _ => {
+            let (pops, pushes) = match opcode.stack_effect() {
+                Some(effect) => effect,
+                None => return Err(Violation::UnknownStackEffect),
+            };
+            if pops > state.depth {
+                return Err(Violation::StackUnderflow);
+            }
+            state.depth - pops + pushes
+        }
+    };
+
+    let callee = match *opcode {
+        OpCode::ConstValue(R_BoxedValue::Func(idx)) => Some(idx),
+        _ => None,
+    };
+    Ok(Some(State { depth: depth, callee: callee }))
+}
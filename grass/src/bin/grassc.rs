@@ -7,6 +7,10 @@ extern crate rustc_driver;
 extern crate getopts;
 
 use grass::bc::{translate, Context};
+use grass::codegen;
+
+use std::env;
+use std::io;
 
 use rustc::session::Session;
 use rustc_driver::{driver, CompilerCalls, Compilation};
@@ -30,7 +34,16 @@ impl<'a> CompilerCalls<'a> for GrassCompilerCalls {
 
             let node_id = state.session.entry_fn.borrow().unwrap().0;
             let def_id = tcx.map.local_def_id(node_id);
-            let mut program = translate::generate_bytecode(&context, def_id);
+            let program = translate::generate_bytecode(&context, def_id).to_program();
+
+            // the module goes to stdout unless GRASS_OUT names a file
+            let res = match env::var_os("GRASS_OUT") {
+                Some(path) => codegen::emit_module(&program, path),
+                None => codegen::write_module(&program, &mut io::stdout()),
+            };
+            if let Err(err) = res {
+                state.session.fatal(&format!("could not write module: {}", err));
+            }
         });
 
         control.after_analysis.stop = Compilation::Stop;
@@ -0,0 +1,39 @@
+//! Rust source for the `this` module of a host, which holds the bytecode
+//! of its guest interpreter as `PROGRAM` and the merge point position as
+//! `IDX`.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use bc::Program;
+
+/// Write the module for `program` to `path`.
+pub fn emit_module<P: AsRef<Path>>(program: &Program, path: P) -> io::Result<()> {
+    let mut out = BufWriter::new(try!(File::create(path)));
+    write_module(program, &mut out)
+}
+
+pub fn write_module<W: Write>(program: &Program, out: &mut W) -> io::Result<()> {
+    try!(writeln!(out, "use grass::core::objects::R_BoxedValue::*;"));
+    try!(writeln!(out, ""));
+    try!(writeln!(out, "use grass::bc::bytecode::OpCode;"));
+    try!(writeln!(out, "use grass::bc::bytecode::BinOp::*;"));
+    try!(writeln!(out, ""));
+    try!(writeln!(out, ""));
+
+    try!(write!(out, "pub static PROGRAM:&'static [(usize, usize, &'static [OpCode])] = &["));
+    for (idx, func) in program.functions.iter().enumerate() {
+        let opcodes: Vec<String> = func.opcodes.iter().map(|oc| oc.to_rs()).collect();
+        if idx == 0 {
+            try!(write!(out, " "));
+        } else {
+            try!(write!(out, "    "));
+        }
+        try!(writeln!(out, "({}, {}, &[{}]),", func.args_cnt, func.locals_cnt, opcodes.join(", ")));
+    }
+    try!(writeln!(out, "];"));
+
+    try!(writeln!(out, "pub const IDX: (usize, usize) = {:?};", program.entry));
+    Ok(())
+}
@@ -1,2 +1,2 @@
 
-pub mod objects;
+pub use grass_core::{cell, objects};
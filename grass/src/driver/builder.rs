@@ -0,0 +1,96 @@
+//! `Driver`s configured up front, for hosts which use it as a standalone
+//! interpreter and want everything settled before the first program is
+//! registered.
+
+use super::{Driver, GuestIo, InterpreterConfig, BreakpointHook};
+use super::meta::hooks::OpcodeHooks;
+#[cfg(feature = "opcode-hooks")]
+use super::OpcodeHook;
+
+/// Collects the settings of a `Driver`, `build` makes it. Strict mode can
+/// only be chosen here, the rest can also be changed later with the setters
+/// of the driver.
+pub struct InterpreterBuilder {
+    strict: bool,
+    config: InterpreterConfig,
+    io: Option<Box<GuestIo>>,
+    fuel: Option<usize>,
+    breakpoint: Option<BreakpointHook>,
+    hooks: OpcodeHooks,
+}
+
+impl InterpreterBuilder {
+    pub fn new() -> Self {
+        InterpreterBuilder {
+            strict: false,
+            config: InterpreterConfig::default(),
+            io: None,
+            fuel: None,
+            breakpoint: None,
+            hooks: OpcodeHooks::default(),
+        }
+    }
+
+    /// Verify every program when it is registered, see `bc::verify`. The
+    /// driver refuses to run a rejected one, see `Driver::verify_error`.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// values the operand stack starts with room for
+    pub fn stack_capacity(mut self, capacity: usize) -> Self {
+        self.config.stack_capacity = capacity;
+        self
+    }
+
+    /// nested calls the frames start with room for
+    pub fn frames_capacity(mut self, capacity: usize) -> Self {
+        self.config.frames_capacity = capacity;
+        self
+    }
+
+    /// See `Driver::set_io`.
+    pub fn io(mut self, io: Box<GuestIo>) -> Self {
+        self.io = Some(io);
+        self
+    }
+
+    /// See `Driver::set_fuel`.
+    pub fn fuel(mut self, fuel: Option<usize>) -> Self {
+        self.fuel = fuel;
+        self
+    }
+
+    /// See `Driver::set_breakpoint_hook`.
+    pub fn breakpoint_hook(mut self, hook: BreakpointHook) -> Self {
+        self.breakpoint = Some(hook);
+        self
+    }
+
+    /// See `Driver::set_opcode_hooks`.
+    #[cfg(feature = "opcode-hooks")]
+    pub fn opcode_hooks(mut self, pre: Option<OpcodeHook>, post: Option<OpcodeHook>) -> Self {
+        self.hooks = OpcodeHooks { pre: pre, post: post };
+        self
+    }
+
+    pub fn build(self) -> Driver {
+        let mut driver = Driver::default();
+        driver.strict = self.strict;
+        driver.set_interpreter_config(self.config);
+        if let Some(io) = self.io {
+            driver.set_io(io);
+        }
+        driver.set_fuel(self.fuel);
+        driver.set_breakpoint_hook(self.breakpoint);
+        driver.runtime.hooks = self.hooks;
+        driver
+    }
+}
+
+impl Default for InterpreterBuilder {
+    fn default() -> Self {
+        InterpreterBuilder::new()
+    }
+}
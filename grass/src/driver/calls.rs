@@ -0,0 +1,176 @@
+//! Traces of whole invocations, for guest code which spends its time in
+//! calls rather than in loops. Once the callee of a call site got hot, its
+//! next invocation from that site is recorded from its first opcode up to its
+//! `Return`, and later calls from the site run the trace instead.
+//!
+//! Only invocations which neither call further functions nor loop can be
+//! traced, a call site whose recording fails is blacklisted.
+
+use bc::opt::{self, Fuel};
+use bc::bytecode::{OpCode, NumType};
+use core::objects::InstructionPointer;
+use super::{Tracer, Trace, TraceKey, TraceRef, MergePointResult, InvalidTrace, traced_opcodes};
+use super::hash::{FastHashMap, FastHashSet};
+use super::specialize::TypeState;
+
+const HOT_CALL_THRESHOLD: usize = 5;
+
+/// The call traces of a `Tracer`. Their keys are the call site as `ip` and
+/// the callee as `specialization`.
+#[derive(Default)]
+pub struct Calls {
+    counter: FastHashMap<TraceKey, usize>,
+    traces: FastHashMap<TraceKey, TraceRef>,
+    active: Option<ActiveCall>,
+}
+
+impl Calls {
+    /// Program positions recorded into call traces.
+    pub fn covered(&self) -> Vec<InstructionPointer> {
+        self.traces.values().flat_map(|trace| trace.covered.iter().cloned()).collect()
+    }
+}
+
+struct ActiveCall {
+    key: TraceKey,
+    /// number of frames while the callee runs
+    depth: usize,
+    ops: Vec<OpCode>,
+    positions: FastHashSet<InstructionPointer>,
+    types: TypeState,
+}
+
+impl Tracer {
+    /// Called once the call at `key.ip` entered its callee, which runs in
+    /// the `depth`th frame. Returns the trace to run for the invocation, or
+    /// `StartTrace` if it is recorded.
+    pub fn handle_call(&mut self, key: TraceKey, depth: usize) -> MergePointResult {
+        // the loop trace being recorded inlines the call, see `trace_opcode`
+        if self.is_recording() {
+            return MergePointResult::None;
+        }
+        if let Some(trace) = self.calls.traces.get(&key) {
+            return MergePointResult::Trace(trace.clone());
+        }
+
+        if self.calls.active.is_some() || self.blacklist.contains(&key) {
+            return MergePointResult::None;
+        }
+
+        let count = {
+            let count = self.calls.counter.entry(key).or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        if count > HOT_CALL_THRESHOLD {
+            self.calls.counter.remove(&key);
+            self.calls.active = Some(ActiveCall {
+                key: key,
+                depth: depth,
+                ops: Vec::new(),
+                positions: FastHashSet::default(),
+                types: TypeState::default(),
+            });
+            return MergePointResult::StartTrace;
+        }
+
+        MergePointResult::None
+    }
+
+    pub fn is_recording_call(&self) -> bool {
+        self.calls.active.is_some()
+    }
+
+    /// Stop recording an invocation and throw away what was recorded.
+    pub fn abort_call_trace(&mut self) {
+        self.calls.active = None;
+    }
+
+    /// Append `opcode`, which is executed at `pos` in the `depth`th frame
+    /// next, to the invocation being recorded. Opcodes of other frames are
+    /// no part of it.
+    pub fn trace_call_opcode(&mut self, opcode: &OpCode, pos: InstructionPointer, depth: usize,
+                             operands: Option<NumType>, condition: Option<bool>,
+                             callee: Option<usize>) {
+        let recording = self.calls.active.as_ref().map_or(false, |active| active.depth == depth);
+        if !recording {
+            return;
+        }
+
+        match *opcode {
+            OpCode::Return => self.finish_call_trace(pos),
+            // not blacklisted, the next invocation may take another path
+            OpCode::Breakpoint(_) => self.abort_call_trace(),
+            OpCode::Call | OpCode::CallVirtual(_) | OpCode::JumpBack(_) | OpCode::JumpBackIf(_) => {
+                let key = self.calls.active.take().unwrap().key;
+                debug!("can't trace the invocation at {:?} through {:?}", key, opcode);
+                self.blacklist.insert(key);
+            }
+            _ => {
+                let active = self.calls.active.as_mut().unwrap();
+                active.positions.insert(pos);
+                for oc in traced_opcodes(&active.types, opcode, pos, operands, condition, callee) {
+                    active.types.record(&oc);
+                    active.ops.push(oc);
+                }
+            }
+        }
+    }
+
+    // The invocation reached its `Return` at `exit`.
+    fn finish_call_trace(&mut self, exit: InstructionPointer) {
+        let mut active = self.calls.active.take().unwrap();
+        match validate_call_trace(&active.ops) {
+            Ok(()) => {
+                let mut covered: Vec<_> = active.positions.into_iter().collect();
+                covered.sort();
+                let mut fuel = Fuel::new(self.opt_fuel);
+                opt::prune_trace(&mut active.ops, &mut fuel);
+                opt::fold_pointer_moves(&mut active.ops, &mut fuel);
+                let mut trace = Trace::new(active.ops, active.key, self.generation);
+                trace.covered = covered;
+                trace.exit = Some(exit);
+                self.generation += 1;
+                self.calls.traces.insert(active.key, TraceRef::new(trace));
+            }
+            Err(reason) => {
+                debug!("discarding call trace for {:?}: {:?}", active.key, reason);
+                self.blacklist.insert(active.key);
+            }
+        }
+    }
+
+    /// The call traces, ordered by their keys.
+    pub fn call_traces(&self) -> Vec<TraceRef> {
+        let mut traces: Vec<_> = self.calls.traces.values().cloned().collect();
+        traces.sort_by_key(|trace| trace.entry_key);
+        traces
+    }
+
+    /// The trace of the invocations from a call site, see `handle_call`.
+    pub fn call_trace(&self, key: TraceKey) -> Option<TraceRef> {
+        self.calls.traces.get(&key).cloned()
+    }
+}
+
+// A call trace runs once and may leave its result on the stack, and it needs
+// no guard.
+fn validate_call_trace(trace: &[OpCode]) -> Result<(), InvalidTrace> {
+    if trace.is_empty() {
+        return Err(InvalidTrace::Empty);
+    }
+
+    let mut depth = 0;
+    for (pos, opcode) in trace.iter().enumerate() {
+        let (pops, pushes) = match opcode.stack_effect() {
+            Some(effect) => effect,
+            None => return Err(InvalidTrace::UnknownStackEffect(pos)),
+        };
+        if pops > depth {
+            return Err(InvalidTrace::StackUnderflow(pos));
+        }
+        depth = depth - pops + pushes;
+    }
+    Ok(())
+}
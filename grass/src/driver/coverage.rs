@@ -0,0 +1,73 @@
+//! Which opcodes of a program run as part of a trace, and which are always
+//! interpreted. Opcodes are only counted for traces which were recorded,
+//! seeded traces don't tell where their opcodes came from.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use bc::Program;
+use core::objects::InstructionPointer;
+
+pub struct Coverage<'a> {
+    program: &'a Program,
+    covered: BTreeSet<InstructionPointer>,
+}
+
+impl<'a> Coverage<'a> {
+    pub fn new<I>(program: &'a Program, covered: I) -> Self
+        where I: IntoIterator<Item = InstructionPointer>
+    {
+        Coverage {
+            program: program,
+            covered: covered.into_iter().collect(),
+        }
+    }
+
+    pub fn is_covered(&self, ip: InstructionPointer) -> bool {
+        self.covered.contains(&ip)
+    }
+
+    /// number of opcodes in the program, host functions have none
+    pub fn total(&self) -> usize {
+        self.program.functions.iter().map(|func| func.opcodes.len()).sum()
+    }
+
+    /// number of opcodes found in traces
+    pub fn traced(&self) -> usize {
+        self.covered.len()
+    }
+
+    /// share of the opcodes found in traces, in percent
+    pub fn percentage(&self) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => self.traced() as f64 * 100.0 / total as f64,
+        }
+    }
+}
+
+/// Disassembly of the program, with traced opcodes marked by `*`.
+impl<'a> fmt::Display for Coverage<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "{} of {} opcodes traced ({:.1}%)",
+                      self.traced(), self.total(), self.percentage()));
+        for (idx, func) in self.program.functions.iter().enumerate() {
+            if func.opcodes.is_empty() {
+                continue;
+            }
+            let traced = (0..func.opcodes.len())
+                .filter(|&pc| self.is_covered(InstructionPointer { func: idx, pc: pc }))
+                .count();
+            try!(writeln!(f, "\nfunction {}: {} of {} traced", idx, traced, func.opcodes.len()));
+            for (pc, opcode) in func.opcodes.iter().enumerate() {
+                let mark = if self.is_covered(InstructionPointer { func: idx, pc: pc }) {
+                    '*'
+                } else {
+                    ' '
+                };
+                try!(writeln!(f, "{} {:>5} {:?}", mark, pc, opcode));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+//! Notifications about what the JIT does, for monitoring which lives outside
+//! of the driver, e.g. in another thread.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use core::objects::InstructionPointer;
+use super::TraceKey;
+
+/// Something that happened to the traces of a program. Traces are identified
+/// by their generation, see `Trace::generation`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JitEvent {
+    /// the merge point got hot and recording started
+    TraceStarted { key: TraceKey },
+    /// a trace of `len` opcodes was recorded and installed
+    TraceFinished { key: TraceKey, id: usize, len: usize },
+    /// the trace was left through its opcode `trace_pc`, a guard which
+    /// recovers at `guard`, with the `GuardInfo::id` `guard_id`. Both are
+    /// within the bridge of the guard `bridge` if the trace was left there.
+    GuardFailed {
+        key: TraceKey,
+        id: usize,
+        guard: InstructionPointer,
+        guard_id: Option<usize>,
+        trace_pc: usize,
+        bridge: Option<usize>,
+    },
+    /// a bridge of `len` opcodes was recorded and attached to the guard
+    /// `guard_id` of the trace
+    BridgeFinished { key: TraceKey, id: usize, guard_id: usize, len: usize },
+    /// the trace got replaced and won't be entered anymore
+    TraceEvicted { key: TraceKey, id: usize },
+    /// the trace has all the bridges it may get, its failing guards go
+    /// back to the interpreter
+    BridgeLimitReached { key: TraceKey, id: usize },
+    /// the merge point can't be traced and won't start recording again
+    Blacklisted { key: TraceKey },
+}
+
+#[derive(Debug, Default)]
+pub struct Subscribers {
+    senders: Vec<Sender<JitEvent>>,
+}
+
+impl Subscribers {
+    pub fn subscribe(&mut self) -> Receiver<JitEvent> {
+        let (sender, receiver) = channel();
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Send `event` to every subscriber, forgetting those which hung up.
+    pub fn emit(&mut self, event: JitEvent) {
+        if self.senders.is_empty() {
+            return;
+        }
+        self.senders.retain(|sender| sender.send(event.clone()).is_ok());
+    }
+}
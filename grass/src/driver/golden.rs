@@ -0,0 +1,105 @@
+//! Golden files of the traces a guest gets. Tests of a guest write its
+//! traces out as text and compare them with a file checked in next to the
+//! test, so that a change to the tracer or the optimizer shows up in review
+//! as a change of that file. With `BLESS_VAR` set, the tests write the
+//! traces they got into the files instead.
+
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use bc::Program;
+use super::{Driver, ProgramId, TraceRef};
+
+/// environment variable which makes `check_golden` update the files
+pub const BLESS_VAR: &'static str = "GRASS_BLESS";
+
+/// The loop traces of `program`, each followed by the pieces it was split
+/// into and its bridges, and then its call traces, each in the order of
+/// their keys, one opcode per line. Guards are named after the guest opcode
+/// they were recorded for, see `GuardInfo::name`.
+pub fn dump_traces(driver: &Driver, program: ProgramId) -> String {
+    let guest = driver.program(program);
+    let mut out = String::new();
+    for trace in driver.traces(program) {
+        out.push_str(&format!("loop trace at guest pc {}, specialization {}\n",
+                              trace.entry_key.pc, trace.entry_key.specialization));
+        dump_ops(&mut out, &trace, guest);
+        for link in &trace.links {
+            out.push_str(&format!("linked piece {}\n", link.generation));
+            dump_ops(&mut out, link, guest);
+        }
+        for (id, bridge) in trace.bridges.iter().enumerate() {
+            if let Some(ref bridge) = *bridge {
+                out.push_str(&format!("bridge at guard {}\n", id));
+                dump_ops(&mut out, bridge, guest);
+            }
+        }
+    }
+    for trace in driver.call_traces(program) {
+        out.push_str(&format!("call trace at {}:{} of function {}\n",
+                              trace.entry_key.ip.func, trace.entry_key.ip.pc,
+                              trace.entry_key.specialization));
+        dump_ops(&mut out, &trace, guest);
+    }
+    out
+}
+
+fn dump_ops(out: &mut String, trace: &TraceRef, program: &Program) {
+    let mut guards = trace.guard_infos(program).into_iter().peekable();
+    for (pc, opcode) in trace.ops.iter().enumerate() {
+        out.push_str(&format!("{:4} {:?}", pc, opcode));
+        if guards.peek().map_or(false, |guard| guard.trace_pc == pc) {
+            out.push_str(&format!("  ; {}", guards.next().unwrap().name()));
+        }
+        out.push('\n');
+    }
+}
+
+/// Panic unless the file at `path` holds `actual`, or write `actual` into
+/// it if `BLESS_VAR` is set.
+pub fn check_golden<P: AsRef<Path>>(path: P, actual: &str) {
+    let path = path.as_ref();
+    if env::var_os(BLESS_VAR).is_some() {
+        if let Err(err) = write_file(path, actual) {
+            panic!("can't bless {}: {}", path.display(), err);
+        }
+        return;
+    }
+
+    let expected = match read_file(path) {
+        Ok(expected) => expected,
+        Err(err) => {
+            panic!("can't read {}: {}, run with {}=1 to create it",
+                   path.display(), err, BLESS_VAR)
+        }
+    };
+    if expected == actual {
+        return;
+    }
+    let mut expected_lines = expected.lines();
+    let mut actual_lines = actual.lines();
+    let mut line = 1;
+    loop {
+        match (expected_lines.next(), actual_lines.next()) {
+            (Some(e), Some(a)) if e == a => line += 1,
+            (e, a) => {
+                panic!("traces differ from {} at line {}:\nexpected: {}\n     got: {}\n\
+                        run with {}=1 if the change is intended",
+                       path.display(), line, e.unwrap_or("<end>"), a.unwrap_or("<end>"),
+                       BLESS_VAR)
+            }
+        }
+    }
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut content = String::new();
+    try!(try!(File::open(path)).read_to_string(&mut content));
+    Ok(content)
+}
+
+fn write_file(path: &Path, content: &str) -> io::Result<()> {
+    try!(File::create(path)).write_all(content.as_bytes())
+}
@@ -0,0 +1,58 @@
+//! Hotness of merge points, kept across runs.
+//!
+//! The format is plain text, one merge point per line: the fields of its
+//! `TraceKey` except the program, followed by the number of times it was
+//! hit, separated by spaces.
+
+use std::io::{self, BufRead, Write};
+
+use core::objects::InstructionPointer;
+use super::ProgramId;
+use super::trace::TraceKey;
+
+pub fn write_hotness<W: Write>(out: &mut W, hotness: &[(TraceKey, usize)]) -> io::Result<()> {
+    for &(key, hits) in hotness {
+        try!(writeln!(out, "{} {} {} {} {}",
+                      key.ip.func, key.ip.pc, key.pc, key.specialization, hits));
+    }
+    Ok(())
+}
+
+/// Read the hotness written by `write_hotness`, for the merge points of
+/// `program`.
+pub fn read_hotness<R: BufRead>(input: R, program: ProgramId)
+                                -> io::Result<Vec<(TraceKey, usize)>> {
+    let mut hotness = Vec::new();
+
+    for line in input.lines() {
+        let line = try!(line);
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let fields: Option<Vec<u64>> = line.split_whitespace()
+            .map(|field| field.parse().ok())
+            .collect();
+        match fields {
+            Some(ref fields) if fields.len() == 5 => {
+                let key = TraceKey {
+                    program: program,
+                    ip: InstructionPointer {
+                        func: fields[0] as usize,
+                        pc: fields[1] as usize,
+                    },
+                    pc: fields[2] as usize,
+                    specialization: fields[3],
+                };
+                hotness.push((key, fields[4] as usize));
+            }
+            _ => {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("malformed hotness entry: {:?}", line)));
+            }
+        }
+    }
+
+    Ok(hotness)
+}
@@ -0,0 +1,47 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+/// Time as seen by the guest through `InternalFunc::Clock`.
+///
+/// wasm32 has no clock of its own, hosts there pass one which asks the
+/// embedder, e.g. `performance.now()` in a browser.
+pub trait GuestClock {
+    /// Monotonic time in nanoseconds since some fixed point.
+    fn nanos(&mut self) -> u64;
+}
+
+/// The monotonic clock of the host system, counting from its creation.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub struct SystemClock {
+    epoch: Instant,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock { epoch: Instant::now() }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl GuestClock for SystemClock {
+    fn nanos(&mut self) -> u64 {
+        let elapsed = self.epoch.elapsed();
+        elapsed.as_secs() * 1_000_000_000 + elapsed.subsec_nanos() as u64
+    }
+}
+
+/// Advances by one nanosecond per reading. It keeps guest programs
+/// deterministic and is the default where there is no system clock.
+#[derive(Debug, Default)]
+pub struct StepClock {
+    now: u64,
+}
+
+impl GuestClock for StepClock {
+    fn nanos(&mut self) -> u64 {
+        self.now += 1;
+        self.now
+    }
+}
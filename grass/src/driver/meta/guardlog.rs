@@ -0,0 +1,133 @@
+//! Samples of guard failures for diagnosing long runs. Only every nth
+//! failure is written, with the whole guest stack at it, and the log stops
+//! once it reaches its size limit.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, BufWriter};
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+
+use driver::{GuardExit, TraceKey};
+use core::cell::SharedCell;
+use core::objects::{CallFrame, R_BoxedValue};
+
+pub struct GuardLog {
+    out: Box<Write>,
+    every: usize,
+    max_bytes: usize,
+    failures: usize,
+    written: usize,
+    sampled: usize,
+    dropped: usize,
+}
+
+impl GuardLog {
+    /// Write every `every`th guard failure to `out`, no more than
+    /// `max_bytes` bytes in all. A sample which doesn't fit is dropped
+    /// whole.
+    pub fn new(out: Box<Write>, every: usize, max_bytes: usize) -> Self {
+        assert!(every > 0, "sample at least every failure");
+        GuardLog {
+            out: out,
+            every: every,
+            max_bytes: max_bytes,
+            failures: 0,
+            written: 0,
+            sampled: 0,
+            dropped: 0,
+        }
+    }
+
+    /// A log written to the file at `path`, see `new`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn create<P: AsRef<Path>>(path: P, every: usize, max_bytes: usize) -> io::Result<Self> {
+        let out = BufWriter::new(try!(File::create(path)));
+        Ok(GuardLog::new(Box::new(out), every, max_bytes))
+    }
+
+    /// guard failures seen, also those which weren't sampled
+    pub fn failures(&self) -> usize {
+        self.failures
+    }
+
+    /// samples written
+    pub fn sampled(&self) -> usize {
+        self.sampled
+    }
+
+    /// samples left out because the log was full
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// The trace of `key` was left at `exit`, with the guest in `frames`,
+    /// the outermost first.
+    pub fn record(&mut self, key: TraceKey, exit: &GuardExit, frames: &[CallFrame]) {
+        self.failures += 1;
+        if self.failures % self.every != 0 {
+            return;
+        }
+        let sample = format_sample(self.failures, key, exit, frames);
+        if self.written + sample.len() > self.max_bytes {
+            self.dropped += 1;
+            return;
+        }
+        // the guest doesn't fail for its diagnostics, a broken log only
+        // stops taking samples
+        match self.out.write_all(sample.as_bytes()).and_then(|_| self.out.flush()) {
+            Ok(()) => {
+                self.written += sample.len();
+                self.sampled += 1;
+            }
+            Err(_) => {
+                self.written = self.max_bytes;
+                self.dropped += 1;
+            }
+        }
+    }
+}
+
+fn format_sample(nth: usize, key: TraceKey, exit: &GuardExit, frames: &[CallFrame]) -> String {
+    let recovery = exit.recovery();
+    let mut sample = format!("guard failure {} in trace of program {} at {}:{} guest pc {}, \
+                              trace pc {}, continues at {}:{}\n",
+                             nth,
+                             key.program.0,
+                             key.ip.func,
+                             key.ip.pc,
+                             key.pc,
+                             exit.trace_pc,
+                             recovery.func,
+                             recovery.pc);
+    sample.push_str(&format!("  stack {}\n", show_values(&exit.stack_snapshot)));
+    for (depth, frame) in frames.iter().rev().enumerate() {
+        let locals: Vec<_> = frame.locals
+            .iter()
+            .enumerate()
+            .map(|(idx, local)| match frame.local_name(idx) {
+                Some(name) => format!("{}={}", name, local.get()),
+                None => local.get().to_string(),
+            })
+            .collect();
+        let locals = format!("[{}]", locals.join(", "));
+        match frame.return_addr {
+            Some(ret) => {
+                sample.push_str(&format!("  frame {} returns to {}:{}, locals {}\n",
+                                         depth,
+                                         ret.func,
+                                         ret.pc,
+                                         locals))
+            }
+            None => sample.push_str(&format!("  frame {}, locals {}\n", depth, locals)),
+        }
+    }
+    sample
+}
+
+fn show_values(values: &[R_BoxedValue]) -> String {
+    let values: Vec<_> = values.iter().map(|val| val.to_string()).collect();
+    format!("[{}]", values.join(", "))
+}
@@ -0,0 +1,99 @@
+//! The last opcodes the interpreter executed, kept as context for when a
+//! guest fails. Recording an opcode only overwrites a slot of a buffer of
+//! fixed size, nothing is formatted until the history is shown.
+
+use std::fmt;
+
+use bc::bytecode::OpCode;
+use core::objects::InstructionPointer;
+
+/// Number of opcodes a `History` keeps unless the host chooses otherwise.
+pub const DEFAULT_HISTORY_LEN: usize = 64;
+
+/// Where an opcode was executed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Interpreted(InstructionPointer),
+    /// position in the trace which ran
+    Traced(usize),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Location::Interpreted(ip) => write!(f, "fn {} pc {}", ip.func, ip.pc),
+            Location::Traced(pc) => write!(f, "trace pc {}", pc),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExecEvent {
+    pub opcode: &'static str,
+    pub at: Location,
+    /// height of the operand stack before the opcode
+    pub depth: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    events: Vec<ExecEvent>,
+    // slot of the next event, the oldest one once the buffer is full
+    next: usize,
+    len: usize,
+}
+
+impl History {
+    /// Keep the last `len` opcodes, nothing if it is 0.
+    pub fn new(len: usize) -> Self {
+        History {
+            events: Vec::with_capacity(len),
+            next: 0,
+            len: len,
+        }
+    }
+
+    #[inline]
+    pub fn record(&mut self, opcode: &OpCode, at: Location, depth: usize) {
+        if self.len == 0 {
+            return;
+        }
+        let event = ExecEvent {
+            opcode: opcode.name(),
+            at: at,
+            depth: depth,
+        };
+        if self.events.len() < self.len {
+            self.events.push(event);
+        } else {
+            self.events[self.next] = event;
+        }
+        self.next = (self.next + 1) % self.len;
+    }
+
+    /// The recorded opcodes, the oldest first.
+    pub fn events(&self) -> Vec<ExecEvent> {
+        let (newer, older) = self.events.split_at(self.next);
+        older.iter().chain(newer).cloned().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+        self.next = 0;
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_HISTORY_LEN)
+    }
+}
+
+impl fmt::Display for History {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for event in self.events() {
+            try!(writeln!(f, "{:>18} {:>14} depth {}", event.at.to_string(), event.opcode, event.depth));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+//! Callbacks of the embedder before and after every interpreted opcode, only
+//! with the `opcode-hooks` feature. Without it `OpcodeHooks` does nothing and
+//! the calls are compiled away.
+
+use grass_core::interp as basic;
+
+use bc::bytecode::OpCode;
+use core::objects::InstructionPointer;
+
+/// Sees the interpreter with the opcode at the position which runs next, or
+/// which just ran.
+pub type OpcodeHook = Box<Fn(&basic::Interpreter, &OpCode, InstructionPointer)>;
+
+/// Sees the interpreter stopped at the `OpCode::Breakpoint` with the id. Not
+/// behind the feature, breakpoints are always interpreted.
+pub type BreakpointHook = Box<Fn(&basic::Interpreter, usize)>;
+
+/// Asked at the end of every iteration of a loop trace whether the host
+/// wants control back.
+pub type YieldHook = Box<Fn() -> bool>;
+
+#[cfg(feature = "opcode-hooks")]
+#[derive(Default)]
+pub struct OpcodeHooks {
+    pub pre: Option<OpcodeHook>,
+    pub post: Option<OpcodeHook>,
+}
+
+#[cfg(feature = "opcode-hooks")]
+impl OpcodeHooks {
+    pub fn pre(&self, interp: &basic::Interpreter, opcode: &OpCode, ip: InstructionPointer) {
+        if let Some(ref hook) = self.pre {
+            hook(interp, opcode, ip);
+        }
+    }
+
+    pub fn post(&self, interp: &basic::Interpreter, opcode: &OpCode, ip: InstructionPointer) {
+        if let Some(ref hook) = self.post {
+            hook(interp, opcode, ip);
+        }
+    }
+}
+
+#[cfg(not(feature = "opcode-hooks"))]
+#[derive(Default)]
+pub struct OpcodeHooks;
+
+#[cfg(not(feature = "opcode-hooks"))]
+impl OpcodeHooks {
+    #[inline(always)]
+    pub fn pre(&self, _interp: &basic::Interpreter, _opcode: &OpCode, _ip: InstructionPointer) {}
+
+    #[inline(always)]
+    pub fn post(&self, _interp: &basic::Interpreter, _opcode: &OpCode, _ip: InstructionPointer) {}
+}
@@ -1,290 +1,865 @@
 
 
 
-use std::rc::Rc;
-use std::cell::RefCell;
 use std::io;
 use std::io::Write;
+use std::mem;
+
+
+use grass_core::interp as basic;
+use grass_core::interp::{PC_LOCAL, USER_PROGRAM_LOCAL};
+pub use grass_core::interp::{StackVal, DispatchResult, InterpreterConfig};
+
+use driver::{Tracer, Trace, TraceKey, GuardExit, ProgramId, MergePointResult, HashValue};
+use driver::ThreadStats;
+use super::error::{InterpResult, ErrorKind, Effect};
+use super::clock::GuestClock;
+use super::guardlog::GuardLog;
+use super::history::{History, Location};
+use super::hooks::{OpcodeHooks, BreakpointHook, YieldHook};
+use super::interrupt::InterruptHandle;
+#[cfg(not(target_arch = "wasm32"))]
+use super::clock::SystemClock;
+#[cfg(target_arch = "wasm32")]
+use super::clock::StepClock;
+use super::io::GuestIo;
+#[cfg(not(target_arch = "wasm32"))]
+use super::io::StdIo;
+#[cfg(target_arch = "wasm32")]
+use super::io::MemoryIo;
+use super::profile::{OpcodeProfile, OpTimer, GuestProfile};
+use super::rng::Rng;
+
+use bc::Program;
+use bc::decode::Code;
+use bc::bytecode::{OpCode, InternalFunc, Guard, NumType};
+use core::cell::SharedCell;
+use core::objects::{R_BoxedValue, CallFrame, InstructionPointer, Heap};
+
+
+// Type of the operands of a `BinOp` which is about to run, if both have the
+// same, for the tracer to specialize it.
+fn operand_type(opcode: &OpCode, stack: &[StackVal]) -> Option<NumType> {
+    match *opcode {
+        OpCode::BinOp(_) => operands_type(stack),
+        _ => None,
+    }
+}
 
+// Value of the condition of a conditional jump which is about to run, for
+// the tracer to guard on it.
+fn branch_condition(opcode: &OpCode, stack: &[StackVal]) -> Option<bool> {
+    match *opcode {
+        OpCode::SkipIf(_) | OpCode::JumpBackIf(_) => condition(stack),
+        _ => None,
+    }
+}
 
-use driver::Tracer;
-
-use bc::bytecode::{OpCode, BinOp, InternalFunc, Guard};
-use core::objects::{R_BoxedValue, CallFrame, R_Pointer, R_Function, R_Struct, InstructionPointer};
+// Function a `Call` which is about to run enters, or vtable a `CallVirtual`
+// dispatches through, for the tracer to guard on it.
+fn called_function(opcode: &OpCode, stack: &[StackVal]) -> Option<usize> {
+    match *opcode {
+        OpCode::Call => callee(stack),
+        OpCode::CallVirtual(_) => vtable(stack),
+        _ => None,
+    }
+}
 
+fn is_call(opcode: &OpCode) -> bool {
+    match *opcode {
+        OpCode::Call | OpCode::CallVirtual(_) => true,
+        _ => false,
+    }
+}
 
-#[derive(Debug, Clone, PartialEq)]
-pub enum StackVal {
-    Owned(R_BoxedValue),
-    Ref(Rc<RefCell<R_BoxedValue>>),
+fn callee(stack: &[StackVal]) -> Option<usize> {
+    match top(stack) {
+        Some(R_BoxedValue::Func(idx)) => Some(idx),
+        _ => None,
+    }
 }
 
-impl StackVal {
-    // to_value should be clone
-    pub fn into_owned(self) -> Self {
-        match self {
-            StackVal::Owned(..) => self,
-            StackVal::Ref(cell) => StackVal::Owned(cell.borrow().clone()),
-        }
+fn vtable(stack: &[StackVal]) -> Option<usize> {
+    match top(stack) {
+        Some(R_BoxedValue::VTable(idx)) => Some(idx),
+        _ => None,
     }
+}
 
-    pub fn into_cell(self) -> Self {
-        match self {
-            StackVal::Owned(boxed) => StackVal::Ref(Rc::new(RefCell::new(boxed))),
-            StackVal::Ref(..) => self,
-        }
+fn top(stack: &[StackVal]) -> Option<R_BoxedValue> {
+    match stack.last() {
+        Some(&StackVal::Owned(ref val)) => Some(val.clone()),
+        Some(&StackVal::Ref(ref cell)) => Some(cell.get()),
+        None => None,
     }
+}
 
-    // self has to be owned
-    pub fn unwrap_value(self) -> R_BoxedValue {
-        if let StackVal::Owned(val) = self {
-            val
-        } else {
-            panic!("expected owned val");
-        }
+fn condition(stack: &[StackVal]) -> Option<bool> {
+    let val = match stack.last() {
+        Some(&StackVal::Owned(ref val)) => val.clone(),
+        Some(&StackVal::Ref(ref cell)) => cell.get(),
+        None => return None,
+    };
+    match val {
+        R_BoxedValue::Bool(value) => Some(value),
+        _ => None,
     }
+}
 
-    pub fn unwrap_cell(self) -> Rc<RefCell<R_BoxedValue>> {
-        if let StackVal::Ref(boxed) = self {
-            boxed
-        } else {
-            panic!("expected ref val");
-        }
+fn operands_type(stack: &[StackVal]) -> Option<NumType> {
+    let ty = |val: &StackVal| match *val {
+        StackVal::Owned(ref val) => NumType::of(val),
+        StackVal::Ref(ref cell) => cell.with(NumType::of),
+    };
+    match stack.len() {
+        0 | 1 => None,
+        len if ty(&stack[len - 2]) == ty(&stack[len - 1]) => ty(&stack[len - 1]),
+        _ => None,
     }
+}
 
-    /// Address as Value
-    pub fn into_pointer(self) -> Self {
-        let cell = self.unwrap_cell();
-        StackVal::Owned(R_BoxedValue::Ptr(R_Pointer { cell: cell }))
+fn castable(stack: &[StackVal], ty: NumType) -> bool {
+    match stack.last() {
+        Some(&StackVal::Owned(ref val)) => ty.cast(val).is_some(),
+        Some(&StackVal::Ref(ref cell)) => cell.with(|val| ty.cast(val).is_some()),
+        None => false,
     }
+}
 
-    /// Deref pointer
-    pub fn deref(self) -> Self {
-        // self contains an owned R_Pointer
-        let val = self.into_owned().unwrap_value();
-        if let R_BoxedValue::Ptr(ptr) = val {
-            StackVal::Ref(ptr.cell)
-        } else {
-            panic!("expected val to be pointer, got {:?}", val);
-        }
+// Recording stops at breakpoints, but seeded traces may have one.
+fn has_breakpoint(trace: &[OpCode]) -> bool {
+    trace.iter().any(|opcode| match *opcode {
+        OpCode::Breakpoint(_) => true,
+        _ => false,
+    })
+}
+
+// tell the tracer about a taken jump
+fn jumped(tracer: &mut Option<&mut Tracer>, func: usize, pc: usize, backwards: bool) {
+    if let Some(ref mut tracer) = *tracer {
+        tracer.jump_target(InstructionPointer { func: func, pc: pc }, backwards);
     }
 }
 
-// type Program<'a> = &'a [&'a (usize, usize, [OpCode])];
-type Program = Vec<(usize, usize, Vec<OpCode>)>;
 
 
-pub struct Interpreter<'a> {
-    pub program: &'a Program,
+/// State of the guest machine which outlives a single `Interpreter`.
+pub struct Runtime {
+    pub io: Box<GuestIo>,
+    /// read by `InternalFunc::Clock`
+    pub clock: Box<GuestClock>,
+    pub rng: Rng,
+    /// memory of guest allocated structs
+    pub heap: Heap,
+    /// deny I/O and calls of host functions, see `Effect`
+    pub sandbox: bool,
+    /// stays empty unless built with the `profile-opcodes` feature
+    pub profile: OpcodeProfile,
+    /// time per guest function, see `OpCode::ProfileEnter`
+    pub guest_profile: GuestProfile,
+    /// called around every interpreted opcode
+    pub hooks: OpcodeHooks,
+    /// called at every interpreted `OpCode::Breakpoint`
+    pub breakpoint: Option<BreakpointHook>,
+    /// iterations after which a loop trace is left, see `Driver::set_max_iterations`
+    pub max_iterations: Option<usize>,
+    /// called at every `OpCode::LoopBack`, the trace is left if it returns true
+    pub should_yield: Option<YieldHook>,
+    /// polled at every `OpCode::LoopBack` and guest function entry
+    pub interrupt: InterruptHandle,
+    /// nanoseconds on `clock` a run may take, see `Driver::set_timeout`
+    pub timeout: Option<u64>,
+    /// opcodes a run may execute, see `Driver::set_fuel`
+    pub fuel: Option<usize>,
+    /// the last executed opcodes
+    pub history: History,
+    /// samples of guard failures, see `Driver::set_guard_log`
+    pub guard_log: Option<GuardLog>,
+    /// what the buffers of new interpreters start with
+    pub config: InterpreterConfig,
+    /// the buffers of the previous interpreter, see `Interpreter::new`
+    buffers: Option<(Vec<StackVal>, Vec<CallFrame>)>,
+    /// how often the traces were entered and left on this thread
+    pub stats: ThreadStats,
+}
 
-    // working stack of the interpreter
-    pub stack: Vec<StackVal>,
+// wasm32 has neither standard streams nor a clock
+#[cfg(not(target_arch = "wasm32"))]
+fn default_io() -> Box<GuestIo> {
+    Box::new(StdIo)
+}
 
-    // the stack of the interpreted program, consisting of frames
-    pub stack_frames: Vec<CallFrame>,
+#[cfg(target_arch = "wasm32")]
+fn default_io() -> Box<GuestIo> {
+    Box::new(MemoryIo::default())
 }
 
-impl<'a> Interpreter<'a> {
-    pub fn new(program: &'a Program) -> Self {
-        Interpreter {
-            program: program,
-            stack: Vec::new(),
-            stack_frames: Vec::new(),
+#[cfg(not(target_arch = "wasm32"))]
+fn default_clock() -> Box<GuestClock> {
+    Box::new(SystemClock::default())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn default_clock() -> Box<GuestClock> {
+    Box::new(StepClock::default())
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Runtime {
+            io: default_io(),
+            clock: default_clock(),
+            rng: Rng::default(),
+            heap: Heap::default(),
+            sandbox: false,
+            profile: OpcodeProfile::default(),
+            guest_profile: GuestProfile::default(),
+            hooks: OpcodeHooks::default(),
+            breakpoint: None,
+            max_iterations: None,
+            should_yield: None,
+            interrupt: InterruptHandle::default(),
+            timeout: None,
+            fuel: None,
+            history: History::default(),
+            guard_log: None,
+            config: InterpreterConfig::default(),
+            buffers: None,
+            stats: ThreadStats::default(),
         }
     }
+}
 
-    pub fn run(&mut self, mut tracer: Option<&mut Tracer>, func_idx: usize, idx: usize) {
-        let mut pc: usize = idx;
-        let mut func_pointer = func_idx;
+impl Runtime {
+    /// Start the next interpreter with buffers of the sizes in `config`.
+    pub fn set_config(&mut self, config: InterpreterConfig) {
+        self.config = config;
+        self.buffers = None;
+    }
+}
 
-        loop {
-            if func_pointer == func_idx && pc < idx {
-                // exit trace after jumping back
-                return;
-            }
+/// The basic interpreter of `grass_core` with a tracer and the `Runtime` of
+/// the driver.
+pub struct Interpreter<'a> {
+    /// executes the opcodes. While a trace runs, `basic.ip` stays at the
+    /// position the trace was entered from.
+    pub basic: basic::Interpreter<'a>,
+    pub runtime: &'a mut Runtime,
+
+    /// copy the locals at the start of every trace iteration, so that a
+    /// failing iteration can be undone with `restore_snapshot`
+    pub take_snapshots: bool,
+    /// the number of frames and the locals of the last one
+    snapshot: Option<(usize, Vec<R_BoxedValue>)>,
+    /// time on the clock of the runtime at which the run times out
+    deadline: Option<u64>,
+    /// opcodes left to the run
+    fuel: Option<usize>,
+    /// the run did guest I/O or drew random numbers, which can't be undone
+    pub had_effects: bool,
+}
 
+impl<'a> Drop for Interpreter<'a> {
+    fn drop(&mut self) {
+        self.runtime.buffers = Some(self.basic.take_buffers());
+    }
+}
 
-            let opcode = self.program[func_pointer].2[pc].clone();
-            {
-                tracer.as_mut().map(|mut t| t.trace_opcode(&opcode,
-                    InstructionPointer {
-                        func: func_pointer,
-                        pc: pc,
-                    }
-                ));
+impl<'a> Interpreter<'a> {
+    /// The operand stack and frames reuse the buffers of the previous
+    /// interpreter of `runtime`, which it returns when dropped.
+    pub fn new(program: &'a Program, code: &'a Code, runtime: &'a mut Runtime) -> Self {
+        let heap = runtime.heap.clone();
+        let mut basic = match runtime.buffers.take() {
+            Some(buffers) => {
+                let mut basic = basic::Interpreter::new(program, code, heap);
+                basic.reuse_buffers(buffers);
+                basic
             }
+            None => basic::Interpreter::with_config(program, code, heap, &runtime.config),
+        };
+        basic.sandbox = runtime.sandbox;
+        let deadline = match runtime.timeout {
+            Some(timeout) => Some(runtime.clock.nanos().saturating_add(timeout)),
+            None => None,
+        };
+        let fuel = runtime.fuel;
+        Interpreter {
+            basic: basic,
+            runtime: runtime,
+            take_snapshots: false,
+            snapshot: None,
+            deadline: deadline,
+            fuel: fuel,
+            had_effects: false,
+        }
+    }
 
-            match opcode {
-                OpCode::Panic => panic!("assertion failed"),
-
-                OpCode::ConstValue(val) => {
-                    self.stack.push(StackVal::Owned(val));
+    /// Reset the locals of the current frame to the start of the trace
+    /// iteration which was left, dropping the frames of calls it was left
+    /// in. Returns false without a snapshot.
+    pub fn restore_snapshot(&mut self) -> bool {
+        let snapshot = self.snapshot.take();
+        if let Some((frames, _)) = snapshot {
+            self.basic.stack_frames.truncate(frames);
+        }
+        match (snapshot, self.basic.stack_frames.last()) {
+            (Some((_, values)), Some(frame)) => {
+                // write into the cells, pointers to them stay valid
+                for (local, value) in frame.locals.iter().zip(values) {
+                    local.set(value);
                 }
+                self.basic.stack.clear();
+                true
+            }
+            _ => false,
+        }
+    }
 
-                OpCode::Tuple(size) => self.o_tuple(size),
-                OpCode::TupleInit(size) => self.o_tuple_init(size),
-                OpCode::TupleGet(idx) => self.o_tuple_get(idx),
-                OpCode::TupleSet(idx) => self.o_tuple_set(idx),
+    /// Interpret from `ip` until the calls a trace was left in returned to
+    /// the `frames`th frame. Returns where that frame continues.
+    pub fn finish_calls(&mut self, mut ip: InstructionPointer, frames: usize)
+                        -> InterpResult<InstructionPointer> {
+        while self.basic.stack_frames.len() > frames {
+            self.basic.ip = ip;
+            let program = self.basic.program;
+            let opcode = &program.functions[ip.func].opcodes[ip.pc];
+            let depth = self.basic.stack.len();
+            self.runtime.history.record(opcode, Location::Interpreted(ip), depth);
+            self.burn_fuel();
+            match try!(self.execute(opcode, &mut None)) {
+                DispatchResult::Next => ip.pc += 1,
+                DispatchResult::Jump(target) => ip = target,
+                DispatchResult::Stop => break,
+            }
+        }
+        Ok(ip)
+    }
 
-                // XXX: proper implementation of unsize
-                OpCode::Unsize | OpCode::Use => {
-                    let val = self.stack.pop().unwrap().into_owned();
-                    self.stack.push(val);
-                }
+    pub fn run(&mut self, mut tracer: Option<&mut Tracer>, func_idx: usize, idx: usize)
+               -> InterpResult<()> {
+        let mut ip = InstructionPointer { func: func_idx, pc: idx };
+        let mut timer = OpTimer::new();
 
-                OpCode::Ref => self.o_ref(),
+        loop {
+            if ip.func == func_idx && ip.pc < idx {
+                // exit trace after jumping back
+                timer.stop(&mut self.runtime.profile);
+                return Ok(());
+            }
 
-                OpCode::Deref => self.o_deref(),
+            self.basic.ip = ip;
+            let program = self.basic.program;
+            let opcode = &program.functions[ip.func].opcodes[ip.pc];
+            timer.start(&mut self.runtime.profile, opcode);
+            let depth = self.basic.stack.len();
+            self.runtime.history.record(opcode, Location::Interpreted(ip), depth);
+            self.burn_fuel();
+            if let Some(ref mut tracer) = tracer {
+                let operands = operand_type(opcode, &self.basic.stack);
+                let condition = branch_condition(opcode, &self.basic.stack);
+                let callee = called_function(opcode, &self.basic.stack);
+                tracer.trace_opcode(opcode, ip, operands, condition, callee);
+            }
 
-                OpCode::Load(local_index) => self.o_load(local_index),
+            match try!(self.execute(opcode, &mut tracer)) {
+                DispatchResult::Next => ip.pc += 1,
+                DispatchResult::Jump(target) => ip = target,
+                DispatchResult::Stop => break,
+            }
+        }
 
-                OpCode::Store(local_index) => self.o_store(local_index),
+        timer.stop(&mut self.runtime.profile);
+        Ok(())
+    }
+
+    /// Execute function `func_idx` of the program until it returns, and
+    /// return its result.
+    ///
+    /// Reaching the merge point of the program works like a call of
+    /// `Driver::merge_point` by the host: hot loops get traced and their
+    /// traces are run. The call of the merge point itself does nothing.
+    /// Without a `tracer` the program is only interpreted.
+    pub fn run_program(&mut self, mut tracer: Option<&mut Tracer>, program: ProgramId,
+                       func_idx: usize)
+                       -> InterpResult<R_BoxedValue> {
+        let entry = InstructionPointer { func: self.basic.program.entry.0, pc: self.basic.program.entry.1 };
+        let mut ip = InstructionPointer { func: func_idx, pc: 0 };
+        let program_id = program;
+        // the trace was left at a breakpoint, interpret the iteration
+        let mut at_breakpoint = false;
 
-                OpCode::Call => {
-                    // load and activate func
-                    func_pointer = self.o_call(func_pointer, pc);
-                    // jump to first instruction of function
-                    // continue is necessary because else pc += 1 would be executed
-                    pc = 0;
+        loop {
+            if ip == entry && !at_breakpoint {
+                let key = try!(self.merge_point_key(program, entry));
+                self.record_visit(key);
+                let res = tracer.as_mut().map(|tracer| tracer.handle_mergepoint(key));
+                if let Some(MergePointResult::Trace(trace)) = res {
+                    debug_assert_eq!(trace.entry_key.program, program_id);
+                    trace.stats.record_entry();
+                    self.record_entry(key);
+                    self.basic.ip = ip;
+                    // continue in the interpreter from the guard
+                    let exit = try!(self.run_trace(&trace));
+                    trace.stats.record_iterations(exit.iterations);
+                    self.record_run(key, exit.iterations);
+                    // the host had its say in the hook, the interpreter
+                    // carries on up to the next entry
+                    if !exit.yielded {
+                        trace.record_exit(&exit);
+                        self.record_guard_failure(key);
+                        self.log_guard_failure(key, &exit);
+                        if let Some(ref mut tracer) = tracer {
+                            tracer.invalidate_if_failing(key);
+                        }
+                    }
+                    at_breakpoint = exit.breakpoint.is_some();
+                    ip = exit.recovery();
                     continue;
                 }
+            }
+            at_breakpoint = false;
 
-                OpCode::Static(static_idx) => {
-                    func_pointer = self.o_load_static(static_idx, func_pointer, pc);
-                    pc = 0;
-                    continue;
-                }
+            self.basic.ip = ip;
+            let program = self.basic.program;
+            let opcode = &program.functions[ip.func].opcodes[ip.pc];
 
-                OpCode::Return => {
-                    if let Some(ret) = self.o_return() {
-                        func_pointer = ret.func;
-                        pc = ret.pc;
-                    } else {
-                        break;
-                    }
-                }
+            if *opcode == OpCode::Call && self.basic.calls_merge_point() {
+                self.basic.skip_merge_point();
+                ip.pc += 1;
+                continue;
+            }
 
-                OpCode::Skip(n) => {
-                    pc += n;
-                    continue;
-                }
-                OpCode::JumpBack(n) => {
-                    pc -= n;
+            let depth = self.basic.stack.len();
+            self.runtime.history.record(opcode, Location::Interpreted(ip), depth);
+            self.burn_fuel();
+            let operands = operand_type(opcode, &self.basic.stack);
+            let condition = branch_condition(opcode, &self.basic.stack);
+            let callee = called_function(opcode, &self.basic.stack);
+            let frames = self.basic.stack_frames.len();
+            if let Some(ref mut tracer) = tracer {
+                tracer.trace_opcode(opcode, ip, operands, condition, callee);
+                tracer.trace_call_opcode(opcode, ip, frames, operands, condition, callee);
+            }
+            let res = try!(self.execute(opcode, &mut tracer));
+
+            if let (true, DispatchResult::Jump(callee)) = (is_call(opcode), res) {
+                if let Some(next) = try!(self.enter_call(&mut tracer, program_id, ip, callee)) {
+                    ip = next;
                     continue;
                 }
+            }
 
-                OpCode::SkipIf(n) => {
-                    let val = self.pop_value();
-                    if let R_BoxedValue::Bool(b) = val {
-                        if b {
-                            pc += n;
-                            continue;
+            match res {
+                DispatchResult::Next => ip.pc += 1,
+                DispatchResult::Jump(target) => {
+                    // back at the top of the loop, same as the end of `run`
+                    if target.func == entry.func && target.pc < entry.pc {
+                        if let Some(ref mut tracer) = tracer {
+                            if tracer.is_recording() {
+                                tracer.finish_trace();
+                            }
                         }
-                    } else {
-                        panic!("expected bool, git {:?}", val);
                     }
+                    ip = target;
                 }
-                OpCode::JumpBackIf(n) => {
-                    let val = self.pop_value();
-                    if let R_BoxedValue::Bool(b) = val {
-                        if b {
-                            pc -= n;
-                            continue;
-                        }
-                    } else {
-                        panic!("expected bool, git {:?}", val);
+                DispatchResult::Stop => break,
+            }
+        }
+
+        Ok(self.basic.result())
+    }
+
+    // The call at `site` entered `callee`, run the trace of the invocation if
+    // there is one. Returns where to continue after it.
+    fn enter_call(&mut self, tracer: &mut Option<&mut Tracer>, program: ProgramId,
+                  site: InstructionPointer, callee: InstructionPointer)
+                  -> InterpResult<Option<InstructionPointer>> {
+        let key = TraceKey {
+            program: program,
+            ip: site,
+            pc: 0,
+            specialization: callee.func as HashValue,
+        };
+        let depth = self.basic.stack_frames.len();
+        let res = match *tracer {
+            Some(ref mut tracer) => tracer.handle_call(key, depth),
+            None => return Ok(None),
+        };
+
+        match res {
+            MergePointResult::Trace(trace) => {
+                debug_assert_eq!(trace.entry_key.program, program);
+                trace.stats.record_entry();
+                self.record_entry(key);
+                self.basic.ip = callee;
+                match try!(self.run_call_trace(&trace.ops)) {
+                    Some(exit) => {
+                        trace.record_guard_failure(exit.trace_pc);
+                        self.record_guard_failure(key);
+                        self.log_guard_failure(key, &exit);
+                        Ok(Some(exit.recovery()))
                     }
+                    None => Ok(trace.exit),
                 }
+            }
+            _ => Ok(None),
+        }
+    }
 
-                OpCode::GetIndex => self.o_get_index(),
-                OpCode::AssignIndex => self.o_assign_index(),
+    /// Sample the failure of the guard of `exit` into the guard log, if
+    /// there is one.
+    pub fn log_guard_failure(&mut self, key: TraceKey, exit: &GuardExit) {
+        if let Some(ref mut log) = self.runtime.guard_log {
+            log.record(key, exit, &self.basic.stack_frames);
+        }
+    }
 
-                OpCode::Array(size) => self.o_array(size),
+    // count for the thread, there are no traces without the JIT
+    #[cfg(not(feature = "no-jit"))]
+    fn record_entry(&self, key: TraceKey) {
+        self.runtime.stats.record_entry(key);
+    }
+
+    #[cfg(not(feature = "no-jit"))]
+    fn record_guard_failure(&self, key: TraceKey) {
+        self.runtime.stats.record_guard_failure(key);
+    }
+
+    #[cfg(not(feature = "no-jit"))]
+    fn record_visit(&mut self, key: TraceKey) {
+        self.runtime.stats.record_visit(key, &mut *self.runtime.clock);
+    }
+
+    /// See `ThreadStats::record_run`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn record_run(&mut self, key: TraceKey, iterations: usize) {
+        self.runtime.stats.record_run(key, iterations, &mut *self.runtime.clock);
+    }
 
-                OpCode::Repeat(size) => self.o_repeat(size),
+    #[cfg(feature = "no-jit")]
+    fn record_entry(&self, _key: TraceKey) {}
 
-                OpCode::Len => self.o_len(),
+    #[cfg(feature = "no-jit")]
+    fn record_visit(&mut self, _key: TraceKey) {}
 
-                OpCode::BinOp(kind) => self.o_binop(kind),
-                OpCode::CheckedBinOp(kind) => self.o_checked_binop(kind),
+    #[cfg(feature = "no-jit")]
+    pub fn record_run(&mut self, _key: TraceKey, _iterations: usize) {}
 
-                OpCode::Not => self.o_not(),
-                OpCode::Neg => unimplemented!(),
-                OpCode::Noop => (),
+    #[cfg(feature = "no-jit")]
+    fn record_guard_failure(&self, _key: TraceKey) {}
 
-                _ => {
-                    println!("XXX: {:?}", opcode);
-                    unimplemented!()
+    // Key of the merge point with the guest state of the active frame, see
+    // `Driver::merge_point`.
+    fn merge_point_key(&self, program: ProgramId, entry: InstructionPointer)
+                       -> InterpResult<TraceKey> {
+        let frame = self.basic.active_frame();
+        let pc = match frame.locals.get(PC_LOCAL).map(|local| local.get()) {
+            Some(R_BoxedValue::Usize(pc)) => pc,
+            _ => return Err(self.basic.error(ErrorKind::MissingGuestPc { func: entry.func })),
+        };
+        let specialization = frame.locals[USER_PROGRAM_LOCAL].with(|user_program| {
+            match *user_program {
+                R_BoxedValue::Struct(ref user_program) => {
+                    match user_program.data.get(pc).map(|op| op.get()) {
+                        Some(R_BoxedValue::Usize(op)) => op as HashValue,
+                        _ => 0,
+                    }
                 }
+                _ => 0,
             }
+        });
 
-            pc += 1;
+        Ok(TraceKey {
+            program: program,
+            ip: entry,
+            pc: pc,
+            specialization: specialization,
+        })
+    }
+
+    /// Execute `opcode`, the one at `basic.ip`, between the hooks of the
+    /// embedder.
+    fn execute(&mut self, opcode: &OpCode, tracer: &mut Option<&mut Tracer>)
+               -> InterpResult<DispatchResult> {
+        let ip = self.basic.ip;
+        self.runtime.hooks.pre(&self.basic, opcode, ip);
+        let res = try!(self.dispatch(opcode, tracer));
+        self.runtime.hooks.post(&self.basic, opcode, ip);
+        Ok(res)
+    }
+
+    // taken jumps are reported to the tracer
+    fn dispatch(&mut self, opcode: &OpCode, tracer: &mut Option<&mut Tracer>)
+                -> InterpResult<DispatchResult> {
+        let pc = self.basic.ip.pc;
+
+        let local_jump = match *opcode {
+            OpCode::InternalFunc(ref func) => {
+                try!(self.o_internal_func(func));
+                return Ok(DispatchResult::Next);
+            }
+            OpCode::ProfileEnter(func) => {
+                self.o_profile_enter(func, false);
+                return Ok(DispatchResult::Next);
+            }
+            OpCode::ProfileExit(func) => {
+                self.o_profile_exit(func);
+                return Ok(DispatchResult::Next);
+            }
+            OpCode::Breakpoint(id) => {
+                self.o_breakpoint(id);
+                return Ok(DispatchResult::Next);
+            }
+            OpCode::Skip(_) | OpCode::JumpBack(_) | OpCode::SkipIf(_) | OpCode::JumpBackIf(_) => true,
+            _ => false,
+        };
+
+        let res = try!(self.basic.execute());
+        if let DispatchResult::Jump(target) = res {
+            if local_jump {
+                jumped(tracer, target.func, target.pc, target.pc < pc);
+            } else if is_call(opcode) {
+                // stopped in the callee, before its first opcode
+                let call = mem::replace(&mut self.basic.ip, target);
+                let res = self.safe_point();
+                self.basic.ip = call;
+                try!(res);
+            }
         }
+        Ok(res)
     }
 
-    pub fn run_trace(&mut self, trace: &[OpCode]) -> InstructionPointer {
+    // Stop the guest if the host interrupted it or the run timed out. Only
+    // called where the state of the guest is consistent.
+    fn safe_point(&mut self) -> InterpResult<()> {
+        if self.runtime.interrupt.take() {
+            return Err(self.basic.error(ErrorKind::Interrupted));
+        }
+        if let Some(deadline) = self.deadline {
+            if self.runtime.clock.nanos() >= deadline {
+                return Err(self.basic.error(ErrorKind::Timeout));
+            }
+        }
+        if self.fuel == Some(0) {
+            return Err(self.basic.error(ErrorKind::OutOfFuel));
+        }
+        Ok(())
+    }
+
+    // Count an executed opcode against the fuel, the run stops at the next
+    // safe point once it is used up.
+    #[inline]
+    fn burn_fuel(&mut self) {
+        if let Some(ref mut fuel) = self.fuel {
+            *fuel = fuel.saturating_sub(1);
+        }
+    }
+
+    // leave the trace through `guard` at `pc`, in its `iterations`th iteration
+    fn guard_exit(&self, guard: &Guard, pc: usize, iterations: usize) -> GuardExit {
+        GuardExit {
+            guard: guard.clone(),
+            trace_pc: pc,
+            stack_snapshot: self.basic
+                .stack
+                .iter()
+                .map(|val| val.clone().into_owned().unwrap_value())
+                .collect(),
+            breakpoint: None,
+            iterations: iterations,
+            yielded: false,
+            bridge: None,
+            link: None,
+        }
+    }
+
+    // The guard of `opcode` if it is a guard which fails on the stack.
+    fn failed_guard<'o>(&self, opcode: &'o OpCode) -> Option<&'o Guard> {
+        let stack = &self.basic.stack;
+        let (holds, guard) = match *opcode {
+            OpCode::Guard(ref guard) => (condition(stack) == Some(guard.expected), guard),
+            OpCode::GuardOperands(ty, ref guard) => (operands_type(stack) == Some(ty), guard),
+            OpCode::GuardFunc(func, ref guard) => (callee(stack) == Some(func), guard),
+            OpCode::GuardVTable(idx, ref guard) => (vtable(stack) == Some(idx), guard),
+            OpCode::GuardCast(ty, ref guard) => (castable(stack, ty), guard),
+            _ => return None,
+        };
+        if holds { None } else { Some(guard) }
+    }
+
+    // Leave the trace at the breakpoint `id` at `pc`, back to where it was
+    // entered with the iteration undone, so that the interpreter stops at the
+    // breakpoint itself.
+    fn breakpoint_exit(&mut self, id: usize, pc: usize, iterations: usize) -> GuardExit {
+        self.restore_snapshot();
+        let guard = Guard {
+            expected: true,
+            recovery: self.basic.ip,
+        };
+        let mut exit = self.guard_exit(&guard, pc, iterations);
+        exit.breakpoint = Some(id);
+        exit
+    }
+
+    // Whether the trace is left after its `iterations`th iteration.
+    fn should_yield(&self, iterations: usize) -> bool {
+        self.runtime.max_iterations.map_or(false, |max| iterations >= max) ||
+        self.runtime.should_yield.as_ref().map_or(false, |hook| hook())
+    }
+
+    // Leave the trace at its `LoopBack` at `pc`. The iteration is done, so
+    // the state is the one the trace is entered with.
+    fn yield_exit(&mut self, pc: usize, iterations: usize) -> GuardExit {
+        let guard = Guard {
+            expected: true,
+            recovery: self.basic.ip,
+        };
+        let mut exit = self.guard_exit(&guard, pc, iterations);
+        exit.yielded = true;
+        exit
+    }
+
+    /// Run the loop `trace` until one of its guards fails. A guard with a
+    /// bridge continues in it, the trace is left when a guard of the bridge
+    /// fails.
+    pub fn run_trace(&mut self, trace: &Trace) -> InterpResult<GuardExit> {
+        let exit = try!(self.run_trace_ops(&trace.ops, Some(trace)));
+        Ok(exit.expect("loop traces end in a LoopBack, they are only left through guards"))
+    }
+
+    /// Run the trace of an invocation, see `Tracer::handle_call`. Returns
+    /// the guard it was left through, if one failed.
+    pub fn run_call_trace(&mut self, trace: &[OpCode]) -> InterpResult<Option<GuardExit>> {
+        self.run_trace_ops(trace, None)
+    }
+
+    // Loop traces come with their `root`, for its bridges.
+    fn run_trace_ops(&mut self, trace: &[OpCode], root: Option<&Trace>)
+                     -> InterpResult<Option<GuardExit>> {
+        let looping = root.is_some();
+        let mut ops = trace;
+        // the guard whose bridge runs
+        let mut in_bridge = None;
+        // the piece of a split trace which runs, by its index in the links
+        let mut in_link = None;
         let mut pc: usize = 0;
+        let mut iterations = 0;
+        let mut timer = OpTimer::new();
+        // a breakpoint undoes the iteration it is reached in
+        let take_snapshots = self.take_snapshots || (looping && has_breakpoint(trace));
 
         loop {
-            if pc >= trace.len() {
-                pc = 0;
+            if pc >= ops.len() {
+                timer.stop(&mut self.runtime.profile);
+                return Ok(None);
+            }
+
+            if pc == 0 && in_bridge.is_none() && in_link.is_none() && take_snapshots {
+                let frames = self.basic.stack_frames.len();
+                self.snapshot = self.basic.stack_frames.last().map(|frame| {
+                    (frames, frame.locals.iter().map(|local| local.get()).collect())
+                });
             }
 
-            let opcode = trace[pc].clone();
+            let opcode = &ops[pc];
+            timer.start(&mut self.runtime.profile, opcode);
+            let depth = self.basic.stack.len();
+            self.runtime.history.record(opcode, Location::Traced(pc), depth);
+            self.burn_fuel();
 
-            match opcode {
+            match *opcode {
                 OpCode::Panic => panic!("assertion failed"),
 
-                OpCode::Guard(Guard { recovery: ip, expected: _ })=> {
-                    return ip;
+                OpCode::Guard(_) | OpCode::GuardOperands(..) | OpCode::GuardFunc(..) |
+                OpCode::GuardVTable(..) | OpCode::GuardCast(..) => {
+                    if let Some(guard) = self.failed_guard(opcode) {
+                        // the bridge takes over with the stack the guard
+                        // left, it runs back into the start of the trace
+                        if let (Some(root), None, None) = (root, in_bridge, in_link) {
+                            let id = root.guard_id(pc);
+                            if let Some(bridge) = id.and_then(|id| root.bridge(id)) {
+                                ops = &bridge.ops;
+                                in_bridge = id;
+                                pc = 0;
+                                continue;
+                            }
+                        }
+                        timer.stop(&mut self.runtime.profile);
+                        let mut exit = self.guard_exit(guard, pc, iterations);
+                        exit.bridge = in_bridge;
+                        exit.link = in_link;
+                        return Ok(Some(exit));
+                    }
                 }
 
-                OpCode::ConstValue(val) => {
-                    self.stack.push(StackVal::Owned(val));
+                OpCode::Breakpoint(id) => {
+                    timer.stop(&mut self.runtime.profile);
+                    return Ok(Some(self.breakpoint_exit(id, pc, iterations)));
                 }
 
-                OpCode::Tuple(size) => self.o_tuple(size),
-                OpCode::TupleInit(size) => self.o_tuple_init(size),
-                OpCode::TupleGet(idx) => self.o_tuple_get(idx),
-                OpCode::TupleSet(idx) => self.o_tuple_set(idx),
+                // a piece of a split trace continues in the next one, the
+                // last piece and bridges start the loop over
+                OpCode::LoopBack(ref info) => {
+                    let next = in_link.map_or(0, |idx| idx + 1);
+                    let link = root.and_then(|root| root.links.get(next));
+                    if let (Some(_), Some(link)) = (info.link, link) {
+                        debug_assert_eq!(info.link, Some(link.generation));
+                        ops = &link.ops;
+                        in_link = Some(next);
+                        pc = 0;
+                        continue;
+                    }
+                    ops = trace;
+                    in_bridge = None;
+                    in_link = None;
+                    iterations += 1;
+                    if let Err(err) = self.safe_point() {
+                        timer.stop(&mut self.runtime.profile);
+                        return Err(err);
+                    }
+                    if self.should_yield(iterations) {
+                        timer.stop(&mut self.runtime.profile);
+                        return Ok(Some(self.yield_exit(pc, iterations)));
+                    }
+                    pc = 0;
+                    continue;
+                }
+
+                OpCode::ConstValue(ref val) => {
+                    self.basic.stack.push(StackVal::Owned(val.clone()));
+                }
+
+                OpCode::Tuple(size) => try!(self.basic.o_tuple(size)),
+                OpCode::TupleFromStack(size) => try!(self.basic.o_tuple_from_stack(size)),
+                OpCode::TupleGet(idx) => self.basic.o_tuple_get(idx),
+                OpCode::TupleSet(idx) => self.basic.o_tuple_set(idx),
 
                 // XXX: proper implementation of unsize
                 OpCode::Unsize | OpCode::Use => {
-                    let val = self.stack.pop().unwrap().into_owned();
-                    self.stack.push(val);
+                    let val = self.basic.stack.pop().unwrap().into_owned();
+                    self.basic.stack.push(val);
                 }
 
-                OpCode::Ref => self.o_ref(),
-
-                OpCode::Deref => self.o_deref(),
+                OpCode::Ref => self.basic.o_ref(),
 
-                OpCode::Load(local_index) => self.o_load(local_index),
+                OpCode::Deref => try!(self.basic.o_deref()),
 
-                OpCode::Store(local_index) => self.o_store(local_index),
+                OpCode::Load(local_index) => self.basic.o_load(local_index),
 
-                // OpCode::Call => {
-                //     // load and activate func
-                //     func_pointer = self.o_call(func_pointer, pc);
-                //     // jump to first instruction of function
-                //     // continue is necessary because else pc += 1 would be executed
-                //     pc = 0;
-                //     continue;
-                // }
+                OpCode::Store(local_index) => self.basic.o_store(local_index),
 
-                // OpCode::Static(static_idx) => {
-                //     func_pointer = self.o_load_static(static_idx, func_pointer, pc);
-                //     pc = 0;
-                //     continue;
-                // }
+                // the opcodes of the callee follow, in a frame of their
+                // own which returns to the call site if a guard leaves the
+                // trace in it
+                OpCode::FlatCall(func, site, _) => {
+                    self.basic.stack.push(StackVal::Owned(R_BoxedValue::Func(func)));
+                    try!(self.basic.o_call(site.func, site.pc));
+                }
 
-                // OpCode::Return => {
-                //     if let Some(ret) = self.o_return() {
-                //         func_pointer = ret.func;
-                //         pc = ret.pc;
-                //     } else {
-                //         break;
-                //     }
-                // }
+                OpCode::Return => {
+                    self.basic.o_return();
+                }
 
                 OpCode::Skip(n) => {
                     pc += n;
@@ -296,7 +871,7 @@ impl<'a> Interpreter<'a> {
                 }
 
                 OpCode::SkipIf(n) => {
-                    let val = self.pop_value();
+                    let val = self.basic.pop_value();
                     if let R_BoxedValue::Bool(b) = val {
                         if b {
                             pc += n;
@@ -307,7 +882,7 @@ impl<'a> Interpreter<'a> {
                     }
                 }
                 OpCode::JumpBackIf(n) => {
-                    let val = self.pop_value();
+                    let val = self.basic.pop_value();
                     if let R_BoxedValue::Bool(b) = val {
                         if b {
                             pc -= n;
@@ -318,295 +893,129 @@ impl<'a> Interpreter<'a> {
                     }
                 }
 
-                OpCode::GetIndex => self.o_get_index(),
-                OpCode::AssignIndex => self.o_assign_index(),
+                OpCode::ConstRef(idx) => self.basic.o_const_ref(idx),
+                OpCode::Static(idx) => try!(self.basic.o_load_static(idx)),
+                OpCode::GetIndex => self.basic.o_get_index(),
+                OpCode::AssignIndex => self.basic.o_assign_index(),
+                OpCode::ElemPtr => self.basic.o_elem_ptr(),
+                OpCode::MovePtr(offset) => self.basic.o_move_ptr(offset),
+                OpCode::DerefStore => try!(self.basic.o_deref_store()),
+                OpCode::CopyRange => self.basic.o_copy_range(),
+                OpCode::Fill => self.basic.o_fill(),
 
-                OpCode::Array(size) => self.o_array(size),
+                OpCode::Array(size) => try!(self.basic.o_array(size)),
 
-                OpCode::Repeat(size) => self.o_repeat(size),
+                OpCode::Repeat(size) => try!(self.basic.o_repeat(size)),
 
-                OpCode::Len => self.o_len(),
+                OpCode::Len => self.basic.o_len(),
 
-                OpCode::BinOp(kind) => self.o_binop(kind),
-                OpCode::CheckedBinOp(kind) => self.o_checked_binop(kind),
+                OpCode::BinOp(kind) => self.basic.o_binop(kind),
+                OpCode::TypedBinOp(kind, ty) => self.basic.o_typed_binop(kind, ty),
+                OpCode::CheckedBinOp(kind) => try!(self.basic.o_checked_binop(kind)),
+                OpCode::WrappingBinOp(kind) => try!(self.basic.o_wrapping_binop(kind)),
+                OpCode::SaturatingBinOp(kind) => try!(self.basic.o_saturating_binop(kind)),
 
-                OpCode::Not => self.o_not(),
-                OpCode::Neg => unimplemented!(),
+                OpCode::Not => self.basic.o_not(),
+                OpCode::Abs => try!(self.basic.o_abs()),
+                OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros => {
+                    try!(self.basic.o_count_bits(opcode))
+                }
+                OpCode::RotateLeft => try!(self.basic.o_rotate(true)),
+                OpCode::RotateRight => try!(self.basic.o_rotate(false)),
+                OpCode::CheckedCast(ty) => try!(self.basic.o_checked_cast(ty)),
+                OpCode::Cast(ty) => try!(self.basic.o_cast(ty)),
                 OpCode::Noop => (),
-
-                _ => {
-                    println!("XXX: {:?}", opcode);
-                    unimplemented!()
+                OpCode::Pop => {
+                    self.basic.stack.pop();
                 }
-            }
-
-            pc += 1;
-        }
-    }
-
-    pub fn stack_ptr(&self) -> usize {
-        self.stack_frames.len() - 1
-    }
-
-    pub fn active_frame(&self) -> &CallFrame {
-        self.stack_frames.last().unwrap()
-    }
-
-    pub fn o_load(&mut self, local_idx: usize) {
-        let cell_ptr = self.active_frame().locals[local_idx].clone();
-        self.stack.push(StackVal::Ref(cell_ptr))
-    }
-
-    pub fn o_store(&mut self, local_idx: usize) {
-        let val = self.stack.pop().unwrap();
-        let mut cell = self.active_frame().locals[local_idx].borrow_mut();
-        *cell = val.unwrap_value();
-    }
-
-    pub fn o_ref(&mut self) {
-        let addr = self.stack.pop().unwrap().into_pointer();
-        self.stack.push(addr);
-    }
 
-    pub fn o_deref(&mut self) {
-        let address = self.stack.pop().unwrap().deref();
-        self.stack.push(address);
-    }
+                OpCode::InternalFunc(ref func) => try!(self.o_internal_func(func)),
+                OpCode::ProfileEnter(func) => self.o_profile_enter(func, true),
+                OpCode::ProfileExit(func) => self.o_profile_exit(func),
 
-    pub fn o_call(&mut self, cur_func: usize, cur_pc: usize) -> usize {
-        if let R_BoxedValue::Func(idx) = self.stack.pop().unwrap().into_owned().unwrap_value() {
-            let func = &self.program[idx];
-            let return_addr = InstructionPointer {
-                func: cur_func,
-                pc: cur_pc,
-            };
-            let mut frame = CallFrame::new(Some(return_addr), func.1);
-            for idx in (0..func.0).rev() {
-                frame.locals[idx] = self.stack.pop().unwrap().into_cell().unwrap_cell();
+                _ => return Err(self.basic.error(ErrorKind::UnsupportedOpcode(opcode.clone()))),
             }
-            self.stack_frames.push(frame);
-            idx
-        } else {
-            panic!("expected func");
-        }
-    }
-
-    pub fn o_load_static(&mut self, static_idx: usize, cur_func: usize, cur_pc: usize) -> usize {
-        let func = &self.program[static_idx];
-        let return_addr = InstructionPointer {
-            func: cur_func,
-            pc: cur_pc,
-        };
-        let mut frame = CallFrame::new(Some(return_addr), 0);
-        self.stack_frames.push(frame);
-        static_idx
-    }
 
-    pub fn o_return(&mut self) -> Option<InstructionPointer> {
-        match self.stack_frames.pop() {
-            Some(frame) => frame.return_addr,
-            None => None,
+            pc += 1;
         }
     }
 
-    pub fn o_tuple(&mut self, size: usize) {
-        let mut tuple = R_Struct::tuple(size);
-        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(tuple)));
+    pub fn o_profile_enter(&mut self, func: usize, traced: bool) {
+        let now = self.runtime.clock.nanos();
+        self.runtime.guest_profile.enter(func, traced, now);
     }
 
-    pub fn o_tuple_init(&mut self, idx: usize) {
-        let val = self.pop_value();
-        if let R_BoxedValue::Struct(ref mut tuple) = self.stack
-            .last()
-            .unwrap()
-            .clone()
-            .unwrap_value() {
-            tuple.set(idx, val);
-        } else {
-            panic!("tuple init");
-        }
+    pub fn o_profile_exit(&mut self, func: usize) {
+        let now = self.runtime.clock.nanos();
+        self.runtime.guest_profile.exit(func, now);
     }
 
-    pub fn o_tuple_set(&mut self, idx: usize) {
-        let boxed_tuple = self.pop_value();
-        let val = self.pop_value();
-
-        if let R_BoxedValue::Struct(mut tuple) = boxed_tuple {
-            tuple.set(idx, val);
-        } else {
-            panic!("expected struct, got {:?}", boxed_tuple);
+    pub fn o_breakpoint(&mut self, id: usize) {
+        if let Some(ref hook) = self.runtime.breakpoint {
+            hook(&self.basic, id);
         }
     }
 
-    pub fn o_tuple_get(&mut self, idx: usize) {
-        let val = self.pop_value();
-        if let R_BoxedValue::Struct(r_struct) = val {
-            let ptr = r_struct.data[idx].clone();
-            self.stack.push(StackVal::Ref(ptr));
-        } else {
-            panic!("expected struct got {:?}", val);
-        }
-    }
-
-    pub fn load_const(&mut self, idx: usize) -> R_BoxedValue {
-        let func = &self.program[idx];
-        if let OpCode::ConstValue(ref val) = func.2[0] {
-            val.clone()
-        } else {
-            panic!("expected const");
-        }
-    }
+    pub fn o_internal_func(&mut self, func: &InternalFunc) -> InterpResult<()> {
+        match *func {
+            InternalFunc::In | InternalFunc::Out | InternalFunc::Print if self.runtime.sandbox => {
+                return Err(self.basic.error(ErrorKind::SandboxViolation(Effect::Io(func.clone()))));
+            }
 
-    pub fn pop_value(&mut self) -> R_BoxedValue {
-        let val = self.stack.pop().unwrap().into_owned().unwrap_value();
-        if let R_BoxedValue::Static(def_id) = val {
-            self.load_const(def_id)
-        } else {
-            val
-        }
-    }
+            InternalFunc::In => {
+                let val = match self.runtime.io.read_byte() {
+                    Ok(Some(byte)) => R_BoxedValue::U64(byte as u64),
+                    Ok(None) => R_BoxedValue::Null,
+                    Err(_) => return Err(self.basic.error(ErrorKind::IoFailed(func.clone()))),
+                };
+                self.basic.stack.push(StackVal::Owned(val));
+                self.had_effects = true;
+            }
 
-    pub fn o_binop(&mut self, kind: BinOp) {
-        let val = self._do_binop(kind);
-        self.stack.push(StackVal::Owned(val));
-    }
-
-    pub fn o_checked_binop(&mut self, kind: BinOp) {
-        // TODO: actually check binops
-        let mut tuple = R_Struct::tuple(2);
-        *tuple.data[0].borrow_mut() = self._do_binop(kind);
-        // false == no error
-        *tuple.data[1].borrow_mut() = R_BoxedValue::Bool(false);
-        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(tuple)));
-    }
-
-    fn _do_binop(&mut self, kind: BinOp) -> R_BoxedValue {
-
-        use core::objects::R_BoxedValue::*;
-        use bc::bytecode::BinOp::*;
-
-        let right = self.pop_value();
-        let left = self.pop_value();
-
-        debug!("#EX2 left: {:?}, right: {:?} ", left, right);
-        // copied from miri
-        macro_rules! int_binops {
-            ($v:ident, $l:ident, $r:ident) => ({
-                match kind {
-                    Add    => $v($l + $r),
-                    Sub    => $v($l - $r),
-                    Mul    => $v($l * $r),
-                    Div    => $v($l / $r),
-                    Rem    => $v($l % $r),
-                    BitXor => $v($l ^ $r),
-                    BitAnd => $v($l & $r),
-                    BitOr  => $v($l | $r),
-
-                    // TODO(solson): Can have differently-typed RHS.
-                    Shl => $v($l << $r),
-                    Shr => $v($l >> $r),
-
-                    Eq => Bool($l == $r),
-                    Ne => Bool($l != $r),
-                    Lt => Bool($l < $r),
-                    Le => Bool($l <= $r),
-                    Gt => Bool($l > $r),
-                    Ge => Bool($l >= $r),
+            InternalFunc::Out => {
+                let byte = match self.basic.pop_value() {
+                    R_BoxedValue::U64(n) => n as u8,
+                    R_BoxedValue::Usize(n) => n as u8,
+                    R_BoxedValue::I64(n) => n as u8,
+                    val => return Err(self.basic.error(ErrorKind::InvalidOutput(val))),
+                };
+                if self.runtime.io.write_byte(byte).is_err() {
+                    return Err(self.basic.error(ErrorKind::IoFailed(func.clone())));
                 }
-            })
-        }
-
-
-        match (left, right) {
-            (I64(l), I64(r)) => int_binops!(I64, l, r),
-            (U64(l), U64(r)) => int_binops!(U64, l, r),
-            (Usize(l), Usize(r)) => int_binops!(Usize, l, r),
-
-            // copied from miri
-            (Bool(l), Bool(r)) => {
-                Bool(match kind {
-                    Eq => l == r,
-                    Ne => l != r,
-                    Lt => l < r,
-                    Le => l <= r,
-                    Gt => l > r,
-                    Ge => l >= r,
-                    BitOr => l | r,
-                    BitXor => l ^ r,
-                    BitAnd => l & r,
-                    Add | Sub | Mul | Div | Rem | Shl | Shr => {
-                        panic!("invalid binary operation on booleans: {:?}", kind)
-                    }
-                })
+                self.had_effects = true;
             }
 
-            (l, r) => {
-                println!("{:?} {:?}", l, r);
-                unimplemented!();
+            InternalFunc::Print => {
+                let line = match self.basic.pop_value() {
+                    R_BoxedValue::I64(n) => format!("{}\n", n),
+                    R_BoxedValue::U64(n) => format!("{}\n", n),
+                    R_BoxedValue::Usize(n) => format!("{}\n", n),
+                    R_BoxedValue::F64(n) => format!("{}\n", n),
+                    R_BoxedValue::Bool(b) => format!("{}\n", b),
+                    val => format!("{:?}\n", val),
+                };
+                if self.runtime.io.write_str(&line).is_err() {
+                    return Err(self.basic.error(ErrorKind::IoFailed(func.clone())));
+                }
+                self.had_effects = true;
             }
-        }
-    }
 
-    pub fn o_not(&mut self) {
-        if let R_BoxedValue::Bool(boolean) = self.pop_value() {
-            self.stack.push(StackVal::Owned(R_BoxedValue::Bool(!boolean)));
-        } else {
-            panic!("expected bool");
-        }
-    }
-
-    pub fn o_get_index(&mut self) {
-        let target = self.pop_value();
-        let index = self.pop_value();
-        if let (R_BoxedValue::Struct(mut r_struct), R_BoxedValue::Usize(idx)) = (target, index) {
-            let val = r_struct.get(idx);
-            self.stack.push(StackVal::Ref(val));
-        } else {
-            panic!("error");
-        }
-    }
-
-    pub fn o_assign_index(&mut self) {
-        let target = self.pop_value();
-        let index = self.pop_value();
-        let val = self.pop_value();
-        if let (R_BoxedValue::Struct(mut r_struct), R_BoxedValue::Usize(idx)) = (target, index) {
-            r_struct.set(idx, val);
-        } else {
-            panic!("error");
-        }
-    }
-
-    pub fn o_array(&mut self, size: usize) {
-        let mut obj = R_Struct::with_size(size);
-        for idx in (0..size).rev() {
-            let val = self.pop_value();
-            obj.set(idx, val.clone());
-        }
-        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(obj)));
-    }
-
-    pub fn o_repeat(&mut self, size: usize) {
-        let val = self.pop_value();
-
-        let mut obj = R_Struct::with_size(size);
-        for idx in 0..size {
-            obj.set(idx, val.clone());
-        }
-
-        self.stack.push(StackVal::Owned(R_BoxedValue::Struct(obj)));
-    }
+            InternalFunc::Clock => {
+                let nanos = self.runtime.clock.nanos();
+                self.basic.stack.push(StackVal::Owned(R_BoxedValue::U64(nanos)));
+            }
 
-    pub fn o_len(&mut self) {
-        let x = self.pop_value();
-        match x {
-            R_BoxedValue::Struct(s) => {
-                self.stack.push(StackVal::Owned(R_BoxedValue::Usize(s.data.len())));
+            InternalFunc::Rand => {
+                let n = self.runtime.rng.next_u64();
+                self.had_effects = true;
+                self.basic.stack.push(StackVal::Owned(R_BoxedValue::U64(n)));
             }
-            R_BoxedValue::Array(inner_vec) => {
-                self.stack.push(StackVal::Owned(R_BoxedValue::Usize(inner_vec.len())));
+
+            InternalFunc::MergePoint | InternalFunc::Assert => {
+                let opcode = OpCode::InternalFunc(func.clone());
+                return Err(self.basic.error(ErrorKind::UnsupportedOpcode(opcode)));
             }
-            _ => panic!("can't get len of {:?}", x),
         }
+        Ok(())
     }
 }
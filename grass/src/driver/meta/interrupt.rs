@@ -0,0 +1,32 @@
+//! Stopping a running guest from outside of it, e.g. from another thread or
+//! a Ctrl-C handler.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Asks the guest of a driver to stop, see `Driver::interrupt_handle`.
+/// Clones share the request.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl InterruptHandle {
+    /// Stop the guest at its next safe point: the end of an iteration of a
+    /// loop trace, or the entry of a guest function. It fails with
+    /// `ErrorKind::Interrupted` there. Only stores a flag, so this can be
+    /// called from a signal handler.
+    pub fn interrupt(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// whether an interrupt waits for the guest to reach a safe point
+    pub fn is_pending(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Take the pending interrupt, the guest runs on after the one it stopped.
+    pub fn take(&self) -> bool {
+        self.requested.swap(false, Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,112 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// Input and output of the guest program.
+///
+/// All I/O opcodes go through this, so that the host decides where guest
+/// output ends up and tests can run guest programs deterministically.
+pub trait GuestIo {
+    /// Next byte of input, `None` once the input is exhausted.
+    fn read_byte(&mut self) -> io::Result<Option<u8>>;
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()>;
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        for byte in s.bytes() {
+            try!(self.write_byte(byte));
+        }
+        Ok(())
+    }
+}
+
+/// Guest I/O on the standard streams of the host process.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+pub struct StdIo;
+
+#[cfg(not(target_arch = "wasm32"))]
+impl GuestIo for StdIo {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0; 1];
+        match try!(io::stdin().read(&mut buf)) {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        io::stdout().write_all(&[byte])
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        io::stdout().write_all(s.as_bytes())
+    }
+}
+
+/// Guest I/O from a fixed input into an in-memory buffer.
+///
+/// The output buffer is shared, a handle to it can be obtained with
+/// `output` before the `MemoryIo` is handed to the interpreter.
+#[derive(Debug, Default)]
+pub struct MemoryIo {
+    input: Vec<u8>,
+    pos: usize,
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl MemoryIo {
+    pub fn new(input: &[u8]) -> Self {
+        MemoryIo {
+            input: input.to_vec(),
+            pos: 0,
+            output: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    pub fn output(&self) -> Rc<RefCell<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl GuestIo for MemoryIo {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let byte = self.input.get(self.pos).cloned();
+        if byte.is_some() {
+            self.pos += 1;
+        }
+        Ok(byte)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.output.borrow_mut().push(byte);
+        Ok(())
+    }
+}
+
+/// Guest output into any `Write`, e.g. `io::sink()` to discard it in
+/// benchmarks. The guest gets no input.
+#[derive(Debug)]
+pub struct WriteIo<W: Write> {
+    out: W,
+}
+
+impl<W: Write> WriteIo<W> {
+    pub fn new(out: W) -> Self {
+        WriteIo { out: out }
+    }
+}
+
+impl<W: Write> GuestIo for WriteIo<W> {
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        Ok(None)
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        self.out.write_all(&[byte])
+    }
+
+    fn write_str(&mut self, s: &str) -> io::Result<()> {
+        self.out.write_all(s.as_bytes())
+    }
+}
@@ -1,2 +1,11 @@
-
+pub mod clock;
+pub mod guardlog;
+pub mod history;
+pub mod hooks;
+pub mod interrupt;
 pub mod interp;
+pub mod io;
+pub mod profile;
+pub mod rng;
+
+pub use grass_core::error;
@@ -0,0 +1,213 @@
+//! Time spent per opcode variant, measured only with the `profile-opcodes`
+//! feature. Without it, and on wasm32 which has no clock, `OpTimer` does
+//! nothing and the profile stays empty.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::Duration;
+#[cfg(all(feature = "profile-opcodes", not(target_arch = "wasm32")))]
+use std::time::Instant;
+
+use bc::bytecode::OpCode;
+
+#[derive(Debug, Default, Clone)]
+pub struct OpcodeProfile {
+    timings: BTreeMap<&'static str, OpcodeTiming>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcodeTiming {
+    pub opcode: &'static str,
+    pub count: usize,
+    pub total: Duration,
+}
+
+impl OpcodeProfile {
+    pub fn record(&mut self, opcode: &'static str, elapsed: Duration) {
+        let timing = self.timings.entry(opcode).or_insert(OpcodeTiming {
+            opcode: opcode,
+            count: 0,
+            total: Duration::new(0, 0),
+        });
+        timing.count += 1;
+        timing.total = timing.total + elapsed;
+    }
+
+    /// Timings of all executed opcodes, the most expensive first.
+    pub fn ranked(&self) -> Vec<OpcodeTiming> {
+        let mut timings: Vec<OpcodeTiming> = self.timings.values().cloned().collect();
+        timings.sort_by(|a, b| b.total.cmp(&a.total));
+        timings
+    }
+}
+
+impl fmt::Display for OpcodeProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for timing in self.ranked() {
+            let nanos = timing.total.as_secs() * 1_000_000_000 + timing.total.subsec_nanos() as u64;
+            try!(writeln!(f, "{:>14} {:>10} {:>14}ns {:>8}ns/op",
+                          timing.opcode, timing.count, nanos, nanos / timing.count as u64));
+        }
+        Ok(())
+    }
+}
+
+/// Measures the time from one opcode to the next.
+#[cfg(all(feature = "profile-opcodes", not(target_arch = "wasm32")))]
+pub struct OpTimer {
+    current: Option<(&'static str, Instant)>,
+}
+
+#[cfg(all(feature = "profile-opcodes", not(target_arch = "wasm32")))]
+impl OpTimer {
+    pub fn new() -> Self {
+        OpTimer { current: None }
+    }
+
+    /// Stop timing the previous opcode and start timing `opcode`.
+    pub fn start(&mut self, profile: &mut OpcodeProfile, opcode: &OpCode) {
+        self.stop(profile);
+        self.current = Some((opcode.name(), Instant::now()));
+    }
+
+    pub fn stop(&mut self, profile: &mut OpcodeProfile) {
+        if let Some((name, start)) = self.current.take() {
+            profile.record(name, start.elapsed());
+        }
+    }
+}
+
+#[cfg(any(not(feature = "profile-opcodes"), target_arch = "wasm32"))]
+pub struct OpTimer;
+
+#[cfg(any(not(feature = "profile-opcodes"), target_arch = "wasm32"))]
+impl OpTimer {
+    #[inline(always)]
+    pub fn new() -> Self {
+        OpTimer
+    }
+
+    #[inline(always)]
+    pub fn start(&mut self, _profile: &mut OpcodeProfile, _opcode: &OpCode) {}
+
+    #[inline(always)]
+    pub fn stop(&mut self, _profile: &mut OpcodeProfile) {}
+}
+
+/// Time spent per guest function, between the `ProfileEnter` and
+/// `ProfileExit` opcodes a front-end put around its code, as read from the
+/// guest clock.
+#[derive(Debug, Default, Clone)]
+pub struct GuestProfile {
+    /// calls which didn't exit yet, the innermost last
+    open: Vec<OpenCall>,
+    functions: BTreeMap<usize, GuestTiming>,
+    /// exclusive time per call stack, the outermost call first
+    stacks: BTreeMap<Vec<(usize, bool)>, u64>,
+    /// names of the guest functions in the flame graph
+    names: BTreeMap<usize, String>,
+}
+
+#[derive(Debug, Clone)]
+struct OpenCall {
+    func: usize,
+    /// whether the call was entered in a trace
+    traced: bool,
+    start: u64,
+    /// inclusive time of the calls made by this one
+    children: u64,
+}
+
+/// Nanoseconds spent in a guest function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuestTiming {
+    pub func: usize,
+    pub calls: usize,
+    /// including the functions it called, recursive calls count once
+    pub inclusive: u64,
+    /// in the function itself
+    pub exclusive: u64,
+}
+
+impl GuestProfile {
+    pub fn enter(&mut self, func: usize, traced: bool, now: u64) {
+        self.open.push(OpenCall {
+            func: func,
+            traced: traced,
+            start: now,
+            children: 0,
+        });
+    }
+
+    /// Close the innermost call of `func`, and the calls within it which
+    /// didn't exit, e.g. because a guard undid their iteration. Exits without
+    /// an open call are ignored.
+    pub fn exit(&mut self, func: usize, now: u64) {
+        if let Some(pos) = self.open.iter().rposition(|call| call.func == func) {
+            while self.open.len() > pos {
+                self.close(now);
+            }
+        }
+    }
+
+    fn close(&mut self, now: u64) {
+        let call = self.open.pop().unwrap();
+        let inclusive = now.saturating_sub(call.start);
+        let exclusive = inclusive.saturating_sub(call.children);
+
+        let mut stack: Vec<(usize, bool)> =
+            self.open.iter().map(|open| (open.func, open.traced)).collect();
+        stack.push((call.func, call.traced));
+        *self.stacks.entry(stack).or_insert(0) += exclusive;
+
+        let recursive = self.open.iter().any(|open| open.func == call.func);
+        let timing = self.functions.entry(call.func).or_insert(GuestTiming {
+            func: call.func,
+            calls: 0,
+            inclusive: 0,
+            exclusive: 0,
+        });
+        timing.calls += 1;
+        timing.exclusive += exclusive;
+        if !recursive {
+            timing.inclusive += inclusive;
+        }
+
+        if let Some(parent) = self.open.last_mut() {
+            parent.children += inclusive;
+        }
+    }
+
+    /// Timings of all exited functions, the most expensive first.
+    pub fn ranked(&self) -> Vec<GuestTiming> {
+        let mut timings: Vec<GuestTiming> = self.functions.values().cloned().collect();
+        timings.sort_by(|a, b| b.exclusive.cmp(&a.exclusive));
+        timings
+    }
+
+    /// Call `func` `name` in the flame graph instead of `func<id>`.
+    pub fn set_name(&mut self, func: usize, name: &str) {
+        self.names.insert(func, name.to_owned());
+    }
+
+    /// The profile in the folded stack format of flame graph tools like
+    /// inferno: one line `outer;inner nanos` of exclusive time per call
+    /// stack. Calls entered in a trace carry the `_[j]` suffix, which the
+    /// tools color as JIT frames.
+    pub fn folded(&self) -> String {
+        let mut out = String::new();
+        for (stack, nanos) in &self.stacks {
+            let frames: Vec<String> = stack.iter()
+                .map(|&(func, traced)| {
+                    let name = match self.names.get(&func) {
+                        Some(name) => name.clone(),
+                        None => format!("func{}", func),
+                    };
+                    if traced { name + "_[j]" } else { name }
+                })
+                .collect();
+            out.push_str(&format!("{} {}\n", frames.join(";"), nanos));
+        }
+        out
+    }
+}
@@ -0,0 +1,32 @@
+/// Pseudo random numbers for `InternalFunc::Rand` (xorshift64*).
+///
+/// The generator is part of the guest machine state, so a guest program run
+/// with the same seed sees the same numbers, no matter whether they are drawn
+/// by the interpreter or by a trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rng {
+    state: u64,
+}
+
+const DEFAULT_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        // xorshift never leaves the all-zero state
+        let state = if seed == 0 { DEFAULT_SEED } else { seed };
+        Rng { state: state }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Rng::new(DEFAULT_SEED)
+    }
+}
@@ -1,194 +1,1845 @@
 
 
 mod meta;
+#[cfg(not(feature = "no-jit"))]
+mod hash;
+#[cfg(not(feature = "no-jit"))]
+mod hotness;
+#[cfg(not(feature = "no-jit"))]
+mod coverage;
+#[cfg(not(feature = "no-jit"))]
+mod events;
+#[cfg(not(feature = "no-jit"))]
+mod specialize;
+#[cfg(not(feature = "no-jit"))]
+mod calls;
+#[cfg(not(feature = "no-jit"))]
+mod stats;
+#[cfg(all(not(feature = "no-jit"), not(target_arch = "wasm32")))]
+pub mod golden;
+#[cfg(all(feature = "tui", not(feature = "no-jit"), not(target_arch = "wasm32")))]
+mod tui;
+#[cfg(feature = "no-jit")]
+mod nojit;
+mod builder;
+mod state;
+pub mod trace;
 
-use std::rc::Rc;
-use std::collections::BTreeMap;
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs::File;
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, BufReader, BufWriter};
+use std::io::Write;
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Path;
+#[cfg(not(feature = "no-jit"))]
+use std::cmp;
+#[cfg(not(feature = "no-jit"))]
+use std::mem;
+#[cfg(not(feature = "no-jit"))]
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
 
+#[cfg(not(feature = "no-jit"))]
+use self::hash::{FastHashMap, FastHashSet};
+#[cfg(feature = "no-jit")]
+pub use self::nojit::{Tracer, ThreadStats};
+pub use self::trace::{GuardExit, GuardInfo, Trace, TraceKey, TraceRef, TraceStats};
+#[cfg(not(feature = "no-jit"))]
+pub use self::coverage::Coverage;
+#[cfg(not(feature = "no-jit"))]
+pub use self::events::JitEvent;
+#[cfg(not(feature = "no-jit"))]
+pub use self::stats::{Stats, StatsRegistry, ThreadStats, ThreadView, TraceCounts, Warmup};
+pub use self::meta::io::{GuestIo, MemoryIo, WriteIo};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::meta::io::StdIo;
+pub use self::meta::clock::{GuestClock, StepClock};
+#[cfg(not(target_arch = "wasm32"))]
+pub use self::meta::clock::SystemClock;
+pub use self::meta::error::{InterpError, InterpResult, ErrorKind, Effect, BacktraceFrame};
+pub use self::meta::profile::{OpcodeProfile, OpcodeTiming, GuestProfile, GuestTiming};
+pub use self::builder::InterpreterBuilder;
+pub use self::state::{CellState, MergeState};
+pub use self::meta::guardlog::GuardLog;
+pub use self::meta::history::{History, ExecEvent, Location, DEFAULT_HISTORY_LEN};
+pub use self::meta::interp::InterpreterConfig;
+pub use self::meta::hooks::{OpcodeHook, BreakpointHook, YieldHook};
+pub use self::meta::interrupt::InterruptHandle;
 
-use bc::bytecode::{OpCode, Guard};
-use core::objects::{CallFrame, InstructionPointer, R_BoxedValue, R_Struct};
+use self::meta::interp::{Interpreter, Runtime};
+use self::meta::rng::Rng;
+#[cfg(not(feature = "no-jit"))]
+use self::events::Subscribers;
+#[cfg(not(feature = "no-jit"))]
+pub use self::specialize::TypeState;
+#[cfg(not(feature = "no-jit"))]
+use self::calls::Calls;
+#[cfg(all(feature = "tui", not(feature = "no-jit"), not(target_arch = "wasm32")))]
+use self::tui::Tui;
+
+
+use bc::{opt, Program};
+#[cfg(not(feature = "no-jit"))]
+use bc::opt::{Fuel, TracePasses};
+use bc::program::AliasClasses;
+use bc::verify::{verify, VerifyError};
+use bc::decode::Code;
+use bc::bytecode::{OpCode, Guard, LoopInfo, NumType};
+use core::cell::SharedCell;
+use core::objects::{CallFrame, InstructionPointer, R_BoxedValue, FunctionRef};
+pub use grass_core::interp::{USER_PROGRAM_LOCAL, CELL_LOCAL, PC_LOCAL};
 
 #[derive(Default)]
 pub struct Driver {
-    tracer: Tracer,
+    guests: Vec<Guest>,
+    /// verify programs when they are registered, see `InterpreterBuilder`
+    strict: bool,
+    #[cfg(not(feature = "no-jit"))]
+    anchoring: Anchoring,
+    #[cfg(not(feature = "no-jit"))]
+    guard_failure: GuardFailurePolicy,
+    #[cfg(not(feature = "no-jit"))]
+    max_bridges: Option<usize>,
+    #[cfg(not(feature = "no-jit"))]
+    max_guard_failures: Option<usize>,
+    #[cfg(not(feature = "no-jit"))]
+    max_trace_len: Option<usize>,
+    #[cfg(not(feature = "no-jit"))]
+    opt_fuel: Option<usize>,
+    #[cfg(not(feature = "no-jit"))]
+    trace_passes: TracePasses,
+    /// counts of the threads besides this one
+    #[cfg(not(feature = "no-jit"))]
+    stats: StatsRegistry,
+    runtime: Runtime,
+    #[cfg(not(feature = "no-jit"))]
+    events: Subscribers,
+    #[cfg(all(feature = "tui", not(feature = "no-jit"), not(target_arch = "wasm32")))]
+    tui: Option<Tui>,
 }
 
-// TODO: pass &mut Tape to merge_point
+/// Handle to a program registered with a `Driver`.
+///
+/// It is part of every `TraceKey`, so keys of two programs never compare
+/// equal and each program only ever finds the traces recorded for it. A
+/// trace never runs on the frames of another program, `execute_trace`
+/// checks that in debug builds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProgramId(usize);
 
-type Program = [(usize, usize, &'static [OpCode])];
+// every program has its own traces
+struct Guest {
+    program: Program,
+    /// `program` decoded for the interpreter
+    code: Code,
+    /// why a strict driver refuses to run the program
+    rejected: Option<VerifyError>,
+    #[cfg(not(feature = "no-jit"))]
+    tracer: Tracer,
+}
 
 impl Driver {
+    /// Route guest input and output through `io` instead of the standard
+    /// streams. On wasm32, which has none, the default is an empty
+    /// `MemoryIo`.
+    pub fn set_io(&mut self, io: Box<GuestIo>) {
+        self.runtime.io = io;
+    }
+
+    /// Write guest output to `out`, the guest reads no input. See `WriteIo`.
+    pub fn set_output<W: Write + 'static>(&mut self, out: W) {
+        self.runtime.io = Box::new(WriteIo::new(out));
+    }
+
+    /// Take the time read by the guest from `clock` instead of the system
+    /// clock. On wasm32, which has none, the default is a `StepClock`.
+    pub fn set_clock(&mut self, clock: Box<GuestClock>) {
+        self.runtime.clock = clock;
+    }
+
+    /// Choose the sizes the operand stack and the frames of the interpreter
+    /// start with. Later runs reuse the buffers of the previous one.
+    pub fn set_interpreter_config(&mut self, config: InterpreterConfig) {
+        self.runtime.set_config(config);
+    }
+
+    /// Limit the memory guest objects may use, in bytes. Exceeding it makes
+    /// `merge_point` fail with `ErrorKind::OutOfMemory`.
+    pub fn set_heap_limit(&mut self, limit: Option<usize>) {
+        self.runtime.heap.limit = limit;
+    }
+
+    /// In sandbox mode guest I/O and calls of host functions fail with
+    /// `ErrorKind::SandboxViolation`, only computation is allowed.
+    pub fn set_sandbox(&mut self, sandbox: bool) {
+        self.runtime.sandbox = sandbox;
+    }
+
+    /// Restart the random numbers drawn by the guest from `seed`.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.runtime.rng = Rng::new(seed);
+    }
+
+    /// Time spent per opcode so far. Only filled in when built with the
+    /// `profile-opcodes` feature.
+    pub fn opcode_profile(&self) -> &OpcodeProfile {
+        &self.runtime.profile
+    }
+
+    /// Time spent per guest function so far, measured where the front-end
+    /// put `ProfileEnter` and `ProfileExit` opcodes.
+    pub fn guest_profile(&self) -> &GuestProfile {
+        &self.runtime.guest_profile
+    }
+
+    /// Call `pre` before and `post` after every interpreted opcode, with the
+    /// interpreter, the opcode and its position. Traces run without them.
+    #[cfg(feature = "opcode-hooks")]
+    pub fn set_opcode_hooks(&mut self, pre: Option<OpcodeHook>, post: Option<OpcodeHook>) {
+        self.runtime.hooks.pre = pre;
+        self.runtime.hooks.post = post;
+    }
+
+    /// Call `hook` whenever the guest reaches an `OpCode::Breakpoint`. That
+    /// works with the JIT on: traces are recorded around breakpoints, and
+    /// one which has a breakpoint is left before it.
+    pub fn set_breakpoint_hook(&mut self, hook: Option<BreakpointHook>) {
+        self.runtime.breakpoint = hook;
+    }
+
+    /// Leave a loop trace after `max` iterations per entry, none for no
+    /// limit. See `set_yield_hook`.
+    pub fn set_max_iterations(&mut self, max: Option<usize>) {
+        self.runtime.max_iterations = max;
+    }
+
+    /// Ask `hook` at the end of every iteration of a loop trace whether to
+    /// leave it. `execute_trace` returns to the host then, with the guest at
+    /// the start of its next iteration. `run_program` can't return early, it
+    /// carries on in the interpreter.
+    pub fn set_yield_hook(&mut self, hook: Option<YieldHook>) {
+        self.runtime.should_yield = hook;
+    }
+
+    /// A handle to stop the guest with, from any thread. Runs fail with
+    /// `ErrorKind::Interrupted` once the guest gets to a safe point.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.runtime.interrupt.clone()
+    }
+
+    /// Fail every run which takes longer than `timeout` with
+    /// `ErrorKind::Timeout`, checked where an interrupt would stop the
+    /// guest. A run is one call of `run_program`, `merge_point` and the
+    /// like, timed on the clock set with `set_clock`.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.runtime.timeout = timeout.map(|timeout| {
+            timeout.as_secs()
+                .saturating_mul(1_000_000_000)
+                .saturating_add(timeout.subsec_nanos() as u64)
+        });
+    }
+
+    /// Fail every run which executes more than `fuel` opcodes with
+    /// `ErrorKind::OutOfFuel`, checked where an interrupt would stop the
+    /// guest. Opcodes of traces count the same as interpreted ones.
+    pub fn set_fuel(&mut self, fuel: Option<usize>) {
+        self.runtime.fuel = fuel;
+    }
+
+    /// Name guest function `func` in the flame graph.
+    pub fn name_guest_function(&mut self, func: usize, name: &str) {
+        self.runtime.guest_profile.set_name(func, name);
+    }
+
+    /// Write the guest profile to `path` in the folded stack format of
+    /// inferno and flamegraph.pl, see `GuestProfile::folded`. There are no
+    /// files on wasm32.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn export_flamegraph<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        out.write_all(self.runtime.guest_profile.folded().as_bytes())
+    }
+
+    /// The last opcodes executed for any program, also those of a run which
+    /// failed. Cheap enough to stay on, it is the place to look when a
+    /// guest fails.
+    pub fn history(&self) -> &History {
+        &self.runtime.history
+    }
+
+    /// Keep the last `len` opcodes in `history`, none if it is 0. The
+    /// default is `DEFAULT_HISTORY_LEN`.
+    pub fn set_history_len(&mut self, len: usize) {
+        self.runtime.history = History::new(len);
+    }
+
+    /// Write samples of the guard failures to `log`, e.g. every 1000th to
+    /// a file of at most a megabyte with `GuardLog::create`. None stops
+    /// sampling.
+    pub fn set_guard_log(&mut self, log: Option<GuardLog>) {
+        self.runtime.guard_log = log;
+    }
+
+    /// The guard log set with `set_guard_log`.
+    pub fn guard_log(&self) -> Option<&GuardLog> {
+        self.runtime.guard_log.as_ref()
+    }
+
+    /// Keep a live view of merge points and traces on `out`, usually a
+    /// terminal. It is redrawn while the host calls `merge_point`.
+    #[cfg(all(feature = "tui", not(feature = "no-jit"), not(target_arch = "wasm32")))]
+    pub fn show_tui(&mut self, out: Box<Write>) {
+        self.tui = Some(Tui::new(out));
+    }
+
+    /// Receive a `JitEvent` for everything that happens to the traces from
+    /// now on. Only merge points handled by `merge_point` are reported.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn subscribe(&mut self) -> Receiver<JitEvent> {
+        self.events.subscribe()
+    }
+
+    /// Make `program` known to the driver. Programs don't share traces, so
+    /// one driver can run several guest programs.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn register_program(&mut self, program: Program) -> ProgramId {
+        let mut tracer = Tracer::default();
+        tracer.anchoring = self.anchoring;
+        tracer.max_bridges = self.max_bridges;
+        tracer.max_guard_failures = self.max_guard_failures;
+        tracer.max_trace_len = self.max_trace_len;
+        tracer.opt_fuel = self.opt_fuel;
+        tracer.trace_passes = self.trace_passes;
+        tracer.aliases = program.aliases.clone();
+        tracer.data = program.data.clone();
+        tracer.functions = program.functions.iter().cloned().map(FunctionRef::new).collect();
+        tracer.vtables = program.vtables.clone();
+        self.guests.push(Guest {
+            code: Code::decode(&program),
+            rejected: self.check(&program),
+            program: program,
+            tracer: tracer,
+        });
+        ProgramId(self.guests.len() - 1)
+    }
+
+    /// Make `program` known to the driver.
+    #[cfg(feature = "no-jit")]
+    pub fn register_program(&mut self, program: Program) -> ProgramId {
+        self.guests.push(Guest {
+            code: Code::decode(&program),
+            rejected: self.check(&program),
+            program: program,
+        });
+        ProgramId(self.guests.len() - 1)
+    }
+
+    // the verifier's objection to `program` if the driver is strict
+    fn check(&self, program: &Program) -> Option<VerifyError> {
+        if self.strict {
+            verify(program).err()
+        } else {
+            None
+        }
+    }
+
+    /// Why the verifier rejected `program`, which a strict driver doesn't
+    /// run. Always `None` unless the driver was built strict with
+    /// `InterpreterBuilder::strict`.
+    pub fn verify_error(&self, program: ProgramId) -> Option<&VerifyError> {
+        self.guests[program.0].rejected.as_ref()
+    }
+
+    // Fail with `ErrorKind::Unverified` if the program was rejected.
+    fn ensure_verified(&self, program: ProgramId) -> InterpResult<()> {
+        match self.guests[program.0].rejected {
+            Some(ref err) => {
+                Err(InterpError {
+                    kind: ErrorKind::Unverified { func: err.func, pc: err.pc },
+                    backtrace: Vec::new(),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Install `ops` as the trace for `key`, so the loop starting there runs
+    /// traced from its first iteration on.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn seed_trace(&mut self, key: TraceKey, ops: Vec<OpCode>) -> Result<(), InvalidTrace> {
+        let tracer = &mut self.guests[key.program.0].tracer;
+        let replaced = tracer.traces.get(&key).map(|trace| trace.generation);
+        try!(tracer.seed_trace(key, ops));
+        if let Some(id) = replaced {
+            self.events.emit(JitEvent::TraceEvicted { key: key, id: id });
+        }
+        Ok(())
+    }
+
+    /// How often the traces were entered and left, by this driver and by
+    /// the threads registered with `stats_registry`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot(&self.runtime.stats)
+    }
+
+    /// Where further threads running traces of this driver register their
+    /// counts, see `StatsRegistry::register`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn stats_registry(&self) -> StatsRegistry {
+        self.stats.clone()
+    }
+
+    /// The trace installed for `key`, if any.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn trace(&self, key: TraceKey) -> Option<TraceRef> {
+        self.guests[key.program.0].tracer.trace(key)
+    }
+
+    /// The loop traces installed for `program`, ordered by their keys.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn traces(&self, program: ProgramId) -> Vec<TraceRef> {
+        self.guests[program.0].tracer.traces()
+    }
+
+    /// The guards of the trace installed for `key`, with the guest
+    /// constructs they come from.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn guards(&self, key: TraceKey) -> Vec<GuardInfo> {
+        let guest = &self.guests[key.program.0];
+        guest.tracer.trace(key).map_or(Vec::new(), |trace| trace.guard_infos(&guest.program))
+    }
+
+    /// The program registered as `program`.
+    pub fn program(&self, program: ProgramId) -> &Program {
+        &self.guests[program.0].program
+    }
+
+    /// The call traces of `program`, ordered by their keys.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn call_traces(&self, program: ProgramId) -> Vec<TraceRef> {
+        self.guests[program.0].tracer.call_traces()
+    }
+
+    /// The trace `run_program` recorded for the calls at `key.ip` of the
+    /// function `key.specialization`, if any.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn call_trace(&self, key: TraceKey) -> Option<TraceRef> {
+        self.guests[key.program.0].tracer.call_trace(key)
+    }
+
+    /// Write how hot each merge point of `program` got during this run to
+    /// `path`, for `load_profile` in a later run. There are no files on
+    /// wasm32.
+    #[cfg(all(not(feature = "no-jit"), not(target_arch = "wasm32")))]
+    pub fn save_profile<P: AsRef<Path>>(&self, program: ProgramId, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(try!(File::create(path)));
+        hotness::write_hotness(&mut out, &self.guests[program.0].tracer.hotness())
+    }
+
+    /// Warm up the merge points which were hot in a previous run, see
+    /// `save_profile`. Loops proven hot start tracing the first time they
+    /// are reached.
+    #[cfg(all(not(feature = "no-jit"), not(target_arch = "wasm32")))]
+    pub fn load_profile<P: AsRef<Path>>(&mut self, program: ProgramId, path: P) -> io::Result<()> {
+        let input = BufReader::new(try!(File::open(path)));
+        let tracer = &mut self.guests[program.0].tracer;
+        for (key, hits) in try!(hotness::read_hotness(input, program)) {
+            tracer.preheat(key, hits);
+        }
+        Ok(())
+    }
+
+    /// Which opcodes of `program` the traces recorded so far cover.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn coverage<'a>(&'a self, program: ProgramId) -> Coverage<'a> {
+        let guest = &self.guests[program.0];
+        Coverage::new(&guest.program, guest.tracer.covered())
+    }
+
+    /// Choose which merge points count towards starting a trace.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_anchoring(&mut self, anchoring: Anchoring) {
+        self.anchoring = anchoring;
+        for guest in &mut self.guests {
+            guest.tracer.anchoring = anchoring;
+        }
+    }
+
+    /// Choose how to continue when a trace is left through a guard.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_guard_failure_policy(&mut self, policy: GuardFailurePolicy) {
+        self.guard_failure = policy;
+    }
+
+    /// Limit the bridges, traces starting at a failing guard, one trace can
+    /// get to `max`. Guards of a trace which has used up its bridges go back
+    /// to the interpreter. The default is `DEFAULT_MAX_BRIDGES`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_max_bridges(&mut self, max: usize) {
+        self.max_bridges = Some(max);
+        for guest in &mut self.guests {
+            guest.tracer.max_bridges = Some(max);
+        }
+    }
+
+    /// Discard traces once their guards failed more than `max` times, if
+    /// they aren't left less often than they go around their loop. The
+    /// merge point gets recorded again when it is hot again. The default is
+    /// `DEFAULT_MAX_GUARD_FAILURES`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_max_guard_failures(&mut self, max: usize) {
+        self.max_guard_failures = Some(max);
+        for guest in &mut self.guests {
+            guest.tracer.max_guard_failures = Some(max);
+        }
+    }
+
+    /// Split traces longer than `max` opcodes into linked pieces which fit,
+    /// see `split_trace`. The default is `DEFAULT_MAX_TRACE_LEN`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_max_trace_len(&mut self, max: usize) {
+        self.max_trace_len = Some(max);
+        for guest in &mut self.guests {
+            guest.tracer.max_trace_len = Some(max);
+        }
+    }
+
+    /// How often the trace of `key` was discarded for failing guards, see
+    /// `set_max_guard_failures`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn invalidations(&self, key: TraceKey) -> usize {
+        self.guests[key.program.0].tracer.invalidations(key)
+    }
+
+    /// Forget how often the merge points of `program` were hit, e.g. between
+    /// the runs of a benchmark which shouldn't make anything hot. Installed
+    /// traces and the blacklist stay.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn reset_counters(&mut self, program: ProgramId) {
+        self.guests[program.0].tracer.reset_counters();
+    }
+
+    /// Let the optimizer apply at most `fuel` rewrites to each trace
+    /// installed from now on, `None` (the default) for any number. Finds
+    /// the rewrite breaking a guest together with `opt::bisect_fuel`.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_opt_fuel(&mut self, fuel: Option<usize>) {
+        self.opt_fuel = fuel;
+        for guest in &mut self.guests {
+            guest.tracer.opt_fuel = fuel;
+        }
+    }
+
+    /// Choose the passes of `opt::optimize_trace_with` which run over each
+    /// trace installed from now on, all of them by default.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn set_trace_passes(&mut self, passes: TracePasses) {
+        self.trace_passes = passes;
+        for guest in &mut self.guests {
+            guest.tracer.trace_passes = passes;
+        }
+    }
+
+    /// Let the JIT handle the guest at `pc`, called by the host at the top
+    /// of its dispatch loop. Returns the pc the host continues from, the
+    /// guest state is copied in from and back into `user_program` and
+    /// `cell`.
+    ///
+    /// This is `merge_point_with` for the state of a `CellState`.
     pub fn merge_point<'a>(&mut self,
-                           program: &Program,
-                           (fn_idx, oc_idx): (usize, usize),
+                           program_id: ProgramId,
                            user_program: &[usize],
                            pc: usize,
                            cell: &'a mut usize)
-                           -> usize {
-        let res = self.tracer.handle_mergepoint(pc as u64);
+                           -> InterpResult<usize> {
+        let mut state = CellState {
+            user_program: user_program,
+            pc: pc,
+            cell: *cell,
+        };
+        let res = self.merge_point_with(program_id, &mut state);
+        // also after a guard failure, the host continues from the snapshot
+        *cell = state.cell;
+        res.map(|()| state.pc)
+    }
 
-        match res {
-            MergePointResult::StartTrace => {
-                let func = &program[fn_idx];
+    /// Let the JIT handle the guest in `state`, called by the host at the
+    /// top of its dispatch loop. The host continues from `state`, which is
+    /// updated with what the guest did.
+    ///
+    /// This does `enter` and whatever the `Decision` asks for with a fresh
+    /// frame each time. Hosts which want to build the frame themselves use
+    /// those directly.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn merge_point_with<S: MergeState>(&mut self, program_id: ProgramId, state: &mut S)
+                                           -> InterpResult<()> {
+        try!(self.ensure_verified(program_id));
+        let key = self.state_key(program_id, state);
 
-                let mut s = R_Struct::with_size(user_program.len());
-                for (i, uoc) in user_program.iter().enumerate() {
-                    s.set(i, R_BoxedValue::Usize(*uoc));
+        match self.enter(key) {
+            Decision::Record(key) => {
+                let mut frame = self.state_frame(program_id, state);
+                // otherwise the host runs the iteration again from its
+                // state, nothing but the copy in `frame` was changed
+                if try!(self.record(key, &mut frame)) {
+                    state.restore(&frame);
                 }
+                Ok(())
+            }
 
-                let mut frame = CallFrame::new(None, func.1);
-                *frame.locals[1].borrow_mut() = R_BoxedValue::Struct(s);
-                *frame.locals[2].borrow_mut() = R_BoxedValue::Usize(*cell);
-                *frame.locals[3].borrow_mut() = R_BoxedValue::Usize(pc);
-                let prog = program.iter().map(|&(fni, pc, ocs)| (fni, pc, ocs.to_vec())).collect();
+            Decision::Execute(key, trace) => {
+                let mut frame = self.state_frame(program_id, state);
+                let res = self.execute_trace(key, &trace, &mut frame);
+                match res {
+                    Ok(()) => state.restore(&frame),
+                    // the host continues from the snapshot
+                    Err(InterpError { kind: ErrorKind::GuardFailed { .. }, .. }) => {
+                        state.restore(&frame)
+                    }
+                    Err(_) => (),
+                }
+                res
+            }
 
-                let mut interp = meta::interp::Interpreter::new(&prog);
-                interp.stack_frames.push(frame);
-                interp.run(Some(&mut self.tracer), fn_idx, oc_idx);
-                self.tracer.finish_trace(pc as u64);
+            Decision::Interpret => Ok(()),
+        }
+    }
 
-                let frame = &interp.stack_frames[0];
+    /// Without the JIT the host always interprets the guest itself.
+    #[cfg(feature = "no-jit")]
+    #[inline(always)]
+    pub fn merge_point_with<S: MergeState>(&mut self, _program_id: ProgramId, _state: &mut S)
+                                           -> InterpResult<()> {
+        Ok(())
+    }
 
-                // retrieve state
-                let boxed_cell  = (*frame.locals[2].borrow()).clone();
-                if let R_BoxedValue::Usize(content) = boxed_cell {
-                    *cell = content;
-                }
+    /// Key of the merge point of `program_id` at guest position `pc`.
+    pub fn trace_key(&self, program_id: ProgramId, user_program: &[usize], pc: usize) -> TraceKey {
+        let state = CellState {
+            user_program: user_program,
+            pc: pc,
+            cell: 0,
+        };
+        self.state_key(program_id, &state)
+    }
 
-                // retrieve pc
-                let boxed_pc = (*frame.locals[3].borrow()).clone();
+    /// Key of the merge point of `program_id` the guest in `state` is at.
+    pub fn state_key<S: MergeState>(&self, program_id: ProgramId, state: &S) -> TraceKey {
+        let (fn_idx, oc_idx) = self.guests[program_id.0].program.entry;
+        TraceKey {
+            program: program_id,
+            ip: InstructionPointer { func: fn_idx, pc: oc_idx },
+            pc: state.pc(),
+            specialization: state.specialization(),
+        }
+    }
 
-                if let R_BoxedValue::Usize(ref new_pc) = boxed_pc {
-                    new_pc.clone()
-                } else {
-                    panic!("");
-                }
-                // println!("Start Trace for PC: {}", pc);
-                // println!("{:?}", program[fn_idx].2[oc_idx]);
+    /// Frame of the function containing the merge point of `program_id`,
+    /// with the state of the host in its locals. It can be passed to
+    /// `record` and `execute_trace` over and over again, the host updates
+    /// the locals `USER_PROGRAM_LOCAL`, `CELL_LOCAL` and `PC_LOCAL` between
+    /// the calls.
+    pub fn entry_frame(&self, program_id: ProgramId, user_program: &[usize], pc: usize, cell: usize)
+                       -> CallFrame {
+        let state = CellState {
+            user_program: user_program,
+            pc: pc,
+            cell: cell,
+        };
+        self.state_frame(program_id, &state)
+    }
+
+    /// Frame of the function containing the merge point of `program_id`,
+    /// with the red variables of `state` in its locals.
+    pub fn state_frame<S: MergeState>(&self, program_id: ProgramId, state: &S) -> CallFrame {
+        state_frame(&self.guests[program_id.0].program, state)
+    }
+
+    /// Count the merge point `key` and decide what to do there. Nothing
+    /// runs yet, the host follows up on the decision.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn enter(&mut self, key: TraceKey) -> Decision {
+        #[cfg(all(feature = "tui", not(target_arch = "wasm32")))]
+        {
+            if let Some(ref mut tui) = self.tui {
+                tui.refresh(self.guests.iter().map(|guest| &guest.tracer));
             }
+        }
 
-            MergePointResult::Trace(trace) => {
-                let func = &program[fn_idx];
+        self.runtime.stats.record_visit(key, &mut *self.runtime.clock);
+        match self.guests[key.program.0].tracer.handle_mergepoint(key) {
+            MergePointResult::StartTrace => {
+                self.events.emit(JitEvent::TraceStarted { key: key });
+                Decision::Record(key)
+            }
+            MergePointResult::Trace(trace) => Decision::Execute(key, trace),
+            MergePointResult::None => Decision::Interpret,
+        }
+    }
 
-                let mut s = R_Struct::with_size(user_program.len());
-                for (i, uoc) in user_program.iter().enumerate() {
-                    s.set(i, R_BoxedValue::Usize(*uoc));
+    #[cfg(feature = "no-jit")]
+    #[inline(always)]
+    pub fn enter(&mut self, _key: TraceKey) -> Decision {
+        Decision::Interpret
+    }
+
+    /// Interpret one iteration of the guest in `frame` while recording a
+    /// trace for `key`, after `enter` returned `Decision::Record`.
+    ///
+    /// Returns false if the loop can't be traced. The merge point is
+    /// blacklisted then and the host has to execute the iteration itself,
+    /// `frame` is left as the failed run left it. If the failed run already
+    /// did guest I/O or drew random numbers, which the host would repeat,
+    /// it fails with `ErrorKind::UnsupportedOpcode` instead.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn record(&mut self, key: TraceKey, frame: &mut CallFrame) -> InterpResult<bool> {
+        let guest = &mut self.guests[key.program.0];
+        let program = &guest.program;
+        let code = &guest.code;
+        let (fn_idx, oc_idx) = program.entry;
+
+        let mut interp = Interpreter::new(program, code, &mut self.runtime);
+        interp.basic.push_frame(take_frame(frame));
+        let res = {
+            let recording = Recording { tracer: &mut guest.tracer };
+            match interp.run(Some(&mut *recording.tracer), fn_idx, oc_idx) {
+                Ok(()) => {
+                    recording.finish();
+                    Ok(true)
                 }
+                Err(err @ InterpError { kind: ErrorKind::UnsupportedOpcode(_), .. }) => {
+                    debug!("unsupported {:?}, blacklisting {:?}", err.kind, key);
+                    recording.tracer.blacklist(key);
+                    self.events.emit(JitEvent::Blacklisted { key: key });
+                    if interp.had_effects { Err(err) } else { Ok(false) }
+                }
+                Err(err) => Err(err),
+            }
+        };
+        give_frame(&mut interp, frame);
 
-                let mut frame = CallFrame::new(None, func.1);
-                *frame.locals[1].borrow_mut() = R_BoxedValue::Struct(s);
-                *frame.locals[2].borrow_mut() = R_BoxedValue::Usize(*cell);
-                *frame.locals[3].borrow_mut() = R_BoxedValue::Usize(pc);
-                let prog = program.iter().map(|&(fni, pc, ocs)| (fni, pc, ocs.to_vec())).collect();
+        // invalid traces are dropped when recording finishes
+        if let Ok(true) = res {
+            if let Some(trace) = guest.tracer.traces.get(&key) {
+                self.events.emit(JitEvent::TraceFinished {
+                    key: key,
+                    id: trace.generation,
+                    len: trace.ops.len(),
+                });
+            }
+        }
+        res
+    }
 
-                let mut interp = meta::interp::Interpreter::new(&prog);
-                interp.stack_frames.push(frame);
-                let inst = interp.run_trace(&*trace);
-                // blackhole?
-                interp.run(None, fn_idx, inst.pc);
+    /// Without the JIT nothing is ever recorded.
+    #[cfg(feature = "no-jit")]
+    pub fn record(&mut self, _key: TraceKey, _frame: &mut CallFrame) -> InterpResult<bool> {
+        Ok(false)
+    }
 
-                let frame = &interp.stack_frames[0];
+    /// Run `trace`, which `enter` returned for `key`, on the guest in
+    /// `frame` and finish the iteration in which it is left according to
+    /// the `GuardFailurePolicy`.
+    ///
+    /// With `GuardFailurePolicy::ReturnToHost` this fails with
+    /// `ErrorKind::GuardFailed`, and `frame` holds the state the host
+    /// continues from. A trace which yields, see `set_yield_hook`, returns
+    /// right away with its last iteration done.
+    ///
+    /// `trace` and `frame` have to belong to the program of `key`, which
+    /// debug builds assert.
+    #[cfg(not(feature = "no-jit"))]
+    pub fn execute_trace(&mut self, key: TraceKey, trace: &TraceRef, frame: &mut CallFrame)
+                         -> InterpResult<()> {
+        let guest = &mut self.guests[key.program.0];
+        let program = &guest.program;
+        let code = &guest.code;
+        let tracer = &mut guest.tracer;
+        let (fn_idx, oc_idx) = program.entry;
 
-                // retrieve state
-                let boxed_cell  = (*frame.locals[2].borrow()).clone();
-                if let R_BoxedValue::Usize(content) = boxed_cell {
-                    *cell = content;
-                }
+        // traces only run on frames of the program they were recorded for
+        debug_assert_eq!(trace.entry_key.program, key.program);
+        debug_assert_eq!(frame.locals.len(), program.functions[fn_idx].locals_cnt);
 
-                // retrieve pc
-                let boxed_pc = (*frame.locals[3].borrow()).clone();
+        let mut interp = Interpreter::new(program, code, &mut self.runtime);
+        interp.basic.push_frame(take_frame(frame));
+        interp.take_snapshots = self.guard_failure != GuardFailurePolicy::Blackhole;
+        interp.basic.ip = InstructionPointer { func: fn_idx, pc: oc_idx };
+        trace.stats.record_entry();
+        interp.runtime.stats.record_entry(key);
+        let res = match interp.run_trace(trace) {
+            Ok(ref exit) if exit.yielded => {
+                interp.record_run(key, exit.iterations);
+                trace.stats.record_iterations(exit.iterations);
+                Ok(())
+            }
+            Ok(exit) => {
+                let inst = exit.recovery();
+                // otherwise traces are only left through guards
+                trace.stats.record_iterations(exit.iterations);
+                interp.record_run(key, exit.iterations);
+                trace.record_exit(&exit);
+                interp.runtime.stats.record_guard_failure(key);
+                interp.log_guard_failure(key, &exit);
+                let discarded = tracer.invalidate_if_failing(key);
+                if let Some(ref discarded) = discarded {
+                    self.events.emit(JitEvent::TraceEvicted {
+                        key: key,
+                        id: discarded.generation,
+                    });
+                }
+                self.events.emit(JitEvent::GuardFailed {
+                    key: key,
+                    id: trace.generation,
+                    guard: inst,
+                    guard_id: trace.exit_guard_id(&exit),
+                    trace_pc: exit.trace_pc,
+                    bridge: exit.bridge,
+                });
+                #[cfg(all(feature = "tui", not(target_arch = "wasm32")))]
+                {
+                    if let Some(ref mut tui) = self.tui {
+                        tui.deopt(key, inst);
+                    }
+                }
 
-                if let R_BoxedValue::Usize(ref new_pc) = boxed_pc {
-                    new_pc.clone()
-                } else {
-                    panic!("");
+                match self.guard_failure {
+                    GuardFailurePolicy::Blackhole => {
+                        // finish the iteration from the guard on, recording
+                        // a bridge if the guard keeps failing
+                        if discarded.is_none() && wants_bridge(trace, &exit) &&
+                           grant_bridge(tracer, &mut self.events, key, trace) &&
+                           tracer.start_bridge(key, &exit) {
+                            let res = {
+                                let recording = Recording { tracer: &mut *tracer };
+                                let res = interp.run(Some(&mut *recording.tracer),
+                                                     fn_idx,
+                                                     inst.pc);
+                                if res.is_ok() {
+                                    recording.finish();
+                                }
+                                res
+                            };
+                            let guard_id = trace.guard_id(exit.trace_pc).unwrap();
+                            let bridge = tracer.trace(key)
+                                .and_then(|trace| trace.bridge(guard_id).cloned());
+                            if let (true, Some(bridge)) = (res.is_ok(), bridge) {
+                                self.events.emit(JitEvent::BridgeFinished {
+                                    key: key,
+                                    id: bridge.generation,
+                                    guard_id: guard_id,
+                                    len: bridge.ops.len(),
+                                });
+                            }
+                            res
+                        } else if interp.basic.stack_frames.len() > 1 {
+                            // the guard was in an inlined call
+                            interp.finish_calls(inst, 1)
+                                .and_then(|resume| interp.run(None, fn_idx, resume.pc))
+                        } else {
+                            interp.run(None, fn_idx, inst.pc)
+                        }
+                    }
+                    GuardFailurePolicy::Snapshot => {
+                        // redo the whole iteration
+                        interp.restore_snapshot();
+                        interp.run(None, fn_idx, oc_idx)
+                    }
+                    GuardFailurePolicy::ReturnToHost => {
+                        interp.restore_snapshot();
+                        let resume = frame_pc(&interp.basic.stack_frames[0]);
+                        Err(interp.basic.error(ErrorKind::GuardFailed { resume: resume }))
+                    }
                 }
+            }
+            Err(err) => Err(err),
+        };
+        give_frame(&mut interp, frame);
+        res
+    }
 
+    /// Without the JIT `enter` never returns a trace to execute.
+    #[cfg(feature = "no-jit")]
+    pub fn execute_trace(&mut self, _key: TraceKey, _trace: &TraceRef, _frame: &mut CallFrame)
+                         -> InterpResult<()> {
+        unreachable!("no traces without the JIT")
+    }
 
+    /// Execute function `entry` of `program` with `args` to its end, as a
+    /// standalone VM, and return its result. Fails with `UnknownFunction` or
+    /// `ArityMismatch` if the program has no such function or it takes
+    /// another number of arguments. The merge point of the program
+    /// is handled the same as if the host called `merge_point` there, except
+    /// that traces are always left with `GuardFailurePolicy::Blackhole`.
+    pub fn run_program(&mut self, program_id: ProgramId, entry: usize, args: Vec<R_BoxedValue>)
+                       -> InterpResult<R_BoxedValue> {
+        try!(self.ensure_verified(program_id));
+        let guest = &mut self.guests[program_id.0];
+        let program = &guest.program;
+        let code = &guest.code;
+
+        let func = match program.functions.get(entry) {
+            Some(func) => func,
+            None => {
+                return Err(InterpError {
+                    kind: ErrorKind::UnknownFunction(entry),
+                    backtrace: Vec::new(),
+                });
             }
+        };
+        if args.len() != func.args_cnt {
+            let kind = ErrorKind::ArityMismatch {
+                func: entry,
+                expected: func.args_cnt,
+                found: args.len(),
+            };
+            return Err(InterpError { kind: kind, backtrace: Vec::new() });
+        }
 
-            MergePointResult::None => pc,
+        let mut frame = CallFrame::new(None, func.locals_cnt);
+        frame.local_names = program.local_names(entry).cloned();
+        for (local, arg) in frame.locals.iter().zip(args) {
+            local.set(arg);
         }
+
+        let mut interp = Interpreter::new(program, code, &mut self.runtime);
+        interp.basic.push_frame(frame);
+        #[cfg(not(feature = "no-jit"))]
+        let res = {
+            let res = interp.run_program(Some(&mut guest.tracer), program_id, entry);
+            if guest.tracer.is_recording() {
+                guest.tracer.abort_trace();
+            }
+            guest.tracer.abort_call_trace();
+            res
+        };
+        #[cfg(feature = "no-jit")]
+        let res = interp.run_program(None, program_id, entry);
+        res
+    }
+
+    /// Execute the guest from `pc` up to its next merge point in the
+    /// interpreter, without tracing, and return the pc to continue from.
+    ///
+    /// This is what the host does itself after `merge_point` returned
+    /// without running a trace, hosts can call this instead.
+    pub fn interpret_until_mergepoint(&mut self,
+                                      program_id: ProgramId,
+                                      user_program: &[usize],
+                                      pc: usize,
+                                      cell: &mut usize)
+                                      -> InterpResult<usize> {
+        let program = &self.guests[program_id.0].program;
+        let code = &self.guests[program_id.0].code;
+        let (fn_idx, oc_idx) = program.entry;
+
+        let mut state = CellState {
+            user_program: user_program,
+            pc: pc,
+            cell: *cell,
+        };
+        let mut interp = Interpreter::new(program, code, &mut self.runtime);
+        interp.basic.push_frame(state_frame(program, &state));
+        try!(interp.run(None, fn_idx, oc_idx));
+
+        state.restore(&interp.basic.stack_frames[0]);
+        *cell = state.cell;
+        Ok(state.pc)
     }
 }
 
+// Whether the guard `exit` left `trace` through failed often enough for a
+// bridge.
+#[cfg(not(feature = "no-jit"))]
+fn wants_bridge(trace: &Trace, exit: &GuardExit) -> bool {
+    if exit.bridge.is_some() || exit.link.is_some() {
+        return false;
+    }
+    trace.guard_id(exit.trace_pc).map_or(false, |id| {
+        trace.bridge(id).is_none() && trace.stats.guard_failures_of(id) >= HOT_GUARD_THRESHOLD
+    })
+}
 
-type HashValue = u64;
+// Whether a bridge may be recorded for a guard of `trace`, reports the first
+// refusal.
+#[cfg(not(feature = "no-jit"))]
+fn grant_bridge(tracer: &mut Tracer, events: &mut Subscribers, key: TraceKey, trace: &Trace)
+                -> bool {
+    if tracer.add_bridge(key) {
+        return true;
+    }
+    if tracer.bridge_attempts(key) == tracer.max_bridges() + 1 {
+        events.emit(JitEvent::BridgeLimitReached {
+            key: key,
+            id: trace.generation,
+        });
+    }
+    false
+}
+
+// Frame of the function containing the merge point, with the host state in
+// its locals.
+fn state_frame<S: MergeState>(program: &Program, state: &S) -> CallFrame {
+    let func = &program.functions[program.entry.0];
+    let mut frame = CallFrame::new(None, func.locals_cnt);
+    frame.local_names = program.local_names(program.entry.0).cloned();
+    for (local, val) in state.red_vars() {
+        frame.locals[local].set(val);
+    }
+    frame
+}
+
+fn frame_pc(frame: &CallFrame) -> usize {
+    if let R_BoxedValue::Usize(pc) = frame.locals[PC_LOCAL].get() {
+        pc
+    } else {
+        panic!("expected guest pc");
+    }
+}
+
+// Move the frame of the host into an interpreter, `give_frame` puts it back
+// once the interpreter is done, whether it failed or not.
+#[cfg(not(feature = "no-jit"))]
+fn take_frame(frame: &mut CallFrame) -> CallFrame {
+    mem::replace(frame, CallFrame::new(None, 0))
+}
+
+#[cfg(not(feature = "no-jit"))]
+fn give_frame(interp: &mut Interpreter, frame: &mut CallFrame) {
+    interp.basic.stack_frames.truncate(1);
+    if let Some(entry) = interp.basic.stack_frames.pop() {
+        *frame = entry;
+    }
+}
+
+
+pub type HashValue = u64;
+#[cfg(not(feature = "no-jit"))]
 const HOT_LOOP_THRESHOLD: usize = 5;
+/// bridges a trace can get unless `Driver::set_max_bridges` says otherwise
+pub const DEFAULT_MAX_BRIDGES: usize = 8;
+/// guard failures after which a trace is discarded unless
+/// `Driver::set_max_guard_failures` says otherwise
+pub const DEFAULT_MAX_GUARD_FAILURES: usize = 100;
+/// opcodes a trace can have unless `Driver::set_max_trace_len` says
+/// otherwise, longer ones are split
+pub const DEFAULT_MAX_TRACE_LEN: usize = 1000;
+/// failures after which a guard gets a bridge
+#[cfg(not(feature = "no-jit"))]
+const HOT_GUARD_THRESHOLD: usize = 5;
+
+/// Which merge points can become the start of a trace.
+///
+/// Hosts usually call `merge_point` at the top of their dispatch loop, i.e.
+/// for every guest instruction. Only a guest position which is reached by
+/// jumping backwards can be the header of a guest loop, so only those are
+/// worth counting.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Anchoring {
+    /// Count a merge point only if the guest pc didn't increase since the
+    /// previous merge point.
+    BackEdges,
+    /// Count every merge point.
+    Everywhere,
+}
+
+impl Default for Anchoring {
+    fn default() -> Self {
+        Anchoring::BackEdges
+    }
+}
+
+/// A trace being recorded by an interpreter run.
+///
+/// Dropping it before `finish`, i.e. when the run failed or panicked, throws
+/// the partial trace away so the tracer doesn't stay in recording mode.
+#[cfg(not(feature = "no-jit"))]
+struct Recording<'a> {
+    tracer: &'a mut Tracer,
+}
+
+#[cfg(not(feature = "no-jit"))]
+impl<'a> Recording<'a> {
+    fn finish(self) {
+        self.tracer.finish_trace();
+    }
+}
 
+#[cfg(not(feature = "no-jit"))]
+impl<'a> Drop for Recording<'a> {
+    fn drop(&mut self) {
+        if self.tracer.is_recording() {
+            debug!("interpreter failed, aborting trace for {:?}", self.tracer.loop_start);
+            self.tracer.abort_trace();
+        }
+    }
+}
+
+/// What the driver does when a trace is left through a guard.
+///
+/// `Snapshot` and `ReturnToHost` undo an iteration by resetting the locals
+/// of the frame the trace was entered in. Other effects of the iteration
+/// stay: writes into structs and shared cells, and guest I/O. They are
+/// meant for traces without such effects.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GuardFailurePolicy {
+    /// Continue the guest iteration from the guard in the interpreter.
+    Blackhole,
+    /// Undo the iteration which failed and interpret it from its start.
+    Snapshot,
+    /// Undo the iteration which failed and leave `merge_point` with
+    /// `ErrorKind::GuardFailed`, the host executes the iteration itself.
+    ReturnToHost,
+}
+
+impl Default for GuardFailurePolicy {
+    fn default() -> Self {
+        GuardFailurePolicy::Blackhole
+    }
+}
+
+#[cfg(not(feature = "no-jit"))]
 #[derive(Default)]
 pub struct Tracer {
     /// counter for program positions
-    counter: BTreeMap<HashValue, usize>,
-    traces: BTreeMap<HashValue, Rc<Vec<OpCode>>>,
-    loop_start: HashValue,
+    counter: FastHashMap<TraceKey, usize>,
+    /// merge points warmed up by a loaded profile, their counts survive
+    /// the start of other recordings
+    preheated: FastHashSet<TraceKey>,
+    traces: FastHashMap<TraceKey, TraceRef>,
+    /// the trace found by the previous lookup, a loop usually enters the
+    /// same trace over and over again
+    last_trace: Option<(TraceKey, TraceRef)>,
+    /// generation of the next installed trace
+    generation: usize,
 
+    /// key of the trace being recorded, the trace is stored under it once
+    /// the loop is closed
+    loop_start: Option<TraceKey>,
     active: Option<Vec<OpCode>>,
+    /// program positions recorded into the active trace
+    active_positions: FastHashSet<InstructionPointer>,
+    /// types of the values the active trace works on
+    types: TypeState,
+
+    /// targets of the jumps taken while recording
+    seen_jump_targets: FastHashSet<InstructionPointer>,
+    /// merge points which never start recording
+    blacklist: FastHashSet<TraceKey>,
+
+    anchoring: Anchoring,
+    /// key of the previous merge point, to detect guest back-edges
+    last_key: Option<TraceKey>,
+
+    /// `None` for `DEFAULT_MAX_BRIDGES`
+    max_bridges: Option<usize>,
+    /// bridges asked for per root trace, including refused ones
+    bridges: FastHashMap<TraceKey, usize>,
+    /// where the bridge being recorded starts, if the active recording is
+    /// one
+    bridge: Option<BridgeStart>,
+
+    /// `None` for `DEFAULT_MAX_GUARD_FAILURES`
+    max_guard_failures: Option<usize>,
+    /// traces discarded per merge point for failing guards
+    invalidations: FastHashMap<TraceKey, usize>,
+    /// `None` for `DEFAULT_MAX_TRACE_LEN`
+    max_trace_len: Option<usize>,
+
+    /// traces of whole invocations
+    calls: Calls,
+    /// which locals of the program alias, for optimizing traces
+    aliases: AliasClasses,
+    /// the read-only data of the program, reads of it are folded into traces
+    data: Vec<R_BoxedValue>,
+    /// the functions and vtables of the program, calls in traces are inlined
+    functions: Vec<FunctionRef>,
+    vtables: Vec<Vec<usize>>,
+    /// rewrites the optimizer may apply to each trace, `None` for any number
+    opt_fuel: Option<usize>,
+    /// the passes of the trace pipeline which run before installing
+    trace_passes: TracePasses,
+}
+
+/// The guard of a root trace a bridge is recorded for.
+#[cfg(not(feature = "no-jit"))]
+#[derive(Debug, Clone, Copy)]
+struct BridgeStart {
+    root: TraceKey,
+    guard_id: usize,
+    recovery: InstructionPointer,
+    /// values the failing guard left on the operand stack
+    depth: usize,
+}
+
+/// What the driver wants the host to do at a merge point, see
+/// `Driver::enter`.
+#[derive(Debug, Clone)]
+pub enum Decision {
+    /// Nothing, the host interprets the guest itself.
+    Interpret,
+    /// The merge point got hot, `Driver::record` interprets the iteration
+    /// and records a trace of it.
+    Record(TraceKey),
+    /// `Driver::execute_trace` runs the trace.
+    Execute(TraceKey, TraceRef),
 }
 
 // glorified Option
 #[derive(Clone)]
 pub enum MergePointResult {
-    Trace(Rc<Vec<OpCode>>),
+    Trace(TraceRef),
     StartTrace,
     None,
 }
 
+#[cfg(not(feature = "no-jit"))]
 impl Tracer {
-    pub fn handle_mergepoint(&mut self, key: HashValue) -> MergePointResult {
+    pub fn handle_mergepoint(&mut self, key: TraceKey) -> MergePointResult {
+        let back_edge = self.last_key.map_or(false, |last| key.pc <= last.pc);
+        self.last_key = Some(key);
 
-        if self.traces.contains_key(&key) {
-            return MergePointResult::Trace(self.traces.get(&key).unwrap().clone());
+        if let Some((last, ref trace)) = self.last_trace {
+            if last == key {
+                return MergePointResult::Trace(trace.clone());
+            }
         }
-        // increase counter for program position
-        else if self.active.is_none() {
-            let count = {
-                let count = self.counter.entry(key).or_insert(0);
-                *count += 1;
-                *count
-            };
 
-            if count > HOT_LOOP_THRESHOLD {
-                self.active = Some(Vec::new());
-                self.counter.clear();
-                self.loop_start = key;
-                return MergePointResult::StartTrace;
-            }
+        if let Some(trace) = self.traces.get(&key) {
+            self.last_trace = Some((key, trace.clone()));
+            return MergePointResult::Trace(trace.clone());
         }
-        // close the loop
-        else if key == self.loop_start {
-            self.finish_trace(key);
+
+        // The loop is closed by the interpreter recording the trace, which
+        // calls `finish_trace` once it jumps back to the merge point. Hitting
+        // a merge point while a trace is recorded therefore doesn't change
+        // any state.
+        if self.is_recording() {
+            return MergePointResult::None;
+        }
+
+        // straight-line guest code, no loop can start here
+        if self.anchoring == Anchoring::BackEdges && !back_edge {
+            return MergePointResult::None;
+        }
+
+        if self.blacklist.contains(&key) {
+            return MergePointResult::None;
+        }
+
+        // increase counter for program position
+        let count = {
+            let count = self.counter.entry(key).or_insert(0);
+            *count += 1;
+            *count
+        };
+
+        if count > HOT_LOOP_THRESHOLD {
+            self.abort_call_trace();
+            self.active = Some(Vec::new());
+            self.active_positions.clear();
+            self.types.clear();
+            self.seen_jump_targets.clear();
+            self.preheated.remove(&key);
+            let preheated = &self.preheated;
+            let counter = self.counter
+                .drain()
+                .filter(|&(key, _)| preheated.contains(&key))
+                .collect();
+            self.counter = counter;
+            self.loop_start = Some(key);
+            return MergePointResult::StartTrace;
         }
 
         MergePointResult::None
     }
 
-    pub fn finish_trace(&mut self, key: HashValue) {
-        let active = self.active.take().unwrap();
-        self.traces.insert(key, Rc::new(active));
+    pub fn is_recording(&self) -> bool {
+        self.active.is_some()
     }
 
-    pub fn trace_opcode(&mut self, opcode: &OpCode, pos: InstructionPointer) {
-        let oc = match *opcode {
-            OpCode::Skip(_) |
-            OpCode::JumpBack(_) => {
+    /// Stop recording and throw away what was recorded so far.
+    pub fn abort_trace(&mut self) {
+        self.active = None;
+        self.bridge = None;
+        self.seen_jump_targets.clear();
+    }
+
+    /// Never record a trace for `key` again, and stop a recording for it.
+    pub fn blacklist(&mut self, key: TraceKey) {
+        if self.loop_start == Some(key) {
+            self.abort_trace();
+        }
+        self.counter.remove(&key);
+        self.blacklist.insert(key);
+    }
+
+    pub fn is_blacklisted(&self, key: &TraceKey) -> bool {
+        self.blacklist.contains(key)
+    }
+
+    /// Register the target of a jump taken while recording.
+    ///
+    /// Jumping back to a target which was already jumped to means that the
+    /// recorded code contains an inner loop, which would get unrolled into
+    /// the trace until it terminates. Recording is aborted instead, the inner
+    /// loop is better served by a merge point of its own.
+    pub fn jump_target(&mut self, target: InstructionPointer, backwards: bool) {
+        if !self.is_recording() {
+            return;
+        }
+
+        let seen = !self.seen_jump_targets.insert(target);
+        if seen && backwards {
+            debug!("inner loop at {:?}, aborting trace for {:?}", target, self.loop_start);
+            self.abort_trace();
+            return;
+        }
+
+        // the interpreter stops at any jump back before the guard, a bridge
+        // has to get to the top of the loop of its root
+        if let Some(start) = self.bridge {
+            if backwards && target.func == start.recovery.func &&
+               target.pc >= start.root.ip.pc && target.pc < start.recovery.pc {
+                debug!("bridge ends inside the loop at {:?}, aborting it", target);
+                self.abort_trace();
+            }
+        }
+    }
+
+    /// Close the loop of the trace being recorded and store it under the key
+    /// recording was started for. Does nothing if no trace is recorded.
+    ///
+    /// Traces which fail validation are discarded.
+    pub fn finish_trace(&mut self) {
+        self.seen_jump_targets.clear();
+        if let (Some(mut active), Some(key)) = (self.active.take(), self.loop_start) {
+            active.push(OpCode::LoopBack(LoopInfo::default()));
+            if let Some(start) = self.bridge.take() {
+                self.install_bridge(start, active);
                 return;
             }
+            let mut covered: Vec<_> = self.active_positions.drain().collect();
+            covered.sort();
+            let res = validate_trace(&active).and_then(|()| self.install_trace(key, active, covered));
+            if let Err(reason) = res {
+                debug!("discarding trace for {:?}: {:?}", key, reason);
+            }
+        }
+    }
+
+    /// Number of hits per merge point. Keys with a trace count as hot as the
+    /// trace got entered, on top of the hits it took to record it.
+    pub fn hotness(&self) -> Vec<(TraceKey, usize)> {
+        let mut hotness: Vec<_> = self.counter.iter().map(|(&key, &hits)| (key, hits)).collect();
+        for (&key, trace) in &self.traces {
+            hotness.push((key, HOT_LOOP_THRESHOLD + 1 + trace.stats.entries()));
+        }
+        hotness.sort();
+        hotness
+    }
+
+    /// The installed loop traces, ordered by their keys.
+    pub fn traces(&self) -> Vec<TraceRef> {
+        let mut traces: Vec<_> = self.traces.values().cloned().collect();
+        traces.sort_by_key(|trace| trace.entry_key);
+        traces
+    }
+
+    /// Program positions recorded into any installed trace or its bridges.
+    pub fn covered(&self) -> Vec<InstructionPointer> {
+        let mut covered: Vec<_> =
+            self.traces.values().flat_map(|trace| trace.covered.iter().cloned()).collect();
+        for trace in self.traces.values() {
+            for bridge in trace.bridges.iter().filter_map(|bridge| bridge.as_ref()) {
+                covered.extend(bridge.covered.iter().cloned());
+            }
+        }
+        covered.extend(self.calls.covered());
+        covered
+    }
+
+    /// Forget the hits counted so far, also preheated ones, as if no merge
+    /// point was reached yet. Traces and the blacklist stay.
+    pub fn reset_counters(&mut self) {
+        self.counter.clear();
+        self.preheated.clear();
+        self.last_key = None;
+    }
+
+    /// Count `hits` for `key` as if they happened in this run. A key which
+    /// was hot enough to be traced starts recording on its next hit.
+    pub fn preheat(&mut self, key: TraceKey, hits: usize) {
+        self.preheated.insert(key);
+        let count = self.counter.entry(key).or_insert(0);
+        *count = cmp::min(cmp::max(*count, hits), HOT_LOOP_THRESHOLD);
+    }
+
+    /// Install a trace which wasn't recorded, replacing any trace for `key`.
+    /// The trace has to pass validation, its loop is closed unless it ends
+    /// in a `LoopBack` already.
+    pub fn seed_trace(&mut self, key: TraceKey, mut trace: Vec<OpCode>)
+                      -> Result<(), InvalidTrace> {
+        try!(validate_trace(&trace));
+        match trace.last() {
+            Some(&OpCode::LoopBack(_)) => (),
+            _ => trace.push(OpCode::LoopBack(LoopInfo::default())),
+        }
+        self.counter.remove(&key);
+        self.install_trace(key, trace, Vec::new())
+    }
+
+    /// The trace installed for `key`, if any.
+    pub fn trace(&self, key: TraceKey) -> Option<TraceRef> {
+        self.traces.get(&key).cloned()
+    }
+
+    /// What the trace of `root` established about types when its opcode at
+    /// `trace_pc` is reached, the context a bridge starting at the guard
+    /// there is recorded in.
+    pub fn guard_context(&self, root: TraceKey, trace_pc: usize) -> Option<TypeState> {
+        self.traces.get(&root).map(|trace| TypeState::at(&trace.ops, trace_pc))
+    }
+
+    /// Continue the active recording knowing `context`, so that guards the
+    /// root trace already passed aren't recorded again.
+    pub fn assume(&mut self, context: TypeState) {
+        if self.is_recording() {
+            self.types = context;
+        }
+    }
+
+    pub fn max_bridges(&self) -> usize {
+        self.max_bridges.unwrap_or(DEFAULT_MAX_BRIDGES)
+    }
+
+    /// Count a bridge for the root trace of `root`. Fails once the trace has
+    /// `max_bridges`, its guards then fall back to the interpreter.
+    pub fn add_bridge(&mut self, root: TraceKey) -> bool {
+        let max = self.max_bridges();
+        let attempts = self.bridges.entry(root).or_insert(0);
+        *attempts += 1;
+        *attempts <= max
+    }
+
+    /// How often a bridge was asked for the root trace of `root`.
+    pub fn bridge_attempts(&self, root: TraceKey) -> usize {
+        self.bridges.get(&root).cloned().unwrap_or(0)
+    }
+
+    pub fn max_guard_failures(&self) -> usize {
+        self.max_guard_failures.unwrap_or(DEFAULT_MAX_GUARD_FAILURES)
+    }
+
+    /// Discard the trace of `key`, with its bridges, if it was left through
+    /// its guards more than `max_guard_failures` times and at least as often
+    /// as it went around its loop. Such a trace costs more than it saves,
+    /// the merge point counts towards a new recording instead. Returns the
+    /// discarded trace.
+    pub fn invalidate_if_failing(&mut self, key: TraceKey) -> Option<TraceRef> {
+        let max = self.max_guard_failures();
+        let failing = self.traces.get(&key).map_or(false, |trace| {
+            let failures = trace.stats.guard_failures();
+            failures > max && failures >= trace.stats.iterations()
+        });
+        if !failing {
+            return None;
+        }
+
+        debug!("guards of the trace for {:?} keep failing, discarding it", key);
+        if self.last_trace.as_ref().map_or(false, |&(last, _)| last == key) {
+            self.last_trace = None;
+        }
+        self.counter.remove(&key);
+        self.bridges.remove(&key);
+        *self.invalidations.entry(key).or_insert(0) += 1;
+        self.traces.remove(&key)
+    }
+
+    /// How often the trace of `key` was discarded, see
+    /// `invalidate_if_failing`.
+    pub fn invalidations(&self, key: TraceKey) -> usize {
+        self.invalidations.get(&key).cloned().unwrap_or(0)
+    }
+
+    /// Start recording a bridge for the guard of the trace of `root` which
+    /// was left at `exit`. The interpreter continues the iteration from the
+    /// guard, and the bridge is attached to it once the iteration gets back
+    /// to the top of the loop. Returns false if no bridge can start there.
+    pub fn start_bridge(&mut self, root: TraceKey, exit: &GuardExit) -> bool {
+        // only the guards of the first piece of a trace get bridges
+        if self.is_recording() || exit.bridge.is_some() || exit.link.is_some() {
+            return false;
+        }
+        let guard_id = match self.traces.get(&root).and_then(|trace| trace.guard_id(exit.trace_pc)) {
+            Some(id) => id,
+            None => return false,
+        };
+        let recovery = exit.recovery();
+        // the interpreter finishes the iteration in the frame of the loop
+        if recovery.func != root.ip.func || self.traces[&root].in_call(exit.trace_pc) {
+            return false;
+        }
+
+        self.abort_call_trace();
+        self.active = Some(Vec::new());
+        self.active_positions.clear();
+        self.seen_jump_targets.clear();
+        // what the root trace checked before the guard holds in the bridge
+        self.types = TypeState::at(&self.traces[&root].ops, exit.trace_pc);
+        self.loop_start = Some(root);
+        self.bridge = Some(BridgeStart {
+            root: root,
+            guard_id: guard_id,
+            recovery: recovery,
+            depth: exit.stack_snapshot.len(),
+        });
+        true
+    }
+
+    fn install_bridge(&mut self, start: BridgeStart, bridge: Vec<OpCode>) {
+        if let Err(reason) = validate_bridge(&bridge, start.depth) {
+            debug!("discarding bridge for {:?}: {:?}", start.root, reason);
+            return;
+        }
+        // not optimized, the passes expect traces starting on an empty stack
+        let mut bridge = Trace::new(bridge, start.root, self.generation);
+        let mut covered: Vec<_> = self.active_positions.drain().collect();
+        covered.sort();
+        bridge.covered = covered;
+        self.generation += 1;
+        let bridge = TraceRef::new(bridge);
+        self.update_trace(start.root, |trace| trace.attach_bridge(start.guard_id, bridge));
+    }
+
+    pub fn max_trace_len(&self) -> usize {
+        self.max_trace_len.unwrap_or(DEFAULT_MAX_TRACE_LEN)
+    }
+
+    fn install_trace(&mut self, key: TraceKey, mut trace: Vec<OpCode>,
+                     covered: Vec<InstructionPointer>)
+                     -> Result<(), InvalidTrace> {
+        let mut fuel = Fuel::new(self.opt_fuel);
+        let pruned = opt::prune_trace(&mut trace, &mut fuel);
+        if pruned > 0 {
+            debug!("pruned {} unreachable opcodes from the trace for {:?}", pruned, key);
+        }
+        // loop traces run in the frame of the merge point
+        opt::forward_loads(&mut trace, &self.aliases, key.ip.func, &mut fuel);
+        opt::fold_constant_loads(&mut trace, &self.data, &mut fuel);
+        opt::fold_pointer_moves(&mut trace, &mut fuel);
+        trace = opt::optimize_trace_with(trace, self.trace_passes, &mut fuel);
+        // pruned traces end in their exit instead of looping
+        let loops = match trace.last() {
+            Some(&OpCode::LoopBack(_)) => true,
+            _ => false,
+        };
+        let pieces = try!(split_trace(trace, self.max_trace_len()));
+        if pieces.len() > 1 {
+            debug!("split the trace for {:?} into {} pieces", key, pieces.len());
+        }
+
+        // a new root starts without bridges
+        self.bridges.remove(&key);
+        let (first, count) = (self.generation, pieces.len());
+        self.generation += count;
+        let mut pieces: Vec<_> = pieces.into_iter()
+            .enumerate()
+            .map(|(idx, mut ops)| {
+                // each piece continues in the next one, the last one back in
+                // the first
+                if idx + 1 < count {
+                    ops.push(OpCode::LoopBack(LoopInfo { link: Some(first + idx + 1) }));
+                } else if loops {
+                    ops.push(OpCode::LoopBack(LoopInfo { link: None }));
+                }
+                Trace::new(ops, key, first + idx)
+            })
+            .collect();
+        let mut trace = pieces.remove(0);
+        trace.links = pieces.into_iter().map(TraceRef::new).collect();
+        trace.covered = covered;
+        self.publish(key, TraceRef::new(trace));
+        Ok(())
+    }
+
+    /// Replace the trace installed for `key` by a changed copy. Executions
+    /// which already hold the old version finish with it.
+    pub fn update_trace<F>(&mut self, key: TraceKey, change: F)
+        where F: FnOnce(&mut Trace)
+    {
+        if let Some(mut trace) = self.traces.remove(&key) {
+            self.last_trace = None;
+            change(TraceRef::make_mut(&mut trace));
+            self.publish(key, trace);
+        }
+    }
+
+    fn publish(&mut self, key: TraceKey, trace: TraceRef) {
+        if self.last_trace.as_ref().map_or(false, |&(last, _)| last == key) {
+            self.last_trace = None;
+        }
+        self.traces.insert(key, trace);
+    }
+
+    /// Append `opcode`, which is executed at `pos` next, to the active
+    /// trace. `operands` is the type of the operands of a `BinOp` if both
+    /// have the same one, which specializes the operation. `condition` is
+    /// the value a conditional jump branches on, which its guard expects.
+    /// `callee` is the function a `Call` enters, or the vtable a
+    /// `CallVirtual` dispatches through, which it is guarded on.
+    pub fn trace_opcode(&mut self, opcode: &OpCode, pos: InstructionPointer,
+                        operands: Option<NumType>, condition: Option<bool>,
+                        callee: Option<usize>) {
+        if !self.is_recording() {
+            return;
+        }
+        if let OpCode::Breakpoint(id) = *opcode {
+            debug!("breakpoint {} at {:?}, aborting trace for {:?}", id, pos, self.loop_start);
+            self.abort_trace();
+            return;
+        }
+        self.active_positions.insert(pos);
+
+        let traced = match self.inlined_call(opcode, pos, callee) {
+            Some(ops) => ops,
+            None => traced_opcodes(&self.types, opcode, pos, operands, condition, callee),
+        };
+        for oc in traced {
+            self.types.record(&oc);
+            self.active.as_mut().unwrap().push(oc);
+        }
+    }
+
+    // A call at `pos` which entered `callee`, or dispatched through it, is
+    // guarded on it like in `traced_opcodes`. The trace goes on with the
+    // opcodes of the function entered, which run in a frame of their own
+    // from the `FlatCall` to their `Return`.
+    fn inlined_call(&self, opcode: &OpCode, pos: InstructionPointer, callee: Option<usize>)
+                    -> Option<Vec<OpCode>> {
+        let guard = Guard {
+            expected: true,
+            recovery: pos,
+        };
+        let (func, guard) = match (opcode, callee) {
+            (&OpCode::Call, Some(func)) => (Some(func), OpCode::GuardFunc(func, guard)),
+            (&OpCode::CallVirtual(method), Some(vtable)) => {
+                let func = self.vtables.get(vtable).and_then(|methods| methods.get(method));
+                (func.cloned(), OpCode::GuardVTable(vtable, guard))
+            }
+            _ => return None,
+        };
+        let function = func.and_then(|func| self.functions.get(func).map(|f| (func, f.clone())));
+        function.map(|(func, function)| {
+            vec![guard, OpCode::Pop, OpCode::FlatCall(func, pos, function)]
+        })
+    }
+}
+
+// What a trace records for `opcode` at `pos`: conditional jumps become
+// guards on the `condition` they saw, and a `BinOp` with operands of one type
+// becomes a `TypedBinOp`, behind a guard unless `types` knows the operands
+// already. A `Call` is guarded on the `callee` it entered, the function it
+// calls may come from a local, and a `CallVirtual` on the concrete vtable. A
+// `CheckedCast` is guarded on the value fitting, as it did when recorded.
+#[cfg(not(feature = "no-jit"))]
+fn traced_opcodes(types: &TypeState, opcode: &OpCode, pos: InstructionPointer,
+                  operands: Option<NumType>, condition: Option<bool>, callee: Option<usize>)
+                  -> Vec<OpCode> {
+    let guard = Guard {
+        expected: condition.unwrap_or(true),
+        recovery: pos,
+    };
+    match (opcode, operands) {
+        (&OpCode::Skip(_), _) |
+        (&OpCode::JumpBack(_), _) => vec![],
 
-            OpCode::SkipIf(_) |
-            OpCode::JumpBackIf(_) => {
-                let guard = Guard {
-                    expected: true,
-                    recovery: pos,
-                };
-                OpCode::Guard(guard)
+        // the guard leaves the condition to the jump if it fails
+        (&OpCode::SkipIf(_), _) |
+        (&OpCode::JumpBackIf(_), _) => vec![OpCode::Guard(guard), OpCode::Pop],
+
+        (&OpCode::BinOp(kind), Some(ty)) => {
+            if types.operands_are(ty) {
+                vec![OpCode::TypedBinOp(kind, ty)]
+            } else {
+                vec![OpCode::GuardOperands(ty, guard), OpCode::TypedBinOp(kind, ty)]
             }
+        }
 
-            _ => opcode.clone(),
+        (&OpCode::CheckedCast(ty), _) => vec![OpCode::GuardCast(ty, guard), OpCode::Cast(ty)],
+
+        (&OpCode::Call, _) if callee.is_some() => {
+            vec![OpCode::GuardFunc(callee.unwrap(), guard), OpCode::Call]
+        }
+        (&OpCode::CallVirtual(method), _) if callee.is_some() => {
+            vec![OpCode::GuardVTable(callee.unwrap(), guard), OpCode::CallVirtual(method)]
+        }
+
+        _ => vec![opcode.clone()],
+    }
+}
+
+
+/// Reason why a recorded trace can't be executed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidTrace {
+    Empty,
+    /// the opcode at the position has no static stack effect
+    UnknownStackEffect(usize),
+    /// the opcode at the position pops more values than are on the stack
+    StackUnderflow(usize),
+    /// number of values left on the stack after one iteration
+    Unbalanced(usize),
+    /// a trace without guard can never be left
+    NoGuard,
+    /// the trace of this many opcodes can't be split into pieces which fit
+    /// the size budget, see `split_trace`
+    TooLong(usize),
+    /// the `Return` at the position leaves a call the trace didn't enter
+    UnmatchedReturn(usize),
+    /// the trace ends in the call it entered at the position
+    UnfinishedCall(usize),
+}
+
+/// Check that a trace can be executed in a loop: every iteration has to leave
+/// the operand stack as it found it, and there has to be a way out.
+pub fn validate_trace(trace: &[OpCode]) -> Result<(), InvalidTrace> {
+    if trace.is_empty() {
+        return Err(InvalidTrace::Empty);
+    }
+
+    match try!(stack_depth(trace, 0)) {
+        (0, true) => Ok(()),
+        (0, false) => Err(InvalidTrace::NoGuard),
+        (depth, _) => Err(InvalidTrace::Unbalanced(depth)),
+    }
+}
+
+/// Check that a bridge, which starts with the `depth` values on the operand
+/// stack the failing guard left, gets back to the start of its root trace
+/// with the stack empty. It is left through the guards of the root if it
+/// has none itself.
+pub fn validate_bridge(bridge: &[OpCode], depth: usize) -> Result<(), InvalidTrace> {
+    match try!(stack_depth(bridge, depth)) {
+        (0, _) => Ok(()),
+        (depth, _) => Err(InvalidTrace::Unbalanced(depth)),
+    }
+}
+
+/// Cut `trace`, a valid trace which ends in its `LoopBack` or in an exit
+/// left by `prune_trace`, into pieces without the `LoopBack` of less than
+/// `max_len` opcodes, leaving room for the `LoopBack` which links each piece
+/// to the next. A trace which fits stays whole.
+///
+/// Cuts are made where the operand stack is empty, so that every piece
+/// starts like the trace does, outside of inlined calls, and where no jump
+/// of the trace crosses.
+pub fn split_trace(mut trace: Vec<OpCode>, max_len: usize)
+                   -> Result<Vec<Vec<OpCode>>, InvalidTrace> {
+    let len = trace.len();
+    if let Some(&OpCode::LoopBack(_)) = trace.last() {
+        trace.pop();
+    }
+    if len <= max_len {
+        return Ok(vec![trace]);
+    }
+
+    // positions a cut before would separate a jump from its target
+    let mut crossed = vec![false; trace.len() + 1];
+    for (pos, opcode) in trace.iter().enumerate() {
+        let (from, to) = match *opcode {
+            OpCode::Skip(n) | OpCode::SkipIf(n) => (pos + 1, pos + n + 1),
+            OpCode::JumpBack(n) | OpCode::JumpBackIf(n) if n <= pos => (pos - n + 1, pos + 1),
+            _ => continue,
+        };
+        for cut in from..to {
+            if let Some(crossed) = crossed.get_mut(cut) {
+                *crossed = true;
+            }
+        }
+    }
+
+    let mut cuts = Vec::new();
+    let (mut start, mut join, mut depth, mut calls) = (0, 0, 0, 0);
+    for (pos, opcode) in trace.iter().enumerate() {
+        // the piece up to `pos` and its `LoopBack` don't fit
+        if pos + 2 - start > max_len {
+            if join <= start {
+                return Err(InvalidTrace::TooLong(len));
+            }
+            cuts.push(join);
+            start = join;
+        }
+        // the stack of the trace is the one of the frame it starts in
+        let inlined = calls > 0;
+        match *opcode {
+            OpCode::FlatCall(..) => calls += 1,
+            OpCode::Return => calls -= 1,
+            _ => (),
+        }
+        if inlined {
+            continue;
+        }
+        let (pops, pushes) = opcode.stack_effect().expect("a valid trace");
+        depth = depth - pops + pushes;
+        if depth == 0 && calls == 0 && !crossed[pos + 1] {
+            join = pos + 1;
+        }
+    }
+
+    let mut pieces = Vec::new();
+    while let Some(cut) = cuts.pop() {
+        pieces.push(trace.split_off(cut));
+    }
+    pieces.push(trace);
+    pieces.reverse();
+    Ok(pieces)
+}
+
+// The depth of the operand stack after `trace` when it starts at `depth`,
+// and whether the trace has a guard. An inlined call starts on an empty
+// stack of its own, the `FlatCall` accounts for the value it returns.
+fn stack_depth(trace: &[OpCode], mut depth: usize) -> Result<(usize, bool), InvalidTrace> {
+    let mut has_guard = false;
+    // where the calls the trace is in were entered, and the depth of the
+    // stack of their caller
+    let mut calls = Vec::new();
+
+    for (pos, opcode) in trace.iter().enumerate() {
+        if *opcode == OpCode::Return {
+            depth = match calls.pop() {
+                Some((_, caller)) => caller,
+                None => return Err(InvalidTrace::UnmatchedReturn(pos)),
+            };
+            continue;
+        }
+        let (pops, pushes) = match opcode.stack_effect() {
+            Some(effect) => effect,
+            None => return Err(InvalidTrace::UnknownStackEffect(pos)),
         };
 
-        // self.active.map(|ocs| ocs.push(oc));
-        self.active.as_mut().unwrap().push(oc);
+        if pops > depth {
+            return Err(InvalidTrace::StackUnderflow(pos));
+        }
+        depth = depth - pops + pushes;
+
+        match *opcode {
+            OpCode::Guard(_) | OpCode::GuardOperands(..) | OpCode::GuardFunc(..) |
+            OpCode::GuardVTable(..) | OpCode::GuardCast(..) => {
+                has_guard = true
+            }
+            OpCode::FlatCall(..) => {
+                calls.push((pos, depth));
+                depth = 0;
+            }
+            _ => (),
+        }
+    }
+
+    match calls.pop() {
+        Some((pos, _)) => Err(InvalidTrace::UnfinishedCall(pos)),
+        None => Ok((depth, has_guard)),
     }
 }
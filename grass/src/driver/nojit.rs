@@ -0,0 +1,51 @@
+//! Stand-ins for the `Tracer` and the `ThreadStats` with the `no-jit`
+//! feature. The tracer has no values, so the tracing code of the interpreter
+//! can never run and is compiled away.
+
+use bc::bytecode::{OpCode, NumType};
+use core::objects::InstructionPointer;
+use super::{MergePointResult, TraceKey, TraceRef};
+
+pub enum Tracer {}
+
+impl Tracer {
+    pub fn handle_mergepoint(&mut self, _key: TraceKey) -> MergePointResult {
+        match *self {}
+    }
+
+    pub fn is_recording(&self) -> bool {
+        match *self {}
+    }
+
+    pub fn jump_target(&mut self, _target: InstructionPointer, _backwards: bool) {
+        match *self {}
+    }
+
+    pub fn finish_trace(&mut self) {
+        match *self {}
+    }
+
+    pub fn trace_opcode(&mut self, _opcode: &OpCode, _pos: InstructionPointer,
+                        _operands: Option<NumType>, _condition: Option<bool>,
+                        _callee: Option<usize>) {
+        match *self {}
+    }
+
+    pub fn invalidate_if_failing(&mut self, _key: TraceKey) -> Option<TraceRef> {
+        match *self {}
+    }
+
+    pub fn handle_call(&mut self, _key: TraceKey, _depth: usize) -> MergePointResult {
+        match *self {}
+    }
+
+    pub fn trace_call_opcode(&mut self, _opcode: &OpCode, _pos: InstructionPointer,
+                             _depth: usize, _operands: Option<NumType>,
+                             _condition: Option<bool>, _callee: Option<usize>) {
+        match *self {}
+    }
+}
+
+/// There are no traces to count.
+#[derive(Debug, Clone, Default)]
+pub struct ThreadStats;
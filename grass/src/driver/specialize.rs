@@ -0,0 +1,120 @@
+//! What the trace being recorded knows about the types of the values it
+//! works on. A `BinOp` is recorded as a `TypedBinOp`, and it needs a
+//! `GuardOperands` in front only if no earlier opcode of the same iteration
+//! established the types of its operands.
+//!
+//! The state at a guard of an installed trace is what a bridge starting at
+//! that guard knows already, see `TypeState::at`.
+
+use bc::bytecode::{OpCode, NumType};
+use super::hash::FastHashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Slot {
+    ty: Option<NumType>,
+    /// local the value was loaded from
+    local: Option<usize>,
+}
+
+const UNKNOWN: Slot = Slot { ty: None, local: None };
+
+#[derive(Debug, Clone, Default)]
+pub struct TypeState {
+    stack: Vec<Slot>,
+    locals: FastHashMap<usize, NumType>,
+}
+
+impl TypeState {
+    /// What `trace` established before its opcode at `pos`. A guard at
+    /// `pos` which fails proves nothing.
+    pub fn at(trace: &[OpCode], pos: usize) -> Self {
+        let mut state = TypeState::default();
+        for opcode in &trace[..pos] {
+            state.record(opcode);
+        }
+        state
+    }
+
+    pub fn clear(&mut self) {
+        self.stack.clear();
+        self.locals.clear();
+    }
+
+    /// Whether both operands on top of the stack are known to be `ty`.
+    pub fn operands_are(&self, ty: NumType) -> bool {
+        let len = self.stack.len();
+        len >= 2 && self.stack[len - 2..].iter().all(|slot| slot.ty == Some(ty))
+    }
+
+    /// The type `local` is known to have.
+    pub fn local_type(&self, local: usize) -> Option<NumType> {
+        self.locals.get(&local).cloned()
+    }
+
+    /// The operands on top of the stack were guarded to be `ty`, and so
+    /// were the locals they came from.
+    pub fn assume_operands(&mut self, ty: NumType) {
+        let len = self.stack.len();
+        let from = if len >= 2 { len - 2 } else { 0 };
+        for slot in &mut self.stack[from..] {
+            slot.ty = Some(ty);
+            if let Some(local) = slot.local {
+                self.locals.insert(local, ty);
+            }
+        }
+    }
+
+    /// Follow the effect of `opcode`, which was appended to the trace.
+    pub fn record(&mut self, opcode: &OpCode) {
+        match *opcode {
+            OpCode::ConstValue(ref val) => {
+                self.stack.push(Slot { ty: NumType::of(val), local: None });
+            }
+            OpCode::Load(local) => {
+                let ty = self.locals.get(&local).cloned();
+                self.stack.push(Slot { ty: ty, local: Some(local) });
+            }
+            OpCode::Store(local) => {
+                match self.stack.pop().and_then(|slot| slot.ty) {
+                    Some(ty) => self.locals.insert(local, ty),
+                    None => self.locals.remove(&local),
+                };
+            }
+            OpCode::GuardOperands(ty, _) => self.assume_operands(ty),
+            OpCode::CheckedCast(ty) | OpCode::Cast(ty) => {
+                self.pop(1);
+                self.stack.push(Slot { ty: Some(ty), local: None });
+            }
+            OpCode::TypedBinOp(kind, ty) => {
+                self.pop(2);
+                let ty = if kind.is_comparison() { None } else { Some(ty) };
+                self.stack.push(Slot { ty: ty, local: None });
+            }
+            // keeps the type of its operand
+            OpCode::Use | OpCode::Unsize | OpCode::Abs |
+            OpCode::CountOnes | OpCode::LeadingZeros | OpCode::TrailingZeros => {
+                if let Some(slot) = self.stack.last_mut() {
+                    slot.local = None;
+                }
+            }
+            // the locals belong to another frame from here on
+            OpCode::FlatCall(..) => self.clear(),
+            _ => {
+                match opcode.stack_effect() {
+                    Some((pops, pushes)) => {
+                        self.pop(pops);
+                        for _ in 0..pushes {
+                            self.stack.push(UNKNOWN);
+                        }
+                    }
+                    None => self.clear(),
+                }
+            }
+        }
+    }
+
+    fn pop(&mut self, n: usize) {
+        let len = self.stack.len();
+        self.stack.truncate(if n < len { len - n } else { 0 });
+    }
+}
@@ -0,0 +1,70 @@
+//! The state a host hands to the driver at a merge point, see
+//! `Driver::merge_point_with`. The driver writes it into the locals of the
+//! function with the merge point, runs the guest on them and gives the host
+//! what they hold afterwards, whether the iteration was recorded, traced or
+//! finished in the interpreter.
+
+use core::cell::SharedCell;
+use core::objects::{CallFrame, R_BoxedValue, R_Struct};
+use grass_core::interp::{USER_PROGRAM_LOCAL, CELL_LOCAL, PC_LOCAL};
+use super::HashValue;
+
+pub trait MergeState {
+    /// Position in the guest program, merge points are keyed by it.
+    fn pc(&self) -> usize;
+
+    /// What else traces at `pc` are specialized for, e.g. the guest opcode
+    /// there. Only what stays the same for every visit of the position.
+    fn specialization(&self) -> HashValue {
+        0
+    }
+
+    /// The locals of the merge point frame which hold the state, and their
+    /// values: the guest program and pc as well as cells, a tape or structs.
+    fn red_vars(&self) -> Vec<(usize, R_BoxedValue)>;
+
+    /// Take the state back out of the locals after the guest ran.
+    fn restore(&mut self, frame: &CallFrame);
+}
+
+/// The state of the hosts `Driver::merge_point` takes: the guest program,
+/// a single cell and the pc, in the locals `USER_PROGRAM_LOCAL`,
+/// `CELL_LOCAL` and `PC_LOCAL`.
+#[derive(Debug)]
+pub struct CellState<'a> {
+    pub user_program: &'a [usize],
+    pub pc: usize,
+    pub cell: usize,
+}
+
+impl<'a> MergeState for CellState<'a> {
+    fn pc(&self) -> usize {
+        self.pc
+    }
+
+    fn specialization(&self) -> HashValue {
+        // the guest opcode, hosts may change their program between calls
+        self.user_program.get(self.pc).map_or(0, |&op| op as HashValue)
+    }
+
+    fn red_vars(&self) -> Vec<(usize, R_BoxedValue)> {
+        let mut program = R_Struct::with_size(self.user_program.len());
+        for (i, &opcode) in self.user_program.iter().enumerate() {
+            program.set(i, R_BoxedValue::Usize(opcode));
+        }
+        vec![(USER_PROGRAM_LOCAL, R_BoxedValue::Struct(program)),
+             (CELL_LOCAL, R_BoxedValue::Usize(self.cell)),
+             (PC_LOCAL, R_BoxedValue::Usize(self.pc))]
+    }
+
+    fn restore(&mut self, frame: &CallFrame) {
+        match frame.locals[CELL_LOCAL].get() {
+            R_BoxedValue::Usize(cell) => self.cell = cell,
+            val => panic!("expected guest cell, got {}", val),
+        }
+        match frame.locals[PC_LOCAL].get() {
+            R_BoxedValue::Usize(pc) => self.pc = pc,
+            val => panic!("expected guest pc, got {}", val),
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Execution counts of traces per thread. A thread running traces counts into
+//! its own `ThreadStats`, whose lock no other thread takes until the counts
+//! are merged, so counting threads don't contend with each other.
+//!
+//! Besides the counts every thread measures how long its merge points take
+//! to warm up, see `Warmup`.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use super::TraceKey;
+use super::meta::clock::GuestClock;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceCounts {
+    /// how often the trace was entered
+    pub entries: usize,
+    /// how often the trace was left through a guard
+    pub guard_failures: usize,
+}
+
+impl TraceCounts {
+    fn add(&mut self, other: &TraceCounts) {
+        self.entries += other.entries;
+        self.guard_failures += other.guard_failures;
+    }
+}
+
+/// How long a merge point took to reach steady state: the first run of its
+/// trace which went around the loop without failing a guard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Warmup {
+    /// Iterations of the guest loop at the merge point before that run,
+    /// interpreted ones and those whose trace failed a guard. It stops
+    /// counting once the merge point is steady.
+    pub iterations: usize,
+    /// nanoseconds on the clock of the driver from the first visit of the
+    /// merge point on, `None` while it isn't steady yet
+    pub nanos: Option<u64>,
+    // time of the first visit
+    since: u64,
+}
+
+impl Warmup {
+    pub fn is_steady(&self) -> bool {
+        self.nanos.is_some()
+    }
+
+    // the quicker one of two threads, a steady one if there is
+    fn merge(&mut self, other: &Warmup) {
+        let quicker = match (self.nanos, other.nanos) {
+            (None, Some(_)) => true,
+            (Some(own), Some(other)) => other < own,
+            _ => false,
+        };
+        if quicker {
+            *self = *other;
+        }
+    }
+}
+
+type Counts = Arc<Mutex<BTreeMap<TraceKey, TraceCounts>>>;
+type Warmups = Arc<Mutex<BTreeMap<TraceKey, Warmup>>>;
+
+/// Counts of the thread which owns it.
+#[derive(Debug, Clone)]
+pub struct ThreadStats {
+    name: String,
+    counts: Counts,
+    warmups: Warmups,
+}
+
+impl ThreadStats {
+    pub fn new(name: &str) -> Self {
+        ThreadStats {
+            name: name.to_owned(),
+            counts: Counts::default(),
+            warmups: Warmups::default(),
+        }
+    }
+
+    /// The guest is at the merge point `key`, about to run an iteration of
+    /// its loop. `clock` is only read at the first visit.
+    pub fn record_visit(&self, key: TraceKey, clock: &mut GuestClock) {
+        let mut warmups = self.warmups.lock().unwrap();
+        if let Some(warmup) = warmups.get_mut(&key) {
+            if !warmup.is_steady() {
+                warmup.iterations += 1;
+            }
+            return;
+        }
+        warmups.insert(key, Warmup {
+            iterations: 1,
+            nanos: None,
+            since: clock.nanos(),
+        });
+    }
+
+    /// The trace of `key` ran, and went around its loop `iterations` times.
+    /// The first run which did makes the merge point steady.
+    pub fn record_run(&self, key: TraceKey, iterations: usize, clock: &mut GuestClock) {
+        if iterations == 0 {
+            return;
+        }
+        let mut warmups = self.warmups.lock().unwrap();
+        if let Some(warmup) = warmups.get_mut(&key) {
+            if !warmup.is_steady() {
+                // the iteration the trace entered with counts as warm
+                warmup.iterations -= 1;
+                warmup.nanos = Some(clock.nanos().saturating_sub(warmup.since));
+            }
+        }
+    }
+
+    pub fn record_entry(&self, key: TraceKey) {
+        self.count(key, |counts| counts.entries += 1);
+    }
+
+    pub fn record_guard_failure(&self, key: TraceKey) {
+        self.count(key, |counts| counts.guard_failures += 1);
+    }
+
+    fn count<F: FnOnce(&mut TraceCounts)>(&self, key: TraceKey, f: F) {
+        let mut counts = self.counts.lock().unwrap();
+        f(counts.entry(key).or_insert_with(TraceCounts::default));
+    }
+
+    fn view(&self) -> ThreadView {
+        let counts = self.counts.lock().unwrap();
+        let warmups = self.warmups.lock().unwrap();
+        ThreadView {
+            name: self.name.clone(),
+            traces: counts.clone(),
+            warmups: warmups.clone(),
+        }
+    }
+}
+
+impl Default for ThreadStats {
+    fn default() -> Self {
+        ThreadStats::new("main")
+    }
+}
+
+/// The `ThreadStats` of the threads of one driver. Clones share them.
+#[derive(Debug, Clone, Default)]
+pub struct StatsRegistry {
+    threads: Arc<Mutex<Vec<ThreadStats>>>,
+}
+
+impl StatsRegistry {
+    /// Counts for a new thread, which are part of every later `snapshot`.
+    pub fn register(&self, name: &str) -> ThreadStats {
+        let stats = ThreadStats::new(name);
+        self.threads.lock().unwrap().push(stats.clone());
+        stats
+    }
+
+    /// The counts of `own` and of every registered thread.
+    pub fn snapshot(&self, own: &ThreadStats) -> Stats {
+        let mut threads = vec![own.view()];
+        threads.extend(self.threads.lock().unwrap().iter().map(ThreadStats::view));
+
+        let mut global: BTreeMap<TraceKey, TraceCounts> = BTreeMap::new();
+        let mut warmups: BTreeMap<TraceKey, Warmup> = BTreeMap::new();
+        for thread in &threads {
+            for (&key, counts) in &thread.traces {
+                global.entry(key).or_insert_with(TraceCounts::default).add(counts);
+            }
+            for (&key, warmup) in &thread.warmups {
+                warmups.entry(key).or_insert(*warmup).merge(warmup);
+            }
+        }
+
+        Stats {
+            threads: threads,
+            global: global,
+            warmups: warmups,
+        }
+    }
+}
+
+/// What one thread counted.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadView {
+    pub name: String,
+    pub traces: BTreeMap<TraceKey, TraceCounts>,
+    /// the merge points the thread visited
+    pub warmups: BTreeMap<TraceKey, Warmup>,
+}
+
+/// Counts of traces per thread and summed up, see `Driver::stats`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub threads: Vec<ThreadView>,
+    pub global: BTreeMap<TraceKey, TraceCounts>,
+    /// the quickest warmup of every merge point among the threads
+    pub warmups: BTreeMap<TraceKey, Warmup>,
+}
+
+impl Stats {
+    /// The counts of all traces of all threads.
+    pub fn total(&self) -> TraceCounts {
+        let mut total = TraceCounts::default();
+        for counts in self.global.values() {
+            total.add(counts);
+        }
+        total
+    }
+}
@@ -0,0 +1,322 @@
+#[cfg(not(feature = "sync-cells"))]
+use std::rc::Rc;
+#[cfg(feature = "sync-cells")]
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bc::Program;
+use bc::bytecode::{OpCode, Guard};
+use core::objects::{InstructionPointer, R_BoxedValue};
+use super::{HashValue, ProgramId};
+
+/// Where a trace starts, and what it was specialized for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TraceKey {
+    pub program: ProgramId,
+    /// merge point in the bytecode of the interpreter
+    pub ip: InstructionPointer,
+    /// position in the guest program
+    pub pc: usize,
+    /// Green value the trace was recorded for besides the position, e.g.
+    /// the guest opcode under `pc`. Keys differing only in this hold
+    /// separate traces.
+    pub specialization: HashValue,
+}
+
+/// Handle to an installed trace.
+///
+/// Installed traces are immutable and shared, so that a trace can keep being
+/// executed while a changed version of it is prepared. Changes are made to a
+/// copy (see `Rc::make_mut`) which then replaces the installed trace.
+///
+/// With `sync-cells` the trace is in an `Arc` and can be shared with other
+/// threads, e.g. one which optimizes it while the driver keeps executing it.
+#[cfg(not(feature = "sync-cells"))]
+pub type TraceRef = Rc<Trace>;
+#[cfg(feature = "sync-cells")]
+pub type TraceRef = Arc<Trace>;
+
+/// A recorded trace together with everything the driver knows about it.
+#[derive(Debug, Clone)]
+pub struct Trace {
+    pub ops: Vec<OpCode>,
+    /// positions of the guards within `ops`
+    pub guards: Vec<usize>,
+    /// opcodes of the program which were recorded into the trace, empty for
+    /// seeded traces
+    pub covered: Vec<InstructionPointer>,
+    /// merge point the trace was recorded for
+    pub entry_key: TraceKey,
+    pub stats: TraceStats,
+    /// Traces are numbered in the order they got installed. A trace which
+    /// is recorded again for the same key gets a new generation.
+    pub generation: usize,
+    /// the `Return` a trace of a whole invocation ends in, loop traces have
+    /// none
+    pub exit: Option<InstructionPointer>,
+    /// Side traces by the id of the guard they start at, a failing guard
+    /// continues in its bridge. Bridges run back into the start of the
+    /// trace, they have no bridges of their own.
+    pub bridges: Vec<Option<TraceRef>>,
+    /// The pieces a trace which was too long got split into besides this
+    /// first one, in the order they run, see `split_trace`.
+    pub links: Vec<TraceRef>,
+}
+
+impl Trace {
+    pub fn new(ops: Vec<OpCode>, entry_key: TraceKey, generation: usize) -> Self {
+        let guards: Vec<usize> = ops.iter()
+            .enumerate()
+            .filter(|&(_, op)| match *op {
+                OpCode::Guard(_) | OpCode::GuardOperands(..) | OpCode::GuardFunc(..) |
+                OpCode::GuardVTable(..) | OpCode::GuardCast(..) => true,
+                _ => false,
+            })
+            .map(|(pos, _)| pos)
+            .collect();
+
+        let stats = TraceStats::with_guards(guards.len());
+        Trace {
+            ops: ops,
+            guards: guards,
+            covered: Vec::new(),
+            entry_key: entry_key,
+            stats: stats,
+            generation: generation,
+            exit: None,
+            bridges: Vec::new(),
+            links: Vec::new(),
+        }
+    }
+
+    /// The id of the guard at `trace_pc`, see `GuardInfo::id`.
+    pub fn guard_id(&self, trace_pc: usize) -> Option<usize> {
+        self.guards.binary_search(&trace_pc).ok()
+    }
+
+    /// Whether the opcode at `trace_pc` belongs to a call inlined into the
+    /// trace, between its `FlatCall` and its `Return`.
+    pub fn in_call(&self, trace_pc: usize) -> bool {
+        let mut calls = 0;
+        for op in &self.ops[..trace_pc] {
+            match *op {
+                OpCode::FlatCall(..) => calls += 1,
+                OpCode::Return => calls -= 1,
+                _ => (),
+            }
+        }
+        calls > 0
+    }
+
+    /// The bridge attached to the guard `id`, if any.
+    pub fn bridge(&self, id: usize) -> Option<&TraceRef> {
+        self.bridges.get(id).and_then(|bridge| bridge.as_ref())
+    }
+
+    /// Attach `bridge` to the guard `id`, replacing the bridge it had.
+    pub fn attach_bridge(&mut self, id: usize, bridge: TraceRef) {
+        if self.bridges.len() <= id {
+            self.bridges.resize(id + 1, None);
+        }
+        self.bridges[id] = Some(bridge);
+    }
+
+    /// Count a run which was left through the guard at `trace_pc`.
+    pub fn record_guard_failure(&self, trace_pc: usize) {
+        self.stats.record_guard_failure();
+        if let Some(id) = self.guard_id(trace_pc) {
+            self.stats.record_guard_failure_of(id);
+        }
+    }
+
+    /// The trace, bridge or linked piece `exit` left.
+    pub fn exited(&self, exit: &GuardExit) -> Option<&Trace> {
+        match (exit.bridge, exit.link) {
+            (Some(id), _) => self.bridge(id).map(|bridge| &**bridge),
+            (None, Some(idx)) => self.links.get(idx).map(|link| &**link),
+            (None, None) => Some(self),
+        }
+    }
+
+    /// Count a run which was left at `exit`, with the guard of the bridge
+    /// or linked piece it was left through if any.
+    pub fn record_exit(&self, exit: &GuardExit) {
+        if exit.bridge.is_none() && exit.link.is_none() {
+            return self.record_guard_failure(exit.trace_pc);
+        }
+        self.stats.record_guard_failure();
+        if let Some(part) = self.exited(exit) {
+            part.record_guard_failure(exit.trace_pc);
+        }
+    }
+
+    /// The id of the guard `exit` left through, within the part of the
+    /// trace it left.
+    pub fn exit_guard_id(&self, exit: &GuardExit) -> Option<usize> {
+        self.exited(exit).and_then(|part| part.guard_id(exit.trace_pc))
+    }
+
+    /// The guards of the trace, with the opcodes of `program` they were
+    /// recorded for.
+    pub fn guard_infos(&self, program: &Program) -> Vec<GuardInfo> {
+        self.guards
+            .iter()
+            .enumerate()
+            .filter_map(|(id, &trace_pc)| {
+                let recovery = match self.ops[trace_pc] {
+                    OpCode::Guard(ref guard) |
+                    OpCode::GuardOperands(_, ref guard) |
+                    OpCode::GuardFunc(_, ref guard) |
+                    OpCode::GuardVTable(_, ref guard) |
+                    OpCode::GuardCast(_, ref guard) => guard.recovery,
+                    _ => return None,
+                };
+                let origin = program.functions
+                    .get(recovery.func)
+                    .and_then(|func| func.opcodes.get(recovery.pc))
+                    .cloned();
+                Some(GuardInfo {
+                    id: id,
+                    trace_pc: trace_pc,
+                    origin: origin,
+                    recovery: recovery,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A guard of a trace and the guest construct it comes from, see
+/// `Driver::guards`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardInfo {
+    /// Ordinal of the guard among the guards of the trace, which stays the
+    /// same while the trace is installed. `TraceStats` counts failures by it.
+    pub id: usize,
+    /// position within `Trace::ops`
+    pub trace_pc: usize,
+    /// the opcode of the program the guard was recorded for, `None` if
+    /// `recovery` is outside of the program
+    pub origin: Option<OpCode>,
+    /// where the interpreter continues if the guard fails
+    pub recovery: InstructionPointer,
+}
+
+impl GuardInfo {
+    /// e.g. `guard 2 (SkipIf at 1:17)`
+    pub fn name(&self) -> String {
+        let origin = self.origin.as_ref().map_or("?", |opcode| opcode.name());
+        format!("guard {} ({} at {}:{})", self.id, origin, self.recovery.func, self.recovery.pc)
+    }
+}
+
+/// How a run of a trace ended: the guard which failed and the state at it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardExit {
+    pub guard: Guard,
+    /// position of the guard within `Trace::ops`
+    pub trace_pc: usize,
+    /// the operand stack when the guard failed, the bottom first
+    pub stack_snapshot: Vec<R_BoxedValue>,
+    /// The `OpCode::Breakpoint` the trace was left at instead of a guard.
+    /// Its `guard` recovers at the entry of the trace.
+    pub breakpoint: Option<usize>,
+    /// how often the trace passed its `LoopBack` before it was left
+    pub iterations: usize,
+    /// The trace was left at its `LoopBack` for the host, not at a guard.
+    /// Its `guard` recovers at the entry of the trace.
+    pub yielded: bool,
+    /// The id of the guard of the trace whose bridge was left, `trace_pc`
+    /// is a position within the bridge then.
+    pub bridge: Option<usize>,
+    /// The index of the piece among `Trace::links` which was left,
+    /// `trace_pc` is a position within it then.
+    pub link: Option<usize>,
+}
+
+impl GuardExit {
+    /// where the interpreter continues
+    pub fn recovery(&self) -> InstructionPointer {
+        self.guard.recovery
+    }
+}
+
+/// Execution counts of a trace, updated while it is shared.
+#[derive(Debug, Default)]
+pub struct TraceStats {
+    entries: AtomicUsize,
+    guard_failures: AtomicUsize,
+    iterations: AtomicUsize,
+    /// failures per guard, by `GuardInfo::id`
+    guard_failures_of: Vec<AtomicUsize>,
+}
+
+impl TraceStats {
+    /// Counts for a trace with `guards` guards.
+    pub fn with_guards(guards: usize) -> Self {
+        TraceStats {
+            guard_failures_of: (0..guards).map(|_| AtomicUsize::new(0)).collect(),
+            ..TraceStats::default()
+        }
+    }
+
+    /// how often the trace was entered
+    pub fn entries(&self) -> usize {
+        self.entries.load(Ordering::Relaxed)
+    }
+
+    /// how often the trace was left through a guard
+    pub fn guard_failures(&self) -> usize {
+        self.guard_failures.load(Ordering::Relaxed)
+    }
+
+    /// how often the trace was left through the guard `id`
+    pub fn guard_failures_of(&self, id: usize) -> usize {
+        self.guard_failures_of.get(id).map_or(0, |count| count.load(Ordering::Relaxed))
+    }
+
+    /// The guard the trace was left through most often, and how often.
+    pub fn hottest_guard(&self) -> Option<(usize, usize)> {
+        (0..self.guard_failures_of.len())
+            .map(|id| (id, self.guard_failures_of(id)))
+            .filter(|&(_, failures)| failures > 0)
+            .max_by_key(|&(id, failures)| (failures, usize::max_value() - id))
+    }
+
+    /// how often the loop of the trace went around, over all entries
+    pub fn iterations(&self) -> usize {
+        self.iterations.load(Ordering::Relaxed)
+    }
+
+    pub fn record_entry(&self) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_guard_failure(&self) {
+        self.guard_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_guard_failure_of(&self, id: usize) {
+        if let Some(count) = self.guard_failures_of.get(id) {
+            count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_iterations(&self, iterations: usize) {
+        self.iterations.fetch_add(iterations, Ordering::Relaxed);
+    }
+}
+
+impl Clone for TraceStats {
+    fn clone(&self) -> Self {
+        TraceStats {
+            entries: AtomicUsize::new(self.entries()),
+            guard_failures: AtomicUsize::new(self.guard_failures()),
+            iterations: AtomicUsize::new(self.iterations()),
+            guard_failures_of: self.guard_failures_of
+                .iter()
+                .map(|count| AtomicUsize::new(count.load(Ordering::Relaxed)))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,129 @@
+//! Live view of the tracer for the terminal, built with the `tui` feature.
+//!
+//! The screen is redrawn from `Driver::merge_point`, at most every
+//! `REFRESH` so that it doesn't slow down the guest too much. It shows how
+//! hot the merge points are, what gets recorded, how often traces are left
+//! through guards, and the most recent guard failures.
+
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, Write};
+use std::iter;
+use std::time::{Duration, Instant};
+
+use core::objects::InstructionPointer;
+use super::{TraceKey, Tracer, HOT_LOOP_THRESHOLD};
+
+const REFRESH_MS: u64 = 100;
+const RECENT_DEOPTS: usize = 8;
+const BAR_WIDTH: usize = 20;
+
+pub struct Tui {
+    out: Box<Write>,
+    last_draw: Option<Instant>,
+    /// guard failures, the latest last
+    deopts: VecDeque<(TraceKey, InstructionPointer, Instant)>,
+}
+
+impl Tui {
+    pub fn new(out: Box<Write>) -> Self {
+        Tui {
+            out: out,
+            last_draw: None,
+            deopts: VecDeque::new(),
+        }
+    }
+
+    /// A trace for `key` was left through the guard at `guard`.
+    pub fn deopt(&mut self, key: TraceKey, guard: InstructionPointer) {
+        if self.deopts.len() == RECENT_DEOPTS {
+            self.deopts.pop_front();
+        }
+        self.deopts.push_back((key, guard, Instant::now()));
+    }
+
+    /// Redraw unless the screen is still fresh. The terminal not taking the
+    /// output doesn't concern the guest, so errors are ignored.
+    pub fn refresh<'a, I>(&mut self, tracers: I)
+        where I: Iterator<Item = &'a Tracer>
+    {
+        let due = self.last_draw
+            .map_or(true, |last| last.elapsed() >= Duration::from_millis(REFRESH_MS));
+        if due {
+            let tracers: Vec<_> = tracers.collect();
+            let _ = self.draw(&tracers);
+            self.last_draw = Some(Instant::now());
+        }
+    }
+
+    fn draw(&mut self, tracers: &[&Tracer]) -> io::Result<()> {
+        let mut screen = Vec::new();
+        // clear the screen and go home
+        try!(write!(screen, "\x1b[2J\x1b[H"));
+
+        for (idx, tracer) in tracers.iter().enumerate() {
+            match tracer.loop_start {
+                Some(key) if tracer.is_recording() => {
+                    try!(writeln!(screen, "program {}: recording at pc {}", idx, key.pc))
+                }
+                _ => try!(writeln!(screen, "program {}: interpreting", idx)),
+            }
+
+            let mut counters: Vec<_> = tracer.counter.iter().collect();
+            // the hottest first
+            counters.sort_by(|a, b| (b.1, a.0).cmp(&(a.1, b.0)));
+            try!(writeln!(screen, "  merge points"));
+            for (key, &hits) in counters {
+                let filled = cmp::min(hits, HOT_LOOP_THRESHOLD) * BAR_WIDTH / HOT_LOOP_THRESHOLD;
+                try!(writeln!(screen, "    pc {:>5}  [{}{}] {}/{}",
+                              key.pc,
+                              bar('#', filled),
+                              bar('-', BAR_WIDTH - filled),
+                              hits,
+                              HOT_LOOP_THRESHOLD));
+            }
+
+            let mut traces: Vec<_> = tracer.traces.values().collect();
+            traces.sort_by_key(|trace| trace.generation);
+            try!(writeln!(screen, "  traces"));
+            for trace in traces {
+                let entries = trace.stats.entries();
+                let failures = trace.stats.guard_failures();
+                let rate = if entries == 0 {
+                    0.0
+                } else {
+                    failures as f64 * 100.0 / entries as f64
+                };
+                try!(write!(screen, "    pc {:>5}  gen {:>3}  {:>4} ops  {:>10} entries  \
+                                     {:>5.1}% guard failures",
+                            trace.entry_key.pc,
+                            trace.generation,
+                            trace.ops.len(),
+                            entries,
+                            rate));
+                if let Some((id, failures)) = trace.stats.hottest_guard() {
+                    try!(write!(screen, ", {} at guard {}", failures, id));
+                }
+                try!(writeln!(screen));
+            }
+        }
+
+        try!(writeln!(screen, "recent deopts"));
+        for &(key, guard, at) in self.deopts.iter().rev() {
+            let ago = at.elapsed();
+            try!(writeln!(screen, "  pc {:>5}  guard at {}:{}  {}.{:03}s ago",
+                          key.pc,
+                          guard.func,
+                          guard.pc,
+                          ago.as_secs(),
+                          ago.subsec_nanos() / 1_000_000));
+        }
+
+        try!(self.out.write_all(&screen));
+        self.out.flush()
+    }
+}
+
+fn bar(c: char, len: usize) -> String {
+    iter::repeat(c).take(len).collect()
+}
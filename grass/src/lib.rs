@@ -8,21 +8,33 @@
 
 #![allow(unused_imports, unused_variables, dead_code)]
 
+// The front-end needs the compiler, which isn't available on wasm32. There
+// only the driver is built, to run programs translated beforehand.
+#[cfg(not(target_arch = "wasm32"))]
 #[macro_use]
 extern crate log;
 
+#[cfg(not(target_arch = "wasm32"))]
 extern crate serialize as rustc_serialize;
 
+#[cfg(not(target_arch = "wasm32"))]
 extern crate rustc;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate syntax;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate rustc_const_math;
+#[cfg(not(target_arch = "wasm32"))]
 extern crate rustc_data_structures;
 
+extern crate grass_core;
+
 // this has to be first for macros to be available in other modules
 #[macro_use]
 mod util;
 
 pub mod bc;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod codegen;
 pub mod core;
 
 pub mod driver;
@@ -31,3 +31,9 @@ macro_rules! create_bind {
 
     }
 }
+
+// `log` comes with the compiler, without it debug output is dropped
+#[cfg(target_arch = "wasm32")]
+macro_rules! debug {
+    ($($arg:tt)*) => (())
+}
@@ -0,0 +1,94 @@
+extern crate grass;
+
+mod common;
+
+use grass::bc::Program;
+use grass::bc::analysis::{find_loops, suggest_merge_points};
+use grass::bc::bytecode::{OpCode, BinOp, MERGE_POINT_FUNC};
+use grass::core::objects::{InstructionPointer, R_BoxedValue};
+
+use common::{function, program};
+
+// 1: while a != 0 { while b != 0 { b -= 1 }; a -= 1 }, with `merge` at the
+// top of the outer loop
+fn nested(merge: Vec<OpCode>) -> Program {
+    let mut opcodes = vec![
+        // 0: outer header
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::BinOp(BinOp::Eq),
+        OpCode::SkipIf(15),
+    ];
+    let shift = merge.len();
+    opcodes.extend(merge);
+    opcodes.extend(vec![
+        // 4: inner header
+        OpCode::Load(1),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::BinOp(BinOp::Eq),
+        OpCode::SkipIf(6),
+        OpCode::Load(1),
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::BinOp(BinOp::Sub),
+        OpCode::Store(1),
+        OpCode::JumpBack(8),
+        // 13
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::BinOp(BinOp::Sub),
+        OpCode::Store(0),
+        OpCode::JumpBack(17 + shift),
+        // 18
+        OpCode::ConstValue(R_BoxedValue::Unit),
+        OpCode::Return,
+    ]);
+    if shift > 0 {
+        opcodes[3] = OpCode::SkipIf(15 + shift);
+    }
+    program(vec![function(2, 2, opcodes)])
+}
+
+#[test]
+fn finds_nested_loops() {
+    let loops = find_loops(&nested(vec![]));
+    assert_eq!(loops.len(), 2);
+
+    let (outer, inner) = (&loops[0], &loops[1]);
+    assert_eq!((outer.func, outer.header, outer.depth), (1, 0, 0));
+    assert_eq!(outer.back_edges, vec![17]);
+    assert_eq!(outer.body, (0..18).collect::<Vec<_>>());
+    assert_eq!((inner.header, inner.depth), (4, 1));
+    assert_eq!(inner.back_edges, vec![12]);
+    assert_eq!(inner.body, (4..13).collect::<Vec<_>>());
+}
+
+#[test]
+fn suggests_the_header_of_the_outer_loop() {
+    assert_eq!(suggest_merge_points(&nested(vec![])),
+               vec![InstructionPointer { func: 1, pc: 0 }]);
+}
+
+#[test]
+fn suggests_nothing_once_the_loop_calls_the_merge_point() {
+    let merge = vec![
+        OpCode::ConstValue(R_BoxedValue::Func(MERGE_POINT_FUNC)),
+        OpCode::Call,
+        OpCode::Pop,
+    ];
+    let program = nested(merge);
+    assert!(find_loops(&program)[0].calls_merge_point);
+    assert_eq!(suggest_merge_points(&program), vec![]);
+}
+
+#[test]
+fn straight_jumps_are_no_loops() {
+    let program = program(vec![
+        function(0, 0, vec![
+            OpCode::Skip(2),
+            OpCode::Panic,
+            OpCode::ConstValue(R_BoxedValue::Unit),
+            OpCode::Return,
+        ]),
+    ]);
+    assert_eq!(find_loops(&program), vec![]);
+}
@@ -0,0 +1,81 @@
+extern crate grass;
+
+use grass::bc::bytecode::{OpCode, BinOp};
+use grass::bc::cfg::{Cfg, successors};
+use grass::core::objects::R_BoxedValue;
+
+// 0: if a { b = 1 } else { b = 2 }; while b != 0 { b -= 1 }; return b
+fn branches_and_loop() -> Vec<OpCode> {
+    vec![
+        // block 0
+        OpCode::Load(0),
+        OpCode::SkipIf(4),
+        // block 1, 2..5
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::Store(1),
+        OpCode::Skip(3),
+        // block 2, 5..7
+        OpCode::ConstValue(R_BoxedValue::Usize(2)),
+        OpCode::Store(1),
+        // block 3, 7..11: loop header
+        OpCode::Load(1),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::BinOp(BinOp::Eq),
+        OpCode::SkipIf(6),
+        // block 4, 11..16
+        OpCode::Load(1),
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::BinOp(BinOp::Sub),
+        OpCode::Store(1),
+        OpCode::JumpBack(8),
+        // block 5
+        OpCode::Load(1),
+        OpCode::Return,
+    ]
+}
+
+#[test]
+fn splits_blocks_at_jumps_and_their_targets() {
+    let opcodes = branches_and_loop();
+    let cfg = Cfg::new(&opcodes);
+    let bounds: Vec<(usize, usize)> = cfg.blocks.iter().map(|b| (b.start, b.end)).collect();
+    assert_eq!(bounds, vec![(0, 2), (2, 5), (5, 7), (7, 11), (11, 16), (16, 18)]);
+    assert_eq!(cfg.block_of(9), 3);
+
+    let successors: Vec<Vec<usize>> = cfg.blocks.iter().map(|b| b.successors.clone()).collect();
+    assert_eq!(successors, vec![vec![1, 2], vec![3], vec![3], vec![4, 5], vec![3], vec![]]);
+    assert_eq!(cfg.blocks[3].predecessors, vec![1, 2, 4]);
+}
+
+#[test]
+fn dominator_tree_of_branches_and_loop() {
+    let opcodes = branches_and_loop();
+    let cfg = Cfg::new(&opcodes);
+    let dom = cfg.dominators();
+    let idom: Vec<Option<usize>> = (0..cfg.blocks.len()).map(|b| dom.immediate_dominator(b)).collect();
+    assert_eq!(idom, vec![None, Some(0), Some(0), Some(0), Some(3), Some(3)]);
+    assert!(dom.dominates(3, 4));
+    assert!(dom.dominates(4, 4));
+    assert!(!dom.dominates(1, 3));
+    assert_eq!(dom.children(0), vec![1, 2, 3]);
+    assert_eq!(cfg.reverse_postorder()[0], 0);
+}
+
+#[test]
+fn code_after_return_is_unreachable() {
+    let opcodes = vec![OpCode::Return, OpCode::ConstValue(R_BoxedValue::Usize(0)), OpCode::Return];
+    let cfg = Cfg::new(&opcodes);
+    assert_eq!(cfg.blocks.len(), 2);
+    let dom = cfg.dominators();
+    assert!(dom.is_reachable(0));
+    assert!(!dom.is_reachable(1));
+    assert!(!dom.dominates(0, 1));
+    assert_eq!(cfg.reverse_postorder(), vec![0]);
+}
+
+#[test]
+fn jumps_out_of_the_function_have_no_successor() {
+    let opcodes = vec![OpCode::JumpBack(3), OpCode::Skip(5)];
+    assert_eq!(successors(&opcodes, 0), Vec::<usize>::new());
+    assert_eq!(successors(&opcodes, 1), Vec::<usize>::new());
+}
@@ -0,0 +1,617 @@
+#![cfg(not(feature = "no-jit"))]
+
+extern crate grass;
+
+#[cfg(feature = "sync-cells")]
+use std::thread;
+
+use grass::bc::{opt, Program};
+use grass::bc::bytecode::{OpCode, Guard, BinOp, LoopInfo, NumType};
+use grass::core::objects::{FunctionRef, InstructionPointer, R_BoxedValue, R_Function, R_Struct};
+use grass::driver::{split_trace, Driver, InvalidTrace, JitEvent, MergePointResult, TraceKey,
+                    Tracer, DEFAULT_MAX_BRIDGES};
+
+fn trace() -> Vec<OpCode> {
+    let guard = Guard {
+        expected: true,
+        recovery: InstructionPointer { func: 1, pc: 0 },
+    };
+    vec![OpCode::ConstValue(R_BoxedValue::Bool(true)), OpCode::Guard(guard), OpCode::Pop]
+}
+
+#[test]
+fn reseeding_evicts_the_trace() {
+    let mut driver = Driver::default();
+    let events = driver.subscribe();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    driver.seed_trace(key, trace()).unwrap();
+    assert!(events.try_recv().is_err());
+
+    driver.seed_trace(key, trace()).unwrap();
+    assert_eq!(events.try_recv(), Ok(JitEvent::TraceEvicted { key: key, id: 0 }));
+}
+
+#[test]
+fn dropped_subscribers_are_forgotten() {
+    let mut driver = Driver::default();
+    drop(driver.subscribe());
+    let events = driver.subscribe();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    driver.seed_trace(key, trace()).unwrap();
+    driver.seed_trace(key, trace()).unwrap();
+    assert_eq!(events.try_recv(), Ok(JitEvent::TraceEvicted { key: key, id: 0 }));
+}
+
+#[test]
+fn bridges_are_limited_per_root_trace() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    let mut tracer = Tracer::default();
+    tracer.seed_trace(key, trace()).unwrap();
+    for _ in 0..DEFAULT_MAX_BRIDGES {
+        assert!(tracer.add_bridge(key));
+    }
+    assert!(!tracer.add_bridge(key));
+
+    // a new root trace gets bridges of its own
+    tracer.seed_trace(key, trace()).unwrap();
+    assert!(tracer.add_bridge(key));
+}
+
+#[test]
+fn bridges_know_the_types_the_root_guarded() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+    let guard = Guard {
+        expected: true,
+        recovery: InstructionPointer { func: 1, pc: 0 },
+    };
+    let ops = vec![
+        OpCode::Load(0),
+        OpCode::Load(1),
+        OpCode::GuardOperands(NumType::Usize, guard.clone()),
+        OpCode::TypedBinOp(BinOp::Add, NumType::Usize),
+        OpCode::Store(2),
+        OpCode::Load(2),
+        OpCode::ConstValue(R_BoxedValue::Usize(10)),
+        OpCode::TypedBinOp(BinOp::Lt, NumType::Usize),
+        OpCode::Guard(guard),
+        OpCode::Pop,
+    ];
+
+    let mut tracer = Tracer::default();
+    tracer.seed_trace(key, ops).unwrap();
+
+    // the failing guard itself proves nothing
+    let context = tracer.guard_context(key, 2).unwrap();
+    assert_eq!(context.local_type(0), None);
+
+    let context = tracer.guard_context(key, 8).unwrap();
+    for local in 0..3 {
+        assert_eq!(context.local_type(local), Some(NumType::Usize));
+    }
+}
+
+#[test]
+fn casts_are_recorded_behind_a_guard() {
+    let key = TraceKey {
+        program: Driver::default().register_program(Program::default()),
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+    let mut tracer = Tracer::default();
+    loop {
+        if let MergePointResult::StartTrace = tracer.handle_mergepoint(key) {
+            break;
+        }
+    }
+
+    let ops = vec![
+        OpCode::Load(0),
+        OpCode::CheckedCast(NumType::Usize),
+        OpCode::Store(1),
+    ];
+    for (pc, opcode) in ops.iter().enumerate() {
+        tracer.trace_opcode(opcode, InstructionPointer { func: 1, pc: pc }, None, None, None);
+    }
+    tracer.finish_trace();
+
+    let guard = Guard {
+        expected: true,
+        recovery: InstructionPointer { func: 1, pc: 1 },
+    };
+    let trace = tracer.trace(key).expect("no trace of the cast");
+    assert_eq!(trace.ops[1..3],
+               [OpCode::GuardCast(NumType::Usize, guard), OpCode::Cast(NumType::Usize)]);
+    assert_eq!(trace.guards, [1]);
+}
+
+#[test]
+fn seeded_traces_end_at_guards_which_always_fail() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+    let guard = Guard {
+        expected: true,
+        recovery: InstructionPointer { func: 1, pc: 0 },
+    };
+
+    driver.seed_trace(key, vec![
+        OpCode::ConstValue(R_BoxedValue::Bool(false)),
+        OpCode::Guard(guard.clone()),
+        OpCode::Pop,
+        OpCode::Load(0),
+        OpCode::Store(1),
+    ]).unwrap();
+    assert_eq!(driver.trace(key).unwrap().ops.len(), 2);
+
+    driver.seed_trace(key, vec![
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::I64(1)),
+        OpCode::GuardOperands(NumType::Usize, guard.clone()),
+        OpCode::BinOp(BinOp::Add),
+        OpCode::Store(0),
+    ]).unwrap();
+    assert_eq!(driver.trace(key).unwrap().ops.len(), 3);
+
+    driver.seed_trace(key, vec![
+        OpCode::ConstValue(R_BoxedValue::Func(2)),
+        OpCode::GuardFunc(3, guard.clone()),
+        OpCode::Pop,
+        OpCode::Load(0),
+        OpCode::Store(1),
+    ]).unwrap();
+    assert_eq!(driver.trace(key).unwrap().ops.len(), 2);
+
+    driver.seed_trace(key, vec![
+        OpCode::ConstValue(R_BoxedValue::VTable(0)),
+        OpCode::GuardVTable(1, guard.clone()),
+        OpCode::Pop,
+        OpCode::Load(0),
+        OpCode::Store(1),
+    ]).unwrap();
+    assert_eq!(driver.trace(key).unwrap().ops.len(), 2);
+
+    // after a guard of the trace, the exit is kept and the loop not closed
+    let exit = vec![
+        OpCode::Load(0),
+        OpCode::Guard(guard.clone()),
+        OpCode::Pop,
+        OpCode::ConstValue(R_BoxedValue::Bool(false)),
+        OpCode::Guard(guard.clone()),
+    ];
+    let mut seeded = exit.clone();
+    seeded.extend(vec![OpCode::Pop, OpCode::Load(1), OpCode::Pop]);
+    driver.seed_trace(key, seeded).unwrap();
+    assert_eq!(driver.trace(key).unwrap().ops, exit);
+
+    // passes every time, the trace goes on and gets its loop closed
+    driver.seed_trace(key, trace()).unwrap();
+    let mut closed = trace();
+    closed.push(OpCode::LoopBack(LoopInfo::default()));
+    assert_eq!(driver.trace(key).unwrap().ops, closed);
+}
+
+#[test]
+fn long_traces_are_split_where_the_stack_is_empty() {
+    let mut long = trace();
+    long.extend(vec![OpCode::Load(0), OpCode::Store(1)]);
+    long.push(OpCode::LoopBack(LoopInfo::default()));
+
+    assert_eq!(split_trace(long.clone(), 6), Ok(vec![long[..5].to_vec()]));
+    assert_eq!(split_trace(long.clone(), 4), Ok(vec![trace(), long[3..5].to_vec()]));
+    assert_eq!(split_trace(long.clone(), 2), Err(InvalidTrace::TooLong(6)));
+
+    // not between a jump and its target
+    let mut skipping = trace();
+    skipping.extend(vec![OpCode::ConstValue(R_BoxedValue::Bool(false)),
+                         OpCode::SkipIf(2),
+                         OpCode::Load(0),
+                         OpCode::Store(1),
+                         OpCode::Load(1),
+                         OpCode::Store(2),
+                         OpCode::LoopBack(LoopInfo::default())]);
+    let pieces = split_trace(skipping.clone(), 5).unwrap();
+    assert_eq!(pieces.iter().map(|piece| piece.len()).collect::<Vec<_>>(), vec![3, 4, 2]);
+}
+
+#[test]
+fn inlined_calls_have_to_return_within_the_trace() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+    // f(x) = x
+    let f = FunctionRef::new(R_Function {
+        args_cnt: 1,
+        locals_cnt: 1,
+        opcodes: vec![OpCode::Load(0), OpCode::Return],
+    });
+    let call = OpCode::FlatCall(2, InstructionPointer { func: 1, pc: 1 }, f);
+
+    let mut inlined = trace();
+    inlined.extend(vec![OpCode::Load(0), call.clone(), OpCode::Load(0), OpCode::Return,
+                        OpCode::Store(0)]);
+    assert_eq!(driver.seed_trace(key, inlined), Ok(()));
+
+    let mut unfinished = trace();
+    unfinished.extend(vec![OpCode::Load(0), call.clone(), OpCode::Load(0), OpCode::Pop]);
+    assert_eq!(driver.seed_trace(key, unfinished), Err(InvalidTrace::UnfinishedCall(4)));
+
+    let mut unmatched = trace();
+    unmatched.extend(vec![OpCode::Load(0), OpCode::Return]);
+    assert_eq!(driver.seed_trace(key, unmatched), Err(InvalidTrace::UnmatchedReturn(4)));
+}
+
+#[test]
+fn seeded_traces_fold_pointer_moves() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    // p += 1; p += 1
+    let mut moves = vec![
+        OpCode::Load(0),
+        OpCode::MovePtr(1),
+        OpCode::Store(0),
+        OpCode::Load(0),
+        OpCode::MovePtr(1),
+        OpCode::Store(0),
+    ];
+    moves.extend(trace());
+    driver.seed_trace(key, moves).unwrap();
+    let ops = driver.trace(key).unwrap().ops.clone();
+    assert_eq!(ops[..3], [OpCode::Load(0), OpCode::MovePtr(2), OpCode::Store(0)]);
+    assert_eq!(ops.len(), 7);
+}
+
+// x = 2; y = x + 3; y = y
+fn constant_trace() -> Vec<OpCode> {
+    let mut ops = vec![
+        OpCode::ConstValue(R_BoxedValue::Usize(2)),
+        OpCode::Store(0),
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(3)),
+        OpCode::BinOp(BinOp::Add),
+        OpCode::Store(1),
+        OpCode::Load(1),
+        OpCode::Use,
+        OpCode::Store(1),
+    ];
+    ops.extend(trace());
+    ops
+}
+
+#[test]
+fn seeded_traces_run_through_the_pipeline() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    driver.seed_trace(key, constant_trace()).unwrap();
+    let ops = driver.trace(key).unwrap().ops.clone();
+    assert_eq!(ops[..5],
+               [OpCode::ConstValue(R_BoxedValue::Usize(2)),
+                OpCode::Store(0),
+                OpCode::ConstValue(R_BoxedValue::Usize(5)),
+                OpCode::Use,
+                OpCode::Store(1)]);
+    assert_eq!(ops.len(), 9);
+
+    driver.set_trace_passes(opt::TracePasses {
+        constants: false,
+        ..opt::TracePasses::default()
+    });
+    driver.seed_trace(key, constant_trace()).unwrap();
+    let ops = driver.trace(key).unwrap().ops.clone();
+    assert_eq!(ops[2], OpCode::Load(0));
+    assert_eq!(ops.len(), 10);
+}
+
+#[test]
+fn dead_stores_only_pop_their_value() {
+    // x = 1; x = 2
+    let mut ops = vec![
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::Store(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(2)),
+        OpCode::Store(0),
+    ];
+    assert_eq!(opt::eliminate_dead_code(&mut ops, &mut opt::Fuel::new(None)), 2);
+    assert_eq!(ops, [OpCode::ConstValue(R_BoxedValue::Usize(2)), OpCode::Store(0)]);
+
+    // a guard failing in between sees the first store
+    let mut ops = vec![OpCode::ConstValue(R_BoxedValue::Usize(1)), OpCode::Store(0)];
+    ops.extend(trace());
+    ops.extend(vec![OpCode::ConstValue(R_BoxedValue::Usize(2)), OpCode::Store(0)]);
+    let optimized = opt::optimize_trace(ops.clone());
+    assert_eq!(optimized, ops);
+}
+
+// if x < 3 { .. } if x < 3 { .. }, with `between` in between
+fn branching_trace(between: Vec<OpCode>) -> Vec<OpCode> {
+    let guard = |pc| Guard {
+        expected: true,
+        recovery: InstructionPointer { func: 1, pc: pc },
+    };
+    let mut ops = vec![
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(3)),
+        OpCode::BinOp(BinOp::Lt),
+        OpCode::Guard(guard(4)),
+        OpCode::Pop,
+    ];
+    ops.extend(between);
+    ops.extend(vec![
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(3)),
+        OpCode::BinOp(BinOp::Lt),
+        OpCode::Guard(guard(9)),
+        OpCode::Pop,
+    ]);
+    ops
+}
+
+#[test]
+fn guards_of_unchanged_conditions_are_removed() {
+    let mut ops = branching_trace(vec![OpCode::Load(1), OpCode::Pop]);
+    assert_eq!(opt::eliminate_redundant_guards(&mut ops, &mut opt::Fuel::new(None)), 1);
+    assert_eq!(ops[..5], branching_trace(Vec::new())[..5]);
+    assert_eq!(ops[5..], [OpCode::Load(1), OpCode::Pop]);
+
+    // the condition may change in between
+    let stored = vec![OpCode::ConstValue(R_BoxedValue::Usize(5)), OpCode::Store(0)];
+    let mut ops = branching_trace(stored.clone());
+    assert_eq!(opt::eliminate_redundant_guards(&mut ops, &mut opt::Fuel::new(None)), 0);
+    assert_eq!(ops, branching_trace(stored));
+}
+
+#[test]
+fn installed_traces_number_the_guards_left() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    driver.seed_trace(key, branching_trace(trace())).unwrap();
+    let guards = driver.trace(key).unwrap().guards.clone();
+    assert_eq!(guards, [3, 6]);
+
+    driver.set_trace_passes(opt::TracePasses {
+        guards: false,
+        ..opt::TracePasses::default()
+    });
+    driver.seed_trace(key, branching_trace(trace())).unwrap();
+    assert_eq!(driver.trace(key).unwrap().guards.len(), 3);
+}
+
+#[cfg(feature = "sync-cells")]
+#[test]
+fn installed_traces_are_shared_with_other_threads() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+    driver.seed_trace(key, trace()).unwrap();
+
+    let installed = driver.trace(key).unwrap();
+    let ops = thread::spawn(move || installed.ops.clone()).join().unwrap();
+    assert_eq!(ops, driver.trace(key).unwrap().ops);
+}
+
+// tape[0] = 1; prog[0] = 5; x = tape[0], with tape in local 0 and prog in 1
+fn forwarding_trace() -> Vec<OpCode> {
+    let mut ops = vec![
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::Load(0),
+        OpCode::AssignIndex,
+        OpCode::ConstValue(R_BoxedValue::Usize(5)),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::Load(1),
+        OpCode::AssignIndex,
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::Load(0),
+        OpCode::GetIndex,
+        OpCode::Store(2),
+    ];
+    ops.extend(trace());
+    ops
+}
+
+#[test]
+fn loads_are_forwarded_past_structs_which_never_alias() {
+    let mut guest = Program::default();
+    guest.aliases.set(1, 0, 0);
+    guest.aliases.set(1, 1, 1);
+    let mut driver = Driver::default();
+    let program = driver.register_program(guest);
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    driver.seed_trace(key, forwarding_trace()).unwrap();
+    let ops = driver.trace(key).unwrap().ops.clone();
+    assert_eq!(ops[8..10], [OpCode::ConstValue(R_BoxedValue::Usize(1)), OpCode::Store(2)]);
+
+    // without the annotation the second write may have changed the field
+    let program = driver.register_program(Program::default());
+    let key = TraceKey { program: program, ..key };
+    driver.seed_trace(key, forwarding_trace()).unwrap();
+    assert_eq!(driver.trace(key).unwrap().ops.len(), forwarding_trace().len() + 1);
+}
+
+// a driver for a program whose data is [_, 7], and a trace reading it
+fn reading_data() -> (Driver, TraceKey, Vec<OpCode>) {
+    let mut array = R_Struct::with_size(2);
+    array.set(1, R_BoxedValue::Usize(7));
+    let mut guest = Program::default();
+    guest.data.push(R_BoxedValue::Struct(array));
+    let mut driver = Driver::default();
+    let program = driver.register_program(guest);
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+
+    // x = DATA[1]; y = len(DATA)
+    let mut reads = vec![
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::ConstRef(0),
+        OpCode::GetIndex,
+        OpCode::Store(0),
+        OpCode::ConstRef(0),
+        OpCode::Len,
+        OpCode::Store(1),
+    ];
+    reads.extend(trace());
+    (driver, key, reads)
+}
+
+#[test]
+fn reads_of_the_data_are_folded_into_traces() {
+    let (mut driver, key, reads) = reading_data();
+    driver.seed_trace(key, reads).unwrap();
+    let ops = driver.trace(key).unwrap().ops.clone();
+    assert_eq!(ops[..4], [
+        OpCode::ConstValue(R_BoxedValue::Usize(7)),
+        OpCode::Store(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(2)),
+        OpCode::Store(1),
+    ]);
+}
+
+#[test]
+fn opt_fuel_limits_the_rewrites_of_a_trace() {
+    let (mut driver, key, reads) = reading_data();
+    driver.set_opt_fuel(Some(1));
+    driver.seed_trace(key, reads.clone()).unwrap();
+    let ops = driver.trace(key).unwrap().ops.clone();
+    assert_eq!(ops[..4], [
+        OpCode::ConstValue(R_BoxedValue::Usize(7)),
+        OpCode::Store(0),
+        OpCode::ConstRef(0),
+        OpCode::Len,
+    ]);
+
+    // pretend folding the length is a miscompile
+    let broken = opt::bisect_fuel(10, |fuel| {
+        driver.set_opt_fuel(Some(fuel));
+        driver.seed_trace(key, reads.clone()).unwrap();
+        driver.trace(key).unwrap().ops.contains(&OpCode::Len)
+    });
+    assert_eq!(broken, Some(2));
+}
+
+#[test]
+fn preheated_loops_stay_hot_while_another_one_records() {
+    let program = Driver::default().register_program(Program::default());
+    let key = |func| {
+        TraceKey {
+            program: program,
+            ip: InstructionPointer { func: func, pc: 0 },
+            pc: 0,
+            specialization: 0,
+        }
+    };
+    let mut tracer = Tracer::default();
+    tracer.preheat(key(1), 100);
+    tracer.preheat(key(2), 100);
+
+    // entering the loop isn't a back-edge yet
+    tracer.handle_mergepoint(key(1));
+    match tracer.handle_mergepoint(key(1)) {
+        MergePointResult::StartTrace => tracer.abort_trace(),
+        _ => panic!("the first preheated loop didn't start recording"),
+    }
+    match tracer.handle_mergepoint(key(2)) {
+        MergePointResult::StartTrace => {}
+        _ => panic!("the second preheated loop didn't start recording"),
+    }
+}
+
+#[test]
+fn reset_counters_start_counting_from_scratch() {
+    let program = Driver::default().register_program(Program::default());
+    let key = TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 0 },
+        pc: 0,
+        specialization: 0,
+    };
+    let mut tracer = Tracer::default();
+    tracer.preheat(key, 100);
+    tracer.reset_counters();
+
+    // entering the loop isn't a back-edge yet, hot after 5 more hits
+    for _ in 0..6 {
+        match tracer.handle_mergepoint(key) {
+            MergePointResult::None => {}
+            _ => panic!("the reset loop got hot too early"),
+        }
+    }
+    match tracer.handle_mergepoint(key) {
+        MergePointResult::StartTrace => {}
+        _ => panic!("the reset loop didn't get hot again"),
+    }
+}
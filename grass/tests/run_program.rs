@@ -0,0 +1,1067 @@
+extern crate grass;
+#[cfg(feature = "opcode-hooks")]
+extern crate grass_core;
+
+mod common;
+
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+
+use grass::bc::{opt, Program};
+use grass::bc::bytecode::{OpCode, BinOp, InternalFunc};
+use grass::core::cell::SharedCell;
+use grass::core::objects::{CallFrame, InstructionPointer, R_BoxedValue};
+use grass::driver::{BacktraceFrame, Driver, Effect, ErrorKind, ExecEvent,
+                    GuestProfile, InterpError, InterpreterBuilder, Location, MemoryIo,
+                    StepClock};
+#[cfg(not(feature = "no-jit"))]
+use grass::driver::{ProgramId, TraceKey};
+#[cfg(feature = "opcode-hooks")]
+use grass_core::interp::Interpreter;
+
+use common::{function, program};
+
+#[test]
+fn returns_constant() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![OpCode::ConstValue(R_BoxedValue::Usize(42)), OpCode::Return]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(42)));
+}
+
+#[test]
+fn returns_argument() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        function(1, 1, vec![OpCode::Load(0), OpCode::Return]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![R_BoxedValue::Usize(7)]),
+               Ok(R_BoxedValue::Usize(7)));
+}
+
+#[test]
+fn runs_only_functions_of_the_program_with_their_arguments() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        function(1, 1, vec![OpCode::Load(0), OpCode::Return]),
+    ]));
+
+    let err = driver.run_program(program, 2, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::UnknownFunction(2));
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::ArityMismatch { func: 1, expected: 1, found: 0 });
+    let args = vec![R_BoxedValue::Usize(7), R_BoxedValue::Usize(8)];
+    let err = driver.run_program(program, 1, args).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::ArityMismatch { func: 1, expected: 1, found: 2 });
+}
+
+#[test]
+fn merge_points_without_a_guest_pc_fail() {
+    // the merge point is at the start of function 1, which has no local for
+    // the guest pc
+    let mut guest = program(vec![
+        function(0, 2, vec![OpCode::ConstValue(R_BoxedValue::Usize(0)), OpCode::Return]),
+    ]);
+    guest.entry = (1, 0);
+    let mut driver = Driver::default();
+    let program = driver.register_program(guest.clone());
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::MissingGuestPc { func: 1 });
+
+    // or one which isn't a `Usize`
+    guest.functions[1].locals_cnt = 4;
+    let program = driver.register_program(guest);
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::MissingGuestPc { func: 1 });
+}
+
+#[test]
+fn returns_through_nested_calls() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        // 1: main() = f(20)
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(20)),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        // 2: f(x) = g(x) + 1
+        function(1, 1, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Func(3)),
+            OpCode::Call,
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+        // 3: g(x) = x * 2
+        function(1, 1, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(2)),
+            OpCode::BinOp(BinOp::Mul),
+            OpCode::Return,
+        ]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(41)));
+}
+
+#[test]
+fn returns_unit_without_result() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![OpCode::Return]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Unit));
+}
+
+#[test]
+fn calls_leave_exactly_one_value() {
+    let functions = vec![
+        // 1: main() = { f(); g() }
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Func(3)),
+            OpCode::Call,
+            OpCode::Pop,
+            OpCode::ConstValue(R_BoxedValue::Func(4)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        // 2: h() = f()
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Func(3)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        // 3: f() without a value
+        function(0, 0, vec![OpCode::Return]),
+        // 4: g() = 7, with a leftover value below the result
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Bool(true)),
+            OpCode::ConstValue(R_BoxedValue::Usize(7)),
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut inlined = plain.clone();
+    opt::inline_calls(&mut inlined, 16);
+    // g can't be inlined, its leftover would stay on the stack of main
+    assert_eq!(inlined.functions[1].opcodes.iter().filter(|oc| **oc == OpCode::Call).count(), 1);
+    assert!(!inlined.functions[2].opcodes.contains(&OpCode::Call));
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let inlined = driver.register_program(inlined);
+    for &program in &[plain, inlined] {
+        assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(7)));
+        assert_eq!(driver.run_program(program, 2, vec![]), Ok(R_BoxedValue::Unit));
+    }
+}
+
+#[test]
+fn inlined_calls_return_the_same() {
+    let functions = vec![
+        // 1: main() = f(20) + f(1)
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(20)),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+        // 2: f(x) = if x > 10 { x - 10 } else { x }
+        function(1, 2, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(10)),
+            OpCode::BinOp(BinOp::Gt),
+            OpCode::SkipIf(4),
+            OpCode::Load(0),
+            OpCode::Use,
+            OpCode::Return,
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(10)),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut inlined = plain.clone();
+    opt::inline_calls(&mut inlined, 16);
+    assert!(!inlined.functions[1].opcodes.contains(&OpCode::Call));
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let inlined = driver.register_program(inlined);
+    assert_eq!(driver.run_program(plain, 1, vec![]), Ok(R_BoxedValue::Usize(11)));
+    assert_eq!(driver.run_program(inlined, 1, vec![]), Ok(R_BoxedValue::Usize(11)));
+}
+
+#[test]
+fn threaded_jumps_return_the_same() {
+    let functions = vec![
+        // 1: main(x) = if true { if x > 1 { 1 } else { 0 } } else { 2 }
+        function(1, 1, vec![
+            OpCode::ConstValue(R_BoxedValue::Bool(true)),
+            OpCode::SkipIf(3),
+            OpCode::ConstValue(R_BoxedValue::Usize(2)),
+            OpCode::Return,
+            OpCode::Skip(1),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Gt),
+            OpCode::SkipIf(3),
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::Skip(3),
+            OpCode::Noop,
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::Skip(1),
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut threaded = plain.clone();
+    opt::thread_jumps(&mut threaded);
+    assert!(threaded.functions[1].opcodes.len() < plain.functions[1].opcodes.len());
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let threaded = driver.register_program(threaded);
+    for x in 0..3 {
+        let arg = vec![R_BoxedValue::Usize(x)];
+        assert_eq!(driver.run_program(threaded, 1, arg.clone()),
+                   driver.run_program(plain, 1, arg));
+    }
+}
+
+#[test]
+fn fused_copy_loops_return_the_same() {
+    let usize = |n| OpCode::ConstValue(R_BoxedValue::Usize(n));
+    let functions = vec![
+        // 1: copy(n) = { src = [1, 2, 3, 4]; dst = [0; 4]; i = 0;
+        //                while i < n { dst[i] = src[i]; i += 1 }; dst }
+        function(1, 4, vec![
+            usize(1),
+            usize(2),
+            usize(3),
+            usize(4),
+            OpCode::Array(4),
+            OpCode::Store(2),
+            usize(0),
+            OpCode::Repeat(4),
+            OpCode::Store(3),
+            usize(0),
+            OpCode::Store(1),
+            OpCode::Load(1),
+            OpCode::Load(0),
+            OpCode::BinOp(BinOp::Ge),
+            OpCode::SkipIf(12),
+            OpCode::Load(1),
+            OpCode::Load(2),
+            OpCode::GetIndex,
+            OpCode::Load(1),
+            OpCode::Load(3),
+            OpCode::AssignIndex,
+            OpCode::Load(1),
+            usize(1),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::JumpBack(14),
+            OpCode::Load(3),
+            OpCode::Return,
+        ]),
+        // 2: fill(n) = { dst = [0; 4]; i = 0; while i < n { dst[i] = 7; i += 1 }; dst }
+        function(1, 3, vec![
+            usize(0),
+            OpCode::Repeat(4),
+            OpCode::Store(2),
+            usize(0),
+            OpCode::Store(1),
+            OpCode::Load(1),
+            OpCode::Load(0),
+            OpCode::BinOp(BinOp::Ge),
+            OpCode::SkipIf(10),
+            usize(7),
+            OpCode::Load(1),
+            OpCode::Load(2),
+            OpCode::AssignIndex,
+            OpCode::Load(1),
+            usize(1),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::JumpBack(12),
+            OpCode::Load(2),
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut fused = plain.clone();
+    opt::fuse_copy_loops(&mut fused);
+    assert!(fused.functions[1].opcodes.contains(&OpCode::CopyRange));
+    assert!(fused.functions[2].opcodes.contains(&OpCode::Fill));
+    assert!(!fused.functions[1].opcodes.contains(&OpCode::JumpBack(14)));
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let fused = driver.register_program(fused);
+    for func in 1..3 {
+        for n in 0..5 {
+            let arg = vec![R_BoxedValue::Usize(n)];
+            assert_eq!(driver.run_program(fused, func, arg.clone()),
+                       driver.run_program(plain, func, arg));
+        }
+    }
+}
+
+#[test]
+fn selected_min_max_return_the_same() {
+    let select = |cmp, taken, other| vec![
+        OpCode::Load(0),
+        OpCode::Load(1),
+        OpCode::BinOp(cmp),
+        OpCode::SkipIf(5),
+        OpCode::Load(other),
+        OpCode::Use,
+        OpCode::Store(2),
+        OpCode::Skip(4),
+        OpCode::Load(taken),
+        OpCode::Use,
+        OpCode::Store(2),
+        OpCode::Load(2),
+        OpCode::Return,
+    ];
+    let functions = vec![
+        // 1: min(a, b) = if a < b { a } else { b }
+        function(2, 3, select(BinOp::Lt, 0, 1)),
+        // 2: max(a, b) = if a <= b { b } else { a }
+        function(2, 3, select(BinOp::Le, 1, 0)),
+        // 3: max(a, b) = if a > b { a } else { b }
+        function(2, 3, select(BinOp::Gt, 0, 1)),
+        // 4: min(a, b) = if a >= b { b } else { a }
+        function(2, 3, select(BinOp::Ge, 1, 0)),
+        // 5: dist(a, b) = (a - b).abs()
+        function(2, 2, vec![
+            OpCode::Load(0),
+            OpCode::Load(1),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Abs,
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut selected = plain.clone();
+    opt::select_min_max(&mut selected);
+    let kinds = [BinOp::Min, BinOp::Max, BinOp::Max, BinOp::Min];
+    for (func, &kind) in (1..5).zip(&kinds) {
+        let opcodes = &selected.functions[func].opcodes;
+        assert!(opcodes.contains(&OpCode::BinOp(kind)));
+        assert!(!opcodes.contains(&OpCode::SkipIf(5)));
+    }
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let selected = driver.register_program(selected);
+    for func in 1..6 {
+        for &(a, b) in &[(-3, 5), (5, -3), (2, 2)] {
+            let args = vec![R_BoxedValue::I64(a), R_BoxedValue::I64(b)];
+            assert_eq!(driver.run_program(selected, func, args.clone()),
+                       driver.run_program(plain, func, args));
+        }
+    }
+    assert_eq!(driver.run_program(plain, 5, vec![R_BoxedValue::I64(-3), R_BoxedValue::I64(5)]),
+               Ok(R_BoxedValue::I64(8)));
+}
+
+#[test]
+fn compacted_locals_return_the_same() {
+    let functions = vec![
+        // 1: main(x) = { let a = x + 1; let b = a * 2; let c = b - x; c }
+        function(1, 4, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::Load(1),
+            OpCode::ConstValue(R_BoxedValue::Usize(2)),
+            OpCode::BinOp(BinOp::Mul),
+            OpCode::Store(2),
+            OpCode::Load(2),
+            OpCode::Load(0),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Store(3),
+            OpCode::Load(3),
+            OpCode::Use,
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut compacted = plain.clone();
+    let slots = opt::compact_locals(&mut compacted);
+    assert_eq!(slots[1][0], Some(0));
+    assert_eq!(compacted.functions[1].locals_cnt, 2);
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let compacted = driver.register_program(compacted);
+    for x in 0..3 {
+        let arg = vec![R_BoxedValue::Usize(x)];
+        assert_eq!(driver.run_program(compacted, 1, arg.clone()),
+                   driver.run_program(plain, 1, arg));
+    }
+}
+
+#[test]
+fn compacted_locals_leave_the_arguments_of_the_caller_alone() {
+    let functions = vec![
+        // 1: main() = { let x = 5; callee(x); x }
+        function(0, 1, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(5)),
+            OpCode::Store(0),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Pop,
+            OpCode::Load(0),
+            OpCode::Return,
+        ]),
+        // 2: callee(a) = { let b = a + 1; b }
+        function(1, 2, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::Load(1),
+            OpCode::Use,
+            OpCode::Return,
+        ]),
+    ];
+
+    let plain = program(functions);
+    let mut compacted = plain.clone();
+    let slots = opt::compact_locals(&mut compacted);
+    assert_eq!(slots[2], [Some(0), Some(1)]);
+
+    let mut driver = Driver::default();
+    let plain = driver.register_program(plain);
+    let compacted = driver.register_program(compacted);
+    assert_eq!(driver.run_program(plain, 1, vec![]), Ok(R_BoxedValue::Usize(5)));
+    assert_eq!(driver.run_program(compacted, 1, vec![]), Ok(R_BoxedValue::Usize(5)));
+}
+
+// calls `f` at pc 10 of `main`, see `call_key`
+fn calls_in_a_loop() -> Program {
+    program(vec![
+        // 1: main() { s = 0; i = 0; while i < 20 { s = f(s); i += 1 }; s }
+        function(0, 2, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::Store(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::Store(1),
+            // 4
+            OpCode::Load(1),
+            OpCode::ConstValue(R_BoxedValue::Usize(20)),
+            OpCode::BinOp(BinOp::Ge),
+            OpCode::SkipIf(10),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            // 10
+            OpCode::Call,
+            OpCode::Store(0),
+            OpCode::Load(1),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::JumpBack(12),
+            // 17
+            OpCode::Load(0),
+            OpCode::Return,
+        ]),
+        // 2: f(x) = x + 3
+        function(1, 1, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(3)),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Return,
+        ]),
+    ])
+}
+
+#[cfg(not(feature = "no-jit"))]
+fn call_key(program: ProgramId) -> TraceKey {
+    TraceKey {
+        program: program,
+        ip: InstructionPointer { func: 1, pc: 10 },
+        pc: 0,
+        specialization: 2,
+    }
+}
+
+#[cfg(not(feature = "no-jit"))]
+#[test]
+fn hot_calls_run_a_trace_of_the_callee() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(calls_in_a_loop());
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(60)));
+
+    let trace = driver.call_trace(call_key(program)).expect("the call got no trace");
+    assert_eq!(trace.exit, Some(InstructionPointer { func: 2, pc: 3 }));
+    assert!(trace.stats.entries() > 0);
+    assert_eq!(trace.stats.guard_failures(), 0);
+}
+
+#[cfg(feature = "no-jit")]
+#[test]
+fn merge_points_do_nothing_without_the_jit() {
+    let mut guest = calls_in_a_loop();
+    guest.entry = (1, 4);
+    let mut driver = Driver::default();
+    let program = driver.register_program(guest);
+    let user_program = [1, 2, 3];
+    let mut cell = 5;
+
+    // however hot the merge point gets, the host keeps interpreting
+    for _ in 0..1000 {
+        assert_eq!(driver.merge_point(program, &user_program, 1, &mut cell), Ok(1));
+    }
+    assert_eq!(cell, 5);
+    let key = driver.trace_key(program, &user_program, 1);
+    let mut frame = CallFrame::new(None, 4);
+    assert_eq!(driver.record(key, &mut frame), Ok(false));
+}
+
+#[cfg(not(feature = "no-jit"))]
+#[test]
+fn breakpoints_stop_every_call_with_the_jit_on() {
+    let mut guest = calls_in_a_loop();
+    guest.functions[2].opcodes.insert(0, OpCode::Breakpoint(7));
+    let hits = Rc::new(RefCell::new(Vec::new()));
+    let mut driver = Driver::default();
+    let program = driver.register_program(guest);
+
+    let seen = hits.clone();
+    driver.set_breakpoint_hook(Some(Box::new(move |_: &_, id: usize| seen.borrow_mut().push(id))));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(60)));
+    assert_eq!(*hits.borrow(), vec![7; 20]);
+    // recording stopped at the breakpoint each time
+    assert!(driver.call_trace(call_key(program)).is_none());
+}
+
+#[test]
+fn interrupts_stop_the_guest_at_its_next_call() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(calls_in_a_loop());
+    driver.interrupt_handle().interrupt();
+
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Interrupted);
+    // only once
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(60)));
+}
+
+// `calls_in_a_loop` with a loop which never ends
+fn runaway_loop() -> Program {
+    let mut guest = calls_in_a_loop();
+    // the loop is left once i < 0, never
+    guest.functions[1].opcodes[5] = OpCode::ConstValue(R_BoxedValue::Usize(0));
+    guest.functions[1].opcodes[6] = OpCode::BinOp(BinOp::Lt);
+    guest
+}
+
+#[test]
+fn interrupts_stop_runaway_guests_from_another_thread() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(runaway_loop());
+
+    let handle = driver.interrupt_handle();
+    let interrupter = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        handle.interrupt();
+    });
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Interrupted);
+    interrupter.join().unwrap();
+}
+
+#[test]
+fn runaway_guests_time_out() {
+    let mut driver = Driver::default();
+    // one nanosecond per call of `f`
+    driver.set_clock(Box::new(StepClock::default()));
+    driver.set_timeout(Some(Duration::new(0, 100)));
+    let program = driver.register_program(runaway_loop());
+
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Timeout);
+    assert_eq!(err.backtrace[0], BacktraceFrame { func: 2, pc: 0 });
+
+    // each run gets the whole timeout for its 20 calls
+    let program = driver.register_program(calls_in_a_loop());
+    for _ in 0..10 {
+        assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(60)));
+    }
+}
+
+#[test]
+fn runaway_guests_run_out_of_fuel() {
+    let mut driver = InterpreterBuilder::new().fuel(Some(1000)).build();
+    let program = driver.register_program(runaway_loop());
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::OutOfFuel);
+
+    // traced or not, each run gets all of the fuel
+    let program = driver.register_program(calls_in_a_loop());
+    for _ in 0..10 {
+        assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(60)));
+    }
+}
+
+#[test]
+fn strict_drivers_refuse_rejected_programs() {
+    let underflow = program(vec![function(0, 0, vec![OpCode::Pop, OpCode::Return])]);
+
+    let mut driver = InterpreterBuilder::new().strict(true).stack_capacity(64).build();
+    let rejected = driver.register_program(underflow.clone());
+    assert_eq!(driver.verify_error(rejected).map(|err| (err.func, err.pc)), Some((1, 0)));
+    let err = driver.run_program(rejected, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::Unverified { func: 1, pc: 0 });
+
+    let accepted = driver.register_program(calls_in_a_loop());
+    assert_eq!(driver.verify_error(accepted), None);
+    assert_eq!(driver.run_program(accepted, 1, vec![]), Ok(R_BoxedValue::Usize(60)));
+
+    // only strict drivers verify
+    let mut driver = Driver::default();
+    let unchecked = driver.register_program(underflow);
+    assert_eq!(driver.verify_error(unchecked), None);
+}
+
+#[cfg(not(feature = "no-jit"))]
+#[test]
+fn stats_count_per_thread_and_in_total() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(calls_in_a_loop());
+    driver.run_program(program, 1, vec![]).unwrap();
+    let key = call_key(program);
+    let entries = driver.call_trace(key).unwrap().stats.entries();
+
+    let registry = driver.stats_registry();
+    thread::spawn(move || {
+        let stats = registry.register("worker");
+        for _ in 0..3 {
+            stats.record_entry(key);
+        }
+    }).join().unwrap();
+
+    let stats = driver.stats();
+    let names: Vec<&str> = stats.threads.iter().map(|thread| &thread.name[..]).collect();
+    assert_eq!(names, vec!["main", "worker"]);
+    assert_eq!(stats.threads[0].traces[&key].entries, entries);
+    assert_eq!(stats.threads[1].traces[&key].entries, 3);
+    assert_eq!(stats.global[&key].entries, entries + 3);
+    assert_eq!(stats.total().guard_failures, 0);
+}
+
+#[test]
+fn profiles_guest_functions() {
+    let mut driver = Driver::default();
+    driver.set_clock(Box::new(StepClock::default()));
+    let program = driver.register_program(program(vec![
+        // 1: guest function 0 calls guest function 1
+        function(0, 0, vec![
+            OpCode::ProfileEnter(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(7)),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::ProfileExit(0),
+            OpCode::Return,
+        ]),
+        function(1, 1, vec![
+            OpCode::ProfileEnter(1),
+            OpCode::Load(0),
+            OpCode::ProfileExit(1),
+            OpCode::Return,
+        ]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(7)));
+
+    // the step clock advances by one per reading
+    let profile = driver.guest_profile();
+    let timings: Vec<(usize, usize, u64, u64)> = profile.ranked()
+        .iter()
+        .map(|timing| (timing.func, timing.calls, timing.inclusive, timing.exclusive))
+        .collect();
+    assert_eq!(timings, vec![(0, 1, 3, 2), (1, 1, 1, 1)]);
+
+    assert_eq!(profile.folded(), "func0 2\nfunc0;func1 1\n");
+
+    driver.name_guest_function(0, "main");
+    driver.name_guest_function(1, "f");
+    let path = env::temp_dir().join("grass-profiles-guest-functions.folded");
+    driver.export_flamegraph(&path).unwrap();
+    let mut folded = String::new();
+    File::open(&path).unwrap().read_to_string(&mut folded).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(folded, "main 2\nmain;f 1\n");
+}
+
+#[test]
+fn flamegraph_marks_calls_entered_in_traces() {
+    let mut profile = GuestProfile::default();
+    profile.enter(0, false, 1);
+    profile.enter(1, true, 2);
+    profile.exit(1, 3);
+    profile.exit(0, 4);
+    assert_eq!(profile.folded(), "func0 2\nfunc0;func1_[j] 1\n");
+}
+
+#[cfg(feature = "opcode-hooks")]
+#[test]
+fn hooks_see_every_interpreted_opcode() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![OpCode::ConstValue(R_BoxedValue::Usize(42)), OpCode::Return]),
+    ]));
+
+    let pre = seen.clone();
+    let post = seen.clone();
+    driver.set_opcode_hooks(
+        Some(Box::new(move |_interp: &Interpreter, opcode: &OpCode, ip: InstructionPointer| {
+            pre.borrow_mut().push(("pre", opcode.name(), ip.pc));
+        })),
+        Some(Box::new(move |interp: &Interpreter, opcode: &OpCode, ip: InstructionPointer| {
+            post.borrow_mut().push(("post", opcode.name(), interp.stack.len()));
+            assert_eq!(ip.func, 1);
+        })));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Usize(42)));
+    assert_eq!(*seen.borrow(), vec![
+        ("pre", "ConstValue", 0),
+        ("post", "ConstValue", 1),
+        ("pre", "Return", 1),
+        ("post", "Return", 1),
+    ]);
+}
+
+#[cfg(not(feature = "no-jit"))]
+#[test]
+fn straight_line_code_is_not_covered() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![OpCode::ConstValue(R_BoxedValue::Usize(42)), OpCode::Return]),
+    ]));
+    driver.run_program(program, 1, vec![]).unwrap();
+
+    let coverage = driver.coverage(program);
+    assert_eq!((coverage.traced(), coverage.total()), (0, 2));
+    assert!(coverage.to_string().starts_with("0 of 2 opcodes traced (0.0%)"));
+}
+
+#[test]
+fn clock_reads_the_clock_of_the_driver() {
+    let mut driver = Driver::default();
+    driver.set_clock(Box::new(StepClock::default()));
+    let program = driver.register_program(program(vec![
+        // 1: clock() < clock()
+        function(0, 0, vec![
+            OpCode::InternalFunc(InternalFunc::Clock),
+            OpCode::InternalFunc(InternalFunc::Clock),
+            OpCode::BinOp(BinOp::Lt),
+            OpCode::Return,
+        ]),
+        // 2: clock()
+        function(0, 0, vec![OpCode::InternalFunc(InternalFunc::Clock), OpCode::Return]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Bool(true)));
+    // the third reading of the clock
+    assert_eq!(driver.run_program(program, 2, vec![]), Ok(R_BoxedValue::U64(3)));
+}
+
+#[test]
+fn keeps_the_last_opcodes_of_a_failed_run() {
+    let mut driver = Driver::default();
+    driver.set_history_len(2);
+    // function 2 belongs to the host
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        function(0, 0, vec![]),
+    ]));
+
+    assert!(driver.run_program(program, 1, vec![]).is_err());
+    assert_eq!(driver.history().events(), vec![
+        ExecEvent {
+            opcode: "ConstValue",
+            at: Location::Interpreted(InstructionPointer { func: 1, pc: 1 }),
+            depth: 1,
+        },
+        ExecEvent {
+            opcode: "Call",
+            at: Location::Interpreted(InstructionPointer { func: 1, pc: 2 }),
+            depth: 2,
+        },
+    ]);
+}
+
+// output which stays readable after it is handed to the driver
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn prints_into_the_output_of_the_driver() {
+    let mut driver = Driver::default();
+    let output = SharedOutput::default();
+    driver.set_output(output.clone());
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(42)),
+            OpCode::InternalFunc(InternalFunc::Print),
+            OpCode::Return,
+        ]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Unit));
+    assert_eq!(&output.0.borrow()[..], b"42\n");
+}
+
+#[test]
+fn echoes_guest_input_into_memory() {
+    let mut driver = Driver::default();
+    let io = MemoryIo::new(b"g");
+    let output = io.output();
+    driver.set_io(Box::new(io));
+    let program = driver.register_program(program(vec![
+        function(0, 0, vec![
+            OpCode::InternalFunc(InternalFunc::In),
+            OpCode::InternalFunc(InternalFunc::Out),
+            OpCode::ConstValue(R_BoxedValue::Usize(7)),
+            OpCode::InternalFunc(InternalFunc::Print),
+            // the input is used up
+            OpCode::InternalFunc(InternalFunc::In),
+            OpCode::Return,
+        ]),
+    ]));
+
+    assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Null));
+    assert_eq!(&output.borrow()[..], b"g7\n");
+}
+
+struct FailingOutput;
+
+impl Write for FailingOutput {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_guest_output_is_an_error() {
+    let output = |val| {
+        program(vec![
+            function(0, 0, vec![
+                OpCode::ConstValue(val),
+                OpCode::InternalFunc(InternalFunc::Out),
+                OpCode::Return,
+            ]),
+        ])
+    };
+    let mut driver = Driver::default();
+    let boolean = driver.register_program(output(R_BoxedValue::Bool(true)));
+    let err = driver.run_program(boolean, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::InvalidOutput(R_BoxedValue::Bool(true)));
+
+    driver.set_output(FailingOutput);
+    let byte = driver.register_program(output(R_BoxedValue::Usize(7)));
+    let err = driver.run_program(byte, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::IoFailed(InternalFunc::Out));
+}
+
+#[test]
+fn default_clock_never_goes_back() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        // clock() <= clock()
+        function(0, 0, vec![
+            OpCode::InternalFunc(InternalFunc::Clock),
+            OpCode::InternalFunc(InternalFunc::Clock),
+            OpCode::BinOp(BinOp::Le),
+            OpCode::Return,
+        ]),
+    ]));
+
+    for _ in 0..10 {
+        assert_eq!(driver.run_program(program, 1, vec![]), Ok(R_BoxedValue::Bool(true)));
+    }
+}
+
+#[test]
+fn seeded_drivers_draw_the_same_numbers() {
+    fn draw(seed: u64) -> Vec<Result<R_BoxedValue, InterpError>> {
+        let mut driver = Driver::default();
+        driver.seed_rng(seed);
+        let program = driver.register_program(program(vec![
+            function(0, 0, vec![OpCode::InternalFunc(InternalFunc::Rand), OpCode::Return]),
+        ]));
+        (0..3).map(|_| driver.run_program(program, 1, vec![])).collect()
+    }
+
+    let numbers = draw(42);
+    assert_eq!(numbers, draw(42));
+    assert!(numbers != draw(43));
+    // the generator moves on between draws
+    assert!(numbers[0] != numbers[1]);
+}
+
+#[test]
+fn guests_over_the_heap_limit_run_out_of_memory() {
+    let mut driver = Driver::default();
+    driver.set_heap_limit(Some(1024));
+    let program = driver.register_program(program(vec![
+        // 1: (0, 0)
+        function(0, 0, vec![OpCode::Tuple(2), OpCode::Return]),
+        // 2: (0, 0, ..) of a thousand elements
+        function(0, 0, vec![OpCode::Tuple(1000), OpCode::Return]),
+    ]));
+
+    assert!(driver.run_program(program, 1, vec![]).is_ok());
+    let err = driver.run_program(program, 2, vec![]).unwrap_err();
+    match err.kind {
+        ErrorKind::OutOfMemory { requested, limit } => {
+            assert!(requested > 1024);
+            assert_eq!(limit, 1024);
+        }
+        kind => panic!("expected OutOfMemory, got {:?}", kind),
+    }
+}
+
+#[test]
+fn sandboxed_guests_only_compute() {
+    let mut driver = Driver::default();
+    let output = SharedOutput::default();
+    driver.set_output(output.clone());
+    driver.set_sandbox(true);
+    // function 3 belongs to the host
+    let program = driver.register_program(program(vec![
+        // 1: print(42)
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(42)),
+            OpCode::InternalFunc(InternalFunc::Print),
+            OpCode::Return,
+        ]),
+        // 2: host()
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Func(3)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        function(0, 0, vec![]),
+    ]));
+
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::SandboxViolation(Effect::Io(InternalFunc::Print)));
+    assert!(output.0.borrow().is_empty());
+    let err = driver.run_program(program, 2, vec![]).unwrap_err();
+    assert_eq!(err.kind, ErrorKind::SandboxViolation(Effect::ExternalCall(3)));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn pointers_into_returned_frames_dangle() {
+    let mut driver = Driver::default();
+    let program = driver.register_program(program(vec![
+        // 1: *escape()
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Deref,
+            OpCode::Return,
+        ]),
+        // 2: let x = 1; &x
+        function(0, 1, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::Store(0),
+            OpCode::Load(0),
+            OpCode::Ref,
+            OpCode::Return,
+        ]),
+    ]));
+
+    let err = driver.run_program(program, 1, vec![]).unwrap_err();
+    match err.kind {
+        ErrorKind::DanglingPointer { .. } => {}
+        kind => panic!("expected DanglingPointer, got {:?}", kind),
+    }
+}
+
+#[cfg(not(feature = "no-jit"))]
+#[test]
+fn failed_recordings_report_effects_the_host_would_repeat() {
+    let mut driver = Driver::default();
+    let output = SharedOutput::default();
+    driver.set_output(output.clone());
+    // the recording interpreter doesn't know `Neg`
+    let record = |driver: &mut Driver, opcodes: Vec<OpCode>| {
+        let mut guest = program(vec![function(1, 1, opcodes)]);
+        guest.entry = (1, 0);
+        let program = driver.register_program(guest);
+        let key = driver.trace_key(program, &[], 0);
+        let mut frame = CallFrame::new(None, 1);
+        frame.locals[0].set(R_BoxedValue::I64(3));
+        driver.record(key, &mut frame)
+    };
+
+    let res = record(&mut driver,
+                     vec![OpCode::Load(0), OpCode::Neg, OpCode::Pop, OpCode::Return]);
+    assert_eq!(res, Ok(false));
+
+    let res = record(&mut driver, vec![
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::InternalFunc(InternalFunc::Print),
+        OpCode::Load(0),
+        OpCode::Neg,
+        OpCode::Pop,
+        OpCode::Return,
+    ]);
+    assert_eq!(res.unwrap_err().kind, ErrorKind::UnsupportedOpcode(OpCode::Neg));
+    assert_eq!(&output.0.borrow()[..], b"1\n");
+}
@@ -0,0 +1,163 @@
+extern crate grass;
+
+use grass::bc::Program;
+use grass::bc::program::AliasClasses;
+use grass::bc::bytecode::{OpCode, BinOp, Guard, NumType};
+use grass::bc::symbolic::{check_trace, Divergence, Sym};
+use grass::core::objects::{InstructionPointer, R_BoxedValue, R_Function};
+#[cfg(not(feature = "no-jit"))]
+use grass::driver::{Driver, MergePointResult, TraceKey, Tracer};
+
+const START: InstructionPointer = InstructionPointer { func: 1, pc: 3 };
+
+// 1: n = 5; sum = 0; loop { if n == 0 { break }; sum += n; n -= 1 }; sum,
+// whose loop is recorded from 3
+fn summing_loop() -> Program {
+    let main = R_Function {
+        args_cnt: 0,
+        locals_cnt: 2,
+        opcodes: vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(5)),
+            OpCode::Store(0),
+            OpCode::Noop,
+            // 3
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(0)),
+            OpCode::BinOp(BinOp::Eq),
+            OpCode::SkipIf(10),
+            OpCode::Load(1),
+            OpCode::Load(0),
+            OpCode::BinOp(BinOp::Add),
+            OpCode::Store(1),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Store(0),
+            OpCode::JumpBack(13),
+            // 16
+            OpCode::Load(1),
+            OpCode::Return,
+        ],
+    };
+    Program {
+        functions: vec![R_Function::default(), main],
+        entry: (1, 3),
+        aliases: AliasClasses::default(),
+        data: Vec::new(),
+        statics: Vec::new(),
+        vtables: Vec::new(),
+        local_names: Vec::new(),
+    }
+}
+
+// the loop as the tracer records it, with `compare` for the `==`
+fn trace(compare: BinOp) -> Vec<OpCode> {
+    let guard = Guard {
+        expected: false,
+        recovery: InstructionPointer { func: 1, pc: 6 },
+    };
+    vec![
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(0)),
+        OpCode::GuardOperands(NumType::Usize, guard.clone()),
+        OpCode::TypedBinOp(compare, NumType::Usize),
+        OpCode::Guard(guard.clone()),
+        OpCode::Pop,
+        OpCode::Load(1),
+        OpCode::Load(0),
+        OpCode::GuardOperands(NumType::Usize, guard),
+        OpCode::TypedBinOp(BinOp::Add, NumType::Usize),
+        OpCode::Store(1),
+        OpCode::Load(0),
+        OpCode::ConstValue(R_BoxedValue::Usize(1)),
+        OpCode::TypedBinOp(BinOp::Sub, NumType::Usize),
+        OpCode::Store(0),
+    ]
+}
+
+#[test]
+fn recorded_loops_do_what_their_bytecode_does() {
+    assert_eq!(check_trace(&summing_loop(), START, &trace(BinOp::Eq), &[false]), Ok(()));
+    // the path has a conditional jump, which needs an outcome
+    assert_eq!(check_trace(&summing_loop(), START, &trace(BinOp::Eq), &[]),
+               Err(Divergence::MissingOutcome));
+}
+
+#[cfg(not(feature = "no-jit"))]
+#[test]
+fn the_tracer_records_what_the_bytecode_does() {
+    let program = summing_loop();
+    let mut driver = Driver::default();
+    let key = TraceKey {
+        program: driver.register_program(program.clone()),
+        ip: START,
+        pc: 0,
+        specialization: 0,
+    };
+    let mut tracer = Tracer::default();
+    loop {
+        if let MergePointResult::StartTrace = tracer.handle_mergepoint(key) {
+            break;
+        }
+    }
+
+    // what the interpreter tells the tracer in the first iteration
+    let opcodes = &program.functions[1].opcodes;
+    for pc in START.pc..opcodes.len() - 2 {
+        let operands = match opcodes[pc] {
+            OpCode::BinOp(_) => Some(NumType::Usize),
+            _ => None,
+        };
+        let condition = match opcodes[pc] {
+            OpCode::SkipIf(_) => Some(false),
+            _ => None,
+        };
+        let pos = InstructionPointer { func: 1, pc: pc };
+        tracer.trace_opcode(&opcodes[pc], pos, operands, condition, None);
+    }
+    tracer.finish_trace();
+
+    let trace = tracer.trace(key).expect("the loop got no trace");
+    assert_eq!(check_trace(&program, START, &trace.ops, &[false]), Ok(()));
+}
+
+#[test]
+fn finds_dropped_stores() {
+    let mut trace = trace(BinOp::Eq);
+    trace[10] = OpCode::Pop;
+
+    let sum = Sym::BinOp(BinOp::Add, Box::new(Sym::Local(1)), Box::new(Sym::Local(0)));
+    assert_eq!(check_trace(&summing_loop(), START, &trace, &[false]),
+               Err(Divergence::Local(1, Sym::Local(1), sum)));
+}
+
+#[test]
+fn finds_conditions_left_on_the_stack() {
+    let mut trace = trace(BinOp::Eq);
+    trace.remove(5);
+
+    match check_trace(&summing_loop(), START, &trace, &[false]) {
+        Err(Divergence::Stack(ref traced, ref path)) => {
+            assert_eq!(traced.len(), 1);
+            assert!(path.is_empty());
+        }
+        res => panic!("expected the stacks to differ, got {:?}", res),
+    }
+}
+
+#[test]
+fn finds_guards_on_other_conditions() {
+    match check_trace(&summing_loop(), START, &trace(BinOp::Ne), &[false]) {
+        Err(Divergence::Conditions(ref traced, ref path)) => {
+            assert_eq!(traced.len(), 1);
+            assert_eq!(path.len(), 1);
+        }
+        res => panic!("expected the conditions to differ, got {:?}", res),
+    }
+
+    // the guard has to expect the outcome the path took
+    match check_trace(&summing_loop(), START, &trace(BinOp::Eq), &[true]) {
+        Err(Divergence::Conditions(..)) => (),
+        res => panic!("expected the outcomes to differ, got {:?}", res),
+    }
+}
@@ -0,0 +1,156 @@
+extern crate grass;
+
+mod common;
+
+use grass::bc::bytecode::{OpCode, BinOp};
+use grass::bc::verify::{verify, VerifyError, Violation};
+use grass::core::objects::R_BoxedValue;
+
+use common::{function, program};
+
+fn error(func: usize, pc: usize, kind: Violation) -> Result<(), VerifyError> {
+    Err(VerifyError { func: func, pc: pc, kind: kind })
+}
+
+#[test]
+fn accepts_calls_by_the_convention() {
+    let program = program(vec![
+        // 1: main() = { f(); g(20) }
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Pop,
+            OpCode::ConstValue(R_BoxedValue::Usize(20)),
+            OpCode::ConstValue(R_BoxedValue::Func(3)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        // 2: f() without a value
+        function(0, 0, vec![OpCode::Return]),
+        // 3: g(x) = if x > 10 { x - 10 } else { x }
+        function(1, 1, vec![
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(10)),
+            OpCode::BinOp(BinOp::Gt),
+            OpCode::SkipIf(3),
+            OpCode::Load(0),
+            OpCode::Skip(4),
+            OpCode::Load(0),
+            OpCode::ConstValue(R_BoxedValue::Usize(10)),
+            OpCode::BinOp(BinOp::Sub),
+            OpCode::Return,
+        ]),
+    ]);
+
+    assert_eq!(verify(&program), Ok(()));
+}
+
+#[test]
+fn rejects_calls_with_missing_arguments() {
+    let program = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Func(2)),
+            OpCode::Call,
+            OpCode::Return,
+        ]),
+        function(1, 1, vec![OpCode::Load(0), OpCode::Return]),
+    ]);
+
+    assert_eq!(verify(&program),
+               error(1, 1, Violation::MissingArguments { callee: 2, expected: 1, found: 0 }));
+}
+
+#[test]
+fn rejects_calls_of_unknown_functions() {
+    let indirect = program(vec![
+        function(1, 1, vec![OpCode::Load(0), OpCode::Call, OpCode::Return]),
+    ]);
+    assert_eq!(verify(&indirect), error(1, 1, Violation::UnknownCallee));
+
+    let unknown = program(vec![
+        function(0, 0, vec![OpCode::ConstValue(R_BoxedValue::Func(7)), OpCode::Call, OpCode::Return]),
+    ]);
+    assert_eq!(verify(&unknown), error(1, 1, Violation::UnknownFunction(7)));
+}
+
+#[test]
+fn rejects_returns_with_leftover_values() {
+    let program = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::ConstValue(R_BoxedValue::Usize(2)),
+            OpCode::Return,
+        ]),
+    ]);
+
+    assert_eq!(verify(&program), error(1, 2, Violation::LeftoverValues(1)));
+}
+
+#[test]
+fn rejects_statics_without_a_value() {
+    let mut program = program(vec![
+        function(0, 0, vec![OpCode::Static(2), OpCode::Static(3), OpCode::BinOp(BinOp::Add),
+                            OpCode::Return]),
+        function(0, 0, vec![OpCode::ConstValue(R_BoxedValue::Usize(1)), OpCode::Return]),
+        function(0, 0, vec![]),
+    ]);
+    program.resolve_statics();
+
+    assert_eq!(verify(&program), error(1, 1, Violation::UnknownStatic(3)));
+}
+
+#[test]
+fn checks_the_arguments_of_methods() {
+    let mut program = program(vec![
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::VTable(0)),
+            OpCode::CallVirtual(0),
+            OpCode::Return,
+        ]),
+        function(1, 1, vec![OpCode::Load(0), OpCode::Return]),
+    ]);
+    program.vtables = vec![vec![2]];
+    assert_eq!(verify(&program), error(1, 1, Violation::MissingArguments {
+        callee: 2,
+        expected: 1,
+        found: 0,
+    }));
+
+    program.functions[1].opcodes[0] = OpCode::ConstValue(R_BoxedValue::VTable(0));
+    program.functions[1].opcodes[1] = OpCode::CallVirtual(1);
+    assert_eq!(verify(&program), error(1, 1, Violation::UnknownMethod(1)));
+}
+
+#[test]
+fn rejects_reads_beyond_the_data() {
+    let mut program = program(vec![
+        function(0, 0, vec![OpCode::ConstRef(1), OpCode::Return]),
+    ]);
+    program.data.push(R_BoxedValue::Usize(1));
+
+    assert_eq!(verify(&program), error(1, 0, Violation::UnknownData(1)));
+}
+
+#[test]
+fn rejects_paths_which_disagree_on_the_stack() {
+    let branches = program(vec![
+        // the value is only pushed if the condition holds
+        function(0, 0, vec![
+            OpCode::ConstValue(R_BoxedValue::Bool(true)),
+            OpCode::SkipIf(2),
+            OpCode::ConstValue(R_BoxedValue::Usize(1)),
+            OpCode::Return,
+        ]),
+    ]);
+    assert_eq!(verify(&branches), error(1, 3, Violation::InconsistentDepth(0, 1)));
+
+    let underflow = program(vec![
+        function(0, 0, vec![OpCode::Pop, OpCode::Return]),
+    ]);
+    assert_eq!(verify(&underflow), error(1, 0, Violation::StackUnderflow));
+
+    let past_end = program(vec![
+        function(0, 0, vec![OpCode::Skip(2)]),
+    ]);
+    assert_eq!(verify(&past_end), error(1, 0, Violation::OutOfBounds));
+}
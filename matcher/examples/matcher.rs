@@ -0,0 +1,15 @@
+extern crate grass;
+extern crate matcher;
+
+use grass::driver;
+
+fn main() {
+    let mut my_driver = driver::Driver::default();
+    let guest = matcher::register(&mut my_driver);
+
+    let text = "the quick brown fox jumps over the lazy dog";
+    for pattern in &["fox", "q.*k", "o.*z", "cat", "x*y*dog"] {
+        let matched = matcher::is_match(&mut my_driver, guest, pattern, text).expect("guest error");
+        println!("{:>8} {}", pattern, matched);
+    }
+}
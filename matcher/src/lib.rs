@@ -0,0 +1,77 @@
+//! The matcher guest: searches text for patterns made of characters, `.`
+//! for any character and `*` for any number of the preceding one. The
+//! interpreter in `this` backtracks over the stars, which makes the branches
+//! it takes depend on the text, and runs standalone on the driver, see
+//! `Driver::run_program`.
+
+extern crate grass;
+
+use grass::bc::Program;
+use grass::core::objects::{R_BoxedValue, R_Struct};
+use grass::driver::{Driver, InterpResult, ProgramId};
+
+pub mod this;
+
+
+/// Compiled patterns are pairs of a kind and a character. `ONE` matches the
+/// character once, `STAR` any number of times.
+pub const ONE: usize = 0;
+pub const STAR: usize = 1;
+/// the character for `.`, outside of the range of bytes
+pub const ANY: usize = 256;
+
+/// stars a pattern may have
+pub const MAX_STARS: usize = 16;
+/// the backtracking stack holds a position in the pattern and the text per
+/// star
+pub const STACK_SIZE: usize = 2 * MAX_STARS;
+
+/// Make the matcher interpreter known to `driver`.
+pub fn register(driver: &mut Driver) -> ProgramId {
+    driver.register_program(Program::from_static(this::PROGRAM, this::IDX))
+}
+
+/// Turn `pattern` into the tokens the interpreter runs.
+pub fn compile(pattern: &str) -> Vec<usize> {
+    let mut tokens = Vec::new();
+    for byte in pattern.bytes() {
+        match byte {
+            b'*' => {
+                let len = tokens.len();
+                if len == 0 || tokens[len - 2] == STAR {
+                    panic!("`*` has to follow a character in {:?}", pattern);
+                }
+                tokens[len - 2] = STAR;
+            }
+            b'.' => tokens.extend(&[ONE, ANY]),
+            byte => tokens.extend(&[ONE, byte as usize]),
+        }
+    }
+    let stars = tokens.chunks(2).filter(|token| token[0] == STAR).count();
+    if stars > MAX_STARS {
+        panic!("{:?} has more than {} stars", pattern, MAX_STARS);
+    }
+    tokens
+}
+
+/// Whether `pattern` matches somewhere in `text`.
+pub fn is_match(driver: &mut Driver, guest: ProgramId, pattern: &str, text: &str)
+                -> InterpResult<bool> {
+    let args = vec![to_struct(text.bytes().map(|byte| byte as usize)),
+                    to_struct(compile(pattern).into_iter()),
+                    R_BoxedValue::Usize(0),
+                    R_BoxedValue::Usize(0)];
+
+    match try!(driver.run_program(guest, this::IDX.0, args)) {
+        R_BoxedValue::Bool(matched) => Ok(matched),
+        result => panic!("expected a bool, got {:?}", result),
+    }
+}
+
+fn to_struct<I: ExactSizeIterator<Item = usize>>(values: I) -> R_BoxedValue {
+    let mut array = R_Struct::with_size(values.len());
+    for (idx, value) in values.enumerate() {
+        array.set(idx, R_BoxedValue::Usize(value));
+    }
+    R_BoxedValue::Struct(array)
+}
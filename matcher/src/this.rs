@@ -0,0 +1,87 @@
+//! Bytecode of the matcher interpreter. It is assembled by hand rather than
+//! generated by `grassc`, the comments give its control flow in pseudo code.
+
+use grass::core::objects::R_BoxedValue::*;
+
+use grass::bc::bytecode::OpCode;
+use grass::bc::bytecode::BinOp::*;
+
+use super::{ANY, STAR, STACK_SIZE};
+
+
+pub static PROGRAM: &'static [(usize, usize, &'static [OpCode])] = &[
+    // the merge point
+    (0, 0, &[]),
+    // fn main(text: &[usize], pattern: &[usize], pos: usize, pc: usize) -> bool
+    // with `start`, `stack`, `sp` and `c` in the locals 4 to 7
+    (4, 8, &[
+        // let mut start = 0;
+        OpCode::ConstValue(Usize(0)), OpCode::Use, OpCode::Store(4),
+        // let mut stack = [0; STACK_SIZE];
+        OpCode::ConstValue(Usize(0)), OpCode::Repeat(STACK_SIZE), OpCode::Store(5),
+        // let mut sp = 0;
+        OpCode::ConstValue(Usize(0)), OpCode::Use, OpCode::Store(6),
+        // head: pc = merge_point(pattern, pos, pc);
+        OpCode::Load(1), OpCode::Load(2), OpCode::Load(3), OpCode::ConstValue(Func(0)),
+        OpCode::Call, OpCode::Store(3),
+        // if pc >= pattern.len() { return true }
+        OpCode::Load(3), OpCode::Load(1), OpCode::Len, OpCode::BinOp(Ge), OpCode::SkipIf(142),
+        // let c = pattern[pc + 1];
+        OpCode::Load(3), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Load(1),
+        OpCode::GetIndex, OpCode::Use, OpCode::Store(7),
+        // if pattern[pc] == STAR { goto star }
+        OpCode::Load(3), OpCode::Load(1), OpCode::GetIndex, OpCode::ConstValue(Usize(STAR)),
+        OpCode::BinOp(Eq), OpCode::SkipIf(110),
+        // if pos < text.len() && (c == ANY || text[pos] == c) {
+        OpCode::Load(2), OpCode::Load(0), OpCode::Len, OpCode::BinOp(Lt), OpCode::SkipIf(2),
+        OpCode::Skip(21), OpCode::Load(7), OpCode::ConstValue(Usize(ANY)), OpCode::BinOp(Eq),
+        OpCode::SkipIf(8), OpCode::Load(2), OpCode::Load(0), OpCode::GetIndex, OpCode::Load(7),
+        OpCode::BinOp(Eq), OpCode::SkipIf(2), OpCode::Skip(10),
+        //     pos += 1; pc += 2; goto head;
+        OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(2),
+        OpCode::Load(3), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add), OpCode::Store(3),
+        OpCode::JumpBack(49),
+        // }
+        // backtrack: let the last star match one more character, or start over
+        // if sp == 0 { goto restart }
+        OpCode::Load(6), OpCode::ConstValue(Usize(0)), OpCode::BinOp(Eq), OpCode::SkipIf(64),
+        // sp -= 2; pc = stack[sp]; pos = stack[sp + 1]; c = pattern[pc + 1];
+        OpCode::Load(6), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Sub), OpCode::Store(6),
+        OpCode::Load(6), OpCode::Load(5), OpCode::GetIndex, OpCode::Use, OpCode::Store(3),
+        OpCode::Load(6), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Load(5),
+        OpCode::GetIndex, OpCode::Use, OpCode::Store(2), OpCode::Load(3),
+        OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Load(1), OpCode::GetIndex,
+        OpCode::Use, OpCode::Store(7),
+        // if pos < text.len() && (c == ANY || text[pos] == c) {
+        OpCode::Load(2), OpCode::Load(0), OpCode::Len, OpCode::BinOp(Lt), OpCode::SkipIf(2),
+        OpCode::JumpBack(32), OpCode::Load(7), OpCode::ConstValue(Usize(ANY)), OpCode::BinOp(Eq),
+        OpCode::SkipIf(8), OpCode::Load(2), OpCode::Load(0), OpCode::GetIndex, OpCode::Load(7),
+        OpCode::BinOp(Eq), OpCode::SkipIf(2), OpCode::JumpBack(43),
+        //     pos += 1; stack[sp] = pc; stack[sp + 1] = pos; sp += 2; pc += 2; goto head;
+        OpCode::Load(2), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(2),
+        OpCode::Load(3), OpCode::Load(6), OpCode::Load(5), OpCode::AssignIndex, OpCode::Load(2),
+        OpCode::Load(6), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Load(5),
+        OpCode::AssignIndex, OpCode::Load(6), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add),
+        OpCode::Store(6), OpCode::Load(3), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add),
+        OpCode::Store(3), OpCode::JumpBack(116),
+        // }
+        // goto backtrack;
+        // restart: start += 1; if start > text.len() { return false }
+        OpCode::Load(4), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Store(4),
+        OpCode::Load(4), OpCode::Load(0), OpCode::Len, OpCode::BinOp(Gt), OpCode::SkipIf(29),
+        // pos = start; pc = 0; goto head;
+        OpCode::Load(4), OpCode::Use, OpCode::Store(2), OpCode::ConstValue(Usize(0)), OpCode::Use,
+        OpCode::Store(3), OpCode::JumpBack(132),
+        // star: stack[sp] = pc; stack[sp + 1] = pos; sp += 2; pc += 2; goto head;
+        OpCode::Load(3), OpCode::Load(6), OpCode::Load(5), OpCode::AssignIndex, OpCode::Load(2),
+        OpCode::Load(6), OpCode::ConstValue(Usize(1)), OpCode::BinOp(Add), OpCode::Load(5),
+        OpCode::AssignIndex, OpCode::Load(6), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add),
+        OpCode::Store(6), OpCode::Load(3), OpCode::ConstValue(Usize(2)), OpCode::BinOp(Add),
+        OpCode::Store(3), OpCode::JumpBack(151),
+        // return true
+        OpCode::ConstValue(Bool(true)), OpCode::Return,
+        // return false
+        OpCode::ConstValue(Bool(false)), OpCode::Return,
+    ]),
+];
+pub const IDX: (usize, usize) = (1, 15);
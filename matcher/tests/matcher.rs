@@ -0,0 +1,102 @@
+extern crate grass;
+extern crate matcher;
+
+use grass::bc::Program;
+use grass::bc::verify::verify;
+use grass::driver::{Anchoring, Driver};
+use grass::driver::golden::{check_golden, dump_traces};
+
+// The same search in Rust: `pattern` matches at some position of `text`.
+fn reference(pattern: &[u8], text: &[u8]) -> bool {
+    (0..text.len() + 1).any(|start| matches_here(pattern, &text[start..]))
+}
+
+fn matches_here(pattern: &[u8], text: &[u8]) -> bool {
+    if pattern.is_empty() {
+        return true;
+    }
+    let c = pattern[0];
+    let one = |text: &[u8]| !text.is_empty() && (c == b'.' || text[0] == c);
+    if pattern.get(1) == Some(&b'*') {
+        let mut text = text;
+        loop {
+            if matches_here(&pattern[2..], text) {
+                return true;
+            }
+            if !one(text) {
+                return false;
+            }
+            text = &text[1..];
+        }
+    }
+    one(text) && matches_here(&pattern[1..], &text[1..])
+}
+
+const PATTERNS: &'static [&'static str] = &[
+    "", "a", "ab", "b.d", "a*", "a*b", "ab*c", ".*", "a.*d", "x*y*z", "c.*a.*b", "aaaa*b", "..c",
+];
+
+const TEXTS: &'static [&'static str] = &[
+    "", "a", "abcd", "aaaaaaaab", "abbbbbbc", "ac", "xxyyzz", "cbacab", "dcba", "aaaaaaaaaaaaaaaaaa",
+];
+
+fn check(driver: &mut Driver) {
+    let guest = matcher::register(driver);
+    for pattern in PATTERNS {
+        for text in TEXTS {
+            assert_eq!(matcher::is_match(driver, guest, pattern, text),
+                       Ok(reference(pattern.as_bytes(), text.as_bytes())),
+                       "{:?} in {:?}", pattern, text);
+        }
+    }
+}
+
+#[test]
+fn matches_like_rust() {
+    check(&mut Driver::default());
+}
+
+// every merge point records, so traces get entered by texts which take
+// other branches than the recorded ones
+#[test]
+fn matches_like_rust_traced_everywhere() {
+    let mut driver = Driver::default();
+    driver.set_anchoring(Anchoring::Everywhere);
+    check(&mut driver);
+}
+
+#[test]
+fn literals_match_like_contains() {
+    let mut driver = Driver::default();
+    let guest = matcher::register(&mut driver);
+    let text = "the quick brown fox jumps over the lazy dog";
+    for pattern in &["the", "fox", "dog", "cat", "lazy fox", "g"] {
+        assert_eq!(matcher::is_match(&mut driver, guest, pattern, text),
+                   Ok(text.contains(pattern)));
+    }
+}
+
+#[test]
+fn backtracking_gets_traced() {
+    let mut driver = Driver::default();
+    let guest = matcher::register(&mut driver);
+    let text = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaac";
+    assert_eq!(matcher::is_match(&mut driver, guest, "a*a*b", text), Ok(false));
+    assert!(driver.coverage(guest).traced() > 0);
+}
+
+#[test]
+fn traces_match_the_golden_file() {
+    let mut driver = Driver::default();
+    let guest = matcher::register(&mut driver);
+    let text = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaac";
+    assert_eq!(matcher::is_match(&mut driver, guest, "a*a*b", text), Ok(false));
+    check_golden(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/backtracking.txt"),
+                 &dump_traces(&driver, guest));
+}
+
+#[test]
+fn bytecode_passes_the_verifier() {
+    let program = Program::from_static(matcher::this::PROGRAM, matcher::this::IDX);
+    assert_eq!(verify(&program), Ok(()));
+}
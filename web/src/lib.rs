@@ -0,0 +1,108 @@
+//! The counter guest running traced in the browser, see `index.html`.
+//!
+//! ```text
+//! cargo build --release --target wasm32-unknown-unknown
+//! cp target/wasm32-unknown-unknown/release/meta_web.wasm .
+//! python -m SimpleHTTPServer    # and open http://localhost:8000
+//! ```
+//!
+//! The driver is kept between runs, so only the first run records the trace
+//! of the loop, later ones execute it right away.
+
+extern crate grass;
+extern crate meta_bf;
+
+use std::cell::RefCell;
+use std::sync::mpsc::Receiver;
+
+use grass::driver::{Driver, GuestClock, JitEvent, ProgramId};
+use meta_bf::{DEC, REP};
+
+extern "C" {
+    // `performance.now()` of the page, in milliseconds
+    fn performance_now() -> f64;
+}
+
+struct BrowserClock;
+
+impl GuestClock for BrowserClock {
+    fn nanos(&mut self) -> u64 {
+        unsafe { (performance_now() * 1_000_000.0) as u64 }
+    }
+}
+
+struct Demo {
+    driver: Driver,
+    guest: ProgramId,
+    events: Receiver<JitEvent>,
+    /// traces recorded since the page was loaded
+    traces: usize,
+    /// executions of a trace since the page was loaded
+    trace_runs: usize,
+    /// duration of the last run in milliseconds
+    elapsed: f64,
+}
+
+impl Demo {
+    fn new() -> Self {
+        let mut driver = Driver::default();
+        driver.set_clock(Box::new(BrowserClock));
+        let events = driver.subscribe();
+        let guest = meta_bf::register(&mut driver);
+
+        Demo {
+            driver: driver,
+            guest: guest,
+            events: events,
+            traces: 0,
+            trace_runs: 0,
+            elapsed: 0.0,
+        }
+    }
+
+    fn count_down(&mut self, start: usize) -> Option<usize> {
+        let began = unsafe { performance_now() };
+        let cell = meta_bf::run(&mut self.driver, self.guest, &[DEC, REP], start);
+        self.elapsed = unsafe { performance_now() } - began;
+
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                JitEvent::TraceFinished { .. } => self.traces += 1,
+                // every execution of a trace ends in a guard failure
+                JitEvent::GuardFailed { .. } => self.trace_runs += 1,
+                _ => (),
+            }
+        }
+        cell.ok()
+    }
+}
+
+thread_local!(static DEMO: RefCell<Demo> = RefCell::new(Demo::new()));
+
+/// Count `start` down to zero with the guest program `[DEC, REP]`. Returns
+/// the final value of the cell, or -1 if the guest failed.
+#[no_mangle]
+pub extern "C" fn count_down(start: usize) -> isize {
+    DEMO.with(|demo| {
+        match demo.borrow_mut().count_down(start) {
+            Some(cell) => cell as isize,
+            None => -1,
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn traces_recorded() -> usize {
+    DEMO.with(|demo| demo.borrow().traces)
+}
+
+#[no_mangle]
+pub extern "C" fn trace_runs() -> usize {
+    DEMO.with(|demo| demo.borrow().trace_runs)
+}
+
+/// Duration of the last `count_down` in milliseconds.
+#[no_mangle]
+pub extern "C" fn elapsed_ms() -> f64 {
+    DEMO.with(|demo| demo.borrow().elapsed)
+}